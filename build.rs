@@ -0,0 +1,25 @@
+use std::{env, fs::File, path::Path};
+
+use gl_generator::{Api, Fallbacks, Profile, Registry, StructGenerator};
+use wayland_scanner::{generate_code, Side};
+
+fn main() {
+    // protocols that are too new for wayland-protocols
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let protocols = [
+        ("protocols/cursor-shape-v1.xml", "cursor_shape_v1.rs"),
+        ("protocols/fractional-scale-v1.xml", "fractional_scale_v1.rs"),
+        // version 4, with the feedback
+        ("protocols/linux-dmabuf-unstable-v1.xml", "linux_dmabuf_unstable_v1.rs"),
+    ];
+    for (protocol, target) in protocols.iter() {
+        println!("cargo:rerun-if-changed={}", protocol);
+        generate_code(protocol, Path::new(&out_dir).join(target), Side::Server);
+    }
+
+    // the few gl calls the renderer of smithay does not make for us
+    let mut file = File::create(Path::new(&out_dir).join("gl_bindings.rs")).unwrap();
+    Registry::new(Api::Gles2, (2, 0), Profile::Core, Fallbacks::None, [])
+        .write_bindings(StructGenerator, &mut file)
+        .unwrap();
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+#[cfg(feature = "wallpaper")]
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+#[cfg(feature = "wallpaper")]
+use image::{imageops, Rgba, RgbaImage};
+use slog::Logger;
+use smithay::backend::renderer::{Frame, Renderer, Texture, Transform};
+
+#[cfg(feature = "wallpaper")]
+use crate::custom::config::BackgroundMode;
+use crate::{
+    custom::config::{Background, BackgroundConfig},
+    drawing::OutputView,
+};
+
+/// The size in physical pixels and the configuration a wallpaper is composed for.
+type WallpaperKey = ((u32, u32), BackgroundConfig);
+
+/// A wallpaper composed by a worker thread, for an output.
+#[cfg(feature = "wallpaper")]
+struct Composed {
+    name: String,
+    key: WallpaperKey,
+    /// `None` if there is no image to show
+    image: Option<RgbaImage>,
+}
+
+/// Paints the backgrounds of the outputs of a renderer.
+///
+/// The wallpaper of an output is composed for its size in physical pixels by a worker thread and
+/// uploaded once it is done, the output shows its color until then. It is composed again when
+/// the output changes its mode or scale.
+pub struct Backgrounds<T> {
+    config: Background,
+    /// The decoded images by path, `None` if the image could not be loaded
+    #[cfg(feature = "wallpaper")]
+    images: Arc<Mutex<HashMap<String, Option<Arc<RgbaImage>>>>>,
+    /// The wallpapers the worker threads compose, by output name
+    #[cfg(feature = "wallpaper")]
+    pending: HashMap<String, WallpaperKey>,
+    /// The wallpapers the worker threads finished, waiting to be uploaded
+    #[cfg(feature = "wallpaper")]
+    composed: HashMap<String, (WallpaperKey, Option<RgbaImage>)>,
+    #[cfg(feature = "wallpaper")]
+    sender: Sender<Composed>,
+    #[cfg(feature = "wallpaper")]
+    receiver: Receiver<Composed>,
+    /// The wallpapers by output name, with the size and configuration they were composed for,
+    /// `None` if there is no image to show
+    textures: HashMap<String, (WallpaperKey, Option<T>)>,
+    /// The textures of outputs that went away, destroyed with the next frame
+    destroyed: Vec<T>,
+    log: Logger,
+}
+
+impl<T: Texture> Backgrounds<T> {
+    pub fn new(config: Background, log: Logger) -> Self {
+        #[cfg(feature = "wallpaper")]
+        let (sender, receiver) = channel();
+        Backgrounds {
+            config,
+            #[cfg(feature = "wallpaper")]
+            images: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "wallpaper")]
+            pending: HashMap::new(),
+            #[cfg(feature = "wallpaper")]
+            composed: HashMap::new(),
+            #[cfg(feature = "wallpaper")]
+            sender,
+            #[cfg(feature = "wallpaper")]
+            receiver,
+            textures: HashMap::new(),
+            destroyed: Vec::new(),
+            log,
+        }
+    }
+
+    /// Collects the wallpapers the worker threads finished, returns the names of the outputs
+    /// that have to be repainted to show them.
+    #[cfg(feature = "wallpaper")]
+    pub fn receive(&mut self) -> Vec<String> {
+        let mut names = Vec::new();
+        while let Ok(Composed { name, key, image }) = self.receiver.try_recv() {
+            // the outputs that changed again meanwhile wait for another one
+            if self.pending.get(&name) != Some(&key) {
+                continue;
+            }
+            self.pending.remove(&name);
+            self.composed.insert(name.clone(), (key, image));
+            names.push(name);
+        }
+        names
+    }
+
+    #[cfg(not(feature = "wallpaper"))]
+    pub fn receive(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Forgets the wallpapers of the outputs that are not in `names`.
+    pub fn retain_outputs(&mut self, names: &[String]) {
+        let gone = self
+            .textures
+            .keys()
+            .filter(|name| !names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in gone {
+            if let Some((_, Some(texture))) = self.textures.remove(&name) {
+                self.destroyed.push(texture);
+            }
+        }
+        #[cfg(feature = "wallpaper")]
+        {
+            self.pending.retain(|name, _| names.contains(name));
+            self.composed.retain(|name, _| names.contains(name));
+        }
+    }
+
+    /// Paints the background of an output, `identifiers` are the names it can be configured by.
+    pub fn draw<R, F, E>(
+        &mut self,
+        renderer: &mut R,
+        frame: &mut F,
+        name: &str,
+        identifiers: &[String],
+        output: &OutputView,
+    ) -> Result<(), E>
+    where
+        R: Renderer<Error = E, TextureId = T, Frame = F>,
+        F: Frame<Error = E, TextureId = T>,
+    {
+        for texture in self.destroyed.drain(..) {
+            renderer.destroy_texture(texture)?;
+        }
+
+        let config = self.config.get(identifiers);
+        frame.clear(config.color())?;
+        if config.image.is_none() {
+            if let Some((_, Some(texture))) = self.textures.remove(name) {
+                renderer.destroy_texture(texture)?;
+            }
+            return Ok(());
+        }
+
+        let size = (
+            (output.geometry.width as f64 * output.scale).round() as u32,
+            (output.geometry.height as f64 * output.scale).round() as u32,
+        );
+        let key = (size, config);
+        self.upload(renderer, name)?;
+        let outdated = self
+            .textures
+            .get(name)
+            .map(|(current, _)| *current != key)
+            .unwrap_or(true);
+        if outdated {
+            self.start_composing(name, key);
+        }
+
+        // an outdated wallpaper is still shown until the new one is composed
+        if let Some((_, Some(texture))) = self.textures.get(name) {
+            let geometry = output.geometry;
+            output.render_texture(
+                frame,
+                texture,
+                (
+                    geometry.x as f64,
+                    geometry.y as f64,
+                    geometry.width as f64,
+                    geometry.height as f64,
+                ),
+                Transform::Normal,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the composed wallpaper of an output, replacing the one it shows.
+    #[cfg(feature = "wallpaper")]
+    fn upload<R, F, E>(&mut self, renderer: &mut R, name: &str) -> Result<(), E>
+    where
+        R: Renderer<Error = E, TextureId = T, Frame = F>,
+        F: Frame<Error = E, TextureId = T>,
+    {
+        let (key, image) = match self.composed.remove(name) {
+            Some(composed) => composed,
+            None => return Ok(()),
+        };
+        if let Some((_, Some(texture))) = self.textures.remove(name) {
+            renderer.destroy_texture(texture)?;
+        }
+        let texture = match image {
+            Some(image) => Some(renderer.import_bitmap(&image)?),
+            None => None,
+        };
+        self.textures.insert(name.to_string(), (key, texture));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "wallpaper"))]
+    fn upload<R, F, E>(&mut self, _renderer: &mut R, _name: &str) -> Result<(), E>
+    where
+        R: Renderer<Error = E, TextureId = T, Frame = F>,
+        F: Frame<Error = E, TextureId = T>,
+    {
+        Ok(())
+    }
+
+    /// Has a worker thread compose the wallpaper of an output, unless one already does.
+    #[cfg(feature = "wallpaper")]
+    fn start_composing(&mut self, name: &str, key: WallpaperKey) {
+        if self.pending.get(name) == Some(&key) {
+            return;
+        }
+        self.pending.insert(name.to_string(), key.clone());
+        let (images, sender, log) = (self.images.clone(), self.sender.clone(), self.log.clone());
+        let name = name.to_string();
+        let spawned = thread::Builder::new().name("wallpaper".into()).spawn(move || {
+            let ((width, height), ref config) = key;
+            let image = match load_image(&images, config.image.as_deref().unwrap_or_default(), &log) {
+                Some(image) if width > 0 && height > 0 => {
+                    Some(compose(&image, (width, height), config.mode(), config.color()))
+                }
+                _ => None,
+            };
+            // the receiving renderer may be gone already
+            let _ = sender.send(Composed { name, key, image });
+        });
+        if let Err(err) = spawned {
+            warn!(self.log, "Failed to start composing the wallpaper: {}", err);
+        }
+    }
+
+    #[cfg(not(feature = "wallpaper"))]
+    fn start_composing(&mut self, name: &str, key: WallpaperKey) {
+        // outputs without a wallpaper texture keep showing their color
+        if !self.textures.contains_key(name) {
+            warn!(self.log, "Wallpapers are not supported without the wallpaper feature");
+            self.textures.insert(name.to_string(), (key, None));
+        }
+    }
+}
+
+/// Decodes an image once, the worker threads of all renderers share them.
+#[cfg(feature = "wallpaper")]
+fn load_image(
+    images: &Mutex<HashMap<String, Option<Arc<RgbaImage>>>>,
+    path: &str,
+    log: &Logger,
+) -> Option<Arc<RgbaImage>> {
+    if let Some(image) = images.lock().unwrap().get(path) {
+        return image.clone();
+    }
+    info!(log, "Loading the wallpaper"; "path" => path);
+    let image = match image::open(path) {
+        Ok(image) => Some(Arc::new(image.to_rgba8())),
+        Err(err) => {
+            warn!(log, "Failed to load the wallpaper {}: {}", path, err);
+            None
+        }
+    };
+    images.lock().unwrap().insert(path.to_string(), image.clone());
+    image
+}
+
+/// Draws the image over the color, covering an area of `size` as the mode says.
+#[cfg(feature = "wallpaper")]
+fn compose(image: &RgbaImage, (width, height): (u32, u32), mode: BackgroundMode, color: [f32; 4]) -> RgbaImage {
+    let pixel = Rgba([
+        (color[0] * 255.0).round() as u8,
+        (color[1] * 255.0).round() as u8,
+        (color[2] * 255.0).round() as u8,
+        (color[3] * 255.0).round() as u8,
+    ]);
+    let mut canvas = RgbaImage::from_pixel(width, height, pixel);
+
+    let (image_width, image_height) = (image.width() as f64, image.height() as f64);
+    let scaled = |factor: f64| {
+        imageops::resize(
+            image,
+            ((image_width * factor).round() as u32).max(1),
+            ((image_height * factor).round() as u32).max(1),
+            imageops::FilterType::Triangle,
+        )
+    };
+    let centered = |image: &RgbaImage| {
+        (
+            (width as i64 - image.width() as i64) / 2,
+            (height as i64 - image.height() as i64) / 2,
+        )
+    };
+
+    match mode {
+        BackgroundMode::Stretch => {
+            let image = imageops::resize(image, width, height, imageops::FilterType::Triangle);
+            place(&mut canvas, &image, (0, 0));
+        }
+        BackgroundMode::Fit => {
+            let image = scaled((width as f64 / image_width).min(height as f64 / image_height));
+            place(&mut canvas, &image, centered(&image));
+        }
+        BackgroundMode::Fill => {
+            let image = scaled((width as f64 / image_width).max(height as f64 / image_height));
+            place(&mut canvas, &image, centered(&image));
+        }
+        BackgroundMode::Center => place(&mut canvas, image, centered(image)),
+        BackgroundMode::Tile => {
+            for y in (0..height).step_by(image.height().max(1) as usize) {
+                for x in (0..width).step_by(image.width().max(1) as usize) {
+                    place(&mut canvas, image, (x as i64, y as i64));
+                }
+            }
+        }
+    }
+    canvas
+}
+
+/// Draws `image` over `canvas` with its top left corner at `(x, y)`, cropping what does not fit.
+#[cfg(feature = "wallpaper")]
+fn place(canvas: &mut RgbaImage, image: &RgbaImage, (x, y): (i64, i64)) {
+    let (left, top) = ((-x).max(0) as u32, (-y).max(0) as u32);
+    if left >= image.width() || top >= image.height() {
+        return;
+    }
+    let visible = imageops::crop_imm(image, left, top, image.width() - left, image.height() - top).to_image();
+    imageops::overlay(canvas, &visible, x.max(0) as u32, y.max(0) as u32);
+}
@@ -0,0 +1,337 @@
+//! Queries and controls a running waybox over its ipc socket.
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process,
+};
+
+use serde_json::{Map, Value};
+
+#[path = "../ipc_socket.rs"]
+mod ipc_socket;
+
+const USAGE: &str = "USAGE: wayboxctl [--json] [--socket <path>] <command>
+
+Queries:
+    windows | tree | outputs | workspaces | config | version
+
+Commands, for the window with --id, the topmost one matching --app-id and --title,
+or the focused one:
+    focus
+    move <x> <y>
+    resize <width> <height>
+    close
+
+Other commands:
+    workspace <number>
+    exec <command>...
+    reload
+    subscribe <window|workspace|output>...";
+
+/// The command succeeded.
+const EXIT_SUCCESS: i32 = 0;
+/// The compositor refused the command.
+const EXIT_FAILURE: i32 = 1;
+/// The command line is wrong.
+const EXIT_USAGE: i32 = 2;
+/// The compositor could not be reached.
+const EXIT_CONNECTION: i32 = 3;
+
+struct Options {
+    json: bool,
+    socket: Option<PathBuf>,
+    id: Option<u64>,
+    app_id: Option<String>,
+    title: Option<String>,
+    /// The command and its arguments
+    words: Vec<String>,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        json: false,
+        socket: None,
+        id: None,
+        app_id: None,
+        title: None,
+        words: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            // everything after the command belongs to it, for exec
+            _ if options.words.first().map(String::as_str) == Some("exec") => options.words.push(arg),
+            "--json" => options.json = true,
+            "--socket" => options.socket = Some(value("--socket")?.into()),
+            "--id" => {
+                let id = value("--id")?;
+                options.id = Some(id.parse().map_err(|_| format!("The id {} is not a number", id))?);
+            }
+            "--app-id" => options.app_id = Some(value("--app-id")?),
+            "--title" => options.title = Some(value("--title")?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => options.words.push(arg),
+        }
+    }
+    Ok(options)
+}
+
+fn number(words: &[String], idx: usize, name: &str) -> Result<i64, String> {
+    words
+        .get(idx)
+        .ok_or(format!("Missing {}", name))?
+        .parse()
+        .map_err(|_| format!("The {} has to be a number", name))
+}
+
+/// Builds the request for a command line.
+fn request(options: &Options) -> Result<Value, String> {
+    let words = &options.words;
+    let command = words.first().ok_or_else(String::new)?;
+    let mut request = Map::new();
+    request.insert("type".into(), command.as_str().into());
+    let arguments = match command.as_str() {
+        "windows" | "tree" | "outputs" | "workspaces" | "config" | "version" | "reload" | "focus" | "close" => 0,
+        "move" => {
+            request.insert("x".into(), number(words, 1, "x")?.into());
+            request.insert("y".into(), number(words, 2, "y")?.into());
+            2
+        }
+        "resize" => {
+            request.insert("width".into(), number(words, 1, "width")?.into());
+            request.insert("height".into(), number(words, 2, "height")?.into());
+            2
+        }
+        "workspace" => {
+            request.insert("number".into(), number(words, 1, "workspace")?.into());
+            1
+        }
+        "exec" if words.len() > 1 => {
+            // the arguments stay apart, the compositor runs the program without a shell
+            request.insert("command".into(), words[1..].to_vec().into());
+            words.len() - 1
+        }
+        "exec" => return Err("Missing the command to run".into()),
+        "subscribe" if words.len() > 1 => {
+            request.insert("events".into(), words[1..].to_vec().into());
+            words.len() - 1
+        }
+        "subscribe" => return Err("Missing the events to subscribe to".into()),
+        _ => return Err(format!("Unknown command {}", command)),
+    };
+    if words.len() > arguments + 1 {
+        return Err(format!("Too many arguments for {}", command));
+    }
+    if matches!(command.as_str(), "focus" | "move" | "resize" | "close") {
+        if let Some(id) = options.id {
+            request.insert("id".into(), id.into());
+        }
+        if let Some(ref app_id) = options.app_id {
+            request.insert("app_id".into(), app_id.as_str().into());
+        }
+        if let Some(ref title) = options.title {
+            request.insert("title".into(), title.as_str().into());
+        }
+    }
+    Ok(Value::Object(request))
+}
+
+/// The socket of the compositor this runs in, or of the one on the same wayland display.
+fn socket_path(options: &Options) -> Result<PathBuf, String> {
+    if let Some(ref path) = options.socket {
+        return Ok(path.clone());
+    }
+    if let Some(path) = env::var_os("WAYBOX_SOCK") {
+        return Ok(path.into());
+    }
+    match (env::var_os("XDG_RUNTIME_DIR"), env::var("WAYLAND_DISPLAY")) {
+        (Some(dir), Ok(display)) => Ok(ipc_socket::socket_path(&PathBuf::from(dir), &display)),
+        _ => Err("WAYBOX_SOCK is not set, is waybox running?".into()),
+    }
+}
+
+fn text(value: &Value) -> &str {
+    value.as_str().unwrap_or("-")
+}
+
+/// Prints a reply for humans.
+fn print_reply(command: &str, reply: &Value) {
+    match command {
+        "windows" => {
+            for window in reply["windows"].as_array().into_iter().flatten() {
+                let geometry = &window["geometry"];
+                println!(
+                    "{} {} {} {:?} {}x{}+{}+{} workspace {} ({})",
+                    if window["focused"] == true { "*" } else { " " },
+                    window["id"],
+                    text(&window["app_id"]),
+                    text(&window["title"]),
+                    geometry["width"],
+                    geometry["height"],
+                    geometry["x"],
+                    geometry["y"],
+                    window["workspace"],
+                    text(&window["kind"]),
+                );
+            }
+        }
+        "outputs" => {
+            for output in reply["outputs"].as_array().into_iter().flatten() {
+                print!(
+                    "{} {}x{}+{}+{} scale {}",
+                    text(&output["name"]),
+                    output["width"],
+                    output["height"],
+                    output["x"],
+                    output["y"],
+                    output["scale"],
+                );
+                if output["mode"].is_object() {
+                    print!(
+                        " mode {}x{}@{}",
+                        output["mode"]["width"], output["mode"]["height"], output["mode"]["refresh"]
+                    );
+                }
+                if output["powered"] == false {
+                    print!(" (off)");
+                }
+                println!();
+            }
+        }
+        "workspaces" => {
+            for workspace in reply["workspaces"].as_array().into_iter().flatten() {
+                println!(
+                    "{} {} ({} windows)",
+                    if workspace["active"] == true { "*" } else { " " },
+                    workspace["number"],
+                    workspace["windows"],
+                );
+            }
+        }
+        "tree" | "config" => println!(
+            "{}",
+            serde_json::to_string_pretty(&reply[command]).unwrap_or_default()
+        ),
+        "version" => println!("waybox {}", text(&reply["version"])),
+        _ => {}
+    }
+}
+
+/// Prints an event for humans.
+fn print_event(event: &Value) {
+    let subject = match event["event"].as_str() {
+        Some("window") if event["window"].is_null() => "none".to_string(),
+        Some("window") => format!(
+            "{} {} {:?}",
+            event["window"]["id"],
+            text(&event["window"]["app_id"]),
+            text(&event["window"]["title"])
+        ),
+        Some("workspace") => format!("{} (was {})", event["workspace"], event["previous"]),
+        Some("output") => text(&event["output"]["name"]).to_string(),
+        _ => event.to_string(),
+    };
+    println!("{} {}: {}", text(&event["event"]), text(&event["change"]), subject);
+}
+
+fn run(options: &Options) -> i32 {
+    let request = match request(options) {
+        Ok(request) => request,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("wayboxctl: {}", err);
+            }
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let path = match socket_path(options) {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("wayboxctl: {}", err);
+            return EXIT_CONNECTION;
+        }
+    };
+    let mut stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("wayboxctl: Failed to connect to {}: {}", path.display(), err);
+            return EXIT_CONNECTION;
+        }
+    };
+    if let Err(err) = writeln!(stream, "{}", request) {
+        eprintln!("wayboxctl: Failed to send the request: {}", err);
+        return EXIT_CONNECTION;
+    }
+
+    let command = request["type"].as_str().unwrap_or_default();
+    let mut lines = BufReader::new(stream).lines();
+    let reply = match lines.next().map(|line| line.map(|line| serde_json::from_str::<Value>(&line))) {
+        Some(Ok(Ok(reply))) => reply,
+        Some(Ok(Err(err))) => {
+            eprintln!("wayboxctl: Invalid reply: {}", err);
+            return EXIT_CONNECTION;
+        }
+        Some(Err(err)) => {
+            eprintln!("wayboxctl: Failed to read the reply: {}", err);
+            return EXIT_CONNECTION;
+        }
+        None => {
+            eprintln!("wayboxctl: The compositor closed the connection");
+            return EXIT_CONNECTION;
+        }
+    };
+
+    if reply["success"] != true {
+        if options.json {
+            println!("{}", reply);
+        } else {
+            eprintln!("wayboxctl: {}", text(&reply["error"]));
+        }
+        return EXIT_FAILURE;
+    }
+    if command != "subscribe" {
+        if options.json {
+            println!("{}", reply);
+        } else {
+            print_reply(command, &reply);
+        }
+        return EXIT_SUCCESS;
+    }
+
+    // events come until the compositor goes away
+    for line in lines {
+        let event = match line.map(|line| serde_json::from_str::<Value>(&line)) {
+            Ok(Ok(event)) => event,
+            _ => return EXIT_CONNECTION,
+        };
+        if options.json {
+            println!("{}", event);
+        } else {
+            print_event(&event);
+        }
+        // keep up with the compositor, it drops clients that lag behind
+        if std::io::stdout().flush().is_err() {
+            return EXIT_SUCCESS;
+        }
+    }
+    EXIT_CONNECTION
+}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("wayboxctl: {}", err);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    process::exit(run(&options));
+}
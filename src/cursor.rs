@@ -0,0 +1,269 @@
+use std::{collections::HashMap, fs::File, io::Read};
+
+use slog::Logger;
+use xcursor::{parser::parse_xcursor, CursorTheme as XCursorTheme};
+
+/// The shape the compositor wants to show when no client provided a cursor surface.
+///
+/// Besides the shapes of its own grabs, these are the shapes clients can ask for by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CursorShape {
+    Default,
+    Move,
+    ResizeTop,
+    ResizeBottom,
+    ResizeLeft,
+    ResizeRight,
+    ResizeTopLeft,
+    ResizeTopRight,
+    ResizeBottomLeft,
+    ResizeBottomRight,
+    ContextMenu,
+    Help,
+    Pointer,
+    Progress,
+    Wait,
+    Cell,
+    Crosshair,
+    Text,
+    VerticalText,
+    Alias,
+    Copy,
+    NoDrop,
+    NotAllowed,
+    Grab,
+    Grabbing,
+    ResizeLeftRight,
+    ResizeTopBottom,
+    ResizeTopRightBottomLeft,
+    ResizeTopLeftBottomRight,
+    ColumnResize,
+    RowResize,
+    AllScroll,
+    ZoomIn,
+    ZoomOut,
+}
+
+impl Default for CursorShape {
+    fn default() -> Self {
+        CursorShape::Default
+    }
+}
+
+impl CursorShape {
+    pub const ALL: [CursorShape; 34] = [
+        CursorShape::Default,
+        CursorShape::Move,
+        CursorShape::ResizeTop,
+        CursorShape::ResizeBottom,
+        CursorShape::ResizeLeft,
+        CursorShape::ResizeRight,
+        CursorShape::ResizeTopLeft,
+        CursorShape::ResizeTopRight,
+        CursorShape::ResizeBottomLeft,
+        CursorShape::ResizeBottomRight,
+        CursorShape::ContextMenu,
+        CursorShape::Help,
+        CursorShape::Pointer,
+        CursorShape::Progress,
+        CursorShape::Wait,
+        CursorShape::Cell,
+        CursorShape::Crosshair,
+        CursorShape::Text,
+        CursorShape::VerticalText,
+        CursorShape::Alias,
+        CursorShape::Copy,
+        CursorShape::NoDrop,
+        CursorShape::NotAllowed,
+        CursorShape::Grab,
+        CursorShape::Grabbing,
+        CursorShape::ResizeLeftRight,
+        CursorShape::ResizeTopBottom,
+        CursorShape::ResizeTopRightBottomLeft,
+        CursorShape::ResizeTopLeftBottomRight,
+        CursorShape::ColumnResize,
+        CursorShape::RowResize,
+        CursorShape::AllScroll,
+        CursorShape::ZoomIn,
+        CursorShape::ZoomOut,
+    ];
+
+    /// The xcursor names to look for, in order of preference.
+    ///
+    /// Themes are not consistent in their naming, so we try the X11 core names first
+    /// and the CSS names afterwards.
+    fn names(&self) -> &'static [&'static str] {
+        match *self {
+            CursorShape::Default => &["left_ptr", "default", "top_left_arrow"],
+            CursorShape::Move => &["fleur", "move", "grabbing", "all-scroll"],
+            CursorShape::ResizeTop => &["top_side", "n-resize", "ns-resize"],
+            CursorShape::ResizeBottom => &["bottom_side", "s-resize", "ns-resize"],
+            CursorShape::ResizeLeft => &["left_side", "w-resize", "ew-resize"],
+            CursorShape::ResizeRight => &["right_side", "e-resize", "ew-resize"],
+            CursorShape::ResizeTopLeft => &["top_left_corner", "nw-resize", "nwse-resize"],
+            CursorShape::ResizeTopRight => &["top_right_corner", "ne-resize", "nesw-resize"],
+            CursorShape::ResizeBottomLeft => &["bottom_left_corner", "sw-resize", "nesw-resize"],
+            CursorShape::ResizeBottomRight => &["bottom_right_corner", "se-resize", "nwse-resize"],
+            CursorShape::ContextMenu => &["context-menu"],
+            CursorShape::Help => &["question_arrow", "help", "left_ptr_help"],
+            CursorShape::Pointer => &["hand2", "pointer", "hand1"],
+            CursorShape::Progress => &["left_ptr_watch", "progress"],
+            CursorShape::Wait => &["watch", "wait"],
+            CursorShape::Cell => &["plus", "cell"],
+            CursorShape::Crosshair => &["crosshair", "cross", "tcross"],
+            CursorShape::Text => &["xterm", "text"],
+            CursorShape::VerticalText => &["vertical-text"],
+            CursorShape::Alias => &["dnd-link", "alias"],
+            CursorShape::Copy => &["dnd-copy", "copy"],
+            CursorShape::NoDrop => &["dnd-no-drop", "no-drop"],
+            CursorShape::NotAllowed => &["crossed_circle", "not-allowed"],
+            CursorShape::Grab => &["hand1", "grab", "openhand"],
+            CursorShape::Grabbing => &["grabbing", "closedhand", "fleur"],
+            CursorShape::ResizeLeftRight => &["sb_h_double_arrow", "ew-resize", "h_double_arrow"],
+            CursorShape::ResizeTopBottom => &["sb_v_double_arrow", "ns-resize", "v_double_arrow"],
+            CursorShape::ResizeTopRightBottomLeft => &["fd_double_arrow", "nesw-resize"],
+            CursorShape::ResizeTopLeftBottomRight => &["bd_double_arrow", "nwse-resize"],
+            CursorShape::ColumnResize => &["sb_h_double_arrow", "col-resize"],
+            CursorShape::RowResize => &["sb_v_double_arrow", "row-resize"],
+            CursorShape::AllScroll => &["fleur", "all-scroll"],
+            CursorShape::ZoomIn => &["zoom-in"],
+            CursorShape::ZoomOut => &["zoom-out"],
+        }
+    }
+}
+
+/// A single image of a (possibly animated) cursor.
+#[derive(Debug, Clone)]
+pub struct CursorFrame {
+    pub width: u32,
+    pub height: u32,
+    pub hotspot: (i32, i32),
+    /// Time this frame stays visible, in milliseconds.
+    pub delay: u32,
+    /// Pixel data in RGBA order.
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    frames: Vec<CursorFrame>,
+    total_delay: u32,
+}
+
+impl Cursor {
+    fn new(frames: Vec<CursorFrame>) -> Cursor {
+        let total_delay = frames.iter().map(|frame| frame.delay).sum();
+        Cursor { frames, total_delay }
+    }
+
+    pub fn frames(&self) -> &[CursorFrame] {
+        &self.frames
+    }
+
+    /// Returns the index of the frame to display at the given time (in milliseconds).
+    pub fn frame_idx(&self, time: u32) -> usize {
+        if self.total_delay == 0 || self.frames.len() == 1 {
+            return 0;
+        }
+
+        let mut time = time % self.total_delay;
+        for (idx, frame) in self.frames.iter().enumerate() {
+            if time < frame.delay {
+                return idx;
+            }
+            time -= frame.delay;
+        }
+        0
+    }
+
+    pub fn frame(&self, time: u32) -> &CursorFrame {
+        &self.frames[self.frame_idx(time)]
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+}
+
+/// All the cursors of the configured xcursor theme the compositor may display.
+#[derive(Debug)]
+pub struct CursorTheme {
+    cursors: HashMap<CursorShape, Cursor>,
+}
+
+impl CursorTheme {
+    pub fn load(theme: &str, size: u32, log: &Logger) -> CursorTheme {
+        info!(log, "Loading cursor theme"; "theme" => theme, "size" => size);
+        let xcursor_theme = XCursorTheme::load(theme);
+
+        let mut cursors = HashMap::new();
+        for shape in CursorShape::ALL.iter() {
+            if let Some(cursor) = load_cursor(&xcursor_theme, *shape, size, log) {
+                cursors.insert(*shape, cursor);
+            }
+        }
+
+        if !cursors.contains_key(&CursorShape::Default) {
+            warn!(log, "No default cursor found in theme, using the builtin one"; "theme" => theme);
+            cursors.insert(CursorShape::Default, fallback_cursor());
+        }
+
+        CursorTheme { cursors }
+    }
+
+    /// Returns the cursor for this shape, or the default cursor if the theme does not have it.
+    pub fn get(&self, shape: CursorShape) -> &Cursor {
+        self.cursors
+            .get(&shape)
+            .unwrap_or_else(|| &self.cursors[&CursorShape::Default])
+    }
+}
+
+fn load_cursor(theme: &XCursorTheme, shape: CursorShape, size: u32, log: &Logger) -> Option<Cursor> {
+    let path = shape.names().iter().find_map(|name| theme.load_icon(name))?;
+
+    let mut data = Vec::new();
+    if let Err(err) = File::open(&path).and_then(|mut file| file.read_to_end(&mut data)) {
+        warn!(log, "Failed to read cursor file"; "path" => path.display(), "err" => format!("{:?}", err));
+        return None;
+    }
+
+    let images = match parse_xcursor(&data) {
+        Some(images) if !images.is_empty() => images,
+        _ => {
+            warn!(log, "Failed to parse cursor file"; "path" => path.display());
+            return None;
+        }
+    };
+
+    // xcursor files contain every frame in several sizes, only keep the closest one
+    let nearest_size = images
+        .iter()
+        .min_by_key(|image| (image.size as i32 - size as i32).abs())
+        .unwrap()
+        .size;
+
+    let frames = images
+        .into_iter()
+        .filter(|image| image.size == nearest_size)
+        .map(|image| CursorFrame {
+            width: image.width,
+            height: image.height,
+            hotspot: (image.xhot as i32, image.yhot as i32),
+            delay: image.delay,
+            pixels: image.pixels_rgba,
+        })
+        .collect();
+
+    Some(Cursor::new(frames))
+}
+
+fn fallback_cursor() -> Cursor {
+    Cursor::new(vec![CursorFrame {
+        width: 64,
+        height: 64,
+        hotspot: (0, 0),
+        delay: 0,
+        pixels: include_bytes!("../resources/cursor2.rgba").to_vec(),
+    }])
+}
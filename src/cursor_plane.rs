@@ -0,0 +1,379 @@
+use std::{error::Error, fmt::Display};
+
+use slog::Logger;
+use smithay::{
+    reexports::{
+        drm::{
+            control::{crtc, Device as ControlDevice},
+            Device as BasicDevice, DriverCapability,
+        },
+        gbm::{BufferObject, BufferObjectFlags, Device as GbmDevice, Format},
+        wayland_server::protocol::{wl_buffer::WlBuffer, wl_output, wl_shm},
+    },
+    wayland::shm::{with_buffer_contents, BufferData},
+};
+
+use crate::cursor::{CursorFrame, CursorShape};
+
+/// A cursor image, in the ARGB8888 format of the cursor planes.
+pub struct CursorImage {
+    pub width: u32,
+    pub height: u32,
+    pub hotspot: (i32, i32),
+    /// Pixel data in BGRA order, which is ARGB8888 in little endian
+    pub pixels: Vec<u8>,
+}
+
+impl CursorImage {
+    /// Converts a frame of a themed cursor.
+    pub fn from_frame(frame: &CursorFrame) -> CursorImage {
+        let mut pixels = frame.pixels.clone();
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        CursorImage {
+            width: frame.width,
+            height: frame.height,
+            hotspot: frame.hotspot,
+            pixels,
+        }
+    }
+
+    /// Copies the contents of a shm buffer, other buffers can not be read by the cpu.
+    pub fn from_buffer(buffer: &WlBuffer, hotspot: (i32, i32)) -> Option<CursorImage> {
+        with_buffer_contents(buffer, |slice, data: BufferData| {
+            let opaque = match data.format {
+                wl_shm::Format::Argb8888 => false,
+                wl_shm::Format::Xrgb8888 => true,
+                _ => return None,
+            };
+            let (width, height) = (data.width as usize, data.height as usize);
+            let mut pixels = Vec::with_capacity(width * height * 4);
+            for row in 0..height {
+                let start = data.offset as usize + row * data.stride as usize;
+                pixels.extend_from_slice(slice.get(start..start + width * 4)?);
+            }
+            if opaque {
+                for pixel in pixels.chunks_exact_mut(4) {
+                    pixel[3] = 0xff;
+                }
+            }
+            Some(CursorImage {
+                width: data.width as u32,
+                height: data.height as u32,
+                hotspot,
+                pixels,
+            })
+        })
+        .ok()
+        .flatten()
+    }
+}
+
+/// A plane showing the cursor over the frames of a crtc.
+///
+/// Positions are in pixels of the crtc, for the top left corner of the image.
+pub trait CursorPlane {
+    type Error: Display;
+
+    /// The size of the cursor buffers, larger images can not be shown.
+    fn size(&self) -> (u32, u32);
+    fn set_image(&mut self, image: &CursorImage) -> Result<(), Self::Error>;
+    fn set_position(&mut self, location: (i32, i32)) -> Result<(), Self::Error>;
+    fn hide(&mut self) -> Result<(), Self::Error>;
+}
+
+/// The cursor plane of a crtc of a DRM device, set through the legacy cursor api.
+pub struct DrmCursorPlane<A: ControlDevice + 'static> {
+    gbm: GbmDevice<A>,
+    crtc: crtc::Handle,
+    size: (u32, u32),
+    /// The buffer the plane shows, it must live until it is replaced
+    buffer: Option<BufferObject<()>>,
+}
+
+impl<A: ControlDevice + 'static> DrmCursorPlane<A> {
+    pub fn new(gbm: GbmDevice<A>, crtc: crtc::Handle) -> Self {
+        // most drivers only support this size
+        let width = gbm.get_driver_capability(DriverCapability::CursorWidth).unwrap_or(64);
+        let height = gbm.get_driver_capability(DriverCapability::CursorHeight).unwrap_or(64);
+        DrmCursorPlane {
+            gbm,
+            crtc,
+            size: (width as u32, height as u32),
+            buffer: None,
+        }
+    }
+}
+
+impl<A: ControlDevice + 'static> CursorPlane for DrmCursorPlane<A> {
+    type Error = Box<dyn Error>;
+
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn set_image(&mut self, image: &CursorImage) -> Result<(), Self::Error> {
+        let (width, height) = self.size;
+        let mut buffer = self.gbm.create_buffer_object::<()>(
+            width,
+            height,
+            Format::Argb8888,
+            BufferObjectFlags::CURSOR | BufferObjectFlags::WRITE,
+        )?;
+        // the rest of the buffer stays transparent
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        let row_len = (image.width * 4) as usize;
+        for (row, source) in image.pixels.chunks_exact(row_len).enumerate() {
+            let start = row * (width * 4) as usize;
+            pixels[start..start + row_len].copy_from_slice(source);
+        }
+        buffer.write(&pixels)??;
+        self.gbm.set_cursor2(self.crtc, Some(&buffer), image.hotspot)?;
+        self.buffer = Some(buffer);
+        Ok(())
+    }
+
+    fn set_position(&mut self, location: (i32, i32)) -> Result<(), Self::Error> {
+        self.gbm.move_cursor(self.crtc, location)?;
+        Ok(())
+    }
+
+    fn hide(&mut self) -> Result<(), Self::Error> {
+        self.gbm.set_cursor2(self.crtc, Option::<&BufferObject<()>>::None, (0, 0))?;
+        self.buffer = None;
+        Ok(())
+    }
+}
+
+/// What a cursor plane shows, to upload images only when they change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CursorKey {
+    /// A frame of a themed cursor
+    Theme(CursorShape, usize),
+    /// The buffer of a client cursor surface, by surface and buffer id
+    Surface(u32, u32),
+}
+
+/// Shows a cursor on a cursor plane, if it can.
+///
+/// When the plane can not show a cursor it has to be drawn into the frames. A plane that failed
+/// once is not used again.
+pub struct HardwareCursor<P> {
+    plane: P,
+    /// The image on the plane, with its hotspot
+    shown: Option<(CursorKey, (i32, i32))>,
+    location: Option<(i32, i32)>,
+    /// Whether the output is scaled or transformed, the plane shows the images as they are
+    transformed: bool,
+    failed: bool,
+}
+
+impl<P: CursorPlane> HardwareCursor<P> {
+    pub fn new(plane: P) -> Self {
+        HardwareCursor {
+            plane,
+            shown: None,
+            location: None,
+            transformed: false,
+            failed: false,
+        }
+    }
+
+    /// Sets the scale and transform of the output of the crtc, the plane only shows cursors on
+    /// outputs that have neither.
+    pub fn set_output(&mut self, scale: f64, transform: wl_output::Transform, log: &Logger) {
+        self.transformed = scale != 1.0 || transform != wl_output::Transform::Normal;
+        if self.transformed {
+            self.hide(log);
+        }
+    }
+
+    /// Shows a cursor with its hotspot at `position`, returns whether it is on the plane.
+    ///
+    /// `image` is only called when `key` differs from the image on the plane.
+    pub fn show<F>(&mut self, key: CursorKey, position: (i32, i32), image: F, log: &Logger) -> bool
+    where
+        F: FnOnce() -> Option<CursorImage>,
+    {
+        if self.failed || self.transformed {
+            return false;
+        }
+        if self.shown.as_ref().map(|(shown, _)| shown) != Some(&key) {
+            let (width, height) = self.plane.size();
+            let image = match image() {
+                Some(image)
+                    if image.width > 0
+                        && image.height > 0
+                        && image.width <= width
+                        && image.height <= height =>
+                {
+                    image
+                }
+                // empty, too large or unreadable, this one is drawn into the frames
+                _ => {
+                    self.hide(log);
+                    return false;
+                }
+            };
+            if let Err(err) = self.plane.set_image(&image) {
+                self.fail(err, log);
+                return false;
+            }
+            self.shown = Some((key, image.hotspot));
+            self.location = None;
+        }
+
+        let hotspot = self.shown.as_ref().map(|(_, hotspot)| *hotspot).unwrap_or((0, 0));
+        let location = (position.0 - hotspot.0, position.1 - hotspot.1);
+        if self.location != Some(location) {
+            if let Err(err) = self.plane.set_position(location) {
+                self.fail(err, log);
+                return false;
+            }
+            self.location = Some(location);
+        }
+        true
+    }
+
+    /// Hides the plane, when there is no cursor on the crtc or it is drawn into the frames.
+    pub fn hide(&mut self, log: &Logger) {
+        if self.shown.take().is_some() {
+            self.location = None;
+            if let Err(err) = self.plane.hide() {
+                self.fail(err, log);
+            }
+        }
+    }
+
+    fn fail(&mut self, err: P::Error, log: &Logger) {
+        warn!(log, "The cursor plane failed, drawing the cursor into the frames: {}", err);
+        self.failed = true;
+        self.shown = None;
+        self.location = None;
+        let _ = self.plane.hide();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cursor plane recording what it shows.
+    #[derive(Default)]
+    struct MockPlane {
+        image: Option<(u32, u32)>,
+        location: Option<(i32, i32)>,
+        uploads: usize,
+        fail_set_image: bool,
+    }
+
+    impl CursorPlane for MockPlane {
+        type Error = String;
+
+        fn size(&self) -> (u32, u32) {
+            (64, 64)
+        }
+
+        fn set_image(&mut self, image: &CursorImage) -> Result<(), String> {
+            if self.fail_set_image {
+                return Err("set_image failed".into());
+            }
+            self.image = Some((image.width, image.height));
+            self.uploads += 1;
+            Ok(())
+        }
+
+        fn set_position(&mut self, location: (i32, i32)) -> Result<(), String> {
+            self.location = Some(location);
+            Ok(())
+        }
+
+        fn hide(&mut self) -> Result<(), String> {
+            self.image = None;
+            self.location = None;
+            Ok(())
+        }
+    }
+
+    fn log() -> Logger {
+        Logger::root(slog::Discard, o!())
+    }
+
+    fn image(width: u32, height: u32) -> Option<CursorImage> {
+        Some(CursorImage {
+            width,
+            height,
+            hotspot: (2, 3),
+            pixels: vec![0xff; (width * height * 4) as usize],
+        })
+    }
+
+    fn key(frame: usize) -> CursorKey {
+        CursorKey::Theme(CursorShape::Default, frame)
+    }
+
+    #[test]
+    fn shows_the_image_at_its_hotspot() {
+        let mut cursor = HardwareCursor::new(MockPlane::default());
+        assert!(cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+        assert_eq!(cursor.plane.image, Some((24, 24)));
+        assert_eq!(cursor.plane.location, Some((8, 7)));
+
+        // the same image is not uploaded again
+        assert!(cursor.show(key(0), (20, 20), || panic!("uploaded again"), &log()));
+        assert_eq!(cursor.plane.uploads, 1);
+        assert_eq!(cursor.plane.location, Some((18, 17)));
+    }
+
+    #[test]
+    fn too_large_images_are_drawn_into_the_frames() {
+        let mut cursor = HardwareCursor::new(MockPlane::default());
+        assert!(cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+        assert!(!cursor.show(key(1), (10, 10), || image(65, 24), &log()));
+        assert_eq!(cursor.plane.image, None);
+        // the plane is used again for images that fit
+        assert!(cursor.show(key(2), (10, 10), || image(64, 64), &log()));
+    }
+
+    #[test]
+    fn empty_images_are_drawn_into_the_frames() {
+        let mut cursor = HardwareCursor::new(MockPlane::default());
+        assert!(!cursor.show(key(0), (10, 10), || image(0, 24), &log()));
+        assert!(!cursor.show(key(1), (10, 10), || image(24, 0), &log()));
+        assert_eq!(cursor.plane.uploads, 0);
+    }
+
+    #[test]
+    fn scaled_outputs_are_not_supported() {
+        let mut cursor = HardwareCursor::new(MockPlane::default());
+        assert!(cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+        cursor.set_output(2.0, wl_output::Transform::Normal, &log());
+        assert_eq!(cursor.plane.image, None);
+        assert!(!cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+
+        cursor.set_output(1.0, wl_output::Transform::Normal, &log());
+        assert!(cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+    }
+
+    #[test]
+    fn transformed_outputs_are_not_supported() {
+        let mut cursor = HardwareCursor::new(MockPlane::default());
+        assert!(cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+        cursor.set_output(1.0, wl_output::Transform::_90, &log());
+        assert_eq!(cursor.plane.image, None);
+        assert!(!cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+    }
+
+    #[test]
+    fn a_failed_plane_is_not_used_again() {
+        let mut cursor = HardwareCursor::new(MockPlane {
+            fail_set_image: true,
+            ..MockPlane::default()
+        });
+        assert!(!cursor.show(key(0), (10, 10), || image(24, 24), &log()));
+        cursor.plane.fail_set_image = false;
+        assert!(!cursor.show(key(1), (10, 10), || image(24, 24), &log()));
+        assert_eq!(cursor.plane.uploads, 0);
+    }
+}
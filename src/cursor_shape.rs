@@ -0,0 +1,130 @@
+//! The cursor shape protocol, clients name a cursor of the theme instead of providing a surface.
+
+use smithay::{
+    reexports::wayland_server::{Display, Filter, Global, Main},
+    wayland::Serial,
+};
+
+use self::protocol::{
+    wp_cursor_shape_device_v1::{self, Shape},
+    wp_cursor_shape_manager_v1::{self, WpCursorShapeManagerV1},
+};
+use crate::{cursor::CursorShape, state::AnvilState};
+
+/// The code generated from `protocols/cursor-shape-v1.xml` by the build script.
+#[allow(
+    dead_code,
+    non_camel_case_types,
+    unused_unsafe,
+    unused_variables,
+    non_upper_case_globals,
+    non_snake_case,
+    unused_imports,
+    missing_docs,
+    clippy::all
+)]
+pub mod protocol {
+    pub(crate) use smithay::reexports::wayland_protocols::unstable::tablet::v2::server::zwp_tablet_tool_v2;
+    pub(crate) use smithay::reexports::wayland_server::{
+        protocol::wl_pointer, AnonymousObject, Main, Resource, ResourceMap,
+    };
+    pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+    pub(crate) use wayland_commons::smallvec;
+    pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+    pub(crate) use wayland_commons::{Interface, MessageGroup};
+    pub(crate) use wayland_sys as sys;
+    include!(concat!(env!("OUT_DIR"), "/cursor_shape_v1.rs"));
+}
+
+impl From<Shape> for CursorShape {
+    fn from(shape: Shape) -> CursorShape {
+        match shape {
+            Shape::ContextMenu => CursorShape::ContextMenu,
+            Shape::Help => CursorShape::Help,
+            Shape::Pointer => CursorShape::Pointer,
+            Shape::Progress => CursorShape::Progress,
+            Shape::Wait => CursorShape::Wait,
+            Shape::Cell => CursorShape::Cell,
+            Shape::Crosshair => CursorShape::Crosshair,
+            Shape::Text => CursorShape::Text,
+            Shape::VerticalText => CursorShape::VerticalText,
+            Shape::Alias => CursorShape::Alias,
+            Shape::Copy => CursorShape::Copy,
+            Shape::Move => CursorShape::Move,
+            Shape::NoDrop => CursorShape::NoDrop,
+            Shape::NotAllowed => CursorShape::NotAllowed,
+            Shape::Grab => CursorShape::Grab,
+            Shape::Grabbing => CursorShape::Grabbing,
+            Shape::EResize => CursorShape::ResizeRight,
+            Shape::NResize => CursorShape::ResizeTop,
+            Shape::NeResize => CursorShape::ResizeTopRight,
+            Shape::NwResize => CursorShape::ResizeTopLeft,
+            Shape::SResize => CursorShape::ResizeBottom,
+            Shape::SeResize => CursorShape::ResizeBottomRight,
+            Shape::SwResize => CursorShape::ResizeBottomLeft,
+            Shape::WResize => CursorShape::ResizeLeft,
+            Shape::EwResize => CursorShape::ResizeLeftRight,
+            Shape::NsResize => CursorShape::ResizeTopBottom,
+            Shape::NeswResize => CursorShape::ResizeTopRightBottomLeft,
+            Shape::NwseResize => CursorShape::ResizeTopLeftBottomRight,
+            Shape::ColResize => CursorShape::ColumnResize,
+            Shape::RowResize => CursorShape::RowResize,
+            Shape::AllScroll => CursorShape::AllScroll,
+            Shape::ZoomIn => CursorShape::ZoomIn,
+            Shape::ZoomOut => CursorShape::ZoomOut,
+            _ => CursorShape::Default,
+        }
+    }
+}
+
+pub fn init_cursor_shape_manager(
+    display: &mut Display,
+    log: ::slog::Logger,
+) -> Global<WpCursorShapeManagerV1> {
+    display.create_global::<WpCursorShapeManagerV1, _>(
+        1,
+        Filter::new(
+            move |(manager, _version): (Main<WpCursorShapeManagerV1>, u32), _, _| {
+                let log = log.clone();
+                manager.quick_assign(move |_, request, _| match request {
+                    wp_cursor_shape_manager_v1::Request::GetPointer {
+                        cursor_shape_device,
+                        pointer,
+                    } => {
+                        trace!(log, "New cursor shape device");
+                        let log = log.clone();
+                        // the shape only goes to the seat that sent the enter with the serial
+                        cursor_shape_device.quick_assign(move |_, request, mut ddata| match request {
+                            wp_cursor_shape_device_v1::Request::SetShape { serial, shape } => {
+                                let state = ddata.get::<AnvilState>().unwrap();
+                                let serial = Serial::from(serial);
+                                let seat = state
+                                    .seats
+                                    .iter()
+                                    .find(|seat| seat.entered_pointer(&pointer, serial));
+                                match seat {
+                                    Some(seat) => seat.request_cursor_shape(shape.into()),
+                                    None => trace!(log, "Cursor shape with a stale serial"),
+                                }
+                            }
+                            wp_cursor_shape_device_v1::Request::Destroy => {}
+                            _ => unreachable!(),
+                        });
+                    }
+                    wp_cursor_shape_manager_v1::Request::GetTabletToolV2 {
+                        cursor_shape_device, ..
+                    } => {
+                        // there are no tablets, their tools never show a cursor
+                        cursor_shape_device.quick_assign(|_, request, _| match request {
+                            wp_cursor_shape_device_v1::Request::SetShape { .. } => {}
+                            wp_cursor_shape_device_v1::Request::Destroy => {}
+                            _ => unreachable!(),
+                        });
+                    }
+                    wp_cursor_shape_manager_v1::Request::Destroy => {}
+                    _ => unreachable!(),
+                });
+            },
+        ),
+    )
+}
@@ -1,8 +1,10 @@
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use slog::Logger;
 use smithay::wayland::seat::ModifiersState;
@@ -11,6 +13,8 @@ use xkbcommon::xkb::keysym_from_name;
 use xkbcommon::xkb::Keysym;
 use xkbcommon::xkb::KEYSYM_NO_FLAGS;
 
+use crate::shell::ResizeEdge;
+
 
 #[derive(Clone, Debug)]
 pub struct Configuration {
@@ -18,6 +22,26 @@ pub struct Configuration {
     pub key_bindings: KeyBindings,
     pub menu: Vec<MenuEntry>,
     pub bar: Bar,
+    pub cursor: Cursor,
+    pub seats: Vec<SeatConfig>,
+    pub shortcuts_inhibit: ShortcutsInhibit,
+    pub outputs: Outputs,
+    pub background: Background,
+    /// The number of workspaces, at least one
+    pub workspaces: usize,
+    pub hooks: Hooks,
+    pub window_cycling: WindowCycling,
+    pub switcher: Switcher,
+    pub focus: Focus,
+    pub snapping: Snapping,
+    /// How far the arrow keys move or resize a window in the keyboard move and resize modes
+    pub keyboard_move_step: i32,
+    /// The script with the hooks and actions, if there is one
+    pub script: Option<PathBuf>,
+    /// The configuration file as it was read, for the ipc clients
+    pub json: Value,
+    /// The file the configuration was read from
+    path: String,
     log: Logger
 }
 
@@ -29,6 +53,21 @@ impl<'a> Configuration {
             key_bindings: KeyBindings::new(),
             menu: Vec::new(),
             bar: Bar::new(""),
+            cursor: Cursor::new(),
+            seats: Vec::new(),
+            shortcuts_inhibit: ShortcutsInhibit::new(),
+            outputs: Outputs::new(),
+            background: Background::new(),
+            workspaces: 4,
+            hooks: Hooks::default(),
+            window_cycling: WindowCycling::default(),
+            switcher: Switcher::default(),
+            focus: Focus::default(),
+            snapping: Snapping::default(),
+            keyboard_move_step: 10,
+            script: None,
+            json: Value::Null,
+            path: String::new(),
             log
         }
     }
@@ -37,20 +76,100 @@ impl<'a> Configuration {
         let data = Configuration::read_file(file);
         let raw_config: Value =
             serde_json::from_str(&data).expect("Unable to parse configuration");
+        Configuration::from_value(raw_config, file.into(), log).unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        let keyboard = Keyboard::from(raw_config["keyboard"].clone());
+    /// Reads the configuration file again, a broken one is reported instead of panicking.
+    pub fn reload(&self) -> Result<Configuration, String> {
+        let data = fs::read_to_string(&self.path)
+            .map_err(|err| format!("Unable to read {}: {}", self.path, err))?;
+        let raw_config: Value = serde_json::from_str(&data)
+            .map_err(|err| format!("Unable to parse configuration: {}", err))?;
+        Configuration::from_value(raw_config, self.path.clone(), self.log.clone())
+    }
 
-        let raw_key_bindings = raw_config["key_bindings"].as_array().expect("Unable to parse key_bindings");
-        let key_bindings = KeyBindings::from(raw_key_bindings);
+    fn from_value(raw_config: Value, path: String, log: Logger) -> Result<Configuration, String> {
+        let keyboard = Keyboard::from(raw_config["keyboard"].clone())?;
 
-        let bar = Bar::from(raw_config["bar"].clone());
+        let raw_key_bindings = raw_config["key_bindings"].as_array().ok_or("Unable to parse key_bindings")?;
+        let key_bindings = KeyBindings::from(raw_key_bindings)?;
 
-        Configuration {
+        let bar = Bar::from(raw_config["bar"].clone())?;
+
+        let cursor = Cursor::from(raw_config["cursor"].clone())?;
+
+        let seats = match raw_config["seats"].as_array() {
+            Some(values) => values
+                .iter()
+                .map(|value| SeatConfig::from(value.clone()))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+
+        let shortcuts_inhibit = ShortcutsInhibit::from(raw_config["shortcuts_inhibit"].clone())?;
+
+        let outputs = Outputs::from(raw_config["outputs"].clone())?;
+
+        let background = Background::from(raw_config["background"].clone())?;
+        for color in background.invalid_colors() {
+            warn!(log, "Invalid background color {:?}, expected \"#rrggbb\"", color);
+        }
+
+        let workspaces = raw_config["workspaces"].as_u64().map(|count| count.max(1) as usize).unwrap_or(4);
+
+        let hooks = Hooks::from(raw_config["hooks"].clone())?;
+
+        let window_cycling = WindowCycling::from(raw_config["window_cycling"].clone())?;
+
+        let switcher = Switcher::from(raw_config["switcher"].clone())?;
+
+        let focus = Focus::from(raw_config["focus"].clone())?;
+
+        let snapping = Snapping::from(raw_config["snapping"].clone())?;
+
+        let keyboard_move_step = raw_config["keyboard_move_step"].as_i64().map(|step| step.max(1) as i32).unwrap_or(10);
+
+        let script = Configuration::script(&raw_config["script"], &path);
+
+        Ok(Configuration {
             keyboard,
             key_bindings,
             menu: Vec::new(),
             bar,
+            cursor,
+            seats,
+            shortcuts_inhibit,
+            outputs,
+            background,
+            workspaces,
+            hooks,
+            window_cycling,
+            switcher,
+            focus,
+            snapping,
+            keyboard_move_step,
+            script,
+            json: raw_config,
+            path,
             log
+        })
+    }
+
+    /// Returns the name of the seat the input device with this name is assigned to
+    pub fn seat_for_device(&self, device: &str) -> Option<&str> {
+        self.seats
+            .iter()
+            .find(|seat| seat.devices.iter().any(|name| name == device))
+            .map(|seat| seat.name.as_str())
+    }
+
+    /// The script named in the configuration, or `waybox.rhai` if there is one, both relative to
+    /// the directory of the configuration file
+    fn script(value: &Value, config_path: &str) -> Option<PathBuf> {
+        let dir = Path::new(config_path).parent().unwrap_or_else(|| Path::new("."));
+        match value.as_str() {
+            Some(script) => Some(dir.join(script)),
+            None => Some(dir.join("waybox.rhai")).filter(|script| script.exists()),
         }
     }
 
@@ -79,6 +198,32 @@ pub enum KeyAction {
     Run(String),
     /// Switch the current screen
     Screen(usize),
+    /// Suspend or resume the keyboard shortcuts inhibitor of the focused window
+    ToggleShortcutsInhibit,
+    /// Run an action the script registered
+    Script(String),
+    /// Select the next window by the focus history, starting the window cycling if needed
+    NextWindow,
+    /// Select the previous window by the focus history, starting the window cycling if needed
+    PreviousWindow,
+    /// Raise and focus the window selected by the window cycling
+    FinishWindowCycle,
+    /// Stop the window cycling without changing the focus
+    CancelWindowCycle,
+    /// Move the focused window by some pixels
+    MoveWindow(i32, i32),
+    /// Move an edge of the focused window outwards by some pixels, negative ones shrink it
+    ResizeWindow(ResizeEdge, i32),
+    /// Start moving the focused window with the arrow keys
+    KeyboardMove,
+    /// Start resizing the focused window with the arrow keys
+    KeyboardResize,
+    /// Move or resize the window of the keyboard move or resize mode by some pixels
+    KeyboardMoveStep(i32, i32),
+    /// Keep the window where the keyboard move or resize mode left it
+    FinishKeyboardMove,
+    /// Put the window back to where the keyboard move or resize mode started
+    CancelKeyboardMove,
     /// Forward the key to the client
     Forward,
     /// Do nothing more
@@ -93,27 +238,68 @@ pub struct KeyBinding {
     description: String,
     keys: Vec<Keysym>,
     modifiers: ModifiersState,
-    command: String
+    command: String,
+    /// The built-in or script action to run instead of the command
+    action: Option<String>
 }
 
 
 impl KeyBinding {
-    fn from(value: Value) -> Self {
-        let description = value["description"].as_str().expect("msg");
-        let keys = value["keys"].as_str().expect("msg");
-        let modifiers = value["modifiers"].as_str().expect("msg");
-        let command = value["command"].as_str().expect("msg");
+    fn from(value: Value) -> Result<Self, String> {
+        let field = |name: &str| {
+            value[name]
+                .as_str()
+                .ok_or_else(|| format!("A key binding needs a {} string: {}", name, value))
+        };
+        let description = field("description")?;
+        let keys = field("keys")?;
+        let modifiers = field("modifiers")?;
+        let action = value["action"].as_str().map(String::from);
+        if let Some(ref action) = action {
+            KeyBinding::parse_action(action)
+                .ok_or_else(|| format!("Unable to parse key binding action {:?}", action))?;
+        }
+        let command = match action {
+            Some(_) => "",
+            None => field("command")?,
+        };
 
         let binding = KeyBinding {
             description: String::from(description),
             keys: KeyBinding::parse_keysyms(keys),
             modifiers: KeyBinding::parse_modkeys(modifiers),
-            command: String::from(command)
+            command: String::from(command),
+            action
         };
-        binding
+        Ok(binding)
     }
 
 
+    /// The built-in action with this name and its arguments, separated by spaces like
+    /// `move_window -20 0` or `resize_window left 20`, other names are script actions
+    fn parse_action(data: &str) -> Option<KeyAction> {
+        let mut tokens = data.split_whitespace();
+        let name = tokens.next()?;
+        let action = match name {
+            "next_window" => KeyAction::NextWindow,
+            "previous_window" => KeyAction::PreviousWindow,
+            "move_window" => {
+                let x = tokens.next()?.parse().ok()?;
+                let y = tokens.next()?.parse().ok()?;
+                KeyAction::MoveWindow(x, y)
+            }
+            "resize_window" => {
+                let edge = tokens.next().and_then(ResizeEdge::from_name)?;
+                let amount = tokens.next()?.parse().ok()?;
+                KeyAction::ResizeWindow(edge, amount)
+            }
+            "keyboard_move" => KeyAction::KeyboardMove,
+            "keyboard_resize" => KeyAction::KeyboardResize,
+            _ => KeyAction::Script(name.to_string()),
+        };
+        Some(action)
+    }
+
     fn parse_keysyms(data: &str) -> Vec<Keysym> {
         let tokens = data.split(" ");
         let mut keysyms = Vec::new();
@@ -164,14 +350,14 @@ impl KeyBindings {
         KeyBindings { bindings: Vec::new() }
     }
 
-    fn from(values: &Vec<Value>) -> Self {
+    fn from(values: &Vec<Value>) -> Result<Self, String> {
         let mut key_bindings = KeyBindings::new();
 
         for value in values {
-            let binding = KeyBinding::from(value.clone());
+            let binding = KeyBinding::from(value.clone())?;
             key_bindings.bindings.push(binding);
         }
-        key_bindings
+        Ok(key_bindings)
     }
 
     fn add_keybinding(&mut self, key_binding: KeyBinding) {
@@ -182,7 +368,11 @@ impl KeyBindings {
         for binding in &self.bindings {
             if binding.modifiers == modifiers &&
                binding.keys.contains(&keysym) {
-                   return KeyAction::Run(binding.command.clone());
+                   return match binding.action.as_deref() {
+                       // checked when the configuration was read
+                       Some(action) => KeyBinding::parse_action(action).unwrap(),
+                       None => KeyAction::Run(binding.command.clone()),
+                   };
                }
         }
         KeyAction::Forward
@@ -202,9 +392,8 @@ impl Bar {
         Bar { command: String::from(cmd) }
     }
 
-    pub fn from(value: Value) -> Self {
-        let bar: Bar = serde_json::from_value(value).expect("Unable to parse configuration");
-        bar
+    pub fn from(value: Value) -> Result<Self, String> {
+        section(value, "bar")
     }
 
     pub fn spawn(&self) {
@@ -233,9 +422,8 @@ impl<'a> Keyboard {
         }
     }
 
-    pub fn from(value: Value) -> Self {
-        let keyboard: Keyboard = serde_json::from_value(value).expect("Unable to parse configuration");
-        keyboard
+    pub fn from(value: Value) -> Result<Self, String> {
+        section(value, "keyboard")
     }
 
     pub fn get_seat_xkbconfig(&'a self) -> XkbConfig<'a> {
@@ -249,6 +437,703 @@ impl<'a> Keyboard {
 }
 
 
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    theme: Option<String>,
+    size: Option<u32>,
+}
+
+impl Cursor {
+    pub fn new() -> Self {
+        Cursor { theme: None, size: None }
+    }
+
+    pub fn from(value: Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(Cursor::new());
+        }
+        section(value, "cursor")
+    }
+
+    /// The xcursor theme, the config takes precedence over XCURSOR_THEME
+    pub fn theme(&self) -> String {
+        self.theme
+            .clone()
+            .or_else(|| env::var("XCURSOR_THEME").ok())
+            .unwrap_or_else(|| String::from("default"))
+    }
+
+    /// The xcursor size, the config takes precedence over XCURSOR_SIZE
+    pub fn size(&self) -> u32 {
+        self.size
+            .or_else(|| env::var("XCURSOR_SIZE").ok().and_then(|size| size.parse().ok()))
+            .unwrap_or(24)
+    }
+
+    /// Exports the effective theme, so that clients we spawn use the same cursors
+    pub fn export_env(&self) {
+        env::set_var("XCURSOR_THEME", self.theme());
+        env::set_var("XCURSOR_SIZE", self.size().to_string());
+    }
+}
+
+
+/// A seat and the input devices (by libinput name) that belong to it
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct SeatConfig {
+    pub name: String,
+    #[serde(default)]
+    pub devices: Vec<String>,
+}
+
+impl SeatConfig {
+    pub fn from(value: Value) -> Result<Self, String> {
+        section(value, "seats")
+    }
+}
+
+
+/// Which clients may inhibit the compositor shortcuts and how to escape from them
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ShortcutsInhibit {
+    escape_keys: Vec<Keysym>,
+    escape_modifiers: ModifiersState,
+    /// The app ids allowed to inhibit the shortcuts, `None` allows every client
+    allow: Option<Vec<String>>,
+}
+
+impl ShortcutsInhibit {
+    pub fn new() -> Self {
+        ShortcutsInhibit {
+            escape_keys: vec![xkbcommon::xkb::keysyms::KEY_Escape],
+            escape_modifiers: ModifiersState {
+                logo: true,
+                shift: true,
+                ..ModifiersState::default()
+            },
+            allow: None,
+        }
+    }
+
+    pub fn from(value: Value) -> Result<Self, String> {
+        let mut shortcuts_inhibit = ShortcutsInhibit::new();
+        if value.is_null() {
+            return Ok(shortcuts_inhibit);
+        }
+
+        let escape = &value["escape"];
+        if !escape.is_null() {
+            let keys = escape["keys"].as_str().ok_or("Unable to parse shortcuts_inhibit escape keys")?;
+            let modifiers = escape["modifiers"].as_str().ok_or("Unable to parse shortcuts_inhibit escape modifiers")?;
+            shortcuts_inhibit.escape_keys = KeyBinding::parse_keysyms(keys);
+            shortcuts_inhibit.escape_modifiers = KeyBinding::parse_modkeys(modifiers);
+        }
+
+        if let Some(allow) = value["allow"].as_array() {
+            shortcuts_inhibit.allow = Some(
+                allow
+                    .iter()
+                    .map(|app_id| app_id.as_str().map(String::from).ok_or("Unable to parse shortcuts_inhibit allow"))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        Ok(shortcuts_inhibit)
+    }
+
+    /// The escape binding always reaches the compositor, even when the shortcuts are inhibited
+    pub fn is_escape(&self, modifiers: ModifiersState, keysym: Keysym) -> bool {
+        self.escape_modifiers == modifiers && self.escape_keys.contains(&keysym)
+    }
+
+    /// Whether the client with this app id may inhibit the shortcuts
+    pub fn allows(&self, app_id: Option<&str>) -> bool {
+        match self.allow {
+            Some(ref allow) => app_id.map(|app_id| allow.iter().any(|allowed| allowed == app_id)).unwrap_or(false),
+            None => true,
+        }
+    }
+}
+
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct OutputMode {
+    pub width: u32,
+    pub height: u32,
+    /// The refresh rate in Hz, any rate is fine if omitted
+    #[serde(default)]
+    pub refresh: Option<u32>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct OutputPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum OutputTransform {
+    #[serde(rename = "normal")]
+    Normal,
+    #[serde(rename = "90")]
+    Rotate90,
+    #[serde(rename = "180")]
+    Rotate180,
+    #[serde(rename = "270")]
+    Rotate270,
+    #[serde(rename = "flipped")]
+    Flipped,
+    #[serde(rename = "flipped-90")]
+    Flipped90,
+    #[serde(rename = "flipped-180")]
+    Flipped180,
+    #[serde(rename = "flipped-270")]
+    Flipped270,
+}
+
+impl Default for OutputTransform {
+    fn default() -> Self {
+        OutputTransform::Normal
+    }
+}
+
+/// The configuration of a single output, every setting is optional
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct OutputConfig {
+    #[serde(default = "OutputConfig::default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub mode: Option<OutputMode>,
+    /// The position in the global space, outputs without one are placed right of the others
+    #[serde(default)]
+    pub position: Option<OutputPosition>,
+    #[serde(default)]
+    pub scale: Option<f64>,
+    #[serde(default)]
+    pub transform: OutputTransform,
+}
+
+impl OutputConfig {
+    fn default_enabled() -> bool {
+        true
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            enabled: true,
+            mode: None,
+            position: None,
+            scale: None,
+            transform: OutputTransform::Normal,
+        }
+    }
+}
+
+/// The output configurations, keyed by connector name (e.g. "HDMI-A-1"), make, model or serial,
+/// in the order of the configuration file
+#[derive(PartialEq, Clone, Debug)]
+pub struct Outputs {
+    outputs: Vec<(String, OutputConfig)>,
+}
+
+impl Outputs {
+    pub fn new() -> Self {
+        Outputs { outputs: Vec::new() }
+    }
+
+    pub fn from(value: Value) -> Result<Self, String> {
+        let mut outputs = Outputs::new();
+        if let Some(values) = value.as_object() {
+            for (key, value) in values {
+                let output: OutputConfig = section(value.clone(), &format!("the output {}", key))?;
+                outputs.outputs.push((key.clone(), output));
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Returns the configuration of the first key matching one of the identifiers of an output
+    pub fn get(&self, identifiers: &[String]) -> Option<&OutputConfig> {
+        self.outputs
+            .iter()
+            .find(|(key, _)| identifiers.iter().any(|identifier| identifier == key))
+            .map(|(_, output)| output)
+    }
+
+    /// Overrides the configuration of an output, e.g. when a client reconfigured it
+    pub fn set(&mut self, identifier: &str, config: OutputConfig) {
+        self.outputs.retain(|(key, _)| key != identifier);
+        self.outputs.insert(0, (identifier.to_string(), config));
+    }
+}
+
+
+/// How the wallpaper image covers an output
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum BackgroundMode {
+    /// Scaled to the output, ignoring the aspect ratio
+    #[serde(rename = "stretch")]
+    Stretch,
+    /// Scaled to fit into the output, the rest shows the color
+    #[serde(rename = "fit")]
+    Fit,
+    /// Scaled to cover the output, cropping what does not fit
+    #[serde(rename = "fill")]
+    Fill,
+    /// Centered at its own size
+    #[serde(rename = "center")]
+    Center,
+    /// Repeated at its own size, from the top left corner
+    #[serde(rename = "tile")]
+    Tile,
+}
+
+impl Default for BackgroundMode {
+    fn default() -> Self {
+        BackgroundMode::Fill
+    }
+}
+
+/// The background of an output, every setting is optional
+#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BackgroundConfig {
+    /// The path of a PNG or JPEG image
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub mode: Option<BackgroundMode>,
+    /// The color around and behind the image, or instead of it, as "#rrggbb"
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+impl BackgroundConfig {
+    /// The color as rgba components, a light gray-blue if it is missing or invalid
+    pub fn color(&self) -> [f32; 4] {
+        self.color
+            .as_deref()
+            .and_then(parse_color)
+            .unwrap_or([0.8, 0.8, 0.9, 1.0])
+    }
+
+    pub fn mode(&self) -> BackgroundMode {
+        self.mode.unwrap_or_default()
+    }
+
+    /// The settings of `self`, with the missing ones taken from `fallback`
+    fn or(&self, fallback: &BackgroundConfig) -> BackgroundConfig {
+        BackgroundConfig {
+            image: self.image.clone().or_else(|| fallback.image.clone()),
+            mode: self.mode.or(fallback.mode),
+            color: self.color.clone().or_else(|| fallback.color.clone()),
+        }
+    }
+}
+
+/// Deserializes a section of the configuration, naming it in the error.
+fn section<T: DeserializeOwned>(value: Value, name: &str) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|err| format!("Unable to parse {}: {}", name, err))
+}
+
+/// Parses a "#rrggbb" color into rgba components.
+fn parse_color(color: &str) -> Option<[f32; 4]> {
+    color
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6)
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .map(|rgb| {
+            [
+                ((rgb >> 16) & 0xff) as f32 / 255.0,
+                ((rgb >> 8) & 0xff) as f32 / 255.0,
+                (rgb & 0xff) as f32 / 255.0,
+                1.0,
+            ]
+        })
+}
+
+/// The background of all outputs, with overrides keyed like the output configurations
+#[derive(PartialEq, Clone, Debug)]
+pub struct Background {
+    default: BackgroundConfig,
+    outputs: Vec<(String, BackgroundConfig)>,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Background {
+            default: BackgroundConfig::default(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn from(value: Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(Background::new());
+        }
+        let default: BackgroundConfig = section(value.clone(), "background")?;
+        let mut outputs = Vec::new();
+        if let Some(values) = value["outputs"].as_object() {
+            for (key, value) in values {
+                let output: BackgroundConfig =
+                    section(value.clone(), &format!("the background of the output {}", key))?;
+                outputs.push((key.clone(), output));
+            }
+        }
+        Ok(Background { default, outputs })
+    }
+
+    /// The colors of the default and the overrides that are not "#rrggbb"
+    pub fn invalid_colors(&self) -> Vec<&str> {
+        std::iter::once(&self.default)
+            .chain(self.outputs.iter().map(|(_, output)| output))
+            .filter_map(|config| config.color.as_deref())
+            .filter(|color| parse_color(color).is_none())
+            .collect()
+    }
+
+    /// Returns the background of an output, from the first override matching one of its identifiers
+    pub fn get(&self, identifiers: &[String]) -> BackgroundConfig {
+        self.outputs
+            .iter()
+            .find(|(key, _)| identifiers.iter().any(|identifier| identifier == key))
+            .map(|(_, output)| output.or(&self.default))
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
+
+
+/// The windows the window cycling goes through
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum CycleScope {
+    /// The windows of the active workspace
+    #[serde(rename = "workspace")]
+    Workspace,
+    /// The windows of the active workspace on the output with the pointer
+    #[serde(rename = "output")]
+    Output,
+    /// The windows of all workspaces
+    #[serde(rename = "all")]
+    All,
+}
+
+impl Default for CycleScope {
+    fn default() -> Self {
+        CycleScope::Workspace
+    }
+}
+
+/// How the next_window and previous_window actions go through the focus history
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct WindowCycling {
+    #[serde(default)]
+    pub scope: CycleScope,
+    #[serde(default)]
+    pub skip_minimized: bool,
+}
+
+impl WindowCycling {
+    pub fn from(value: Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(WindowCycling::default());
+        }
+        section(value, "window_cycling")
+    }
+}
+
+/// The window switcher shown while cycling through the windows
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct Switcher {
+    #[serde(default = "Switcher::default_enabled")]
+    pub enabled: bool,
+    /// The largest side of a window preview, in logical pixels
+    #[serde(default = "Switcher::default_thumbnail_size")]
+    pub thumbnail_size: u32,
+    /// The font file of the titles, the sans-serif font of fontconfig if omitted
+    #[serde(default)]
+    pub font: Option<String>,
+    /// The icon theme searched before hicolor
+    #[serde(default)]
+    pub icon_theme: Option<String>,
+}
+
+impl Switcher {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_thumbnail_size() -> u32 {
+        200
+    }
+
+    pub fn from(value: Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(Switcher::default());
+        }
+        section(value, "switcher")
+    }
+}
+
+impl Default for Switcher {
+    fn default() -> Self {
+        Switcher {
+            enabled: true,
+            thumbnail_size: Switcher::default_thumbnail_size(),
+            font: None,
+            icon_theme: None,
+        }
+    }
+}
+
+
+/// How the keyboard focus follows the pointer
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum FocusModel {
+    /// A click focuses the window
+    #[serde(rename = "click")]
+    Click,
+    /// The window under the pointer has the focus, none has it over the desktop
+    #[serde(rename = "follows-mouse")]
+    FollowsMouse,
+    /// Like follows-mouse, but the focus stays with the last window over the desktop
+    #[serde(rename = "sloppy")]
+    Sloppy,
+}
+
+impl Default for FocusModel {
+    fn default() -> Self {
+        FocusModel::Click
+    }
+}
+
+/// When windows get the keyboard focus and are raised
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Focus {
+    #[serde(default)]
+    pub model: FocusModel,
+    /// Whether clicking a window raises it
+    #[serde(default = "Focus::default_true")]
+    pub click_raises: bool,
+    /// Whether a window focused by the pointer is raised after resting on it
+    #[serde(default)]
+    pub auto_raise: bool,
+    /// The milliseconds the pointer rests on a window until it is raised
+    #[serde(default = "Focus::default_auto_raise_delay")]
+    pub auto_raise_delay: u64,
+    /// Whether new windows get the focus
+    #[serde(default = "Focus::default_true")]
+    pub focus_new_windows: bool,
+    /// Whether new windows of other clients wait while the user types into the focused window
+    #[serde(default = "Focus::default_true")]
+    pub prevent_stealing: bool,
+}
+
+impl Focus {
+    fn default_true() -> bool {
+        true
+    }
+
+    fn default_auto_raise_delay() -> u64 {
+        300
+    }
+
+    pub fn from(value: Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(Focus::default());
+        }
+        section(value, "focus")
+    }
+}
+
+impl Default for Focus {
+    fn default() -> Self {
+        Focus {
+            model: FocusModel::Click,
+            click_raises: true,
+            auto_raise: false,
+            auto_raise_delay: Focus::default_auto_raise_delay(),
+            focus_new_windows: true,
+            prevent_stealing: true,
+        }
+    }
+}
+
+
+/// What windows moved with the pointer snap to, the thresholds are in logical pixels and 0
+/// turns a kind of snapping off
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Snapping {
+    /// The edges of the outputs
+    #[serde(default = "Snapping::default_threshold")]
+    pub output_edges: i32,
+    /// The edges of the area of the outputs the windows are placed in
+    #[serde(default = "Snapping::default_threshold")]
+    pub usable_area: i32,
+    /// The edges of the other windows
+    #[serde(default = "Snapping::default_threshold")]
+    pub window_edges: i32,
+    /// Whether moving the pointer to the left or right edge of an output tiles the window to
+    /// that half, and to the top edge maximizes it
+    #[serde(default = "Snapping::default_zones")]
+    pub zones: bool,
+    /// How close the pointer has to get to the edge of an output for the zones
+    #[serde(default = "Snapping::default_zone_size")]
+    pub zone_size: i32,
+}
+
+impl Snapping {
+    fn default_threshold() -> i32 {
+        16
+    }
+
+    fn default_zones() -> bool {
+        true
+    }
+
+    fn default_zone_size() -> i32 {
+        4
+    }
+
+    pub fn from(value: Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(Snapping::default());
+        }
+        section(value, "snapping")
+    }
+}
+
+impl Default for Snapping {
+    fn default() -> Self {
+        Snapping {
+            output_edges: Snapping::default_threshold(),
+            usable_area: Snapping::default_threshold(),
+            window_edges: Snapping::default_threshold(),
+            zones: true,
+            zone_size: Snapping::default_zone_size(),
+        }
+    }
+}
+
+
+/// The compositor events commands can be hooked to
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Hook {
+    WindowOpened,
+    WindowClosed,
+    WorkspaceSwitched,
+    OutputConnected,
+    OutputDisconnected,
+    Idle,
+    Resumed,
+    SessionLocked,
+    SessionUnlocked,
+}
+
+impl Hook {
+    /// The name of the hook in the configuration, also given to the command as WAYBOX_EVENT
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::WindowOpened => "window_opened",
+            Hook::WindowClosed => "window_closed",
+            Hook::WorkspaceSwitched => "workspace_switched",
+            Hook::OutputConnected => "output_connected",
+            Hook::OutputDisconnected => "output_disconnected",
+            Hook::Idle => "idle",
+            Hook::Resumed => "resumed",
+            Hook::SessionLocked => "session_locked",
+            Hook::SessionUnlocked => "session_unlocked",
+        }
+    }
+}
+
+/// The commands run on compositor events, by `sh -c` and with the details in WAYBOX_* variables
+#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hooks {
+    #[serde(default)]
+    window_opened: Option<String>,
+    #[serde(default)]
+    window_closed: Option<String>,
+    #[serde(default)]
+    workspace_switched: Option<String>,
+    #[serde(default)]
+    output_connected: Option<String>,
+    #[serde(default)]
+    output_disconnected: Option<String>,
+    #[serde(default)]
+    idle: Option<String>,
+    /// The seconds without input until the session is idle
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    #[serde(default)]
+    resumed: Option<String>,
+    /// Run when the session is paused, by a vt switch or a lock of logind
+    #[serde(default)]
+    session_locked: Option<String>,
+    /// Run when the session is active again
+    #[serde(default)]
+    session_unlocked: Option<String>,
+}
+
+impl Hooks {
+    pub fn from(value: Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(Hooks::default());
+        }
+        section(value, "hooks")
+    }
+
+    pub fn command(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::WindowOpened => self.window_opened.as_deref(),
+            Hook::WindowClosed => self.window_closed.as_deref(),
+            Hook::WorkspaceSwitched => self.workspace_switched.as_deref(),
+            Hook::OutputConnected => self.output_connected.as_deref(),
+            Hook::OutputDisconnected => self.output_disconnected.as_deref(),
+            Hook::Idle => self.idle.as_deref(),
+            Hook::Resumed => self.resumed.as_deref(),
+            Hook::SessionLocked => self.session_locked.as_deref(),
+            Hook::SessionUnlocked => self.session_unlocked.as_deref(),
+        }
+    }
+
+    /// Whether a command is hooked to the windows or the workspaces changing
+    pub fn wants_events(&self) -> bool {
+        self.window_opened.is_some() || self.window_closed.is_some() || self.workspace_switched.is_some()
+    }
+
+    /// Runs the command of a hook, if it has one, with the variables added to the environment
+    pub fn run(&self, hook: Hook, vars: &[(&str, String)], log: &Logger) {
+        let command = match self.command(hook) {
+            Some(command) => command,
+            None => return,
+        };
+        debug!(log, "Running hook"; "hook" => hook.name(), "cmd" => command);
+        let mut environment = vec![("WAYBOX_EVENT", hook.name().to_string())];
+        environment.extend_from_slice(vars);
+        if let Err(err) = spawn_detached(&["/bin/sh", "-c", command], &environment) {
+            error!(log, "Failed to run hook"; "hook" => hook.name(), "cmd" => command, "err" => format!("{:?}", err));
+        }
+    }
+}
+
+/// Starts a program with its arguments in the background, with the variables added to its
+/// environment.
+///
+/// An outer shell starts the program and exits right away, it is waited for and the program is
+/// left to init, which reaps it once it is done.
+pub fn spawn_detached(argv: &[&str], env: &[(&str, String)]) -> io::Result<()> {
+    Command::new("/bin/sh")
+        .arg("-c")
+        .arg("\"$@\" &")
+        .arg("sh")
+        .args(argv)
+        .envs(env.iter().map(|(name, value)| (*name, value)))
+        .spawn()
+        .and_then(|mut child| child.wait())
+        .map(|_| ())
+}
+
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct MenuEntry {
     title: String,
@@ -0,0 +1,416 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+};
+
+use smithay::{
+    reexports::wayland_server::protocol::wl_surface,
+    utils::Rectangle,
+    wayland::{
+        compositor::{roles::Role, SubsurfaceRole, TraversalAction},
+        seat::{CursorImageRole, CursorImageStatus},
+    },
+};
+
+use crate::{
+    gl,
+    seat::SeatCursor,
+    shell::{MyCompositorToken, SurfaceData},
+};
+
+/// The frames whose damage is kept, buffers drawn into longer ago are repainted in full
+const MAX_BUFFER_AGE: usize = 8;
+
+/// The rectangles a frame is repainted with at most, more are merged into their bounding box
+const MAX_REPAINT_RECTS: usize = 4;
+
+/// The smallest rectangle containing both rectangles.
+pub fn bounding_box(a: Rectangle, b: Rectangle) -> Rectangle {
+    let (x, y) = (a.x.min(b.x), a.y.min(b.y));
+    Rectangle {
+        x,
+        y,
+        width: (a.x + a.width).max(b.x + b.width) - x,
+        height: (a.y + a.height).max(b.y + b.height) - y,
+    }
+}
+
+/// The part both rectangles cover, empty if they do not overlap.
+pub fn intersection(a: Rectangle, b: Rectangle) -> Rectangle {
+    let (x, y) = (a.x.max(b.x), a.y.max(b.y));
+    Rectangle {
+        x,
+        y,
+        width: ((a.x + a.width).min(b.x + b.width) - x).max(0),
+        height: ((a.y + a.height).min(b.y + b.height) - y).max(0),
+    }
+}
+
+/// Takes the damage a surface tree collected since the last call, in global coordinates.
+pub fn take_surface_damage(
+    root: &wl_surface::WlSurface,
+    location: (i32, i32),
+    token: MyCompositorToken,
+    damage: &mut Vec<Rectangle>,
+) {
+    token.with_surface_tree_upward(
+        root,
+        location,
+        |_, attributes, role, &(mut x, mut y)| {
+            if let Some(data) = attributes.user_data.get::<RefCell<SurfaceData>>() {
+                if Role::<SubsurfaceRole>::has(role) {
+                    let (sub_x, sub_y) = data.borrow().current_state.sub_location;
+                    x += sub_x;
+                    y += sub_y;
+                }
+                TraversalAction::DoChildren((x, y))
+            } else {
+                TraversalAction::SkipChildren
+            }
+        },
+        |_, attributes, role, &(mut x, mut y)| {
+            if let Some(data) = attributes.user_data.get::<RefCell<SurfaceData>>() {
+                let mut data = data.borrow_mut();
+                // we need to re-extract the subsurface offset, as the previous closure
+                // only passes it to our children
+                if Role::<SubsurfaceRole>::has(role) {
+                    x += data.current_state.sub_location.0;
+                    y += data.current_state.sub_location.1;
+                }
+                damage.extend(data.damage.drain(..).map(|rect| Rectangle {
+                    x: rect.x + x,
+                    y: rect.y + y,
+                    ..rect
+                }));
+            }
+        },
+        |_, _, _, _| true,
+    );
+}
+
+/// The place of a surface in the global space, if it has contents.
+pub fn surface_rectangle(
+    surface: &wl_surface::WlSurface,
+    (x, y): (i32, i32),
+    token: MyCompositorToken,
+) -> Option<Rectangle> {
+    token.with_surface_data(surface, |attributes| {
+        let data = attributes.user_data.get::<RefCell<SurfaceData>>()?;
+        let (width, height) = data.borrow().size()?;
+        Some(Rectangle { x, y, width, height })
+    })
+}
+
+/// The location of the surface of a client cursor, from the pointer location.
+pub fn cursor_surface_location(
+    surface: &wl_surface::WlSurface,
+    (x, y): (i32, i32),
+    token: MyCompositorToken,
+) -> (i32, i32) {
+    let (dx, dy) = token
+        .with_role_data::<CursorImageRole, _, _>(surface, |data| data.hotspot)
+        .unwrap_or((0, 0));
+    (x - dx, y - dy)
+}
+
+/// The surfaces drawn over the windows: the input method popups, the drag and drop icons and the
+/// cursors set by clients.
+///
+/// The cursor of the default seat is left out when it is on a cursor plane.
+pub fn surface_overlays(
+    cursors: &[SeatCursor],
+    input_popups: &[(wl_surface::WlSurface, (i32, i32))],
+    first_cursor_on_plane: bool,
+    token: MyCompositorToken,
+) -> Vec<(Rectangle, usize)> {
+    let mut overlays = input_popups
+        .iter()
+        .filter_map(|(surface, location)| surface_rectangle(surface, *location, token))
+        .collect::<Vec<_>>();
+    for (idx, cursor) in cursors.iter().enumerate() {
+        let (x, y) = *cursor.location.borrow();
+        let location = (x as i32, y as i32);
+        if let Some(ref surface) = *cursor.dnd_icon.lock().unwrap() {
+            if surface.as_ref().is_alive() {
+                overlays.extend(surface_rectangle(surface, location, token));
+            }
+        }
+        if idx == 0 && first_cursor_on_plane {
+            continue;
+        }
+        if let CursorImageStatus::Image(ref surface) = *cursor.status.lock().unwrap() {
+            if surface.as_ref().is_alive() {
+                let location = cursor_surface_location(surface, location, token);
+                overlays.extend(surface_rectangle(surface, location, token));
+            }
+        }
+    }
+    overlays.into_iter().map(|rect| (rect, 0)).collect()
+}
+
+/// The parts of an output that changed since its last frame.
+///
+/// An output without damage gets no new frame. A frame repaints the damage of the frames since
+/// the buffer it is drawn into was last painted, which the damage of the last frames is kept for.
+#[derive(Debug)]
+pub struct OutputDamage {
+    /// The damage since the last frame, in global coordinates
+    damage: Vec<Rectangle>,
+    /// Whether the whole output needs to be repainted
+    full: bool,
+    /// The damage of the last frames, the latest first, `None` for the frames repainted in full
+    history: VecDeque<Option<Vec<Rectangle>>>,
+    /// The frames of the output so far
+    frames: usize,
+    /// The frame the buffers of a DRM surface were last painted in, by framebuffer object
+    buffers: HashMap<u32, usize>,
+    /// The framebuffer object of the frame being drawn
+    drawing: Option<u32>,
+    /// The cursors, icons and popups drawn over the windows in the last frame, with the animation
+    /// frame they showed
+    overlays: Vec<(Rectangle, usize)>,
+    /// Whether a frame was queued and its vblank did not arrive yet
+    pub frame_pending: bool,
+}
+
+impl Default for OutputDamage {
+    fn default() -> Self {
+        OutputDamage {
+            damage: Vec::new(),
+            full: true,
+            history: VecDeque::new(),
+            frames: 0,
+            buffers: HashMap::new(),
+            drawing: None,
+            overlays: Vec::new(),
+            // a new output waits for the vblank of its initial frame
+            frame_pending: true,
+        }
+    }
+}
+
+impl OutputDamage {
+    /// Adds the parts of the damage that are on the output.
+    pub fn add(&mut self, geometry: &Rectangle, damage: &[Rectangle]) {
+        self.damage
+            .extend(damage.iter().filter(|rect| geometry.overlaps(rect)).cloned());
+    }
+
+    /// Makes the next frame repaint the whole output.
+    pub fn damage_all(&mut self) {
+        self.full = true;
+    }
+
+    /// Repaints the whole output and forgets the buffers drawn into, once the rendering surface
+    /// was set up again with new ones.
+    pub fn reset_buffers(&mut self) {
+        self.full = true;
+        self.buffers.clear();
+    }
+
+    /// Damages the overlays that moved, changed or disappeared since the last frame.
+    pub fn set_overlays(&mut self, geometry: &Rectangle, overlays: Vec<(Rectangle, usize)>) {
+        if overlays != self.overlays {
+            let changed = self
+                .overlays
+                .iter()
+                .chain(overlays.iter())
+                .map(|&(rect, _)| rect)
+                .collect::<Vec<_>>();
+            self.add(geometry, &changed);
+            self.overlays = overlays;
+        }
+    }
+
+    /// Whether the last overlays cover a part of the output.
+    pub fn has_overlays(&self, geometry: &Rectangle) -> bool {
+        self.overlays.iter().any(|(rect, _)| geometry.overlaps(rect))
+    }
+
+    pub fn is_damaged(&self) -> bool {
+        self.full || !self.damage.is_empty()
+    }
+
+    /// The parts of the output the frame being drawn repaints, in global coordinates.
+    ///
+    /// Must be called with the buffer of the frame bound.
+    pub fn repaint(&mut self, geometry: &Rectangle) -> Vec<Rectangle> {
+        let framebuffer = gl::framebuffer();
+        let age = if framebuffer == 0 {
+            gl::egl_buffer_age().unwrap_or(0)
+        } else {
+            self.buffers
+                .get(&framebuffer)
+                .map_or(0, |&frame| self.frames - frame)
+        };
+        self.drawing = Some(framebuffer);
+        self.repaint_region(age, geometry)
+    }
+
+    /// The parts of the output to repaint in a buffer of the given age, 0 for a buffer with
+    /// unknown contents and 1 for the buffer of the last frame.
+    fn repaint_region(&self, age: usize, geometry: &Rectangle) -> Vec<Rectangle> {
+        if self.full || age == 0 || age > self.history.len() + 1 {
+            return vec![*geometry];
+        }
+        let mut damage = self.damage.clone();
+        for frame in self.history.iter().take(age - 1) {
+            match frame {
+                Some(frame) => damage.extend_from_slice(frame),
+                None => return vec![*geometry],
+            }
+        }
+        merge_damage(&damage, geometry)
+    }
+
+    /// Clears the damage, once the output showed it with a new frame.
+    pub fn reset(&mut self) {
+        let damage = std::mem::take(&mut self.damage);
+        self.history
+            .push_front(if self.full { None } else { Some(damage) });
+        self.history.truncate(MAX_BUFFER_AGE);
+        // the buffers of the window of a host have their age told by egl
+        if let Some(framebuffer) = self.drawing.take().filter(|&framebuffer| framebuffer != 0) {
+            self.buffers.insert(framebuffer, self.frames);
+        }
+        self.frames += 1;
+        self.full = false;
+    }
+}
+
+/// The damage on an output as a few rectangles, overlapping ones merged.
+fn merge_damage(damage: &[Rectangle], geometry: &Rectangle) -> Vec<Rectangle> {
+    let mut merged: Vec<Rectangle> = Vec::new();
+    for rect in damage {
+        let mut rect = intersection(*rect, *geometry);
+        if rect.width == 0 || rect.height == 0 {
+            continue;
+        }
+        while let Some(idx) = merged.iter().position(|other| other.overlaps(&rect)) {
+            rect = bounding_box(rect, merged.swap_remove(idx));
+        }
+        merged.push(rect);
+    }
+    if merged.len() > MAX_REPAINT_RECTS {
+        let first = merged[0];
+        merged = vec![merged.into_iter().fold(first, bounding_box)];
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> Rectangle {
+        Rectangle { x, y, width, height }
+    }
+
+    const OUTPUT: Rectangle = Rectangle {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+
+    /// An output past its initial full frame.
+    fn painted() -> OutputDamage {
+        let mut damage = OutputDamage::default();
+        damage.reset();
+        damage
+    }
+
+    #[test]
+    fn first_frame_is_full() {
+        let damage = OutputDamage::default();
+        assert_eq!(damage.repaint_region(1, &OUTPUT), vec![OUTPUT]);
+    }
+
+    #[test]
+    fn unknown_buffer_is_full() {
+        let mut damage = painted();
+        damage.add(&OUTPUT, &[rect(10, 10, 10, 10)]);
+        assert_eq!(damage.repaint_region(0, &OUTPUT), vec![OUTPUT]);
+    }
+
+    #[test]
+    fn last_buffer_repaints_the_damage() {
+        let mut damage = painted();
+        damage.add(&OUTPUT, &[rect(10, 10, 10, 10)]);
+        assert_eq!(damage.repaint_region(1, &OUTPUT), vec![rect(10, 10, 10, 10)]);
+    }
+
+    #[test]
+    fn older_buffer_repaints_the_frames_since() {
+        let mut damage = painted();
+        damage.add(&OUTPUT, &[rect(10, 10, 10, 10)]);
+        damage.reset();
+        damage.add(&OUTPUT, &[rect(100, 100, 10, 10)]);
+        damage.reset();
+        damage.add(&OUTPUT, &[rect(500, 500, 10, 10)]);
+        assert_eq!(
+            damage.repaint_region(2, &OUTPUT),
+            vec![rect(500, 500, 10, 10), rect(100, 100, 10, 10)]
+        );
+        assert_eq!(
+            damage.repaint_region(3, &OUTPUT),
+            vec![
+                rect(500, 500, 10, 10),
+                rect(100, 100, 10, 10),
+                rect(10, 10, 10, 10)
+            ]
+        );
+        // the initial frame was repainted in full
+        assert_eq!(damage.repaint_region(4, &OUTPUT), vec![OUTPUT]);
+        assert_eq!(damage.repaint_region(5, &OUTPUT), vec![OUTPUT]);
+    }
+
+    #[test]
+    fn buffer_older_than_the_history_is_full() {
+        let mut damage = painted();
+        for _ in 0..MAX_BUFFER_AGE + 1 {
+            damage.add(&OUTPUT, &[rect(10, 10, 10, 10)]);
+            damage.reset();
+        }
+        assert_eq!(
+            damage.repaint_region(MAX_BUFFER_AGE + 1, &OUTPUT),
+            vec![rect(10, 10, 10, 10)]
+        );
+        assert_eq!(damage.repaint_region(MAX_BUFFER_AGE + 2, &OUTPUT), vec![OUTPUT]);
+    }
+
+    #[test]
+    fn damage_all_is_full() {
+        let mut damage = painted();
+        damage.damage_all();
+        assert_eq!(damage.repaint_region(1, &OUTPUT), vec![OUTPUT]);
+        damage.reset();
+        damage.add(&OUTPUT, &[rect(10, 10, 10, 10)]);
+        assert_eq!(damage.repaint_region(1, &OUTPUT), vec![rect(10, 10, 10, 10)]);
+        assert_eq!(damage.repaint_region(2, &OUTPUT), vec![OUTPUT]);
+    }
+
+    #[test]
+    fn merge_overlapping_damage() {
+        let damage = [rect(0, 0, 20, 20), rect(10, 10, 20, 20), rect(100, 100, 10, 10)];
+        assert_eq!(
+            merge_damage(&damage, &OUTPUT),
+            vec![rect(0, 0, 30, 30), rect(100, 100, 10, 10)]
+        );
+    }
+
+    #[test]
+    fn merge_clips_to_the_output() {
+        let damage = [rect(-10, -10, 20, 20), rect(2000, 0, 10, 10)];
+        assert_eq!(merge_damage(&damage, &OUTPUT), vec![rect(0, 0, 10, 10)]);
+    }
+
+    #[test]
+    fn merge_many_into_bounding_box() {
+        let damage = (0..MAX_REPAINT_RECTS as i32 + 1)
+            .map(|i| rect(i * 100, 0, 10, 10))
+            .collect::<Vec<_>>();
+        let merged = merge_damage(&damage, &OUTPUT);
+        assert_eq!(merged, vec![rect(0, 0, MAX_REPAINT_RECTS as i32 * 100 + 10, 10)]);
+    }
+}
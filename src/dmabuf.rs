@@ -0,0 +1,630 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    path::PathBuf,
+    rc::Rc,
+};
+
+use smithay::{
+    backend::{
+        allocator::{
+            dmabuf::{Dmabuf, DmabufFlags},
+            Fourcc, Modifier,
+        },
+        renderer::ImportDma,
+    },
+    reexports::{
+        nix::{
+            sys::stat::dev_t,
+            unistd::{close, lseek, Whence},
+        },
+        wayland_server::{
+            protocol::wl_buffer::{self, WlBuffer},
+            Display, Filter, Global, Main,
+        },
+    },
+};
+
+use self::protocol::{
+    zwp_linux_buffer_params_v1::{self, ZwpLinuxBufferParamsV1},
+    zwp_linux_dmabuf_feedback_v1::ZwpLinuxDmabufFeedbackV1,
+    zwp_linux_dmabuf_v1::{self, ZwpLinuxDmabufV1},
+};
+
+/// The code generated from `protocols/linux-dmabuf-unstable-v1.xml` by the build script.
+#[allow(
+    dead_code,
+    non_camel_case_types,
+    unused_unsafe,
+    unused_variables,
+    non_upper_case_globals,
+    non_snake_case,
+    unused_imports,
+    missing_docs,
+    clippy::all
+)]
+pub mod protocol {
+    pub(crate) use smithay::reexports::wayland_server::{
+        protocol::{wl_buffer, wl_surface},
+        AnonymousObject, Main, Resource, ResourceMap,
+    };
+    pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+    pub(crate) use wayland_commons::smallvec;
+    pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+    pub(crate) use wayland_commons::{Interface, MessageGroup};
+    pub(crate) use wayland_sys as sys;
+    include!(concat!(env!("OUT_DIR"), "/linux_dmabuf_unstable_v1.rs"));
+}
+
+/// Dmabufs have up to four planes.
+const MAX_PLANES: u32 = 4;
+
+/// The formats clients can create dmabufs in, as DRM fourcc codes with their modifiers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DmabufFormats {
+    /// Sorted and without duplicates, the indices are the ones of the format table
+    formats: Vec<(u32, u64)>,
+}
+
+impl DmabufFormats {
+    pub fn new<I>(formats: I) -> Self
+    where
+        I: IntoIterator<Item = (u32, u64)>,
+    {
+        DmabufFormats {
+            formats: formats
+                .into_iter()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// The format codes, for the format events of versions 1 and 2.
+    pub fn codes(&self) -> Vec<u32> {
+        let mut codes = self.formats.iter().map(|&(code, _)| code).collect::<Vec<_>>();
+        codes.dedup();
+        codes
+    }
+
+    /// The formats with their modifiers, for the modifier events of version 3.
+    pub fn modifiers(&self) -> &[(u32, u64)] {
+        &self.formats
+    }
+
+    /// The contents of the format table of version 4, 16 bytes per format: the code, 4 bytes of
+    /// padding and the modifier, in native endianness.
+    pub fn table(&self) -> Vec<u8> {
+        let mut table = Vec::with_capacity(self.formats.len() * 16);
+        for &(code, modifier) in &self.formats {
+            table.extend_from_slice(&code.to_ne_bytes());
+            table.extend_from_slice(&[0; 4]);
+            table.extend_from_slice(&modifier.to_ne_bytes());
+        }
+        table
+    }
+
+    /// The indices of all formats in the table as 16 bit integers, for a tranche.
+    pub fn tranche_indices(&self) -> Vec<u8> {
+        (0..self.formats.len().min(u16::MAX as usize + 1))
+            .flat_map(|idx| (idx as u16).to_ne_bytes().to_vec())
+            .collect()
+    }
+
+    pub fn supports(&self, code: u32, modifier: u64) -> bool {
+        self.formats.binary_search(&(code, modifier)).is_ok()
+    }
+
+    /// Checks the parameters of a buffer, returns the modifier of its planes.
+    pub fn validate(
+        &self,
+        planes: &[Plane],
+        (width, height): (i32, i32),
+        code: u32,
+    ) -> Result<u64, (zwp_linux_buffer_params_v1::Error, String)> {
+        use zwp_linux_buffer_params_v1::Error;
+
+        if planes.is_empty() {
+            return Err((Error::Incomplete, "No planes were added.".into()));
+        }
+        if (0..planes.len() as u32).any(|idx| !planes.iter().any(|plane| plane.idx == idx)) {
+            return Err((Error::Incomplete, "The plane indices have gaps.".into()));
+        }
+        if width <= 0 || height <= 0 {
+            return Err((
+                Error::InvalidDimensions,
+                format!("Invalid size {}x{}.", width, height),
+            ));
+        }
+        let modifier = planes[0].modifier;
+        if planes.iter().any(|plane| plane.modifier != modifier) {
+            return Err((Error::InvalidFormat, "The planes have different modifiers.".into()));
+        }
+        if !self.supports(code, modifier) {
+            return Err((
+                Error::InvalidFormat,
+                format!("Format {:#x} with modifier {:#x} is not supported.", code, modifier),
+            ));
+        }
+        for plane in planes {
+            let out_of_bounds = match (plane.offset.checked_add(plane.stride), plane.size) {
+                (None, _) => true,
+                (Some(end), Some(size)) => {
+                    // the other planes may be subsampled, only the first one has the full height
+                    end as u64 > size
+                        || (plane.idx == 0 && plane.offset as u64 + plane.stride as u64 * height as u64 > size)
+                }
+                (Some(_), None) => false,
+            };
+            if out_of_bounds {
+                return Err((
+                    Error::OutOfBounds,
+                    format!("Plane {} is out of the bounds of its dmabuf.", plane.idx),
+                ));
+            }
+        }
+        Ok(modifier)
+    }
+}
+
+/// The formats a renderer can import.
+pub fn renderer_formats<R: ImportDma>(renderer: &R) -> DmabufFormats {
+    DmabufFormats::new(
+        renderer
+            .dmabuf_formats()
+            .map(|format| (format.code as u32, u64::from(format.modifier))),
+    )
+}
+
+/// Checks whether a renderer can import a dmabuf, the texture is destroyed again.
+pub fn can_import<R: ImportDma>(renderer: &mut R, dmabuf: &Dmabuf) -> bool {
+    match renderer.import_dmabuf(dmabuf) {
+        Ok(texture) => renderer.destroy_texture(texture).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// A plane added to buffer parameters, its fd is closed unless it makes it into a buffer.
+#[derive(Debug)]
+pub struct Plane {
+    pub fd: RawFd,
+    pub idx: u32,
+    pub offset: u32,
+    pub stride: u32,
+    pub modifier: u64,
+    /// The size of the dmabuf, if it could be found out
+    pub size: Option<u64>,
+}
+
+impl Plane {
+    fn into_fd(self) -> RawFd {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for Plane {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+/// The dmabuf of a `wl_buffer` created through linux-dmabuf, kept in its user data.
+pub struct DmabufBuffer {
+    pub dmabuf: Dmabuf,
+    pub width: i32,
+    pub height: i32,
+    pub format: u32,
+    pub modifier: u64,
+    /// The fd, offset and stride of every plane, the fds are owned by the dmabuf
+    pub planes: Vec<(RawFd, u32, u32)>,
+}
+
+/// Returns the dmabuf of a buffer, if it was created through linux-dmabuf.
+pub fn buffer_dmabuf(buffer: &WlBuffer) -> Option<&DmabufBuffer> {
+    buffer.as_ref().user_data().get::<DmabufBuffer>()
+}
+
+/// The parameters of a buffer a client is collecting.
+#[derive(Default)]
+struct BufferParams {
+    planes: Vec<Plane>,
+    used: bool,
+}
+
+enum CreateError {
+    /// The parameters are wrong, the client is disconnected
+    Protocol(zwp_linux_buffer_params_v1::Error, String),
+    /// The renderer can not import the buffer
+    Import,
+}
+
+struct DmabufGlobal {
+    formats: DmabufFormats,
+    /// The device clients should allocate on and the format table, to send feedback
+    feedback: Option<(dev_t, File, u32)>,
+    import: Box<dyn FnMut(&Dmabuf) -> bool>,
+    log: ::slog::Logger,
+}
+
+impl DmabufGlobal {
+    fn create(
+        &mut self,
+        params: &RefCell<BufferParams>,
+        size: (i32, i32),
+        format: u32,
+        flags: u32,
+    ) -> Result<DmabufBuffer, CreateError> {
+        let mut params = params.borrow_mut();
+        if params.used {
+            return Err(CreateError::Protocol(
+                zwp_linux_buffer_params_v1::Error::AlreadyUsed,
+                "The parameters were already used.".into(),
+            ));
+        }
+        params.used = true;
+        let modifier = self
+            .formats
+            .validate(&params.planes, size, format)
+            .map_err(|(err, message)| CreateError::Protocol(err, message))?;
+
+        let mut planes = std::mem::take(&mut params.planes);
+        planes.sort_by_key(|plane| plane.idx);
+        let fourcc = Fourcc::try_from(format).map_err(|_| {
+            CreateError::Protocol(
+                zwp_linux_buffer_params_v1::Error::InvalidFormat,
+                format!("Unknown format {:#x}.", format),
+            )
+        })?;
+        let mut builder = Dmabuf::builder(size, fourcc, DmabufFlags::from_bits_truncate(flags));
+        for plane in &planes {
+            builder.add_plane(plane.fd, plane.idx, plane.offset, plane.stride, Modifier::from(modifier));
+        }
+        // the planes close their fds until the dmabuf owns them
+        let dmabuf = builder.build().ok_or(CreateError::Import)?;
+        let plane_info = planes
+            .into_iter()
+            .map(|plane| {
+                let (offset, stride) = (plane.offset, plane.stride);
+                (plane.into_fd(), offset, stride)
+            })
+            .collect();
+        if !(self.import)(&dmabuf) {
+            debug!(self.log, "The renderer can not import a dmabuf"; "format" => format, "modifier" => modifier);
+            return Err(CreateError::Import);
+        }
+        Ok(DmabufBuffer {
+            dmabuf,
+            width: size.0,
+            height: size.1,
+            format,
+            modifier,
+            planes: plane_info,
+        })
+    }
+
+    /// Sends the single tranche of the main device.
+    fn send_feedback(&self, feedback: &ZwpLinuxDmabufFeedbackV1) {
+        if let Some((device, ref table, table_size)) = self.feedback {
+            let device = device.to_ne_bytes().to_vec();
+            feedback.format_table(table.as_raw_fd(), table_size);
+            feedback.main_device(device.clone());
+            feedback.tranche_target_device(device);
+            feedback.tranche_flags(0);
+            feedback.tranche_formats(self.formats.tranche_indices());
+            feedback.tranche_done();
+            feedback.done();
+        }
+    }
+}
+
+/// Writes the format table into an unlinked file clients can map.
+fn format_table_file(formats: &DmabufFormats) -> io::Result<(File, u32)> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let path = runtime_dir.join(format!("waybox-dmabuf-formats-{}", std::process::id()));
+
+    let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+    fs::remove_file(&path)?;
+    let table = formats.table();
+    file.write_all(&table)?;
+    file.flush()?;
+    Ok((file, table.len() as u32))
+}
+
+fn init_buffer(buffer: Main<WlBuffer>, dmabuf: DmabufBuffer) -> WlBuffer {
+    buffer.quick_assign(|_, request, _| match request {
+        wl_buffer::Request::Destroy => {}
+        _ => unreachable!(),
+    });
+    buffer.as_ref().user_data().set(move || dmabuf);
+    (*buffer).clone()
+}
+
+/// Creates the linux-dmabuf global with the formats the renderer can import.
+///
+/// `import` checks whether the renderer can import a new buffer. With the device the renderer
+/// uses, clients get the formats through the feedback of version 4, otherwise the global stays at
+/// version 3.
+pub fn init_linux_dmabuf<F>(
+    display: &mut Display,
+    formats: DmabufFormats,
+    main_device: Option<dev_t>,
+    import: F,
+    log: ::slog::Logger,
+) -> Global<ZwpLinuxDmabufV1>
+where
+    F: FnMut(&Dmabuf) -> bool + 'static,
+{
+    let feedback = main_device.and_then(|device| match format_table_file(&formats) {
+        Ok((file, size)) => Some((device, file, size)),
+        Err(err) => {
+            warn!(log, "Failed to write the dmabuf format table: {}", err);
+            None
+        }
+    });
+    let version = if feedback.is_some() { 4 } else { 3 };
+    info!(log, "Initializing linux-dmabuf"; "version" => version, "formats" => formats.modifiers().len());
+
+    let global = Rc::new(RefCell::new(DmabufGlobal {
+        formats,
+        feedback,
+        import: Box::new(import),
+        log,
+    }));
+    display.create_global::<ZwpLinuxDmabufV1, _>(
+        version,
+        Filter::new(move |(dmabuf, version): (Main<ZwpLinuxDmabufV1>, u32), _, _| {
+            {
+                // version 4 clients get the formats through the feedback only
+                let global = global.borrow();
+                if version < 3 {
+                    for code in global.formats.codes() {
+                        dmabuf.format(code);
+                    }
+                } else if version == 3 {
+                    for &(code, modifier) in global.formats.modifiers() {
+                        dmabuf.format(code);
+                        dmabuf.modifier(code, (modifier >> 32) as u32, modifier as u32);
+                    }
+                }
+            }
+
+            let global = global.clone();
+            dmabuf.quick_assign(move |_, request, _| match request {
+                zwp_linux_dmabuf_v1::Request::CreateParams { params_id } => {
+                    init_params(params_id, global.clone());
+                }
+                zwp_linux_dmabuf_v1::Request::GetDefaultFeedback { id }
+                | zwp_linux_dmabuf_v1::Request::GetSurfaceFeedback { id, .. } => {
+                    // all surfaces get the same formats, there are no scanout tranches yet
+                    id.quick_assign(|_, _, _| {});
+                    global.borrow().send_feedback(&id);
+                }
+                zwp_linux_dmabuf_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            });
+        }),
+    )
+}
+
+fn init_params(params: Main<ZwpLinuxBufferParamsV1>, global: Rc<RefCell<DmabufGlobal>>) {
+    let state = RefCell::new(BufferParams::default());
+    params.quick_assign(move |params, request, _| match request {
+        zwp_linux_buffer_params_v1::Request::Add {
+            fd,
+            plane_idx,
+            offset,
+            stride,
+            modifier_hi,
+            modifier_lo,
+        } => {
+            let plane = Plane {
+                fd,
+                idx: plane_idx,
+                offset,
+                stride,
+                modifier: (modifier_hi as u64) << 32 | modifier_lo as u64,
+                size: lseek(fd, 0, Whence::SeekEnd).ok().map(|size| size as u64),
+            };
+            let mut state = state.borrow_mut();
+            let error = if state.used {
+                Some((
+                    zwp_linux_buffer_params_v1::Error::AlreadyUsed,
+                    "The parameters were already used.",
+                ))
+            } else if plane.idx >= MAX_PLANES {
+                Some((zwp_linux_buffer_params_v1::Error::PlaneIdx, "The plane index is too large."))
+            } else if state.planes.iter().any(|other| other.idx == plane.idx) {
+                Some((zwp_linux_buffer_params_v1::Error::PlaneSet, "The plane was already added."))
+            } else {
+                None
+            };
+            match error {
+                Some((err, message)) => params.as_ref().post_error(err.to_raw(), message.into()),
+                None => state.planes.push(plane),
+            }
+        }
+        zwp_linux_buffer_params_v1::Request::Create {
+            width,
+            height,
+            format,
+            flags,
+        } => match global.borrow_mut().create(&state, (width, height), format, flags) {
+            Ok(dmabuf) => {
+                let buffer = params
+                    .as_ref()
+                    .client()
+                    .and_then(|client| client.create_resource::<WlBuffer>(1));
+                if let Some(buffer) = buffer {
+                    params.created(&init_buffer(buffer, dmabuf));
+                }
+            }
+            Err(CreateError::Protocol(err, message)) => params.as_ref().post_error(err.to_raw(), message),
+            Err(CreateError::Import) => params.failed(),
+        },
+        zwp_linux_buffer_params_v1::Request::CreateImmed {
+            buffer_id,
+            width,
+            height,
+            format,
+            flags,
+        } => match global.borrow_mut().create(&state, (width, height), format, flags) {
+            Ok(dmabuf) => {
+                init_buffer(buffer_id, dmabuf);
+            }
+            Err(CreateError::Protocol(err, message)) => params.as_ref().post_error(err.to_raw(), message),
+            Err(CreateError::Import) => params.as_ref().post_error(
+                zwp_linux_buffer_params_v1::Error::InvalidWlBuffer.to_raw(),
+                "The buffer can not be imported.".into(),
+            ),
+        },
+        zwp_linux_buffer_params_v1::Request::Destroy => {}
+        _ => unreachable!(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zwp_linux_buffer_params_v1::Error;
+
+    const ARGB8888: u32 = 0x3432_5241;
+    const XRGB8888: u32 = 0x3432_5258;
+    const NV12: u32 = 0x3231_564e;
+    const LINEAR: u64 = 0;
+    const INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+    const X_TILED: u64 = 0x0100_0000_0000_0001;
+
+    fn plane(idx: u32, offset: u32, stride: u32, modifier: u64) -> Plane {
+        // no fd, closing it fails quietly
+        Plane {
+            fd: -1,
+            idx,
+            offset,
+            stride,
+            modifier,
+            size: Some(1024 * 768 * 4),
+        }
+    }
+
+    fn formats() -> DmabufFormats {
+        DmabufFormats::new(vec![
+            (XRGB8888, X_TILED),
+            (ARGB8888, LINEAR),
+            (XRGB8888, LINEAR),
+            (ARGB8888, INVALID),
+            (XRGB8888, LINEAR),
+            (NV12, LINEAR),
+        ])
+    }
+
+    fn error(result: Result<u64, (Error, String)>) -> Error {
+        result.unwrap_err().0
+    }
+
+    #[test]
+    fn the_tranche_indexes_the_sorted_table() {
+        let formats = formats();
+        assert_eq!(
+            formats.modifiers(),
+            &[
+                (NV12, LINEAR),
+                (ARGB8888, LINEAR),
+                (ARGB8888, INVALID),
+                (XRGB8888, LINEAR),
+                (XRGB8888, X_TILED),
+            ]
+        );
+        assert_eq!(formats.codes(), vec![NV12, ARGB8888, XRGB8888]);
+
+        let table = formats.table();
+        assert_eq!(table.len(), 5 * 16);
+        let indices = formats.tranche_indices();
+        assert_eq!(indices.len(), 5 * 2);
+        for (idx, &(code, modifier)) in formats.modifiers().iter().enumerate() {
+            let entry = u16::from_ne_bytes([indices[idx * 2], indices[idx * 2 + 1]]) as usize;
+            let row = &table[entry * 16..entry * 16 + 16];
+            assert_eq!(&row[..4], &code.to_ne_bytes());
+            assert_eq!(&row[8..], &modifier.to_ne_bytes());
+        }
+    }
+
+    #[test]
+    fn supported_buffers_are_accepted() {
+        let planes = [plane(0, 0, 1024 * 4, LINEAR)];
+        assert_eq!(formats().validate(&planes, (1024, 768), XRGB8888), Ok(LINEAR));
+    }
+
+    #[test]
+    fn a_missing_format_and_modifier_is_rejected() {
+        let planes = [plane(0, 0, 1024 * 4, X_TILED)];
+        assert_eq!(
+            error(formats().validate(&planes, (1024, 768), ARGB8888)),
+            Error::InvalidFormat
+        );
+        let planes = [plane(0, 0, 1024 * 4, LINEAR)];
+        assert_eq!(
+            error(formats().validate(&planes, (1024, 768), 0x3631_5258)),
+            Error::InvalidFormat
+        );
+    }
+
+    #[test]
+    fn the_implicit_modifier_has_to_be_supported() {
+        let planes = [plane(0, 0, 1024 * 4, INVALID)];
+        assert_eq!(formats().validate(&planes, (1024, 768), ARGB8888), Ok(INVALID));
+        assert_eq!(
+            error(formats().validate(&planes, (1024, 768), XRGB8888)),
+            Error::InvalidFormat
+        );
+    }
+
+    #[test]
+    fn the_planes_have_to_be_complete() {
+        assert_eq!(error(formats().validate(&[], (1024, 768), NV12)), Error::Incomplete);
+        let planes = [plane(0, 0, 1024, LINEAR), plane(2, 1024 * 768, 1024, LINEAR)];
+        assert_eq!(error(formats().validate(&planes, (1024, 768), NV12)), Error::Incomplete);
+        let planes = [plane(1, 1024 * 768, 1024, LINEAR), plane(0, 0, 1024, LINEAR)];
+        assert_eq!(formats().validate(&planes, (1024, 768), NV12), Ok(LINEAR));
+    }
+
+    #[test]
+    fn the_planes_have_to_share_their_modifier() {
+        let planes = [plane(0, 0, 1024, LINEAR), plane(1, 1024 * 768, 1024, INVALID)];
+        assert_eq!(error(formats().validate(&planes, (1024, 768), NV12)), Error::InvalidFormat);
+    }
+
+    #[test]
+    fn the_size_has_to_be_positive() {
+        let planes = [plane(0, 0, 1024 * 4, LINEAR)];
+        assert_eq!(
+            error(formats().validate(&planes, (0, 768), XRGB8888)),
+            Error::InvalidDimensions
+        );
+    }
+
+    #[test]
+    fn the_planes_have_to_fit_their_dmabuf() {
+        // the stride of the first plane covers too many rows
+        let planes = [plane(0, 0, 1024 * 8, LINEAR)];
+        assert_eq!(error(formats().validate(&planes, (1024, 768), XRGB8888)), Error::OutOfBounds);
+        // the offset is past the end
+        let planes = [plane(0, 1024 * 768 * 4, 1024 * 4, LINEAR)];
+        assert_eq!(error(formats().validate(&planes, (1024, 768), XRGB8888)), Error::OutOfBounds);
+        // the offset and stride overflow
+        let planes = [plane(0, u32::MAX, 1024 * 4, LINEAR)];
+        assert_eq!(error(formats().validate(&planes, (1024, 768), XRGB8888)), Error::OutOfBounds);
+        // without a known size only the overflow is checked
+        let mut unknown = plane(0, 1024 * 768 * 4, 1024 * 4, LINEAR);
+        unknown.size = None;
+        let planes = [unknown];
+        assert_eq!(formats().validate(&planes, (1024, 768), XRGB8888), Ok(LINEAR));
+    }
+}
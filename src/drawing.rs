@@ -2,23 +2,29 @@
 
 use std::cell::RefCell;
 
+use cgmath::{Matrix3, SquareMatrix, Vector2, Vector3};
 use slog::Logger;
 use smithay::{
     backend::{
         egl::display::EGLBufferReader,
-        renderer::{Frame, Renderer, Texture, Transform},
+        renderer::{Frame, ImportDma, Renderer, Texture, Transform},
         SwapBuffersError,
     },
-    reexports::wayland_server::protocol::{wl_buffer, wl_surface},
+    reexports::wayland_server::protocol::{wl_buffer, wl_output, wl_surface},
     utils::Rectangle,
     wayland::{
         compositor::{roles::Role, Damage, SubsurfaceRole, TraversalAction},
         data_device::DnDIconRole,
         seat::CursorImageRole,
+        shm::with_buffer_contents,
     },
 };
 
-use crate::shell::{MyCompositorToken, MyWindowMap, SurfaceData};
+use crate::{
+    dmabuf::buffer_dmabuf,
+    gl,
+    shell::{MyCompositorToken, MyWindowMap, SurfaceData},
+};
 
 struct BufferTextures<T> {
     buffer: Option<wl_buffer::WlBuffer>,
@@ -33,17 +39,197 @@ impl<T> Drop for BufferTextures<T> {
     }
 }
 
+/// The buffer a surface shows, before it is imported or while its texture holds it.
+///
+/// Shm buffers are released once they are copied into a texture, so they are only returned until
+/// then.
+pub fn surface_buffer<T: 'static>(data: &SurfaceData) -> Option<wl_buffer::WlBuffer> {
+    data.current_state.buffer.clone().or_else(|| {
+        data.texture
+            .as_ref()?
+            .downcast_ref::<BufferTextures<T>>()?
+            .buffer
+            .clone()
+    })
+}
+
+/// The part of the global space a frame shows.
+#[derive(Clone, Copy, Debug)]
+pub struct OutputView {
+    /// The area of the output in the global space, in logical pixels
+    pub geometry: Rectangle,
+    /// The number of physical pixels per logical pixel
+    pub scale: f64,
+    /// How the content is rotated and flipped to be shown upright on the monitor
+    pub transform: Transform,
+    /// Whether the rows of the framebuffer count from the bottom of the monitor, as in the window
+    /// of a host, instead of from its top as in a scanout buffer
+    pub bottom_up: bool,
+}
+
+impl OutputView {
+    /// The size of the frame, in physical pixels.
+    pub fn framebuffer_size(&self) -> (u32, u32) {
+        let (width, height) = self.physical_size();
+        if swaps_axes(self.transform) {
+            (height as u32, width as u32)
+        } else {
+            (width as u32, height as u32)
+        }
+    }
+
+    /// Maps a point of the frame, in physical pixels, to the global space.
+    pub fn map_from_framebuffer(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let inverse = self.output_matrix().invert().unwrap_or_else(Matrix3::identity);
+        let point = inverse * Vector3::new(x as f32, y as f32, 1.0);
+        (
+            point.x as f64 / self.scale + self.geometry.x as f64,
+            point.y as f64 / self.scale + self.geometry.y as f64,
+        )
+    }
+
+    /// Renders a texture stretched over a rectangle of the global space.
+    ///
+    /// `buffer_transform` is the transform the client applied to the content of the texture.
+    pub fn render_texture<F, E, T>(
+        &self,
+        frame: &mut F,
+        texture: &T,
+        (x, y, width, height): (f64, f64, f64, f64),
+        buffer_transform: Transform,
+    ) -> Result<(), E>
+    where
+        F: Frame<Error = E, TextureId = T>,
+        T: Texture,
+    {
+        // snap to physical pixels, so unscaled content stays sharp
+        let x = ((x - self.geometry.x as f64) * self.scale).round() as f32;
+        let y = ((y - self.geometry.y as f64) * self.scale).round() as f32;
+        let width = (width * self.scale).round() as f32;
+        let height = (height * self.scale).round() as f32;
+        let (texture_width, texture_height) = if swaps_axes(buffer_transform) {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        // undo the buffer transform around the center of the rectangle
+        let matrix = self.output_matrix()
+            * Matrix3::from_translation(Vector2::new(x + width / 2.0, y + height / 2.0))
+            * buffer_transform.invert().matrix()
+            * Matrix3::from_nonuniform_scale(texture_width, texture_height)
+            * Matrix3::from_translation(Vector2::new(-0.5, -0.5));
+        frame.render_texture(texture, matrix, 1.0)
+    }
+
+    /// Renders a texture stretched over a rectangle of the global space, showing only the part
+    /// within `clip`.
+    pub fn render_texture_clipped<F, E, T>(
+        &self,
+        frame: &mut F,
+        texture: &T,
+        rect: (f64, f64, f64, f64),
+        clip: (f64, f64, f64, f64),
+        buffer_transform: Transform,
+    ) -> Result<(), E>
+    where
+        F: Frame<Error = E, TextureId = T>,
+        T: Texture,
+    {
+        gl::with_clip(self.framebuffer_rect(clip), || {
+            self.render_texture(frame, texture, rect, buffer_transform)
+        })
+    }
+
+    /// Restricts the drawing to a rectangle of the global space, or lifts the restriction.
+    pub fn clip(&self, clip: Option<Rectangle>) {
+        gl::set_clip(clip.map(|clip| {
+            self.framebuffer_rect((
+                clip.x as f64,
+                clip.y as f64,
+                clip.width as f64,
+                clip.height as f64,
+            ))
+        }));
+    }
+
+    /// The pixels of the framebuffer a rectangle of the global space covers, as gl counts them.
+    pub fn framebuffer_rect(&self, (x, y, width, height): (f64, f64, f64, f64)) -> Rectangle {
+        // snapped like the textures
+        let x = ((x - self.geometry.x as f64) * self.scale).round() as f32;
+        let y = ((y - self.geometry.y as f64) * self.scale).round() as f32;
+        let width = (width * self.scale).round() as f32;
+        let height = (height * self.scale).round() as f32;
+        let matrix = self.output_matrix();
+        let a = matrix * Vector3::new(x, y, 1.0);
+        let b = matrix * Vector3::new(x + width, y + height, 1.0);
+        let (left, top) = (a.x.min(b.x).round() as i32, a.y.min(b.y).round() as i32);
+        let (right, bottom) = (a.x.max(b.x).round() as i32, a.y.max(b.y).round() as i32);
+        Rectangle {
+            x: left,
+            y: if self.bottom_up {
+                self.framebuffer_size().1 as i32 - bottom
+            } else {
+                top
+            },
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    /// The size of the output after its transform, in physical pixels.
+    fn physical_size(&self) -> (f32, f32) {
+        (
+            (self.geometry.width as f64 * self.scale).round() as f32,
+            (self.geometry.height as f64 * self.scale).round() as f32,
+        )
+    }
+
+    /// Maps the physical pixels of the output after its transform to the pixels of the frame.
+    fn output_matrix(&self) -> Matrix3<f32> {
+        let (width, height) = self.physical_size();
+        let (framebuffer_width, framebuffer_height) = self.framebuffer_size();
+        Matrix3::from_translation(Vector2::new(
+            framebuffer_width as f32 / 2.0,
+            framebuffer_height as f32 / 2.0,
+        )) * self.transform.matrix()
+            * Matrix3::from_translation(Vector2::new(-width / 2.0, -height / 2.0))
+    }
+}
+
+/// Whether a transform swaps the width and height of what it applies to.
+fn swaps_axes(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+pub fn renderer_transform(transform: wl_output::Transform) -> Transform {
+    match transform {
+        wl_output::Transform::_90 => Transform::_90,
+        wl_output::Transform::_180 => Transform::_180,
+        wl_output::Transform::_270 => Transform::_270,
+        wl_output::Transform::Flipped => Transform::Flipped,
+        wl_output::Transform::Flipped90 => Transform::Flipped90,
+        wl_output::Transform::Flipped180 => Transform::Flipped180,
+        wl_output::Transform::Flipped270 => Transform::Flipped270,
+        _ => Transform::Normal,
+    }
+}
+
 pub fn draw_cursor<R, E, F, T>(
     renderer: &mut R,
     frame: &mut F,
     surface: &wl_surface::WlSurface,
     egl_buffer_reader: Option<&EGLBufferReader>,
     (x, y): (i32, i32),
+    output: &OutputView,
     token: MyCompositorToken,
     log: &Logger,
 ) -> Result<(), SwapBuffersError>
 where
-    R: Renderer<Error = E, TextureId = T, Frame = F>,
+    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma,
     F: Frame<Error = E, TextureId = T>,
     E: std::error::Error + Into<SwapBuffersError>,
     T: Texture + 'static,
@@ -64,6 +250,7 @@ where
         surface,
         egl_buffer_reader,
         (x - dx, y - dy),
+        output,
         token,
         log,
     )
@@ -75,20 +262,53 @@ fn draw_surface_tree<R, E, F, T>(
     root: &wl_surface::WlSurface,
     egl_buffer_reader: Option<&EGLBufferReader>,
     location: (i32, i32),
+    output: &OutputView,
+    compositor_token: MyCompositorToken,
+    log: &Logger,
+) -> Result<(), SwapBuffersError>
+where
+    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma,
+    F: Frame<Error = E, TextureId = T>,
+    E: std::error::Error + Into<SwapBuffersError>,
+    T: Texture + 'static,
+{
+    draw_surface_tree_scaled(
+        renderer,
+        frame,
+        root,
+        egl_buffer_reader,
+        (location.0 as f64, location.1 as f64),
+        1.0,
+        output,
+        compositor_token,
+        log,
+    )
+}
+
+/// Draws a surface tree with its root at `location` and scaled by `scale`, e.g. as a preview.
+pub fn draw_surface_tree_scaled<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    root: &wl_surface::WlSurface,
+    egl_buffer_reader: Option<&EGLBufferReader>,
+    location: (f64, f64),
+    scale: f64,
+    output: &OutputView,
     compositor_token: MyCompositorToken,
     log: &Logger,
 ) -> Result<(), SwapBuffersError>
 where
-    R: Renderer<Error = E, TextureId = T, Frame = F>,
+    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma,
     F: Frame<Error = E, TextureId = T>,
     E: std::error::Error + Into<SwapBuffersError>,
     T: Texture + 'static,
 {
     let mut result = Ok(());
 
+    // the tree is walked relative to the root, the offsets are scaled when drawing
     compositor_token.with_surface_tree_upward(
         root,
-        location,
+        (0, 0),
         |_surface, attributes, role, &(mut x, mut y)| {
             // Pull a new buffer if available
             if let Some(data) = attributes.user_data.get::<RefCell<SurfaceData>>() {
@@ -104,17 +324,21 @@ where
                                 Damage::Surface(rect) => rect.scale(attributes.buffer_scale),
                             })
                             .collect::<Vec<_>>();
-                        match renderer.import_buffer(&buffer, Some(&attributes), &damage, egl_buffer_reader) {
+                        // buffers of linux-dmabuf are not known to the renderer
+                        let texture = match buffer_dmabuf(&buffer) {
+                            Some(dmabuf) => renderer.import_dmabuf(&dmabuf.dmabuf),
+                            None => {
+                                renderer.import_buffer(&buffer, Some(&attributes), &damage, egl_buffer_reader)
+                            }
+                        };
+                        match texture {
                             Ok(m) => {
-                                let buffer = if smithay::wayland::shm::with_buffer_contents(
-                                    &buffer,
-                                    |_, _| (),
-                                )
-                                .is_ok()
-                                {
+                                let buffer = if with_buffer_contents(&buffer, |_, _| ()).is_ok() {
                                     buffer.release();
                                     None
                                 } else {
+                                    // the texture reads from hardware buffers, they are released
+                                    // with it once the client attached the next one
                                     Some(buffer)
                                 };
                                 data.texture = Some(Box::new(BufferTextures { buffer, texture: m })
@@ -150,6 +374,10 @@ where
             if let Some(ref data) = attributes.user_data.get::<RefCell<SurfaceData>>() {
                 let mut data = data.borrow_mut();
                 let (sub_x, sub_y) = data.current_state.sub_location;
+                let (width, height) = data.size().unwrap_or((0, 0));
+                let source = data.current_state.viewport.source;
+                let buffer_scale = attributes.buffer_scale.max(1) as f64;
+                let buffer_transform = renderer_transform(attributes.buffer_transform);
                 if let Some(texture) = data
                     .texture
                     .as_mut()
@@ -161,12 +389,45 @@ where
                         x += sub_x;
                         y += sub_y;
                     }
-                    if let Err(err) = frame.render_texture_at(
-                        &texture.texture,
-                        (x, y),
-                        Transform::Normal, /* TODO */
-                        1.0,
-                    ) {
+                    // the buffer scale and the viewport destination are part of the surface size
+                    let rect = (
+                        location.0 + x as f64 * scale,
+                        location.1 + y as f64 * scale,
+                        width as f64 * scale,
+                        height as f64 * scale,
+                    );
+                    let drawn = match source {
+                        // the whole buffer is stretched for the source rectangle to fill the
+                        // surface, and clipped to it
+                        Some((source_x, source_y, source_width, source_height))
+                            if source_width > 0.0 && source_height > 0.0 =>
+                        {
+                            let (buffer_width, buffer_height) =
+                                (texture.texture.width() as f64, texture.texture.height() as f64);
+                            let (buffer_width, buffer_height) = if swaps_axes(buffer_transform) {
+                                (buffer_height, buffer_width)
+                            } else {
+                                (buffer_width, buffer_height)
+                            };
+                            let scale_x = rect.2 / source_width;
+                            let scale_y = rect.3 / source_height;
+                            let buffer_rect = (
+                                rect.0 - source_x * scale_x,
+                                rect.1 - source_y * scale_y,
+                                buffer_width / buffer_scale * scale_x,
+                                buffer_height / buffer_scale * scale_y,
+                            );
+                            output.render_texture_clipped(
+                                frame,
+                                &texture.texture,
+                                buffer_rect,
+                                rect,
+                                buffer_transform,
+                            )
+                        }
+                        _ => output.render_texture(frame, &texture.texture, rect, buffer_transform),
+                    };
+                    if let Err(err) = drawn {
                         result = Err(err.into());
                     }
                 }
@@ -183,26 +444,23 @@ pub fn draw_windows<R, E, F, T>(
     frame: &mut F,
     egl_buffer_reader: Option<&EGLBufferReader>,
     window_map: &MyWindowMap,
-    output_rect: Option<Rectangle>,
+    output: &OutputView,
     compositor_token: MyCompositorToken,
     log: &::slog::Logger,
 ) -> Result<(), SwapBuffersError>
 where
-    R: Renderer<Error = E, TextureId = T, Frame = F>,
+    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma,
     F: Frame<Error = E, TextureId = T>,
     E: std::error::Error + Into<SwapBuffersError>,
     T: Texture + 'static,
 {
     let mut result = Ok(());
 
-    // redraw the frame, in a simple but inneficient way
-    window_map.with_windows_from_bottom_to_top(|toplevel_surface, mut initial_place, bounding_box| {
-        // skip windows that do not overlap with a given output
-        if let Some(output) = output_rect {
-            if !output.overlaps(bounding_box) {
-                return;
-            }
-            initial_place.0 -= output.x;
+    // the backends clip the drawing to the damage of the output
+    window_map.with_windows_from_bottom_to_top(|toplevel_surface, initial_place, bounding_box| {
+        // skip windows that do not overlap with the output
+        if !output.geometry.overlaps(bounding_box) {
+            return;
         }
         if let Some(wl_surface) = toplevel_surface.get_surface() {
             // this surface is a root of a subsurface tree that needs to be drawn
@@ -212,6 +470,7 @@ where
                 &wl_surface,
                 egl_buffer_reader,
                 initial_place,
+                output,
                 compositor_token,
                 log,
             ) {
@@ -223,17 +482,49 @@ where
     result
 }
 
+/// Draws the popups of the input methods, `popups` are surfaces with their global location.
+pub fn draw_input_popups<R, E, F, T>(
+    renderer: &mut R,
+    frame: &mut F,
+    egl_buffer_reader: Option<&EGLBufferReader>,
+    popups: &[(wl_surface::WlSurface, (i32, i32))],
+    output: &OutputView,
+    compositor_token: MyCompositorToken,
+    log: &::slog::Logger,
+) -> Result<(), SwapBuffersError>
+where
+    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma,
+    F: Frame<Error = E, TextureId = T>,
+    E: std::error::Error + Into<SwapBuffersError>,
+    T: Texture + 'static,
+{
+    for &(ref surface, location) in popups {
+        draw_surface_tree(
+            renderer,
+            frame,
+            surface,
+            egl_buffer_reader,
+            location,
+            output,
+            compositor_token,
+            log,
+        )?;
+    }
+    Ok(())
+}
+
 pub fn draw_dnd_icon<R, E, F, T>(
     renderer: &mut R,
     frame: &mut F,
     surface: &wl_surface::WlSurface,
     egl_buffer_reader: Option<&EGLBufferReader>,
     (x, y): (i32, i32),
+    output: &OutputView,
     token: MyCompositorToken,
     log: &::slog::Logger,
 ) -> Result<(), SwapBuffersError>
 where
-    R: Renderer<Error = E, TextureId = T, Frame = F>,
+    R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma,
     F: Frame<Error = E, TextureId = T>,
     E: std::error::Error + Into<SwapBuffersError>,
     T: Texture + 'static,
@@ -244,5 +535,5 @@ where
             "Trying to display as a dnd icon a surface that does not have the DndIcon role."
         );
     }
-    draw_surface_tree(renderer, frame, surface, egl_buffer_reader, (x, y), token, log)
+    draw_surface_tree(renderer, frame, surface, egl_buffer_reader, (x, y), output, token, log)
 }
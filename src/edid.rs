@@ -0,0 +1,57 @@
+/// The identification of a monitor, read from its EDID.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Edid {
+    pub make: String,
+    pub model: String,
+    pub serial: String,
+}
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const DESCRIPTOR_SERIAL: u8 = 0xff;
+const DESCRIPTOR_NAME: u8 = 0xfc;
+
+impl Edid {
+    /// Parses the base block of an EDID.
+    ///
+    /// The make is the three letter PNP id of the manufacturer, model and serial come from the
+    /// display descriptors and fall back to the numeric product code and serial number.
+    pub fn parse(data: &[u8]) -> Option<Edid> {
+        if data.len() < 128 || data[0..8] != HEADER {
+            return None;
+        }
+
+        let id = u16::from_be_bytes([data[8], data[9]]);
+        let make = [(id >> 10) & 0x1f, (id >> 5) & 0x1f, id & 0x1f]
+            .iter()
+            .map(|&letter| (b'A' - 1 + letter as u8) as char)
+            .collect();
+
+        let mut model = None;
+        let mut serial = None;
+        for descriptor in data[54..126].chunks(18) {
+            // display descriptors start with a zero pixel clock
+            if descriptor[0..3] != [0, 0, 0] {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&descriptor[5..18])
+                .split('\n')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            match descriptor[3] {
+                DESCRIPTOR_NAME => model = Some(text),
+                DESCRIPTOR_SERIAL => serial = Some(text),
+                _ => {}
+            }
+        }
+
+        let product = u16::from_le_bytes([data[10], data[11]]);
+        let serial_number = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+        Some(Edid {
+            make,
+            model: model.unwrap_or_else(|| format!("0x{:04X}", product)),
+            serial: serial.unwrap_or_else(|| format!("0x{:08X}", serial_number)),
+        })
+    }
+}
@@ -0,0 +1,143 @@
+//! The focus model: which window gets the keyboard focus on clicks, pointer motion and new
+//! windows, and when windows are raised.
+//!
+//! With `follows-mouse` and `sloppy` the window under the pointer gets the focus as the pointer
+//! moves, unless a grab or the window cycling is going on. Auto-raise raises such a window once
+//! the pointer rested on it for the delay, clicks raise windows in every model.
+//!
+//! New windows get the focus as they first show something, unless the stealing prevention holds
+//! them back: while the user types into a window of another client on any seat, a new window does
+//! not take the focus from it. Keys the compositor handles, like a binding starting the new
+//! window, do not count as typing.
+
+use std::time::Duration;
+
+use smithay::{
+    reexports::wayland_server::protocol::wl_surface::WlSurface, wayland::SERIAL_COUNTER as SCOUNTER,
+};
+
+use crate::{
+    custom::config::FocusModel,
+    seat::MySeat,
+    shell::Roles,
+    state::AnvilState,
+    window_map::{Kind, WindowId},
+};
+
+/// How long after the last key sent to a client a new window counts as stealing the focus.
+const STEALING_GRACE: Duration = Duration::from_secs(1);
+
+impl AnvilState {
+    /// The window with the keyboard focus of a seat.
+    pub fn seat_focus(&self, seat: &MySeat) -> Option<Kind<Roles>> {
+        let focus = seat.keyboard_focus.borrow().clone()?;
+        self.window_of(&self.window_map.borrow(), &focus)
+    }
+
+    /// Focuses and raises the window clicked with the pointer of a seat, as the model says.
+    pub fn focus_on_click(&mut self, seat: &MySeat) {
+        let options = self.config.focus;
+        let location = seat.pointer_location();
+        let under = if options.click_raises {
+            self.window_map.borrow_mut().get_surface_and_bring_to_top(location)
+        } else {
+            self.window_map.borrow().get_surface_under(location)
+        };
+        // the pointer models gave the focus already, sloppy keeps it over the desktop
+        if under.is_some() || options.model != FocusModel::Sloppy {
+            seat.keyboard
+                .set_focus(under.as_ref().map(|&(ref s, _)| s), SCOUNTER.next_serial());
+        }
+    }
+
+    /// Gives the focus of a seat to the window under its pointer, for the pointer models.
+    pub fn focus_on_motion(&mut self, seat: &MySeat) {
+        let options = self.config.focus;
+        if options.model == FocusModel::Click || seat.pointer.is_grabbed() || self.window_cycle.is_some() {
+            return;
+        }
+        let under = {
+            let window_map = self.window_map.borrow();
+            window_map
+                .get_surface_under(seat.pointer_location())
+                .and_then(|(surface, _)| self.window_of(&window_map, &surface))
+        };
+        let focused = self.seat_focus(seat);
+        match (under, focused) {
+            (Some(under), Some(focused)) if under.equals(&focused) => {}
+            (Some(under), _) => {
+                seat.keyboard.set_focus(under.get_surface(), SCOUNTER.next_serial());
+                let id = self.window_map.borrow().id(&under);
+                self.schedule_auto_raise(id.filter(|_| options.auto_raise));
+            }
+            (None, Some(_)) if options.model == FocusModel::FollowsMouse => {
+                seat.keyboard.set_focus(None, SCOUNTER.next_serial());
+                self.schedule_auto_raise(None);
+            }
+            (None, _) => self.schedule_auto_raise(None),
+        }
+    }
+
+    /// Raises the window once the pointer rested on it for the delay, forgetting the window
+    /// waiting before.
+    fn schedule_auto_raise(&mut self, id: Option<WindowId>) {
+        if let Some(timeout) = self.auto_raise.take() {
+            self.auto_raise_timer.cancel_timeout(&timeout);
+        }
+        if let Some(id) = id {
+            let delay = Duration::from_millis(self.config.focus.auto_raise_delay);
+            self.auto_raise = Some(self.auto_raise_timer.add_timeout(delay, id));
+        }
+    }
+
+    /// Raises the window the auto-raise delay passed for.
+    pub fn auto_raise_window(&mut self, id: WindowId) {
+        self.auto_raise = None;
+        let mut window_map = self.window_map.borrow_mut();
+        if let Some(toplevel) = window_map.find_by_id(id) {
+            window_map.bring_to_top(&toplevel);
+        }
+    }
+
+    /// Whether a new window would take the focus from a window the user is typing into on any
+    /// of the seats.
+    fn steals_focus(&self, surface: &WlSurface) -> bool {
+        self.seats.iter().any(|seat| {
+            let typing = seat
+                .last_typed
+                .get()
+                .map_or(false, |typed| typed.elapsed() < STEALING_GRACE);
+            // the dialogs of the app the user is typing into are fine
+            typing
+                && seat
+                    .keyboard_focus
+                    .borrow()
+                    .as_ref()
+                    .map_or(false, |focus| !focus.as_ref().same_client_as(surface.as_ref()))
+        })
+    }
+
+    /// Gives the focus to the windows mapped since the last call, as the options say.
+    pub fn focus_new_windows(&mut self) {
+        let new_windows = self.window_map.borrow_mut().take_mapped_windows();
+        let options = self.config.focus;
+        if !options.focus_new_windows {
+            return;
+        }
+        for id in new_windows {
+            let toplevel = match self.window_map.borrow().find_by_id(id) {
+                Some(toplevel) if toplevel.alive() => toplevel,
+                _ => continue,
+            };
+            let surface = match toplevel.get_surface() {
+                Some(surface) => surface.clone(),
+                None => continue,
+            };
+            if options.prevent_stealing && self.steals_focus(&surface) {
+                info!(self.log, "Not focusing a new window while the user types"; "window" => id);
+                continue;
+            }
+            self.focus_window(&toplevel);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::{cell::RefCell, rc::Rc};
+
+use smithay::reexports::wayland_server::{protocol::wl_surface::WlSurface, Display, Filter, Global, Main};
+
+use self::protocol::{
+    wp_fractional_scale_manager_v1::{self, WpFractionalScaleManagerV1},
+    wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+};
+
+/// The code generated from `protocols/fractional-scale-v1.xml` by the build script.
+#[allow(
+    dead_code,
+    non_camel_case_types,
+    unused_unsafe,
+    unused_variables,
+    non_upper_case_globals,
+    non_snake_case,
+    unused_imports,
+    missing_docs,
+    clippy::all
+)]
+pub mod protocol {
+    pub(crate) use smithay::reexports::wayland_server::{
+        protocol::wl_surface, AnonymousObject, Main, Resource, ResourceMap,
+    };
+    pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+    pub(crate) use wayland_commons::smallvec;
+    pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+    pub(crate) use wayland_commons::{Interface, MessageGroup};
+    pub(crate) use wayland_sys as sys;
+    include!(concat!(env!("OUT_DIR"), "/fractional_scale_v1.rs"));
+}
+
+struct FractionalScale {
+    fractional_scale: WpFractionalScaleV1,
+    surface: WlSurface,
+    /// The last scale sent, in 120ths
+    scale: Option<u32>,
+}
+
+/// The surfaces that want to know the fractional scale they should render at.
+#[derive(Default)]
+pub struct FractionalScales {
+    scales: Vec<FractionalScale>,
+}
+
+impl FractionalScales {
+    /// Sends the preferred scale to every surface whose scale changed.
+    pub fn update<F>(&mut self, scale_of: F)
+    where
+        F: Fn(&WlSurface) -> f64,
+    {
+        self.scales.retain(|scale| {
+            scale.fractional_scale.as_ref().is_alive() && scale.surface.as_ref().is_alive()
+        });
+        for scale in &mut self.scales {
+            let preferred = (scale_of(&scale.surface) * 120.0).round() as u32;
+            if scale.scale != Some(preferred) {
+                scale.scale = Some(preferred);
+                scale.fractional_scale.preferred_scale(preferred);
+            }
+        }
+    }
+
+    fn has_scale(&self, surface: &WlSurface) -> bool {
+        self.scales.iter().any(|scale| {
+            scale.fractional_scale.as_ref().is_alive() && scale.surface.as_ref().equals(surface.as_ref())
+        })
+    }
+}
+
+pub fn init_fractional_scale_manager(
+    display: &mut Display,
+    log: ::slog::Logger,
+) -> (Rc<RefCell<FractionalScales>>, Global<WpFractionalScaleManagerV1>) {
+    let scales = Rc::new(RefCell::new(FractionalScales::default()));
+
+    let manager_scales = scales.clone();
+    let global = display.create_global::<WpFractionalScaleManagerV1, _>(
+        1,
+        Filter::new(move |(manager, _version): (Main<WpFractionalScaleManagerV1>, u32), _, _| {
+            let scales = manager_scales.clone();
+            let log = log.clone();
+            manager.quick_assign(move |manager, request, _| match request {
+                wp_fractional_scale_manager_v1::Request::GetFractionalScale { id, surface } => {
+                    let mut scales = scales.borrow_mut();
+                    if scales.has_scale(&surface) {
+                        manager.as_ref().post_error(
+                            wp_fractional_scale_manager_v1::Error::FractionalScaleExists.to_raw(),
+                            "The surface already has a fractional scale object.".into(),
+                        );
+                        return;
+                    }
+                    trace!(log, "New fractional scale");
+
+                    id.quick_assign(|_, request, _| match request {
+                        wp_fractional_scale_v1::Request::Destroy => {}
+                        _ => unreachable!(),
+                    });
+                    // the scale is sent with the next update, once the surface is mapped
+                    scales.scales.push(FractionalScale {
+                        fractional_scale: (*id).clone(),
+                        surface,
+                        scale: None,
+                    });
+                }
+                wp_fractional_scale_manager_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            });
+        }),
+    );
+
+    (scales, global)
+}
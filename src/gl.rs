@@ -0,0 +1,287 @@
+//! The gl calls the renderer of smithay does not offer, made on the context it draws a frame with.
+//!
+//! The frames of the renderer only draw whole textures, the scissor test clips them to a part of
+//! the framebuffer. The clip is in pixels of the framebuffer, counted from its bottom left as gl
+//! does. The age of the buffer a frame is drawn into comes from egl for the window of a host, and
+//! from the framebuffer object the renderer bound for the buffers of a DRM surface.
+//!
+//! An offscreen texture is drawn into by the frame with a framebuffer object of its own and a
+//! viewport moving its place in the frame to the texture. As the renderer can not draw a texture
+//! it did not create, the texture is drawn into the frame by a program of its own.
+
+use std::{cell::Cell, os::raw::c_void, ptr};
+
+use smithay::{backend::egl::get_proc_address, utils::Rectangle};
+
+use crate::damage::intersection;
+
+/// The code generated by the build script.
+#[allow(clippy::all, non_upper_case_globals, non_snake_case, dead_code, missing_docs)]
+mod ffi {
+    include!(concat!(env!("OUT_DIR"), "/gl_bindings.rs"));
+}
+
+thread_local! {
+    // the functions of the gles library are the same for all contexts
+    static GL: ffi::Gles2 = ffi::Gles2::load_with(|symbol| unsafe { get_proc_address(symbol) });
+    static CLIP: Cell<Option<Rectangle>> = Cell::new(None);
+    // where the offscreen texture being drawn into is in the frame
+    static ORIGIN: Cell<(i32, i32)> = Cell::new((0, 0));
+}
+
+/// Restricts the drawing to a rectangle of the framebuffer, or lifts the restriction.
+pub fn set_clip(clip: Option<Rectangle>) {
+    CLIP.with(|current| current.set(clip));
+    let (x, y) = ORIGIN.with(Cell::get);
+    GL.with(|gl| unsafe {
+        match clip {
+            Some(clip) => {
+                gl.Enable(ffi::SCISSOR_TEST);
+                gl.Scissor(clip.x - x, clip.y - y, clip.width.max(0), clip.height.max(0));
+            }
+            None => gl.Disable(ffi::SCISSOR_TEST),
+        }
+    });
+}
+
+/// The framebuffer object the frame is drawn into, 0 for the window surface of egl.
+pub fn framebuffer() -> u32 {
+    let mut framebuffer = 0;
+    GL.with(|gl| unsafe { gl.GetIntegerv(ffi::FRAMEBUFFER_BINDING, &mut framebuffer) });
+    framebuffer as u32
+}
+
+const EGL_DRAW: i32 = 0x3059;
+const EGL_BUFFER_AGE_EXT: i32 = 0x313D;
+
+/// The age of the back buffer of the egl window surface the frame is drawn into, as
+/// `EGL_EXT_buffer_age` defines it, `None` if egl does not know it.
+pub fn egl_buffer_age() -> Option<usize> {
+    type GetCurrentDisplay = unsafe extern "system" fn() -> *mut c_void;
+    type GetCurrentSurface = unsafe extern "system" fn(i32) -> *mut c_void;
+    type QuerySurface = unsafe extern "system" fn(*mut c_void, *mut c_void, i32, *mut i32) -> u32;
+
+    unsafe {
+        let get_current_display = get_proc_address("eglGetCurrentDisplay");
+        let get_current_surface = get_proc_address("eglGetCurrentSurface");
+        let query_surface = get_proc_address("eglQuerySurface");
+        if get_current_display.is_null() || get_current_surface.is_null() || query_surface.is_null() {
+            return None;
+        }
+        let get_current_display: GetCurrentDisplay = std::mem::transmute(get_current_display);
+        let get_current_surface: GetCurrentSurface = std::mem::transmute(get_current_surface);
+        let query_surface: QuerySurface = std::mem::transmute(query_surface);
+
+        let (display, surface) = (get_current_display(), get_current_surface(EGL_DRAW));
+        if display.is_null() || surface.is_null() {
+            return None;
+        }
+        let mut age = 0;
+        // fails without the extension
+        if query_surface(display, surface, EGL_BUFFER_AGE_EXT, &mut age) == 0 {
+            return None;
+        }
+        Some(age.max(0) as usize)
+    }
+}
+
+/// Draws with the drawing restricted to the part of `clip` within the current restriction.
+pub fn with_clip<T>(clip: Rectangle, draw: impl FnOnce() -> T) -> T {
+    let previous = CLIP.with(|current| current.get());
+    set_clip(Some(match previous {
+        Some(previous) => intersection(previous, clip),
+        None => clip,
+    }));
+    let result = draw();
+    set_clip(previous);
+    result
+}
+
+const VERTEX_SOURCE: &[u8] = b"
+attribute vec2 position;
+varying vec2 coords;
+void main() {
+    coords = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+\0";
+
+const FRAGMENT_SOURCE: &[u8] = b"
+precision mediump float;
+uniform sampler2D tex;
+varying vec2 coords;
+void main() {
+    gl_FragColor = texture2D(tex, coords);
+}
+\0";
+
+/// The corners of the viewport, as a triangle strip.
+static QUAD: [f32; 8] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+
+/// The program drawing offscreen textures into the viewport, usable by the contexts sharing the
+/// one it was compiled with.
+pub struct TextureProgram {
+    program: u32,
+    texture: i32,
+}
+
+impl TextureProgram {
+    /// Compiles the program, `None` if the driver rejects it.
+    pub fn new() -> Option<TextureProgram> {
+        GL.with(|gl| unsafe {
+            let vertex = compile(gl, ffi::VERTEX_SHADER, VERTEX_SOURCE)?;
+            let fragment = match compile(gl, ffi::FRAGMENT_SHADER, FRAGMENT_SOURCE) {
+                Some(fragment) => fragment,
+                None => {
+                    gl.DeleteShader(vertex);
+                    return None;
+                }
+            };
+            let program = gl.CreateProgram();
+            gl.AttachShader(program, vertex);
+            gl.AttachShader(program, fragment);
+            gl.BindAttribLocation(program, 0, b"position\0".as_ptr() as *const _);
+            gl.LinkProgram(program);
+            gl.DeleteShader(vertex);
+            gl.DeleteShader(fragment);
+            let mut linked = 0;
+            gl.GetProgramiv(program, ffi::LINK_STATUS, &mut linked);
+            if linked == 0 {
+                gl.DeleteProgram(program);
+                return None;
+            }
+            let texture = gl.GetUniformLocation(program, b"tex\0".as_ptr() as *const _);
+            Some(TextureProgram { program, texture })
+        })
+    }
+}
+
+unsafe fn compile(gl: &ffi::Gles2, kind: u32, source: &[u8]) -> Option<u32> {
+    let shader = gl.CreateShader(kind);
+    let source = source.as_ptr() as *const ffi::types::GLchar;
+    gl.ShaderSource(shader, 1, &source, ptr::null());
+    gl.CompileShader(shader);
+    let mut compiled = 0;
+    gl.GetShaderiv(shader, ffi::COMPILE_STATUS, &mut compiled);
+    if compiled == 0 {
+        gl.DeleteShader(shader);
+        return None;
+    }
+    Some(shader)
+}
+
+/// A texture drawn into apart from the frames, with premultiplied alpha.
+///
+/// It is shared by the contexts sharing the one it was created with, it has to be destroyed with
+/// one of them current.
+pub struct Offscreen {
+    texture: u32,
+    /// The size in pixels
+    pub size: (i32, i32),
+}
+
+impl Offscreen {
+    /// Creates a texture of `size` pixels.
+    pub fn new((width, height): (i32, i32)) -> Offscreen {
+        GL.with(|gl| unsafe {
+            let mut texture = 0;
+            gl.GenTextures(1, &mut texture);
+            gl.BindTexture(ffi::TEXTURE_2D, texture);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MIN_FILTER, ffi::LINEAR as i32);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_MAG_FILTER, ffi::LINEAR as i32);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_S, ffi::CLAMP_TO_EDGE as i32);
+            gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_T, ffi::CLAMP_TO_EDGE as i32);
+            gl.TexImage2D(
+                ffi::TEXTURE_2D,
+                0,
+                ffi::RGBA as i32,
+                width.max(1),
+                height.max(1),
+                0,
+                ffi::RGBA,
+                ffi::UNSIGNED_BYTE,
+                ptr::null(),
+            );
+            gl.BindTexture(ffi::TEXTURE_2D, 0);
+            Offscreen {
+                texture,
+                size: (width, height),
+            }
+        })
+    }
+
+    /// Replaces the content of the texture with what `draw` draws into the frame at `place`, in
+    /// pixels of the framebuffer as gl counts them. The texture has the size of `place`.
+    ///
+    /// The drawing is not restricted to the clip of the frame.
+    pub fn draw_into<T>(&self, place: Rectangle, draw: impl FnOnce() -> T) -> T {
+        let clip = CLIP.with(Cell::get);
+        let (framebuffer, previous, viewport) = GL.with(|gl| unsafe {
+            let mut previous = 0;
+            gl.GetIntegerv(ffi::FRAMEBUFFER_BINDING, &mut previous);
+            let mut viewport = [0; 4];
+            gl.GetIntegerv(ffi::VIEWPORT, viewport.as_mut_ptr());
+            // framebuffer objects are not shared between contexts, one is made for every drawing
+            let mut framebuffer = 0;
+            gl.GenFramebuffers(1, &mut framebuffer);
+            gl.BindFramebuffer(ffi::FRAMEBUFFER, framebuffer);
+            gl.FramebufferTexture2D(
+                ffi::FRAMEBUFFER,
+                ffi::COLOR_ATTACHMENT0,
+                ffi::TEXTURE_2D,
+                self.texture,
+                0,
+            );
+            // the frame is drawn as before, shifted for its place to be at the origin
+            gl.Viewport(
+                viewport[0] - place.x,
+                viewport[1] - place.y,
+                viewport[2],
+                viewport[3],
+            );
+            (framebuffer, previous as u32, viewport)
+        });
+        ORIGIN.with(|origin| origin.set((place.x, place.y)));
+        set_clip(None);
+        GL.with(|gl| unsafe {
+            gl.ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl.Clear(ffi::COLOR_BUFFER_BIT);
+        });
+
+        let result = draw();
+
+        ORIGIN.with(|origin| origin.set((0, 0)));
+        GL.with(|gl| unsafe {
+            gl.BindFramebuffer(ffi::FRAMEBUFFER, previous);
+            gl.Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            gl.DeleteFramebuffers(1, &framebuffer);
+        });
+        set_clip(clip);
+        result
+    }
+
+    /// Draws the texture stretched over `place` of the frame, in pixels of the framebuffer as gl
+    /// counts them, within the clip of the frame.
+    pub fn draw(&self, program: &TextureProgram, place: Rectangle) {
+        GL.with(|gl| unsafe {
+            let mut viewport = [0; 4];
+            gl.GetIntegerv(ffi::VIEWPORT, viewport.as_mut_ptr());
+            gl.Viewport(place.x, place.y, place.width.max(0), place.height.max(0));
+            gl.UseProgram(program.program);
+            gl.ActiveTexture(ffi::TEXTURE0);
+            gl.BindTexture(ffi::TEXTURE_2D, self.texture);
+            gl.Uniform1i(program.texture, 0);
+            gl.VertexAttribPointer(0, 2, ffi::FLOAT, ffi::FALSE, 0, QUAD.as_ptr() as *const _);
+            gl.EnableVertexAttribArray(0);
+            gl.DrawArrays(ffi::TRIANGLE_STRIP, 0, 4);
+            gl.DisableVertexAttribArray(0);
+            gl.BindTexture(ffi::TEXTURE_2D, 0);
+            gl.Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        });
+    }
+
+    /// Frees the texture.
+    pub fn destroy(self) {
+        GL.with(|gl| unsafe { gl.DeleteTextures(1, &self.texture) });
+    }
+}
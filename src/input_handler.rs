@@ -1,30 +1,63 @@
 use crate::custom::config::KeyAction;
-use std::{process::Command, sync::atomic::Ordering};
+use std::{cell::RefCell, process::Command, sync::atomic::Ordering, time::Instant};
 
-use crate::AnvilState;
+use crate::{pointer_constraints::ConstraintKind, seat::MySeat, shell::SurfaceData, AnvilState};
 
 #[cfg(feature = "udev")]
-use smithay::backend::session::Session;
+use {
+    crate::drawing::OutputView,
+    smithay::backend::{renderer::Transform, session::Session},
+};
 use smithay::{
     backend::input::{
         self, Event, InputBackend, InputEvent, KeyState, KeyboardKeyEvent, PointerAxisEvent,
-        PointerButtonEvent, PointerMotionAbsoluteEvent, PointerMotionEvent,
+        PointerButtonEvent, PointerMotionAbsoluteEvent, PointerMotionEvent, UnusedEvent,
     },
-    reexports::wayland_server::protocol::wl_pointer,
+    reexports::wayland_server::protocol::{wl_pointer, wl_surface::WlSurface},
     wayland::{
         seat::{keysyms as xkb, AxisFrame, Keysym, ModifiersState},
         SERIAL_COUNTER as SCOUNTER,
     },
 };
 
+/// Access to the deltas of a relative motion before pointer acceleration got applied.
+///
+/// Backends that do not provide them report the accelerated deltas.
+pub trait UnacceleratedMotion {
+    fn delta_unaccel(&self) -> (f64, f64);
+}
+
+impl UnacceleratedMotion for UnusedEvent {
+    fn delta_unaccel(&self) -> (f64, f64) {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "udev")]
+impl UnacceleratedMotion for smithay::reexports::input::event::pointer::PointerMotionEvent {
+    fn delta_unaccel(&self) -> (f64, f64) {
+        (self.dx_unaccelerated(), self.dy_unaccelerated())
+    }
+}
+
 impl AnvilState {
-    pub fn process_input_event<B: InputBackend>(&mut self, event: InputEvent<B>) {
+    /// Processes an input event, `device` is the name of the device it originates from, which
+    /// decides the seat handling it.
+    pub fn process_input_event<B: InputBackend>(&mut self, event: InputEvent<B>, device: Option<&str>)
+    where
+        B::PointerMotionEvent: UnacceleratedMotion,
+    {
+        self.input_activity();
+        let index = self.seat_for_device(device);
+        let seat = self.seats[index].clone();
         match event {
-            InputEvent::Keyboard { event, .. } => self.on_keyboard_key::<B>(event),
-            InputEvent::PointerMotion { event, .. } => self.on_pointer_move::<B>(event),
-            InputEvent::PointerMotionAbsolute { event, .. } => self.on_pointer_move_absolute::<B>(event),
-            InputEvent::PointerButton { event, .. } => self.on_pointer_button::<B>(event),
-            InputEvent::PointerAxis { event, .. } => self.on_pointer_axis::<B>(event),
+            InputEvent::Keyboard { event, .. } => self.on_keyboard_key::<B>(&seat, index, event),
+            InputEvent::PointerMotion { event, .. } => self.on_pointer_move::<B>(&seat, event),
+            InputEvent::PointerMotionAbsolute { event, .. } => {
+                self.on_pointer_move_absolute::<B>(&seat, event)
+            }
+            InputEvent::PointerButton { event, .. } => self.on_pointer_button::<B>(&seat, event),
+            InputEvent::PointerAxis { event, .. } => self.on_pointer_axis::<B>(&seat, event),
             _ => {
                 // other events are not handled in anvil (yet)
             }
@@ -32,7 +65,36 @@ impl AnvilState {
     }
 
 
-    fn process_keyboard_shortcut(&self, modifiers: ModifiersState, keysym: Keysym) -> KeyAction {
+    fn process_keyboard_shortcut(
+        &self,
+        seat: &MySeat,
+        index: usize,
+        keycode: u32,
+        modifiers: ModifiersState,
+        keysym: Keysym,
+        state: KeyState,
+    ) -> KeyAction {
+        // the keys of the other seats are handled as usual while a seat cycles or moves a window
+        if let Some(cycle) = self.window_cycle.as_ref().filter(|cycle| cycle.seat == index) {
+            let binding = self.config.key_bindings.process_keyboard_shortcut(modifiers, keysym);
+            return cycle.key_action(modifiers, keysym, matches!(state, KeyState::Pressed), binding);
+        }
+        if let Some(keyboard_move) = self.keyboard_move.as_ref().filter(|moving| moving.seat == index) {
+            let pressed = matches!(state, KeyState::Pressed);
+            return keyboard_move.key_action(modifiers, keysym, pressed, self.config.keyboard_move_step);
+        }
+
+        // the script sees the presses before the key bindings
+        #[cfg(feature = "scripting")]
+        {
+            if matches!(state, KeyState::Pressed) && self.script_key(modifiers, keysym) {
+                seat.script_keys.borrow_mut().push(keycode);
+                return KeyAction::None;
+            }
+        }
+        #[cfg(not(feature = "scripting"))]
+        let _ = (seat, keycode);
+
         let action = self.config.key_bindings.process_keyboard_shortcut(modifiers, keysym);
     
         if action != KeyAction::Forward {
@@ -59,7 +121,8 @@ impl AnvilState {
     }
     
 
-    fn on_keyboard_key<B: InputBackend>(&mut self, evt: B::KeyboardKeyEvent) {
+    /// Processes a key of a seat, `index` is the index of the seat.
+    fn on_keyboard_key<B: InputBackend>(&mut self, seat: &MySeat, index: usize, evt: B::KeyboardKeyEvent) {
         let keycode = evt.key_code();
         let state = evt.state();
         debug!(self.log, "key"; "keycode" => keycode, "state" => format!("{:?}", state));
@@ -67,18 +130,51 @@ impl AnvilState {
         let log = &self.log;
         let time = Event::time(&evt);
         let mut action = KeyAction::None;
-        self.keyboard
+        let mut held = ModifiersState::default();
+        let (inhibited, escapable) = {
+            let inhibitors = seat.shortcuts_inhibitors.borrow();
+            (inhibitors.is_active(), inhibitors.has_focused_inhibitor())
+        };
+        seat.keyboard
             .input(keycode, state, serial, time, |modifiers, keysym| {
                 debug!(log, "keysym";
                     "state" => format!("{:?}", state),
                     "mods" => format!("{:?}", modifiers),
                     "keysym" => ::xkbcommon::xkb::keysym_get_name(keysym)
                 );
-                action = self.process_keyboard_shortcut(*modifiers, keysym);
+                held = *modifiers;
+                action = if matches!(state, KeyState::Released) && seat.release_script_key(keycode) {
+                    // the client did not see the press the script handled, whatever started since
+                    KeyAction::None
+                } else if escapable && self.config.shortcuts_inhibit.is_escape(*modifiers, keysym) {
+                    KeyAction::ToggleShortcutsInhibit
+                } else if inhibited {
+                    // the focused client gets every key, except the escape binding
+                    KeyAction::Forward
+                } else {
+                    self.process_keyboard_shortcut(seat, index, keycode, *modifiers, keysym, state)
+                };
                 // forward to client only if action == KeyAction::Forward
                 // both for pressed and released, to avoid inconsistencies
-                matches!(action, KeyAction::Forward)
+                // the release of the modifier ending the window cycling goes to the client that
+                // saw it pressed, before the focus changes
+                // while the input method grabs the keyboard, it gets them instead of the client
+                matches!(action, KeyAction::Forward | KeyAction::FinishWindowCycle)
+                    && !seat
+                        .input_method
+                        .borrow_mut()
+                        .grabbed_key(serial, time, keycode, state)
             });
+        #[cfg(feature = "scripting")]
+        self.run_script_commands();
+        if let (KeyAction::Forward, KeyState::Pressed) = (&action, state) {
+            seat.last_typed.set(Some(Instant::now()));
+        }
+        if let KeyAction::FinishWindowCycle = action {
+            // the cycling ends with a release
+            self.finish_window_cycle();
+            return;
+        }
         if let KeyState::Released = state {
             // only process special actions on key press, not release
             return;
@@ -112,127 +208,253 @@ impl AnvilState {
                 let output_map = self.output_map.as_ref().unwrap();
                 let outputs = output_map.borrow();
                 if let Some(output) = outputs.get(num) {
-                    let x = outputs
-                        .iter()
-                        .take(num)
-                        .fold(0, |acc, output| acc + output.size.0) as f64
-                        + (output.size.0 as f64 / 2.0);
-                    let y = output.size.1 as f64 / 2.0;
-                    *self.pointer_location.borrow_mut() = (x as f64, y as f64)
+                    let geometry = output.geometry();
+                    let x = geometry.x as f64 + geometry.width as f64 / 2.0;
+                    let y = geometry.y as f64 + geometry.height as f64 / 2.0;
+                    seat.set_pointer_location((x, y));
                 }
             }
+            #[cfg(feature = "scripting")]
+            KeyAction::Script(name) => self.run_script_action(&name),
+            #[cfg(not(feature = "scripting"))]
+            KeyAction::Script(name) => {
+                warn!(self.log, "Waybox was built without scripting, there is no action {}", name);
+            }
+            KeyAction::NextWindow => self.cycle_windows(index, held, true),
+            KeyAction::PreviousWindow => self.cycle_windows(index, held, false),
+            KeyAction::CancelWindowCycle => self.cancel_window_cycle(),
+            KeyAction::MoveWindow(dx, dy) => self.move_window_by(seat, (dx, dy)),
+            KeyAction::ResizeWindow(edges, amount) => self.resize_window_by(seat, edges, amount),
+            KeyAction::KeyboardMove => self.start_keyboard_move(seat, index, false),
+            KeyAction::KeyboardResize => self.start_keyboard_move(seat, index, true),
+            KeyAction::KeyboardMoveStep(dx, dy) => self.keyboard_move_step((dx, dy)),
+            KeyAction::FinishKeyboardMove => self.finish_keyboard_move(),
+            KeyAction::CancelKeyboardMove => self.cancel_keyboard_move(),
+            KeyAction::ToggleShortcutsInhibit => {
+                info!(self.log, "Toggling the keyboard shortcuts inhibitor"; "seat" => seat.name.clone());
+                seat.shortcuts_inhibitors.borrow_mut().toggle_suspended();
+            }
             _ => (),
         }
     }
 
-    fn on_pointer_move<B: InputBackend>(&mut self, evt: B::PointerMotionEvent) {
+    fn on_pointer_move<B: InputBackend>(&mut self, seat: &MySeat, evt: B::PointerMotionEvent)
+    where
+        B::PointerMotionEvent: UnacceleratedMotion,
+    {
         let (x, y) = (evt.delta_x(), evt.delta_y());
         let serial = SCOUNTER.next_serial();
-        let mut location = self.pointer_location.borrow_mut();
-        location.0 += x as f64;
-        location.1 += y as f64;
+        let current = seat.pointer_location();
+
+        // relative motion is not affected by constraints or screen limits
+        if let Some((surface, _)) = seat.pointer_focus.borrow().as_ref() {
+            seat.relative_pointers.borrow_mut().relative_motion(
+                surface,
+                evt.time() as u64 * 1000,
+                (x, y),
+                evt.delta_unaccel(),
+            );
+        }
+
+        #[allow(unused_mut)]
+        let mut location = (current.0 + x, current.1 + y);
 
         #[cfg(feature = "udev")]
         {
             // clamp to screen limits
             // this event is never generated by winit
-            *location = self.clamp_coords(*location);
+            location = self.clamp_coords(location);
         }
 
-        let under = self
-            .window_map
-            .borrow()
-            .get_surface_under((location.0, location.1));
-        self.pointer.motion(*location, under, serial, evt.time());
+        let location = match self.constrain_pointer(seat, current, location) {
+            Some(location) => location,
+            // the pointer is locked
+            None => return,
+        };
+        seat.set_pointer_location(location);
+        #[cfg(feature = "switcher")]
+        self.point_switcher(location);
+
+        self.focus_on_motion(seat);
+        let under = self.pointer_surface_under(seat, location);
+        seat.pointer_motion(location, under, serial, evt.time());
+        self.update_pointer_constraint(seat);
     }
 
-    fn on_pointer_move_absolute<B: InputBackend>(&mut self, evt: B::PointerMotionAbsoluteEvent) {
+    /// Maps an absolute motion in the winit window onto the logical size of its output.
+    fn window_position<B: InputBackend>(&self, evt: &B::PointerMotionAbsoluteEvent) -> (f64, f64) {
+        match self.winit_output {
+            Some(view) => evt.position_transformed((view.geometry.width as u32, view.geometry.height as u32)),
+            None => evt.position(),
+        }
+    }
+
+    fn on_pointer_move_absolute<B: InputBackend>(&mut self, seat: &MySeat, evt: B::PointerMotionAbsoluteEvent) {
         // different cases depending on the context:
         let (x, y) = {
             #[cfg(feature = "udev")]
             {
-                if self.session.is_some() {
-                    // we are started on a tty
-                    let x = self.pointer_location.borrow().0;
-                    let screen_size = self.current_output_size(x);
-                    // monitor coordinates
-                    let (ux, uy) = evt.position_transformed(screen_size);
-                    (ux + self.current_output_offset(x) as f64, uy as f64)
+                if self.output_map.is_some() {
+                    // we are started on a tty or a raw drm device
+                    let view = self.current_output_view(seat.pointer_location());
+                    // the device reports positions on the monitor, before the output transform
+                    let size = view.framebuffer_size();
+                    view.map_from_framebuffer(evt.position_transformed(size))
                 } else {
                     // we are started in winit
-                    evt.position()
+                    self.window_position::<B>(&evt)
                 }
             }
             #[cfg(not(feature = "udev"))]
             {
-                evt.position()
+                self.window_position::<B>(&evt)
             }
         };
-        *self.pointer_location.borrow_mut() = (x, y);
+        let current = seat.pointer_location();
+        let (x, y) = match self.constrain_pointer(seat, current, (x, y)) {
+            Some(location) => location,
+            // the pointer is locked
+            None => return,
+        };
+        seat.set_pointer_location((x, y));
+        #[cfg(feature = "switcher")]
+        self.point_switcher((x, y));
         let serial = SCOUNTER.next_serial();
-        let under = self.window_map.borrow().get_surface_under((x as f64, y as f64));
-        self.pointer.motion((x, y), under, serial, evt.time());
+        self.focus_on_motion(seat);
+        let under = self.pointer_surface_under(seat, (x, y));
+        seat.pointer_motion((x, y), under, serial, evt.time());
+        self.update_pointer_constraint(seat);
     }
 
-    #[cfg(feature = "udev")]
-    fn clamp_coords(&self, pos: (f64, f64)) -> (f64, f64) {
-        let output_map = self.output_map.as_ref().unwrap();
-        let outputs = output_map.borrow();
+    /// Applies the pointer constraint active on `seat` to a motion from `current` to `target`.
+    ///
+    /// Returns the allowed location, or `None` if the pointer is locked.
+    fn constrain_pointer(
+        &self,
+        seat: &MySeat,
+        current: (f64, f64),
+        target: (f64, f64),
+    ) -> Option<(f64, f64)> {
+        let constraints = self.pointer_constraints.borrow();
+        let active = seat.pointer_constraint.borrow();
+        let constraint = match active.as_ref().and_then(|kind| constraints.get(kind)) {
+            Some(constraint) => constraint,
+            None => return Some(target),
+        };
+        if let ConstraintKind::Locked(_) = constraint.kind {
+            return None;
+        }
+
+        // the region of the surface itself, whatever is stacked above it
+        let (origin_x, origin_y) = constraint.origin;
+        let inside = |point: (f64, f64)| {
+            let local = (point.0 - origin_x, point.1 - origin_y);
+            constraint.contains_point(local)
+                && self
+                    .ctoken
+                    .with_surface_data(&constraint.surface, |attributes| {
+                        attributes
+                            .user_data
+                            .get::<RefCell<SurfaceData>>()
+                            .map_or(false, |data| data.borrow().contains_point(local))
+                    })
+        };
 
-        if outputs.len() == 0 {
-            return pos;
+        // slide along the border of the region instead of stopping dead
+        Some(
+            [target, (target.0, current.1), (current.0, target.1)]
+                .iter()
+                .copied()
+                .find(|point| inside(*point))
+                .unwrap_or(current),
+        )
+    }
+
+    /// The surface under the pointer of `seat` at `location` with its origin, the constrained
+    /// surface keeps the pointer while its constraint is active on the seat.
+    fn pointer_surface_under(&self, seat: &MySeat, location: (f64, f64)) -> Option<(WlSurface, (f64, f64))> {
+        let constraints = self.pointer_constraints.borrow();
+        let active = seat.pointer_constraint.borrow();
+        if let Some(constraint) = active.as_ref().and_then(|kind| constraints.get(kind)) {
+            return Some((constraint.surface.clone(), constraint.origin));
         }
+        self.window_map.borrow().get_surface_under(location)
+    }
 
-        let (mut x, mut y) = pos;
-        // max_x is the sum of the width of all outputs
-        let max_x = outputs.iter().fold(0u32, |acc, output| acc + output.size.0);
-        x = x.max(0.0).min(max_x as f64);
+    /// Activates the pointer constraint of the surface under the pointer of `seat` if it has the
+    /// keyboard focus of that seat, and breaks the constraint active on the seat once its surface
+    /// lost the focus.
+    fn update_pointer_constraint(&mut self, seat: &MySeat) {
+        let location = seat.pointer_location();
+        let under = seat.pointer_focus.borrow().clone();
+        let focus = under.as_ref().and_then(|(surface, origin)| {
+            let client = surface.as_ref().client()?;
+            if seat.keyboard.has_focus(&client) {
+                Some((surface, *origin))
+            } else {
+                None
+            }
+        });
 
-        // max y depends on the current output
-        let max_y = self.current_output_size(x).1;
-        y = y.max(0.0).min(max_y as f64);
+        let unlocked = self.pointer_constraints.borrow_mut().update_focus(
+            &mut *seat.pointer_constraint.borrow_mut(),
+            focus,
+            location,
+        );
 
-        (x, y)
+        // honor the cursor position hint of a pointer that just got unlocked
+        if let Some(location) = unlocked {
+            seat.set_pointer_location(location);
+        }
     }
 
     #[cfg(feature = "udev")]
-    fn current_output_idx(&self, x: f64) -> usize {
+    fn clamp_coords(&self, pos: (f64, f64)) -> (f64, f64) {
         let output_map = self.output_map.as_ref().unwrap();
         let outputs = output_map.borrow();
 
+        // keep the pointer on the closest point of the layout
+        let (x, y) = pos;
         outputs
             .iter()
-            // map each output to their x position
-            .scan(0u32, |acc, output| {
-                let curr_x = *acc;
-                *acc += output.size.0;
-                Some(curr_x)
+            .map(|output| {
+                let geometry = output.geometry();
+                let clamped_x = x
+                    .max(geometry.x as f64)
+                    .min((geometry.x + geometry.width) as f64 - 1.0);
+                let clamped_y = y
+                    .max(geometry.y as f64)
+                    .min((geometry.y + geometry.height) as f64 - 1.0);
+                (clamped_x, clamped_y)
             })
-            // get an index
-            .enumerate()
-            // find the first one with a greater x
-            .find(|(_idx, x_pos)| *x_pos as f64 > x)
-            // the previous output is the one we are on
-            .map(|(idx, _)| idx - 1)
-            .unwrap_or(outputs.len() - 1)
-    }
-    #[cfg(feature = "udev")]
-    fn current_output_size(&self, x: f64) -> (u32, u32) {
-        let output_map = self.output_map.as_ref().unwrap();
-        let outputs = output_map.borrow();
-        outputs[self.current_output_idx(x)].size
+            .min_by(|a, b| {
+                let distance = |point: &(f64, f64)| (point.0 - x).powi(2) + (point.1 - y).powi(2);
+                distance(a)
+                    .partial_cmp(&distance(b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(pos)
     }
+
+    /// The view of the output under `pos`, or of the first output if there is none.
     #[cfg(feature = "udev")]
-    fn current_output_offset(&self, x: f64) -> u32 {
+    fn current_output_view(&self, pos: (f64, f64)) -> OutputView {
         let output_map = self.output_map.as_ref().unwrap();
         let outputs = output_map.borrow();
         outputs
             .iter()
-            .take(self.current_output_idx(x))
-            .fold(0u32, |acc, output| acc + output.size.0)
+            .map(|output| output.view())
+            .find(|view| view.geometry.contains((pos.0 as i32, pos.1 as i32)))
+            .or_else(|| outputs.first().map(|output| output.view()))
+            .unwrap_or(OutputView {
+                geometry: Default::default(),
+                scale: 1.0,
+                transform: Transform::Normal,
+                bottom_up: false,
+            })
     }
 
-    fn on_pointer_button<B: InputBackend>(&mut self, evt: B::PointerButtonEvent) {
+    fn on_pointer_button<B: InputBackend>(&mut self, seat: &MySeat, evt: B::PointerButtonEvent) {
         let serial = SCOUNTER.next_serial();
         let button = match evt.button() {
             input::MouseButton::Left => 0x110,
@@ -241,24 +463,23 @@ impl AnvilState {
             input::MouseButton::Other(b) => b as u32,
         };
         let state = match evt.state() {
+            // a click into the window switcher stays with the compositor
+            #[cfg(feature = "switcher")]
+            input::MouseButtonState::Pressed if self.click_switcher(seat.pointer_location()) => return,
             input::MouseButtonState::Pressed => {
                 // change the keyboard focus unless the pointer is grabbed
-                if !self.pointer.is_grabbed() {
-                    let under = self
-                        .window_map
-                        .borrow_mut()
-                        .get_surface_and_bring_to_top(*self.pointer_location.borrow());
-                    self.keyboard
-                        .set_focus(under.as_ref().map(|&(ref s, _)| s), serial);
+                if !seat.pointer.is_grabbed() {
+                    self.focus_on_click(seat);
+                    self.update_pointer_constraint(seat);
                 }
                 wl_pointer::ButtonState::Pressed
             }
             input::MouseButtonState::Released => wl_pointer::ButtonState::Released,
         };
-        self.pointer.button(button, state, serial, evt.time());
+        seat.pointer.button(button, state, serial, evt.time());
     }
 
-    fn on_pointer_axis<B: InputBackend>(&mut self, evt: B::PointerAxisEvent) {
+    fn on_pointer_axis<B: InputBackend>(&mut self, seat: &MySeat, evt: B::PointerAxisEvent) {
         let source = match evt.source() {
             input::AxisSource::Continuous => wl_pointer::AxisSource::Continuous,
             input::AxisSource::Finger => wl_pointer::AxisSource::Finger,
@@ -291,7 +512,7 @@ impl AnvilState {
             } else if source == wl_pointer::AxisSource::Finger {
                 frame = frame.stop(wl_pointer::Axis::VerticalScroll);
             }
-            self.pointer.axis(frame);
+            seat.pointer.axis(frame);
         }
     }
 }
@@ -0,0 +1,539 @@
+use std::{cell::RefCell, os::unix::io::AsRawFd, rc::Rc};
+
+use smithay::{
+    backend::input::KeyState,
+    reexports::{
+        wayland_protocols::{
+            misc::zwp_input_method_v2::server::{
+                zwp_input_method_keyboard_grab_v2::{self, ZwpInputMethodKeyboardGrabV2},
+                zwp_input_method_manager_v2::{self, ZwpInputMethodManagerV2},
+                zwp_input_method_v2::{self, ZwpInputMethodV2},
+                zwp_input_popup_surface_v2::{self, ZwpInputPopupSurfaceV2},
+            },
+            unstable::text_input::v3::server::{
+                zwp_text_input_manager_v3::{self, ZwpTextInputManagerV3},
+                zwp_text_input_v3::{self, ZwpTextInputV3},
+            },
+        },
+        wayland_server::{
+            protocol::{wl_keyboard, wl_surface::WlSurface},
+            Display, Filter, Global, Main,
+        },
+    },
+    utils::Rectangle,
+    wayland::{compositor::CompositorToken, seat::Seat, Serial},
+};
+use xkbcommon::xkb;
+
+use crate::{
+    keymap::SeatKeymap,
+    seat::{seat_input_method, seat_keymap},
+    shell::{MyWindowMap, Roles, SurfaceData},
+};
+
+/// The role of the popup surfaces of an input method.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct InputPopupRole;
+
+/// The state a text input sends to the input method, double buffered by `commit`.
+#[derive(Default, Clone)]
+struct TextInputState {
+    enabled: bool,
+    surrounding_text: Option<(String, u32, u32)>,
+    change_cause: u32,
+    content_type: (u32, u32),
+    cursor_rectangle: Option<Rectangle>,
+}
+
+struct TextInput {
+    text_input: ZwpTextInputV3,
+    pending: TextInputState,
+    current: TextInputState,
+    /// The number of commits, the input method's changes are acknowledged with it
+    serial: u32,
+}
+
+/// The changes an input method sends to the text input, double buffered by `commit`.
+#[derive(Default)]
+struct InputMethodChanges {
+    commit_string: Option<String>,
+    preedit_string: Option<(String, i32, i32)>,
+    delete_surrounding_text: Option<(u32, u32)>,
+}
+
+struct InputPopup {
+    popup: ZwpInputPopupSurfaceV2,
+    surface: WlSurface,
+}
+
+/// A keyboard grab of the input method, it gets the keys instead of the focused client.
+struct KeyboardGrab {
+    grab: ZwpInputMethodKeyboardGrabV2,
+    state: xkb::State,
+    /// The last modifiers sent, as (depressed, latched, locked, group)
+    modifiers: (u32, u32, u32, u32),
+}
+
+impl KeyboardGrab {
+    fn key(&mut self, serial: Serial, time: u32, keycode: u32, state: KeyState) {
+        let (direction, wl_state) = match state {
+            KeyState::Pressed => (xkb::KeyDirection::Down, wl_keyboard::KeyState::Pressed),
+            KeyState::Released => (xkb::KeyDirection::Up, wl_keyboard::KeyState::Released),
+        };
+        self.grab.key(serial.into(), time, keycode, wl_state.to_raw());
+
+        self.state.update_key(keycode + 8, direction);
+        let modifiers = (
+            self.state.serialize_mods(xkb::STATE_MODS_DEPRESSED),
+            self.state.serialize_mods(xkb::STATE_MODS_LATCHED),
+            self.state.serialize_mods(xkb::STATE_MODS_LOCKED),
+            self.state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE),
+        );
+        if modifiers != self.modifiers {
+            self.modifiers = modifiers;
+            let (depressed, latched, locked, group) = modifiers;
+            self.grab.modifiers(serial.into(), depressed, latched, locked, group);
+        }
+    }
+}
+
+/// The input method of a seat, and the text inputs of its clients.
+///
+/// The text input of the client with the keyboard focus is relayed to the input method, and the
+/// text the input method produces back to that text input.
+#[derive(Default)]
+pub struct InputMethodState {
+    input_method: Option<ZwpInputMethodV2>,
+    /// Whether the input method got activated, and not deactivated since
+    active: bool,
+    /// The number of `done` events the input method got, it acknowledges them with `commit`
+    done_count: u32,
+    changes: InputMethodChanges,
+    popups: Vec<InputPopup>,
+    grab: Option<KeyboardGrab>,
+    text_inputs: Vec<TextInput>,
+    focus: Option<WlSurface>,
+}
+
+impl InputMethodState {
+    /// Called when the keyboard focus of the seat changes.
+    pub fn set_focus(&mut self, focus: Option<&WlSurface>) {
+        if let Some(ref old_focus) = self.focus {
+            if focus.map(|focus| focus.as_ref().equals(old_focus.as_ref())).unwrap_or(false) {
+                return;
+            }
+            for text_input in self.text_inputs_of(old_focus) {
+                text_input.text_input.leave(old_focus);
+            }
+        }
+        self.deactivate();
+
+        self.focus = focus.cloned();
+        if let Some(ref focus) = self.focus {
+            for text_input in self.text_inputs_of(focus) {
+                text_input.text_input.enter(focus);
+            }
+        }
+    }
+
+    /// Forwards a key to the keyboard grab of the input method.
+    ///
+    /// Returns `false` if the input method did not grab the keyboard.
+    pub fn grabbed_key(&mut self, serial: Serial, time: u32, keycode: u32, state: KeyState) -> bool {
+        match self.grab {
+            Some(ref mut grab) if grab.grab.as_ref().is_alive() => {
+                grab.key(serial, time, keycode, state);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The popups of the input method and where to draw them, below the cursor rectangle of the
+    /// focused text input.
+    pub fn popups(&self, window_map: &MyWindowMap) -> Vec<(WlSurface, (i32, i32))> {
+        if !self.active {
+            return Vec::new();
+        }
+        let rectangle = match self.active_text_input().and_then(|text_input| text_input.current.cursor_rectangle) {
+            Some(rectangle) => rectangle,
+            None => return Vec::new(),
+        };
+        // the cursor rectangle is relative to the xdg geometry of the window, which starts
+        // `geometry.x/y` into its surface, after the client side shadows
+        let toplevel = match self.focus.as_ref().and_then(|focus| window_map.find(focus)) {
+            Some(toplevel) => toplevel,
+            None => return Vec::new(),
+        };
+        let origin = match (window_map.location(&toplevel), window_map.geometry_offset(&toplevel)) {
+            (Some(location), Some(offset)) => (location.0 + offset.0, location.1 + offset.1),
+            _ => return Vec::new(),
+        };
+        let (x, y) = popup_offset(rectangle);
+        self.popups
+            .iter()
+            .filter(|popup| popup.surface.as_ref().is_alive())
+            .map(|popup| (popup.surface.clone(), (origin.0 + x, origin.1 + y)))
+            .collect()
+    }
+
+    /// Sends the frame callbacks of the popups.
+    pub fn send_frames(&self, time: u32, token: CompositorToken<Roles>) {
+        for popup in &self.popups {
+            if !popup.surface.as_ref().is_alive() {
+                continue;
+            }
+            token.with_surface_data(&popup.surface, |attributes| {
+                if let Some(data) = attributes.user_data.get::<RefCell<SurfaceData>>() {
+                    data.borrow_mut().send_frame(time)
+                }
+            });
+        }
+    }
+
+    fn text_inputs_of<'a>(&'a self, surface: &'a WlSurface) -> impl Iterator<Item = &'a TextInput> + 'a {
+        self.text_inputs
+            .iter()
+            .filter(move |text_input| text_input.text_input.as_ref().same_client_as(surface.as_ref()))
+    }
+
+    /// The enabled text input of the focused client.
+    fn active_text_input(&self) -> Option<&TextInput> {
+        let focus = self.focus.as_ref()?;
+        self.text_inputs_of(focus).find(|text_input| text_input.current.enabled)
+    }
+
+    fn deactivate(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        self.changes = InputMethodChanges::default();
+        if let Some(ref input_method) = self.input_method {
+            input_method.deactivate();
+        }
+        self.done();
+    }
+
+    fn done(&mut self) {
+        if let Some(ref input_method) = self.input_method {
+            input_method.done();
+            self.done_count = self.done_count.wrapping_add(1);
+        }
+    }
+
+    /// Applies the pending state of a text input, and tells the input method about it if the text
+    /// input belongs to the focused client.
+    fn commit_text_input(&mut self, text_input: &ZwpTextInputV3) {
+        let idx = match self
+            .text_inputs
+            .iter()
+            .position(|other| other.text_input.as_ref().equals(text_input.as_ref()))
+        {
+            Some(idx) => idx,
+            None => return,
+        };
+        let focused = self
+            .focus
+            .as_ref()
+            .map(|focus| text_input.as_ref().same_client_as(focus.as_ref()))
+            .unwrap_or(false);
+
+        let state = {
+            let text_input = &mut self.text_inputs[idx];
+            text_input.serial = text_input.serial.wrapping_add(1);
+            text_input.current = text_input.pending.clone();
+            text_input.current.clone()
+        };
+        if !focused {
+            return;
+        }
+
+        if !state.enabled {
+            self.deactivate();
+            return;
+        }
+        let input_method = match self.input_method {
+            Some(ref input_method) => input_method.clone(),
+            None => return,
+        };
+        if !self.active {
+            self.active = true;
+            input_method.activate();
+        }
+        if let Some((ref text, cursor, anchor)) = state.surrounding_text {
+            input_method.surrounding_text(text.clone(), cursor, anchor);
+        }
+        input_method.text_change_cause(state.change_cause);
+        input_method.content_type(state.content_type.0, state.content_type.1);
+        self.done();
+
+        if let Some(rectangle) = state.cursor_rectangle {
+            for popup in &self.popups {
+                send_text_input_rectangle(&popup.popup, rectangle);
+            }
+        }
+    }
+
+    /// Sends the changes of the input method to the active text input.
+    fn commit_input_method(&mut self, serial: u32) {
+        let changes = std::mem::take(&mut self.changes);
+        // the input method may be behind, its changes were made for an older state then
+        if !self.active || serial != self.done_count {
+            return;
+        }
+        let text_input = match self.active_text_input() {
+            Some(text_input) => text_input,
+            None => return,
+        };
+
+        if let Some((text, begin, end)) = changes.preedit_string {
+            text_input.text_input.preedit_string(Some(text), begin, end);
+        }
+        if let Some((before, after)) = changes.delete_surrounding_text {
+            text_input.text_input.delete_surrounding_text(before, after);
+        }
+        if let Some(text) = changes.commit_string {
+            text_input.text_input.commit_string(Some(text));
+        }
+        text_input.text_input.done(text_input.serial);
+    }
+}
+
+/// Where a popup goes relative to the surface of the text input: right below its cursor
+/// rectangle.
+fn popup_offset(cursor_rectangle: Rectangle) -> (i32, i32) {
+    (cursor_rectangle.x, cursor_rectangle.y + cursor_rectangle.height)
+}
+
+/// Tells a popup where the cursor rectangle of the text input is, relative to the popup.
+fn send_text_input_rectangle(popup: &ZwpInputPopupSurfaceV2, cursor_rectangle: Rectangle) {
+    let (x, y) = popup_offset(cursor_rectangle);
+    popup.text_input_rectangle(
+        cursor_rectangle.x - x,
+        cursor_rectangle.y - y,
+        cursor_rectangle.width,
+        cursor_rectangle.height,
+    );
+}
+
+pub fn init_text_input_manager(display: &mut Display, log: ::slog::Logger) -> Global<ZwpTextInputManagerV3> {
+    display.create_global::<ZwpTextInputManagerV3, _>(
+        1,
+        Filter::new(move |(manager, _version): (Main<ZwpTextInputManagerV3>, u32), _, _| {
+            let log = log.clone();
+            manager.quick_assign(move |_, request, _| match request {
+                zwp_text_input_manager_v3::Request::GetTextInput { id, seat } => {
+                    let state = match Seat::from_resource(&seat) {
+                        Some(seat) => seat_input_method(&seat),
+                        None => return,
+                    };
+                    trace!(log, "New text input");
+
+                    let text_input_state = Rc::downgrade(&state);
+                    id.quick_assign(move |text_input, request, _| {
+                        let state = match text_input_state.upgrade() {
+                            Some(state) => state,
+                            None => return,
+                        };
+                        let mut state = state.borrow_mut();
+                        if let zwp_text_input_v3::Request::Commit = request {
+                            state.commit_text_input(&text_input);
+                            return;
+                        }
+                        if let zwp_text_input_v3::Request::Destroy = request {
+                            state
+                                .text_inputs
+                                .retain(|other| !other.text_input.as_ref().equals(text_input.as_ref()));
+                            if state.active_text_input().is_none() {
+                                state.deactivate();
+                            }
+                            return;
+                        }
+
+                        let pending = match state
+                            .text_inputs
+                            .iter_mut()
+                            .find(|other| other.text_input.as_ref().equals(text_input.as_ref()))
+                        {
+                            Some(other) => &mut other.pending,
+                            None => return,
+                        };
+                        match request {
+                            zwp_text_input_v3::Request::Enable => {
+                                // enabling resets the state to the defaults
+                                *pending = TextInputState {
+                                    enabled: true,
+                                    ..TextInputState::default()
+                                };
+                            }
+                            zwp_text_input_v3::Request::Disable => pending.enabled = false,
+                            zwp_text_input_v3::Request::SetSurroundingText { text, cursor, anchor } => {
+                                pending.surrounding_text = Some((text, cursor as u32, anchor as u32))
+                            }
+                            zwp_text_input_v3::Request::SetTextChangeCause { cause } => {
+                                pending.change_cause = cause.to_raw()
+                            }
+                            zwp_text_input_v3::Request::SetContentType { hint, purpose } => {
+                                pending.content_type = (hint.bits(), purpose.to_raw())
+                            }
+                            zwp_text_input_v3::Request::SetCursorRectangle { x, y, width, height } => {
+                                pending.cursor_rectangle = Some(Rectangle { x, y, width, height })
+                            }
+                            _ => unreachable!(),
+                        }
+                    });
+
+                    let mut state = state.borrow_mut();
+                    if let Some(ref focus) = state.focus {
+                        if id.as_ref().same_client_as(focus.as_ref()) {
+                            id.enter(focus);
+                        }
+                    }
+                    state.text_inputs.push(TextInput {
+                        text_input: (*id).clone(),
+                        pending: TextInputState::default(),
+                        current: TextInputState::default(),
+                        serial: 0,
+                    });
+                }
+                zwp_text_input_manager_v3::Request::Destroy => {}
+                _ => unreachable!(),
+            });
+        }),
+    )
+}
+
+pub fn init_input_method_manager(
+    display: &mut Display,
+    token: CompositorToken<Roles>,
+    log: ::slog::Logger,
+) -> Global<ZwpInputMethodManagerV2> {
+    display.create_global::<ZwpInputMethodManagerV2, _>(
+        1,
+        Filter::new(move |(manager, _version): (Main<ZwpInputMethodManagerV2>, u32), _, _| {
+            let log = log.clone();
+            manager.quick_assign(move |_, request, _| match request {
+                zwp_input_method_manager_v2::Request::GetInputMethod { seat, input_method } => {
+                    let seat = match Seat::from_resource(&seat) {
+                        Some(seat) => seat,
+                        None => return,
+                    };
+                    let state = seat_input_method(&seat);
+
+                    // only one input method per seat
+                    let taken = state
+                        .borrow()
+                        .input_method
+                        .as_ref()
+                        .map(|other| other.as_ref().is_alive())
+                        .unwrap_or(false);
+                    if taken {
+                        input_method.quick_assign(|_, _, _| {});
+                        input_method.unavailable();
+                        return;
+                    }
+                    info!(log, "New input method");
+
+                    let keymap = seat_keymap(&seat);
+                    let input_method_state = Rc::downgrade(&state);
+                    let log = log.clone();
+                    input_method.quick_assign(move |input_method, request, _| {
+                        let state = match input_method_state.upgrade() {
+                            Some(state) => state,
+                            None => return,
+                        };
+                        let mut state = state.borrow_mut();
+                        match request {
+                            zwp_input_method_v2::Request::CommitString { text } => {
+                                state.changes.commit_string = Some(text)
+                            }
+                            zwp_input_method_v2::Request::SetPreeditString {
+                                text,
+                                cursor_begin,
+                                cursor_end,
+                            } => state.changes.preedit_string = Some((text, cursor_begin, cursor_end)),
+                            zwp_input_method_v2::Request::DeleteSurroundingText {
+                                before_length,
+                                after_length,
+                            } => state.changes.delete_surrounding_text = Some((before_length, after_length)),
+                            zwp_input_method_v2::Request::Commit { serial } => state.commit_input_method(serial),
+                            zwp_input_method_v2::Request::GetInputPopupSurface { id, surface } => {
+                                if token.give_role_with(&surface, InputPopupRole).is_err() {
+                                    input_method.as_ref().post_error(
+                                        zwp_input_method_v2::Error::Role.to_raw(),
+                                        "Surface already has a role.".into(),
+                                    );
+                                    return;
+                                }
+                                id.quick_assign(|_, request, _| match request {
+                                    zwp_input_popup_surface_v2::Request::Destroy => {}
+                                    _ => unreachable!(),
+                                });
+                                if let Some(rectangle) = state
+                                    .active_text_input()
+                                    .and_then(|text_input| text_input.current.cursor_rectangle)
+                                {
+                                    send_text_input_rectangle(&id, rectangle);
+                                }
+                                state.popups.retain(|popup| popup.popup.as_ref().is_alive());
+                                state.popups.push(InputPopup {
+                                    popup: (*id).clone(),
+                                    surface,
+                                });
+                            }
+                            zwp_input_method_v2::Request::GrabKeyboard { keyboard } => {
+                                let grab_state = input_method_state.clone();
+                                keyboard.quick_assign(move |_, request, _| match request {
+                                    zwp_input_method_keyboard_grab_v2::Request::Release => {
+                                        if let Some(state) = grab_state.upgrade() {
+                                            state.borrow_mut().grab = None;
+                                        }
+                                    }
+                                    _ => unreachable!(),
+                                });
+                                grab_keyboard(&mut state, &keyboard, &keymap, &log);
+                            }
+                            zwp_input_method_v2::Request::Destroy => {
+                                state.input_method = None;
+                                state.active = false;
+                                state.grab = None;
+                                state.popups.clear();
+                            }
+                            _ => unreachable!(),
+                        }
+                    });
+
+                    let mut state = state.borrow_mut();
+                    state.input_method = Some((*input_method).clone());
+                    state.active = false;
+                    state.done_count = 0;
+                }
+                zwp_input_method_manager_v2::Request::Destroy => {}
+                _ => unreachable!(),
+            });
+        }),
+    )
+}
+
+fn grab_keyboard(
+    state: &mut InputMethodState,
+    grab: &ZwpInputMethodKeyboardGrabV2,
+    keymap: &SeatKeymap,
+    log: &::slog::Logger,
+) {
+    match keymap.to_file() {
+        Ok((file, size)) => grab.keymap(wl_keyboard::KeymapFormat::XkbV1.to_raw(), file.as_raw_fd(), size),
+        Err(err) => {
+            warn!(log, "Failed to send the keymap to the input method"; "err" => format!("{:?}", err));
+            return;
+        }
+    }
+    // same as the seat keyboard
+    grab.repeat_info(25, 200);
+    state.grab = Some(KeyboardGrab {
+        grab: grab.clone(),
+        state: xkb::State::new(keymap.keymap()),
+        modifiers: (0, 0, 0, 0),
+    });
+}
@@ -0,0 +1,607 @@
+//! A unix socket to query and control the compositor from outside.
+//!
+//! The clients send one JSON object per line and get one JSON object per line back, with a
+//! `success` member and an `error` message if it failed. The `type` of a request is one of
+//!
+//! - the queries `windows`, `tree` (the windows with the workspaces and the focus), `outputs`,
+//!   `workspaces`, `config` and `version`,
+//! - the commands `focus`, `move` (`x`, `y`), `resize` (`width`, `height`) and `close`, which apply
+//!   to the window with the `id` of the request, the topmost window matching its `app_id` and
+//!   `title`, or the focused one,
+//! - `workspace` (`number`, counting from 1), `exec` (`command`, a string run by `sh -c` or an
+//!   array of the program and its arguments) and `reload`, which reads the configuration file
+//!   again,
+//! - `subscribe` (`events`, a list of `window`, `workspace` and `output`), after which the client
+//!   also gets event objects with an `event` and a `change` member.
+//!
+//! The path of the socket is exported as `WAYBOX_SOCK`.
+
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+use serde_json::{json, Value};
+use smithay::reexports::calloop::{generic::Generic, Interest, LoopHandle, Mode, Source};
+
+use crate::{
+    custom::config::spawn_detached,
+    ipc_socket::socket_path,
+    shell::{app_id, request_size, title, Roles},
+    state::AnvilState,
+    window_map::Kind,
+    window_tree::{WindowInfo, WindowTree},
+};
+
+/// A client leaving more of its replies and events unread is disconnected.
+const MAX_OUTPUT_SIZE: usize = 1 << 22;
+
+/// A client sending a longer line is disconnected.
+const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+/// The kinds of events a client can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Window,
+    Workspace,
+    Output,
+}
+
+impl EventKind {
+    fn from_name(name: &str) -> Option<EventKind> {
+        match name {
+            "window" => Some(EventKind::Window),
+            "workspace" => Some(EventKind::Workspace),
+            "output" => Some(EventKind::Output),
+            _ => None,
+        }
+    }
+}
+
+struct IpcClient {
+    /// A second handle on the connection, the event source owns the first one
+    writer: UnixStream,
+    /// The start of a request whose line did not end yet
+    input: Vec<u8>,
+    /// The replies and events the connection did not take yet
+    output: Vec<u8>,
+    subscriptions: Vec<EventKind>,
+    source: Source<Generic<UnixStream>>,
+    /// Waits for the connection to take more of the output, while there is some left
+    flusher: Option<Source<Generic<UnixStream>>>,
+}
+
+impl IpcClient {
+    /// Queues a message and writes as much of the output as the connection takes.
+    ///
+    /// Fails if the connection is closed or the client left too much of its output unread.
+    fn send(&mut self, id: u64, message: &Value, handle: &LoopHandle<AnvilState>) -> io::Result<()> {
+        self.output.extend_from_slice(message.to_string().as_bytes());
+        self.output.push(b'\n');
+        self.flush(id, handle)?;
+        if self.output.len() > MAX_OUTPUT_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::Other,
+                "the client does not read its output",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the output until the connection would block, and waits for it to take the rest.
+    fn flush(&mut self, id: u64, handle: &LoopHandle<AnvilState>) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match self.writer.write(&self.output) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.output.drain(..len);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if self.output.is_empty() {
+            if let Some(flusher) = self.flusher.take() {
+                handle.kill(flusher);
+            }
+        } else if self.flusher.is_none() {
+            let stream = self.writer.try_clone()?;
+            let flusher = handle
+                .insert_source(
+                    Generic::new(stream, Interest::Writable, Mode::Level),
+                    move |_, _, state: &mut AnvilState| {
+                        client_writable(state, id);
+                        Ok(())
+                    },
+                )
+                .expect("Failed to init an ipc client event source.");
+            self.flusher = Some(flusher);
+        }
+        Ok(())
+    }
+
+    fn close(self, handle: &LoopHandle<AnvilState>) {
+        handle.kill(self.source);
+        if let Some(flusher) = self.flusher {
+            handle.kill(flusher);
+        }
+    }
+}
+
+/// The windows and outputs the events are derived from.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub tree: WindowTree,
+    pub outputs: Vec<Value>,
+}
+
+impl Snapshot {
+    /// The events leading from this snapshot to the `current` one.
+    pub fn changes(&self, current: &Snapshot) -> Vec<(EventKind, Value)> {
+        let mut events = Vec::new();
+        let window_event = |change: &str, info: Option<&WindowInfo>| {
+            (
+                EventKind::Window,
+                json!({ "event": "window", "change": change, "window": info }),
+            )
+        };
+
+        for info in &self.tree.windows {
+            if current.tree.get(info.id).is_none() {
+                events.push(window_event("closed", Some(info)));
+            }
+        }
+        for info in &current.tree.windows {
+            if self.tree.get(info.id).is_none() {
+                events.push(window_event("opened", Some(info)));
+            }
+        }
+        if self.tree.focused != current.tree.focused {
+            let info = current.tree.focused.and_then(|id| current.tree.get(id));
+            events.push(window_event("focused", info));
+        }
+
+        if self.tree.active_workspace != current.tree.active_workspace {
+            events.push((
+                EventKind::Workspace,
+                json!({
+                    "event": "workspace",
+                    "change": "switched",
+                    "workspace": current.tree.active_workspace,
+                    "previous": self.tree.active_workspace,
+                }),
+            ));
+        }
+
+        let output_event = |change: &str, info: &Value| {
+            (
+                EventKind::Output,
+                json!({ "event": "output", "change": change, "output": info }),
+            )
+        };
+        for info in &self.outputs {
+            match current.outputs.iter().find(|other| other["name"] == info["name"]) {
+                None => events.push(output_event("removed", info)),
+                Some(other) if other != info => events.push(output_event("changed", other)),
+                Some(_) => {}
+            }
+        }
+        for info in &current.outputs {
+            if !self.outputs.iter().any(|other| other["name"] == info["name"]) {
+                events.push(output_event("added", info));
+            }
+        }
+
+        events
+    }
+}
+
+/// The listening socket and the connected clients.
+pub struct IpcServer {
+    path: PathBuf,
+    clients: HashMap<u64, IpcClient>,
+    next_client: u64,
+    _listener: Source<Generic<UnixListener>>,
+}
+
+impl IpcServer {
+    /// Listens on a socket in `XDG_RUNTIME_DIR`, named after the wayland socket.
+    pub fn new(handle: &LoopHandle<AnvilState>, socket_name: &str, log: slog::Logger) -> Option<IpcServer> {
+        let runtime_dir = match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                warn!(log, "XDG_RUNTIME_DIR is not set, there is no ipc socket");
+                return None;
+            }
+        };
+        let path = socket_path(&runtime_dir, socket_name);
+        // a compositor with the same wayland socket is not running anymore
+        let _ = fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        }) {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!(log, "Failed to create the ipc socket {:?}: {}", path, err);
+                return None;
+            }
+        };
+
+        let listener = handle
+            .insert_source(
+                Generic::new(listener, Interest::Readable, Mode::Level),
+                |_, listener, state: &mut AnvilState| {
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                if let Some(ref mut ipc) = state.ipc {
+                                    ipc.add_client(stream, &state.handle, &state.log);
+                                }
+                            }
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                            Err(err) => {
+                                warn!(state.log, "Failed to accept an ipc client: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    Ok(())
+                },
+            )
+            .expect("Failed to init the ipc event source.");
+
+        info!(log, "Listening on ipc socket"; "path" => path.to_string_lossy().into_owned());
+        env::set_var("WAYBOX_SOCK", &path);
+        Some(IpcServer {
+            path,
+            clients: HashMap::new(),
+            next_client: 0,
+            _listener: listener,
+        })
+    }
+
+    fn add_client(&mut self, stream: UnixStream, handle: &LoopHandle<AnvilState>, log: &slog::Logger) {
+        // a client not reading its output must not block the compositor
+        let writer = match stream.set_nonblocking(true).and_then(|_| stream.try_clone()) {
+            Ok(writer) => writer,
+            Err(err) => {
+                warn!(log, "Failed to set up an ipc client: {}", err);
+                return;
+            }
+        };
+        let id = self.next_client;
+        self.next_client += 1;
+        let source = handle
+            .insert_source(
+                Generic::new(stream, Interest::Readable, Mode::Level),
+                move |_, stream, state: &mut AnvilState| {
+                    client_readable(state, id, stream);
+                    Ok(())
+                },
+            )
+            .expect("Failed to init an ipc client event source.");
+        debug!(log, "New ipc client"; "id" => id);
+        self.clients.insert(
+            id,
+            IpcClient {
+                writer,
+                input: Vec::new(),
+                output: Vec::new(),
+                subscriptions: Vec::new(),
+                source,
+                flusher: None,
+            },
+        );
+    }
+
+    /// Whether a client is subscribed to events.
+    pub fn subscribed(&self) -> bool {
+        self.clients.values().any(|client| !client.subscriptions.is_empty())
+    }
+
+    /// Sends the events to the clients subscribed to them.
+    pub fn publish(&mut self, events: &[(EventKind, Value)], handle: &LoopHandle<AnvilState>) {
+        for (kind, event) in events {
+            self.broadcast(*kind, event, handle);
+        }
+    }
+
+    /// Sends an event to the clients subscribed to it, and drops those not taking it.
+    fn broadcast(&mut self, kind: EventKind, event: &Value, handle: &LoopHandle<AnvilState>) {
+        let failed = self
+            .clients
+            .iter_mut()
+            .filter(|(_, client)| client.subscriptions.contains(&kind))
+            .filter_map(|(id, client)| client.send(*id, event, handle).err().map(|_| *id))
+            .collect::<Vec<_>>();
+        for id in failed {
+            if let Some(client) = self.clients.remove(&id) {
+                client.close(handle);
+            }
+        }
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads from a client and answers every complete request.
+fn client_readable(state: &mut AnvilState, id: u64, stream: &mut UnixStream) {
+    // the client is taken out while its requests are handled, they need the whole state
+    let mut client = match state.ipc.as_mut().and_then(|ipc| ipc.clients.remove(&id)) {
+        Some(client) => client,
+        None => return,
+    };
+
+    let mut buffer = [0; 4096];
+    let mut open = match stream.read(&mut buffer) {
+        Ok(0) => false,
+        Ok(len) => {
+            client.input.extend_from_slice(&buffer[..len]);
+            true
+        }
+        Err(err) => err.kind() == ErrorKind::Interrupted || err.kind() == ErrorKind::WouldBlock,
+    };
+
+    while open {
+        let end = match client.input.iter().position(|&byte| byte == b'\n') {
+            Some(end) => end,
+            None => {
+                open = client.input.len() <= MAX_REQUEST_SIZE;
+                break;
+            }
+        };
+        let line = client.input.drain(..=end).collect::<Vec<_>>();
+        if line.iter().all(|byte| byte.is_ascii_whitespace()) {
+            continue;
+        }
+        let reply = match serde_json::from_slice::<Value>(&line) {
+            Ok(request) => state.handle_ipc_request(&request, &mut client.subscriptions),
+            Err(err) => failure(format!("Invalid request: {}", err)),
+        };
+        open = client.send(id, &reply, &state.handle).is_ok();
+    }
+
+    if open {
+        if let Some(ref mut ipc) = state.ipc {
+            ipc.clients.insert(id, client);
+        }
+    } else {
+        debug!(state.log, "Ipc client left"; "id" => id);
+        client.close(&state.handle);
+    }
+}
+
+/// Writes more of the output of a client, once its connection takes it.
+fn client_writable(state: &mut AnvilState, id: u64) {
+    let ipc = match state.ipc {
+        Some(ref mut ipc) => ipc,
+        None => return,
+    };
+    let failed = match ipc.clients.get_mut(&id) {
+        Some(client) => client.flush(id, &state.handle).is_err(),
+        None => return,
+    };
+    if failed {
+        debug!(state.log, "Ipc client left"; "id" => id);
+        if let Some(client) = ipc.clients.remove(&id) {
+            client.close(&state.handle);
+        }
+    }
+}
+
+fn failure(error: String) -> Value {
+    json!({ "success": false, "error": error })
+}
+
+impl AnvilState {
+    fn handle_ipc_request(&mut self, request: &Value, subscriptions: &mut Vec<EventKind>) -> Value {
+        let result = match request["type"].as_str() {
+            Some("subscribe") => self.ipc_subscribe(request, subscriptions),
+            _ => self.handle_command(request),
+        };
+        match result {
+            Ok(mut reply) => {
+                reply["success"] = true.into();
+                reply
+            }
+            Err(error) => failure(error),
+        }
+    }
+
+    /// Runs a query or command of the ipc protocol, besides `subscribe`, which is also what the
+    /// scripts ask for.
+    pub fn handle_command(&mut self, request: &Value) -> Result<Value, String> {
+        match request["type"].as_str() {
+            Some("windows") => Ok(json!({ "windows": self.window_tree().windows })),
+            Some("tree") => Ok(json!({ "tree": self.window_tree() })),
+            Some("outputs") => Ok(json!({ "outputs": self.ipc_outputs() })),
+            Some("workspaces") => Ok(json!({ "workspaces": self.ipc_workspaces() })),
+            Some("config") => Ok(json!({ "config": self.config.json.clone() })),
+            Some("version") => Ok(json!({ "version": env!("CARGO_PKG_VERSION") })),
+            Some("focus") => self.ipc_target(request).map(|toplevel| {
+                self.focus_window(&toplevel);
+                json!({})
+            }),
+            Some("move") => match (request["x"].as_i64(), request["y"].as_i64()) {
+                (Some(x), Some(y)) => self.ipc_target(request).map(|toplevel| {
+                    self.window_map
+                        .borrow_mut()
+                        .set_location(&toplevel, (x as i32, y as i32));
+                    json!({})
+                }),
+                _ => Err("A move needs x and y".into()),
+            },
+            Some("resize") => match (request["width"].as_i64(), request["height"].as_i64()) {
+                (Some(width), Some(height)) => self.ipc_target(request).and_then(|toplevel| {
+                    if request_size(&toplevel, (width as i32, height as i32), self.ctoken) {
+                        Ok(json!({}))
+                    } else {
+                        Err("The window can not be resized".into())
+                    }
+                }),
+                _ => Err("A resize needs width and height".into()),
+            },
+            Some("close") => self.ipc_target(request).and_then(|toplevel| {
+                if toplevel.close() {
+                    Ok(json!({}))
+                } else {
+                    Err("The window can not be asked to close".into())
+                }
+            }),
+            Some("workspace") => match request["number"].as_u64() {
+                Some(number) if number >= 1 && number as usize <= self.config.workspaces => {
+                    self.switch_workspace(number as usize - 1);
+                    Ok(json!({}))
+                }
+                _ => Err(format!(
+                    "The workspace number has to be between 1 and {}",
+                    self.config.workspaces
+                )),
+            },
+            Some("exec") => {
+                let argv = match request["command"] {
+                    Value::String(ref command) => vec!["/bin/sh", "-c", command.as_str()],
+                    Value::Array(ref argv) if !argv.is_empty() => argv
+                        .iter()
+                        .map(|arg| arg.as_str().ok_or("The arguments of an exec have to be strings"))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    _ => return Err("An exec needs a command".into()),
+                };
+                info!(self.log, "Starting program"; "cmd" => request["command"].to_string());
+                // the program is not a child of the compositor, nothing has to reap it
+                spawn_detached(&argv, &[])
+                    .map(|_| json!({}))
+                    .map_err(|err| format!("Failed to start {}: {}", request["command"], err))
+            }
+            Some("reload") => self.reload_config().map(|_| json!({})),
+            Some(other) => Err(format!("Unknown request type {}", other)),
+            None => Err("The request has no type".into()),
+        }
+    }
+
+    fn ipc_subscribe(&mut self, request: &Value, subscriptions: &mut Vec<EventKind>) -> Result<Value, String> {
+        let names = request["events"].as_array().ok_or("A subscribe needs a list of events")?;
+        for name in names {
+            let kind = name
+                .as_str()
+                .and_then(EventKind::from_name)
+                .ok_or_else(|| format!("Unknown event {}", name))?;
+            if !subscriptions.contains(&kind) {
+                subscriptions.push(kind);
+            }
+        }
+        // the events start from here, not from whenever nobody was interested anymore
+        if self.published.is_none() {
+            self.published = Some(self.ipc_snapshot());
+        }
+        Ok(json!({}))
+    }
+
+    /// The window a command applies to: the one with the `id` of the request, the topmost one
+    /// matching its `app_id` and `title`, or the focused one if the request has none of them.
+    fn ipc_target(&self, request: &Value) -> Result<Kind<Roles>, String> {
+        if !request["id"].is_null() {
+            return request["id"]
+                .as_u64()
+                .and_then(|id| self.window_map.borrow().find_by_id(id))
+                .ok_or_else(|| format!("No window has the id {}", request["id"]));
+        }
+        let wanted_app_id = request["app_id"].as_str();
+        let wanted_title = request["title"].as_str();
+        if wanted_app_id.is_none() && wanted_title.is_none() {
+            return self.focused_window().ok_or_else(|| "No window has the focus".into());
+        }
+        let mut found = None;
+        self.window_map.borrow().with_all_windows(|_, toplevel, _, _| {
+            let surface = match toplevel.get_surface() {
+                Some(surface) if found.is_none() => surface,
+                _ => return,
+            };
+            let matches = |wanted: Option<&str>, value: Option<String>| {
+                wanted.map_or(true, |wanted| value.as_deref() == Some(wanted))
+            };
+            if matches(wanted_app_id, app_id(surface, self.ctoken))
+                && matches(wanted_title, title(surface, self.ctoken))
+            {
+                found = Some(toplevel.clone());
+            }
+        });
+        found.ok_or_else(|| "No window matches".into())
+    }
+
+    fn ipc_outputs(&self) -> Vec<Value> {
+        let mut outputs = Vec::new();
+        if let Some(view) = self.winit_output {
+            outputs.push(json!({
+                "name": "winit",
+                "x": view.geometry.x,
+                "y": view.geometry.y,
+                "width": view.geometry.width,
+                "height": view.geometry.height,
+                "scale": view.scale,
+            }));
+        }
+        #[cfg(feature = "udev")]
+        {
+            if let Some(ref output_map) = self.output_map {
+                for output in output_map.borrow().iter() {
+                    let geometry = output.geometry();
+                    outputs.push(json!({
+                        "name": output.name,
+                        "x": geometry.x,
+                        "y": geometry.y,
+                        "width": geometry.width,
+                        "height": geometry.height,
+                        "scale": output.scale,
+                        "mode": {
+                            "width": output.size.0,
+                            "height": output.size.1,
+                            "refresh": output.refresh,
+                        },
+                        "transform": format!("{:?}", output.transform),
+                        "powered": output.powered,
+                    }));
+                }
+            }
+        }
+        outputs
+    }
+
+    fn ipc_workspaces(&self) -> Vec<Value> {
+        let window_map = self.window_map.borrow();
+        let mut windows = vec![0; self.config.workspaces];
+        window_map.with_all_windows(|_, _, _, workspace| {
+            if let Some(count) = windows.get_mut(workspace) {
+                *count += 1;
+            }
+        });
+        windows
+            .into_iter()
+            .enumerate()
+            .map(|(workspace, count)| {
+                json!({
+                    "number": workspace + 1,
+                    "active": workspace == window_map.active_workspace(),
+                    "windows": count,
+                })
+            })
+            .collect()
+    }
+
+    pub fn ipc_snapshot(&self) -> Snapshot {
+        Snapshot {
+            tree: self.window_tree(),
+            outputs: self.ipc_outputs(),
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! The name of the ipc socket, shared by the compositor and wayboxctl.
+
+use std::path::{Path, PathBuf};
+
+/// The ipc socket of the compositor listening on the wayland socket `socket_name`.
+pub fn socket_path(runtime_dir: &Path, socket_name: &str) -> PathBuf {
+    runtime_dir.join(format!("waybox.{}.sock", socket_name))
+}
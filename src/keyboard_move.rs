@@ -0,0 +1,212 @@
+//! Moving and resizing windows with the keyboard, for clients that do not start the moves and
+//! resizes themselves.
+//!
+//! The `move_window` and `resize_window` actions move the focused window or one of its edges by
+//! a number of pixels. The `keyboard_move` and `keyboard_resize` actions start a mode in which
+//! the arrow keys move the window or its bottom right corner, by the `keyboard_move_step` or
+//! by one pixel with shift held. Enter keeps the window as it is, Escape puts it back.
+
+use smithay::wayland::{
+    seat::{keysyms as xkb, Keysym, ModifiersState},
+    SERIAL_COUNTER as SCOUNTER,
+};
+
+use crate::{
+    custom::config::KeyAction,
+    seat::MySeat,
+    shell::{Resize, ResizeEdge, Roles},
+    state::AnvilState,
+    window_map::Kind,
+};
+
+/// What the keyboard move or resize mode does to the window.
+enum Mode {
+    Move {
+        initial_location: (i32, i32),
+        location: (i32, i32),
+    },
+    Resize {
+        resize: Resize,
+        /// How far the bottom right corner moved
+        offset: (i32, i32),
+    },
+}
+
+/// A keyboard move or resize going on.
+pub struct KeyboardMove {
+    toplevel: Kind<Roles>,
+    mode: Mode,
+    /// The index of the seat whose keyboard started it, the only one whose keys it gets
+    pub seat: usize,
+}
+
+impl KeyboardMove {
+    /// What a key does while the mode goes on, the clients get none of the keys but the
+    /// releases of the modifiers.
+    pub fn key_action(
+        &self,
+        modifiers: ModifiersState,
+        keysym: Keysym,
+        pressed: bool,
+        step: i32,
+    ) -> KeyAction {
+        if !pressed {
+            return if (xkb::KEY_Shift_L..=xkb::KEY_Hyper_R).contains(&keysym) {
+                KeyAction::Forward
+            } else {
+                KeyAction::None
+            };
+        }
+        let step = if modifiers.shift { 1 } else { step };
+        match keysym {
+            xkb::KEY_Left => KeyAction::KeyboardMoveStep(-step, 0),
+            xkb::KEY_Right => KeyAction::KeyboardMoveStep(step, 0),
+            xkb::KEY_Up => KeyAction::KeyboardMoveStep(0, -step),
+            xkb::KEY_Down => KeyAction::KeyboardMoveStep(0, step),
+            xkb::KEY_Return | xkb::KEY_KP_Enter => KeyAction::FinishKeyboardMove,
+            xkb::KEY_Escape => KeyAction::CancelKeyboardMove,
+            _ => KeyAction::None,
+        }
+    }
+}
+
+impl AnvilState {
+    /// Moves the window focused by a seat by some pixels.
+    pub fn move_window_by(&mut self, seat: &MySeat, (dx, dy): (i32, i32)) {
+        let toplevel = match self.seat_focus(seat) {
+            Some(toplevel) => toplevel,
+            None => return,
+        };
+        let mut window_map = self.window_map.borrow_mut();
+        if let Some((x, y)) = window_map.location(&toplevel) {
+            window_map.set_location(&toplevel, (x + dx, y + dy));
+        }
+    }
+
+    /// Moves an edge of the window focused by a seat outwards by some pixels, the window
+    /// shrinks for negative ones.
+    pub fn resize_window_by(&mut self, seat: &MySeat, edges: ResizeEdge, amount: i32) {
+        let toplevel = match self.seat_focus(seat) {
+            Some(toplevel) => toplevel,
+            None => return,
+        };
+        let resize = Resize::start(self.ctoken, toplevel, edges, &self.window_map.borrow());
+        if let Some(mut resize) = resize {
+            // the offset of a pointer dragging the edges outwards
+            let amount = amount as f64;
+            let dx = if edges.intersects(ResizeEdge::LEFT) {
+                -amount
+            } else if edges.intersects(ResizeEdge::RIGHT) {
+                amount
+            } else {
+                0.0
+            };
+            let dy = if edges.intersects(ResizeEdge::TOP) {
+                -amount
+            } else if edges.intersects(ResizeEdge::BOTTOM) {
+                amount
+            } else {
+                0.0
+            };
+            resize.update((dx, dy), SCOUNTER.next_serial());
+            resize.finish(SCOUNTER.next_serial());
+        }
+    }
+
+    /// Starts moving or resizing the window focused by a seat with the arrow keys, `index` is the
+    /// index of the seat.
+    pub fn start_keyboard_move(&mut self, seat: &MySeat, index: usize, resize: bool) {
+        if self.keyboard_move.is_some() || seat.pointer.is_grabbed() {
+            return;
+        }
+        let toplevel = match self.seat_focus(seat) {
+            Some(toplevel) => toplevel,
+            None => return,
+        };
+        let mode = if resize {
+            let resize = Resize::start(
+                self.ctoken,
+                toplevel.clone(),
+                ResizeEdge::BOTTOM_RIGHT,
+                &self.window_map.borrow(),
+            );
+            match resize {
+                Some(resize) => Mode::Resize {
+                    resize,
+                    offset: (0, 0),
+                },
+                None => return,
+            }
+        } else {
+            match self.window_map.borrow().location(&toplevel) {
+                Some(location) => Mode::Move {
+                    initial_location: location,
+                    location,
+                },
+                None => return,
+            }
+        };
+        self.window_map.borrow_mut().bring_to_top(&toplevel);
+        self.keyboard_move = Some(KeyboardMove {
+            toplevel,
+            mode,
+            seat: index,
+        });
+    }
+
+    /// Moves the window of the keyboard move or resize mode, or its bottom right corner.
+    pub fn keyboard_move_step(&mut self, (dx, dy): (i32, i32)) {
+        let keyboard_move = match self.keyboard_move {
+            Some(ref mut keyboard_move) if keyboard_move.toplevel.alive() => keyboard_move,
+            _ => {
+                self.keyboard_move = None;
+                return;
+            }
+        };
+        match keyboard_move.mode {
+            Mode::Move { ref mut location, .. } => {
+                *location = (location.0 + dx, location.1 + dy);
+                self.window_map
+                    .borrow_mut()
+                    .set_location(&keyboard_move.toplevel, *location);
+            }
+            Mode::Resize {
+                ref mut resize,
+                ref mut offset,
+            } => {
+                *offset = (offset.0 + dx, offset.1 + dy);
+                resize.update((offset.0 as f64, offset.1 as f64), SCOUNTER.next_serial());
+            }
+        }
+    }
+
+    /// Ends the keyboard move or resize mode, keeping the window as it is.
+    pub fn finish_keyboard_move(&mut self) {
+        if let Some(KeyboardMove {
+            mode: Mode::Resize { resize, .. },
+            ..
+        }) = self.keyboard_move.take()
+        {
+            resize.finish(SCOUNTER.next_serial());
+        }
+    }
+
+    /// Ends the keyboard move or resize mode, putting the window back to where it started.
+    pub fn cancel_keyboard_move(&mut self) {
+        let KeyboardMove { toplevel, mode, .. } = match self.keyboard_move.take() {
+            Some(keyboard_move) => keyboard_move,
+            None => return,
+        };
+        match mode {
+            Mode::Move { initial_location, .. } => {
+                self.window_map
+                    .borrow_mut()
+                    .set_location(&toplevel, initial_location);
+            }
+            Mode::Resize { mut resize, .. } => {
+                resize.update((0.0, 0.0), SCOUNTER.next_serial());
+                resize.finish(SCOUNTER.next_serial());
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use xkbcommon::xkb;
+
+use crate::custom::config::Keyboard;
+
+static KEYMAP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The xkb keymap of a seat, compiled from the same configuration as the one of the
+/// `KeyboardHandle`.
+///
+/// The virtual keyboard and input method protocols need it to translate keys and to send the
+/// keymap to their clients themselves.
+#[derive(Clone)]
+pub struct SeatKeymap {
+    keymap: xkb::Keymap,
+}
+
+impl SeatKeymap {
+    pub fn new(config: &Keyboard) -> SeatKeymap {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            "",
+            &config.model,
+            &config.layout,
+            &config.variant,
+            None,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("Failed to compile the seat keymap");
+        SeatKeymap { keymap }
+    }
+
+    pub fn keymap(&self) -> &xkb::Keymap {
+        &self.keymap
+    }
+
+    /// Returns the (evdev) keycode producing `keysym` on the first layout, at any shift level.
+    pub fn keycode_for(&self, keysym: xkb::Keysym) -> Option<u32> {
+        (self.keymap.min_keycode()..=self.keymap.max_keycode()).find_map(|keycode| {
+            let levels = self.keymap.num_levels_for_key(keycode, 0);
+            (0..levels)
+                .any(|level| self.keymap.key_get_syms_by_level(keycode, 0, level).contains(&keysym))
+                .then(|| keycode - 8)
+        })
+    }
+
+    /// Writes the keymap into an unlinked file, ready to be sent to a client.
+    ///
+    /// Returns the file and the size of the keymap, including the terminating nul byte.
+    pub fn to_file(&self) -> io::Result<(File, u32)> {
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let path = runtime_dir.join(format!(
+            "waybox-keymap-{}-{}",
+            std::process::id(),
+            KEYMAP_FILES.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        // clients map the keymap, so the file must be readable
+        let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        fs::remove_file(&path)?;
+        let keymap = self.keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
+        file.write_all(keymap.as_bytes())?;
+        file.write_all(&[0])?;
+        file.flush()?;
+        Ok((file, keymap.len() as u32 + 1))
+    }
+}
@@ -12,13 +12,49 @@ use std::{cell::RefCell, rc::Rc};
 use slog::Drain;
 use smithay::{reexports::{calloop::EventLoop, wayland_server::Display}};
 
+mod background;
+mod cursor;
+#[cfg(feature = "udev")]
+mod cursor_plane;
+mod cursor_shape;
+mod damage;
+mod dmabuf;
 mod drawing;
+#[cfg(feature = "udev")]
+mod edid;
+mod focus;
+mod fractional_scale;
+mod gl;
 mod input_handler;
+mod input_method;
+mod ipc;
+mod ipc_socket;
+mod keyboard_move;
+mod keymap;
+#[cfg(feature = "udev")]
+mod output_management;
+mod pointer_constraints;
+#[cfg(feature = "raw")]
+mod raw;
+mod relative_pointer;
+#[cfg(feature = "udev")]
+mod scanout;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod seat;
 mod shell;
+mod shortcuts_inhibit;
+mod snap;
 mod state;
+#[cfg(feature = "switcher")]
+mod switcher;
 #[cfg(feature = "udev")]
 mod udev;
+mod viewporter;
+mod virtual_keyboard;
+mod window_cycle;
 mod window_map;
+mod window_tree;
 #[cfg(feature = "winit")]
 mod winit;
 #[cfg(feature = "xwayland")]
@@ -34,6 +70,8 @@ static POSSIBLE_BACKENDS: &[&str] = &[
     "--winit : Run anvil as a X11 or Wayland client using winit.",
     #[cfg(feature = "udev")]
     "--tty-udev : Run anvil as a tty udev client (requires root if without logind).",
+    #[cfg(feature = "raw")]
+    "--raw-drm <device> : Run anvil directly on a drm device like /dev/dri/card0, without udev or a session.",
 ];
 
 fn main() {
@@ -45,6 +83,7 @@ fn main() {
     );
 
     let config = Configuration::parse("./config.json", log.clone());
+    config.cursor.export_env();
 
     let mut event_loop = EventLoop::<AnvilState>::new().unwrap();
     let display = Rc::new(RefCell::new(Display::new()));
@@ -65,6 +104,16 @@ fn main() {
                 crit!(log, "Failed to initialize tty backend.");
             }
         }
+        #[cfg(feature = "raw")]
+        Some("--raw-drm") => match ::std::env::args().nth(2) {
+            Some(path) => {
+                info!(log, "Starting anvil on drm device {}", path);
+                if let Err(()) = raw::run_raw(display, &mut event_loop, &path, config, log.clone()) {
+                    crit!(log, "Failed to initialize raw drm backend.");
+                }
+            }
+            None => println!("USAGE: anvil --raw-drm /dev/dri/cardN"),
+        },
         _ => {
             println!("USAGE: anvil --backend");
             println!();
@@ -0,0 +1,631 @@
+use std::{cell::RefCell, rc::Rc};
+
+use smithay::reexports::{
+    wayland_protocols::wlr::unstable::{
+        output_management::v1::server::{
+            zwlr_output_configuration_head_v1::{self, ZwlrOutputConfigurationHeadV1},
+            zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+            zwlr_output_head_v1::ZwlrOutputHeadV1,
+            zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+            zwlr_output_mode_v1::ZwlrOutputModeV1,
+        },
+        output_power_management::v1::server::{
+            zwlr_output_power_manager_v1::{self, ZwlrOutputPowerManagerV1},
+            zwlr_output_power_v1::{self, ZwlrOutputPowerV1},
+        },
+    },
+    wayland_server::{protocol::wl_output, Display, Filter, Global, Main},
+};
+
+use crate::{
+    custom::config::{OutputConfig, OutputMode, OutputPosition, OutputTransform},
+    udev::MyOutput,
+};
+
+/// A mode of a head, the refresh rate is in mHz like in the protocol.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeadMode {
+    pub width: i32,
+    pub height: i32,
+    pub refresh: i32,
+    pub preferred: bool,
+}
+
+impl HeadMode {
+    fn output_mode(&self) -> OutputMode {
+        OutputMode {
+            width: self.width as u32,
+            height: self.height as u32,
+            refresh: Some(((self.refresh + 500) / 1000) as u32),
+        }
+    }
+
+    fn matches(&self, mode: &OutputMode) -> bool {
+        let refresh = (self.refresh + 500) / 1000;
+        self.width as u32 == mode.width
+            && self.height as u32 == mode.height
+            && mode.refresh.map(|wanted| wanted == refresh as u32).unwrap_or(true)
+    }
+}
+
+/// A connected monitor, enabled or not, as it is advertised to the output management clients.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Head {
+    /// The connector name, heads are identified by it
+    pub name: String,
+    pub description: String,
+    pub make: String,
+    pub model: String,
+    pub serial: String,
+    /// The physical size in millimeters
+    pub physical_size: (i32, i32),
+    pub modes: Vec<HeadMode>,
+    /// Index into `modes`
+    pub current_mode: Option<usize>,
+    pub enabled: bool,
+    pub position: (i32, i32),
+    pub transform: wl_output::Transform,
+    pub scale: f64,
+}
+
+impl Head {
+    /// The configuration keeping the head as it is.
+    fn current_config(&self) -> OutputConfig {
+        OutputConfig {
+            enabled: true,
+            mode: self.current_mode.map(|idx| self.modes[idx].output_mode()),
+            position: if self.enabled {
+                Some(OutputPosition {
+                    x: self.position.0,
+                    y: self.position.1,
+                })
+            } else {
+                None
+            },
+            scale: Some(self.scale),
+            transform: config_transform(self.transform).unwrap_or_default(),
+        }
+    }
+}
+
+/// A request of a client the backend has to carry out.
+pub enum OutputRequest {
+    /// Applies the configuration of every head, `configuration` gets notified of the result.
+    Apply {
+        configuration: ZwlrOutputConfigurationV1,
+        heads: Vec<(String, OutputConfig)>,
+    },
+    /// Turns an output on or off.
+    Power { name: String, on: bool },
+}
+
+struct HeadInstance {
+    head: ZwlrOutputHeadV1,
+    name: String,
+    modes: Vec<(ZwlrOutputModeV1, HeadMode)>,
+}
+
+struct ManagerInstance {
+    manager: ZwlrOutputManagerV1,
+    version: u32,
+    heads: Vec<HeadInstance>,
+}
+
+/// The state of the output management and output power management protocols.
+///
+/// The backend publishes its heads with `set_heads` and carries out the requests it gets from
+/// `take_requests`, the protocols only validate the configurations of the clients.
+pub struct OutputManagement {
+    heads: Vec<Head>,
+    serial: u32,
+    managers: Vec<ManagerInstance>,
+    powers: Vec<(ZwlrOutputPowerV1, String)>,
+    requests: Vec<OutputRequest>,
+    log: ::slog::Logger,
+}
+
+impl OutputManagement {
+    /// Replaces the heads, the clients get the new ones if anything changed.
+    pub fn set_heads(&mut self, heads: Vec<Head>) {
+        if heads == self.heads {
+            return;
+        }
+        self.heads = heads;
+        self.serial = self.serial.wrapping_add(1);
+        debug!(self.log, "Output heads changed"; "serial" => self.serial);
+
+        self.managers
+            .retain(|instance| instance.manager.as_ref().is_alive());
+        for instance in &mut self.managers {
+            for head in instance.heads.drain(..) {
+                for (mode, _) in head.modes {
+                    mode.finished();
+                }
+                head.head.finished();
+            }
+            instance.heads = send_heads(&instance.manager, instance.version, &self.heads);
+            instance.manager.done(self.serial);
+        }
+
+        // the power objects of outputs that went away are useless now
+        let heads = &self.heads;
+        self.powers.retain(|(power, name)| {
+            let enabled = heads.iter().any(|head| head.enabled && &head.name == name);
+            if power.as_ref().is_alive() && !enabled {
+                power.failed();
+            }
+            power.as_ref().is_alive() && enabled
+        });
+    }
+
+    /// Returns the requests the backend did not carry out yet.
+    pub fn take_requests(&mut self) -> Vec<OutputRequest> {
+        std::mem::take(&mut self.requests)
+    }
+
+    /// Notifies the clients that an output was turned on or off.
+    pub fn power_changed(&self, name: &str, on: bool) {
+        for (power, _) in self.powers.iter().filter(|(_, other)| other == name) {
+            power.mode(power_mode(on));
+        }
+    }
+
+    fn head_name(&self, head: &ZwlrOutputHeadV1) -> Option<String> {
+        self.managers
+            .iter()
+            .flat_map(|instance| instance.heads.iter())
+            .find(|instance| instance.head.as_ref().equals(head.as_ref()))
+            .map(|instance| instance.name.clone())
+    }
+
+    fn find_mode(&self, name: &str, mode: &ZwlrOutputModeV1) -> Option<HeadMode> {
+        self.managers
+            .iter()
+            .flat_map(|instance| instance.heads.iter())
+            .filter(|instance| instance.name == name)
+            .flat_map(|instance| instance.modes.iter())
+            .find(|(other, _)| other.as_ref().equals(mode.as_ref()))
+            .map(|&(_, mode)| mode)
+    }
+
+    /// Whether the backend can apply the configuration of a head.
+    fn is_valid(&self, name: &str, config: &OutputConfig) -> bool {
+        let head = match self.heads.iter().find(|head| head.name == name) {
+            Some(head) => head,
+            None => return false,
+        };
+        let mode_supported = config
+            .mode
+            .map(|mode| head.modes.iter().any(|head_mode| head_mode.matches(&mode)))
+            .unwrap_or(true);
+        !config.enabled || mode_supported
+    }
+}
+
+/// Sends every head with its modes to a manager.
+fn send_heads(manager: &ZwlrOutputManagerV1, version: u32, heads: &[Head]) -> Vec<HeadInstance> {
+    let client = match manager.as_ref().client() {
+        Some(client) => client,
+        None => return Vec::new(),
+    };
+    heads
+        .iter()
+        .filter_map(|head| {
+            let resource = client.create_resource::<ZwlrOutputHeadV1>(version)?;
+            resource.quick_assign(|_, _, _| {});
+            manager.head(&resource);
+            resource.name(head.name.clone());
+            resource.description(head.description.clone());
+            if head.physical_size.0 > 0 && head.physical_size.1 > 0 {
+                resource.physical_size(head.physical_size.0, head.physical_size.1);
+            }
+
+            let mut modes = Vec::new();
+            for mode in &head.modes {
+                let mode_resource = client.create_resource::<ZwlrOutputModeV1>(version)?;
+                mode_resource.quick_assign(|_, _, _| {});
+                resource.mode(&mode_resource);
+                mode_resource.size(mode.width, mode.height);
+                if mode.refresh > 0 {
+                    mode_resource.refresh(mode.refresh);
+                }
+                if mode.preferred {
+                    mode_resource.preferred();
+                }
+                modes.push(((*mode_resource).clone(), *mode));
+            }
+
+            resource.enabled(head.enabled as i32);
+            if head.enabled {
+                if let Some(idx) = head.current_mode {
+                    resource.current_mode(&modes[idx].0);
+                }
+                resource.position(head.position.0, head.position.1);
+                resource.transform(head.transform.to_raw() as i32);
+                resource.scale(head.scale);
+            }
+            if version >= 2 {
+                resource.make(head.make.clone());
+                resource.model(head.model.clone());
+                resource.serial_number(head.serial.clone());
+            }
+
+            Some(HeadInstance {
+                head: (*resource).clone(),
+                name: head.name.clone(),
+                modes,
+            })
+        })
+        .collect()
+}
+
+fn config_transform(transform: wl_output::Transform) -> Option<OutputTransform> {
+    match transform {
+        wl_output::Transform::Normal => Some(OutputTransform::Normal),
+        wl_output::Transform::_90 => Some(OutputTransform::Rotate90),
+        wl_output::Transform::_180 => Some(OutputTransform::Rotate180),
+        wl_output::Transform::_270 => Some(OutputTransform::Rotate270),
+        wl_output::Transform::Flipped => Some(OutputTransform::Flipped),
+        wl_output::Transform::Flipped90 => Some(OutputTransform::Flipped90),
+        wl_output::Transform::Flipped180 => Some(OutputTransform::Flipped180),
+        wl_output::Transform::Flipped270 => Some(OutputTransform::Flipped270),
+        _ => None,
+    }
+}
+
+fn power_mode(on: bool) -> zwlr_output_power_v1::Mode {
+    if on {
+        zwlr_output_power_v1::Mode::On
+    } else {
+        zwlr_output_power_v1::Mode::Off
+    }
+}
+
+#[derive(Default)]
+struct PendingConfiguration {
+    serial: u32,
+    used: bool,
+    /// Whether a head of an older serial got configured
+    stale: bool,
+    /// The configured heads, disabled ones have no configuration
+    heads: Vec<(String, Option<Rc<RefCell<PendingHead>>>)>,
+}
+
+struct PendingHead {
+    config: OutputConfig,
+    mode_set: bool,
+    position_set: bool,
+    transform_set: bool,
+    scale_set: bool,
+}
+
+pub fn init_output_management(
+    display: &mut Display,
+    output_map: Rc<RefCell<Vec<MyOutput>>>,
+    log: ::slog::Logger,
+) -> (
+    Rc<RefCell<OutputManagement>>,
+    Global<ZwlrOutputManagerV1>,
+    Global<ZwlrOutputPowerManagerV1>,
+) {
+    let state = Rc::new(RefCell::new(OutputManagement {
+        heads: Vec::new(),
+        serial: 0,
+        managers: Vec::new(),
+        powers: Vec::new(),
+        requests: Vec::new(),
+        log: log.clone(),
+    }));
+
+    let manager_state = state.clone();
+    let manager_global = display.create_global::<ZwlrOutputManagerV1, _>(
+        2,
+        Filter::new(move |(manager, version): (Main<ZwlrOutputManagerV1>, u32), _, _| {
+            let state = manager_state.clone();
+            manager.quick_assign(move |manager, request, _| match request {
+                zwlr_output_manager_v1::Request::CreateConfiguration { id, serial } => {
+                    init_configuration(id, serial, state.clone())
+                }
+                zwlr_output_manager_v1::Request::Stop => {
+                    state
+                        .borrow_mut()
+                        .managers
+                        .retain(|instance| !instance.manager.as_ref().equals(manager.as_ref()));
+                    manager.finished();
+                }
+                _ => unreachable!(),
+            });
+
+            let mut state = manager_state.borrow_mut();
+            let heads = send_heads(&manager, version, &state.heads);
+            manager.done(state.serial);
+            state.managers.push(ManagerInstance {
+                manager: (*manager).clone(),
+                version,
+                heads,
+            });
+        }),
+    );
+
+    let power_state = state.clone();
+    let power_global = display.create_global::<ZwlrOutputPowerManagerV1, _>(
+        1,
+        Filter::new(move |(manager, _version): (Main<ZwlrOutputPowerManagerV1>, u32), _, _| {
+            let state = power_state.clone();
+            let output_map = output_map.clone();
+            manager.quick_assign(move |_, request, _| match request {
+                zwlr_output_power_manager_v1::Request::GetOutputPower { id, output } => {
+                    let found = output_map
+                        .borrow()
+                        .iter()
+                        .find(|other| other.owns(&output))
+                        .map(|other| (other.name.clone(), other.powered));
+
+                    let power_name = found.as_ref().map(|(name, _)| name.clone());
+                    let power_state = state.clone();
+                    id.quick_assign(move |_, request, _| match request {
+                        zwlr_output_power_v1::Request::SetMode { mode } => {
+                            if let Some(ref name) = power_name {
+                                power_state.borrow_mut().requests.push(OutputRequest::Power {
+                                    name: name.clone(),
+                                    on: mode == zwlr_output_power_v1::Mode::On,
+                                });
+                            }
+                        }
+                        zwlr_output_power_v1::Request::Destroy => {}
+                        _ => unreachable!(),
+                    });
+
+                    match found {
+                        Some((name, powered)) => {
+                            id.mode(power_mode(powered));
+                            state.borrow_mut().powers.push(((*id).clone(), name));
+                        }
+                        None => id.failed(),
+                    }
+                }
+                zwlr_output_power_manager_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            });
+        }),
+    );
+
+    (state, manager_global, power_global)
+}
+
+fn init_configuration(
+    configuration: Main<ZwlrOutputConfigurationV1>,
+    serial: u32,
+    state: Rc<RefCell<OutputManagement>>,
+) {
+    let pending = Rc::new(RefCell::new(PendingConfiguration {
+        serial,
+        ..PendingConfiguration::default()
+    }));
+    configuration.quick_assign(move |configuration, request, _| match request {
+        zwlr_output_configuration_v1::Request::EnableHead { id, head } => {
+            let name = enable_or_disable(&configuration, &head, &pending, &state);
+            let config = state
+                .borrow()
+                .heads
+                .iter()
+                .find(|head| Some(&head.name) == name.as_ref())
+                .map(|head| head.current_config())
+                .unwrap_or_default();
+            let pending_head = Rc::new(RefCell::new(PendingHead {
+                config,
+                mode_set: false,
+                position_set: false,
+                transform_set: false,
+                scale_set: false,
+            }));
+            init_configuration_head(id, name.clone(), pending_head.clone(), state.clone());
+            if let Some(name) = name {
+                pending.borrow_mut().heads.push((name, Some(pending_head)));
+            }
+        }
+        zwlr_output_configuration_v1::Request::DisableHead { head } => {
+            if let Some(name) = enable_or_disable(&configuration, &head, &pending, &state) {
+                pending.borrow_mut().heads.push((name, None));
+            }
+        }
+        zwlr_output_configuration_v1::Request::Apply => apply(&configuration, &pending, &state, false),
+        zwlr_output_configuration_v1::Request::Test => apply(&configuration, &pending, &state, true),
+        zwlr_output_configuration_v1::Request::Destroy => {}
+        _ => unreachable!(),
+    });
+}
+
+/// Checks that a head is configured only once, returns its name if it is a current head.
+fn enable_or_disable(
+    configuration: &ZwlrOutputConfigurationV1,
+    head: &ZwlrOutputHeadV1,
+    pending: &RefCell<PendingConfiguration>,
+    state: &RefCell<OutputManagement>,
+) -> Option<String> {
+    let mut pending = pending.borrow_mut();
+    let name = match state.borrow().head_name(head) {
+        Some(name) => name,
+        None => {
+            // the head is gone already, the configuration gets cancelled anyway
+            pending.stale = true;
+            return None;
+        }
+    };
+    if pending.heads.iter().any(|(other, _)| other == &name) {
+        configuration.as_ref().post_error(
+            zwlr_output_configuration_v1::Error::AlreadyConfiguredHead.to_raw(),
+            format!("The head {} is already configured.", name),
+        );
+        return None;
+    }
+    Some(name)
+}
+
+fn apply(
+    configuration: &ZwlrOutputConfigurationV1,
+    pending: &RefCell<PendingConfiguration>,
+    state: &RefCell<OutputManagement>,
+    test_only: bool,
+) {
+    let mut pending = pending.borrow_mut();
+    if pending.used {
+        configuration.as_ref().post_error(
+            zwlr_output_configuration_v1::Error::AlreadyUsed.to_raw(),
+            "The configuration was already applied or tested.".into(),
+        );
+        return;
+    }
+    pending.used = true;
+
+    let mut state = state.borrow_mut();
+    if pending.stale || pending.serial != state.serial {
+        configuration.cancelled();
+        return;
+    }
+    if let Some(head) = state
+        .heads
+        .iter()
+        .find(|head| !pending.heads.iter().any(|(name, _)| name == &head.name))
+    {
+        configuration.as_ref().post_error(
+            zwlr_output_configuration_v1::Error::UnconfiguredHead.to_raw(),
+            format!("The head {} is neither enabled nor disabled.", head.name),
+        );
+        return;
+    }
+
+    let heads = pending
+        .heads
+        .iter()
+        .map(|(name, head)| {
+            let config = match head {
+                Some(head) => head.borrow().config.clone(),
+                None => OutputConfig {
+                    enabled: false,
+                    ..OutputConfig::default()
+                },
+            };
+            (name.clone(), config)
+        })
+        .collect::<Vec<_>>();
+    if !heads.iter().all(|(name, config)| state.is_valid(name, config)) {
+        configuration.failed();
+    } else if test_only {
+        configuration.succeeded();
+    } else {
+        info!(state.log, "Applying an output configuration"; "heads" => heads.len());
+        state.requests.push(OutputRequest::Apply {
+            configuration: configuration.clone(),
+            heads,
+        });
+    }
+}
+
+fn init_configuration_head(
+    configuration_head: Main<ZwlrOutputConfigurationHeadV1>,
+    name: Option<String>,
+    pending_head: Rc<RefCell<PendingHead>>,
+    state: Rc<RefCell<OutputManagement>>,
+) {
+    configuration_head.quick_assign(move |configuration_head, request, _| {
+        let mut pending_head = pending_head.borrow_mut();
+        let already_set = match request {
+            zwlr_output_configuration_head_v1::Request::SetMode { .. }
+            | zwlr_output_configuration_head_v1::Request::SetCustomMode { .. } => {
+                std::mem::replace(&mut pending_head.mode_set, true)
+            }
+            zwlr_output_configuration_head_v1::Request::SetPosition { .. } => {
+                std::mem::replace(&mut pending_head.position_set, true)
+            }
+            zwlr_output_configuration_head_v1::Request::SetTransform { .. } => {
+                std::mem::replace(&mut pending_head.transform_set, true)
+            }
+            zwlr_output_configuration_head_v1::Request::SetScale { .. } => {
+                std::mem::replace(&mut pending_head.scale_set, true)
+            }
+            _ => unreachable!(),
+        };
+        if already_set {
+            post_error(
+                &configuration_head,
+                zwlr_output_configuration_head_v1::Error::AlreadySet,
+                "The property is already set.",
+            );
+            return;
+        }
+
+        match request {
+            zwlr_output_configuration_head_v1::Request::SetMode { mode } => {
+                // the head is gone already if it has no name, the configuration gets cancelled
+                let name = match name {
+                    Some(ref name) => name,
+                    None => return,
+                };
+                match state.borrow().find_mode(name, &mode) {
+                    Some(mode) => pending_head.config.mode = Some(mode.output_mode()),
+                    None => post_error(
+                        &configuration_head,
+                        zwlr_output_configuration_head_v1::Error::InvalidMode,
+                        "The mode does not belong to the head.",
+                    ),
+                }
+            }
+            zwlr_output_configuration_head_v1::Request::SetCustomMode { width, height, refresh } => {
+                if width <= 0 || height <= 0 || refresh < 0 {
+                    post_error(
+                        &configuration_head,
+                        zwlr_output_configuration_head_v1::Error::InvalidCustomMode,
+                        "The custom mode is invalid.",
+                    );
+                    return;
+                }
+                // only custom modes matching a mode of the connector can be applied
+                pending_head.config.mode = Some(OutputMode {
+                    width: width as u32,
+                    height: height as u32,
+                    refresh: if refresh > 0 {
+                        Some(((refresh + 500) / 1000) as u32)
+                    } else {
+                        None
+                    },
+                });
+            }
+            zwlr_output_configuration_head_v1::Request::SetPosition { x, y } => {
+                pending_head.config.position = Some(OutputPosition { x, y });
+            }
+            zwlr_output_configuration_head_v1::Request::SetTransform { transform } => {
+                match wl_output::Transform::from_raw(transform as u32).and_then(config_transform) {
+                    Some(transform) => pending_head.config.transform = transform,
+                    None => post_error(
+                        &configuration_head,
+                        zwlr_output_configuration_head_v1::Error::InvalidTransform,
+                        "The transform is invalid.",
+                    ),
+                }
+            }
+            zwlr_output_configuration_head_v1::Request::SetScale { scale } => {
+                if scale <= 0.0 {
+                    post_error(
+                        &configuration_head,
+                        zwlr_output_configuration_head_v1::Error::InvalidScale,
+                        "The scale must be positive.",
+                    );
+                    return;
+                }
+                pending_head.config.scale = Some(scale);
+            }
+            _ => unreachable!(),
+        }
+    });
+}
+
+fn post_error(
+    configuration_head: &ZwlrOutputConfigurationHeadV1,
+    error: zwlr_output_configuration_head_v1::Error,
+    message: &str,
+) {
+    configuration_head
+        .as_ref()
+        .post_error(error.to_raw(), message.into());
+}
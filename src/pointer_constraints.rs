@@ -0,0 +1,286 @@
+use std::{cell::RefCell, rc::Rc};
+
+use smithay::{
+    reexports::{
+        wayland_protocols::unstable::pointer_constraints::v1::server::{
+            zwp_confined_pointer_v1::{self, ZwpConfinedPointerV1},
+            zwp_locked_pointer_v1::{self, ZwpLockedPointerV1},
+            zwp_pointer_constraints_v1::{self, ZwpPointerConstraintsV1},
+        },
+        wayland_server::{
+            protocol::{wl_region::WlRegion, wl_surface::WlSurface},
+            Display, Filter, Global, Main,
+        },
+    },
+    wayland::compositor::{get_region_attributes, RegionAttributes},
+};
+
+#[derive(Clone)]
+pub enum ConstraintKind {
+    Locked(ZwpLockedPointerV1),
+    Confined(ZwpConfinedPointerV1),
+}
+
+impl ConstraintKind {
+    fn alive(&self) -> bool {
+        match self {
+            ConstraintKind::Locked(locked) => locked.as_ref().is_alive(),
+            ConstraintKind::Confined(confined) => confined.as_ref().is_alive(),
+        }
+    }
+
+    fn equals(&self, other: &ConstraintKind) -> bool {
+        match (self, other) {
+            (ConstraintKind::Locked(a), ConstraintKind::Locked(b)) => a.as_ref().equals(b.as_ref()),
+            (ConstraintKind::Confined(a), ConstraintKind::Confined(b)) => a.as_ref().equals(b.as_ref()),
+            _ => false,
+        }
+    }
+}
+
+pub struct PointerConstraint {
+    pub kind: ConstraintKind,
+    pub surface: WlSurface,
+    /// The region the constraint applies to, in surface local coordinates.
+    ///
+    /// `None` means the whole surface.
+    pub region: Option<RegionAttributes>,
+    /// The cursor position hint of a locked pointer, in surface local coordinates.
+    pub cursor_hint: Option<(f64, f64)>,
+    /// Where the surface was when the pointer was last over it, in global coordinates.
+    pub origin: (f64, f64),
+    oneshot: bool,
+    active: bool,
+    /// A oneshot constraint can not be activated again once it got deactivated.
+    defunct: bool,
+}
+
+impl PointerConstraint {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Checks if the point (in surface local coordinates) is in the constraint region.
+    pub fn contains_point(&self, point: (f64, f64)) -> bool {
+        match self.region {
+            Some(ref region) => region.contains((point.0 as i32, point.1 as i32)),
+            None => true,
+        }
+    }
+
+    fn activate(&mut self) {
+        if self.active {
+            return;
+        }
+        self.active = true;
+        match self.kind {
+            ConstraintKind::Locked(ref locked) => locked.locked(),
+            ConstraintKind::Confined(ref confined) => confined.confined(),
+        }
+    }
+
+    fn deactivate(&mut self) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        self.defunct = self.oneshot;
+        if self.kind.alive() {
+            match self.kind {
+                ConstraintKind::Locked(ref locked) => locked.unlocked(),
+                ConstraintKind::Confined(ref confined) => confined.unconfined(),
+            }
+        }
+    }
+}
+
+/// The pointer constraints requested by clients, at most one per surface.
+#[derive(Default)]
+pub struct PointerConstraints {
+    constraints: Vec<PointerConstraint>,
+}
+
+impl PointerConstraints {
+    /// Returns the constraint of the given handle, if it still exists.
+    pub fn get(&self, kind: &ConstraintKind) -> Option<&PointerConstraint> {
+        self.constraints
+            .iter()
+            .find(|constraint| constraint.kind.equals(kind) && constraint.kind.alive())
+    }
+
+    /// Updates `active`, the constraint active on a seat: the constraint of `focus`, the surface
+    /// under the pointer of the seat and its origin, activates if the pointer at `location` is
+    /// inside its region, the active constraint breaks once its surface lost the focus.
+    ///
+    /// If a locked pointer with a cursor position hint got unlocked, the hint is returned in
+    /// global coordinates, so the pointer can be warped there.
+    pub fn update_focus(
+        &mut self,
+        active: &mut Option<ConstraintKind>,
+        focus: Option<(&WlSurface, (f64, f64))>,
+        location: (f64, f64),
+    ) -> Option<(f64, f64)> {
+        self.cleanup();
+        let mut unlocked = None;
+        if let Some(kind) = active.take() {
+            if let Some(constraint) = self.constraints.iter_mut().find(|c| c.kind.equals(&kind)) {
+                let focused = focus.map_or(false, |(surface, _)| {
+                    constraint.surface.as_ref().equals(surface.as_ref())
+                });
+                if focused {
+                    *active = Some(kind);
+                } else {
+                    constraint.deactivate();
+                    if let (ConstraintKind::Locked(_), Some(hint)) = (&constraint.kind, constraint.cursor_hint)
+                    {
+                        unlocked = Some((constraint.origin.0 + hint.0, constraint.origin.1 + hint.1));
+                    }
+                }
+            }
+        }
+
+        if let Some((surface, origin)) = focus {
+            let constraint = self
+                .constraints
+                .iter_mut()
+                .find(|constraint| constraint.surface.as_ref().equals(surface.as_ref()));
+            if let Some(constraint) = constraint {
+                constraint.origin = origin;
+                let point = (location.0 - origin.0, location.1 - origin.1);
+                // the constraint of a surface is active on a single seat at a time
+                let available = !constraint.active && !constraint.defunct;
+                if active.is_none() && available && constraint.contains_point(point) {
+                    constraint.activate();
+                    *active = Some(constraint.kind.clone());
+                }
+            }
+        }
+        unlocked
+    }
+
+    fn has_constraint(&self, surface: &WlSurface) -> bool {
+        self.constraints
+            .iter()
+            .any(|constraint| constraint.surface.as_ref().equals(surface.as_ref()))
+    }
+
+    fn with_constraint<F: FnOnce(&mut PointerConstraint)>(&mut self, kind: &ConstraintKind, f: F) {
+        if let Some(constraint) = self.constraints.iter_mut().find(|c| c.kind.equals(kind)) {
+            f(constraint)
+        }
+    }
+
+    fn remove(&mut self, kind: &ConstraintKind) {
+        self.constraints.retain(|constraint| !constraint.kind.equals(kind));
+    }
+
+    /// Removes the constraints of dead surfaces and the ones destroyed by their client.
+    fn cleanup(&mut self) {
+        self.constraints
+            .retain(|constraint| constraint.kind.alive() && constraint.surface.as_ref().is_alive());
+    }
+}
+
+fn region_attributes(region: Option<WlRegion>) -> Option<RegionAttributes> {
+    region.map(|region| get_region_attributes(&region))
+}
+
+pub fn init_pointer_constraints(
+    display: &mut Display,
+    log: ::slog::Logger,
+) -> (Rc<RefCell<PointerConstraints>>, Global<ZwpPointerConstraintsV1>) {
+    let pointer_constraints = Rc::new(RefCell::new(PointerConstraints::default()));
+
+    let constraints = pointer_constraints.clone();
+    let global = display.create_global::<ZwpPointerConstraintsV1, _>(
+        1,
+        Filter::new(move |(manager, _version): (Main<ZwpPointerConstraintsV1>, u32), _, _| {
+            let constraints = constraints.clone();
+            let log = log.clone();
+            manager.quick_assign(move |manager, request, _| {
+                let (kind, surface, region, lifetime) = match request {
+                    zwp_pointer_constraints_v1::Request::LockPointer {
+                        id,
+                        surface,
+                        region,
+                        lifetime,
+                        ..
+                    } => {
+                        let kind = ConstraintKind::Locked((*id).clone());
+                        let locked_constraints = constraints.clone();
+                        let locked_kind = kind.clone();
+                        id.quick_assign(move |_, request, _| {
+                            let mut constraints = locked_constraints.borrow_mut();
+                            match request {
+                                zwp_locked_pointer_v1::Request::SetCursorPositionHint {
+                                    surface_x,
+                                    surface_y,
+                                } => constraints.with_constraint(&locked_kind, |constraint| {
+                                    constraint.cursor_hint = Some((surface_x, surface_y))
+                                }),
+                                zwp_locked_pointer_v1::Request::SetRegion { region } => constraints
+                                    .with_constraint(&locked_kind, |constraint| {
+                                        constraint.region = region_attributes(region)
+                                    }),
+                                zwp_locked_pointer_v1::Request::Destroy => constraints.remove(&locked_kind),
+                                _ => unreachable!(),
+                            }
+                        });
+                        (kind, surface, region, lifetime)
+                    }
+                    zwp_pointer_constraints_v1::Request::ConfinePointer {
+                        id,
+                        surface,
+                        region,
+                        lifetime,
+                        ..
+                    } => {
+                        let kind = ConstraintKind::Confined((*id).clone());
+                        let confined_constraints = constraints.clone();
+                        let confined_kind = kind.clone();
+                        id.quick_assign(move |_, request, _| {
+                            let mut constraints = confined_constraints.borrow_mut();
+                            match request {
+                                zwp_confined_pointer_v1::Request::SetRegion { region } => constraints
+                                    .with_constraint(&confined_kind, |constraint| {
+                                        constraint.region = region_attributes(region)
+                                    }),
+                                zwp_confined_pointer_v1::Request::Destroy => {
+                                    constraints.remove(&confined_kind)
+                                }
+                                _ => unreachable!(),
+                            }
+                        });
+                        (kind, surface, region, lifetime)
+                    }
+                    zwp_pointer_constraints_v1::Request::Destroy => return,
+                    _ => unreachable!(),
+                };
+
+                let mut constraints = constraints.borrow_mut();
+                if constraints.has_constraint(&surface) {
+                    manager.as_ref().post_error(
+                        zwp_pointer_constraints_v1::Error::AlreadyConstrained.to_raw(),
+                        "The surface already has a pointer constraint.".into(),
+                    );
+                    return;
+                }
+
+                let oneshot = lifetime == zwp_pointer_constraints_v1::Lifetime::Oneshot.to_raw();
+                debug!(log, "New pointer constraint"; "oneshot" => oneshot);
+                constraints.constraints.push(PointerConstraint {
+                    kind,
+                    surface,
+                    region: region_attributes(region),
+                    cursor_hint: None,
+                    origin: (0.0, 0.0),
+                    oneshot,
+                    active: false,
+                    defunct: false,
+                });
+            });
+        }),
+    );
+
+    (pointer_constraints, global)
+}
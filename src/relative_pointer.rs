@@ -0,0 +1,62 @@
+use smithay::reexports::{
+    wayland_protocols::unstable::relative_pointer::v1::server::{
+        zwp_relative_pointer_manager_v1::{self, ZwpRelativePointerManagerV1},
+        zwp_relative_pointer_v1::{self, ZwpRelativePointerV1},
+    },
+    wayland_server::{protocol::wl_surface::WlSurface, Display, Filter, Global, Main},
+};
+
+use crate::state::AnvilState;
+
+/// The relative pointer objects of a seat.
+#[derive(Default)]
+pub struct RelativePointers {
+    pointers: Vec<ZwpRelativePointerV1>,
+}
+
+impl RelativePointers {
+    /// Sends a relative motion to all relative pointers of the client owning `focus`.
+    ///
+    /// `utime` is in microseconds, the unaccelerated deltas come straight from the device.
+    pub fn relative_motion(&mut self, focus: &WlSurface, utime: u64, delta: (f64, f64), delta_unaccel: (f64, f64)) {
+        self.pointers.retain(|pointer| pointer.as_ref().is_alive());
+        for pointer in &self.pointers {
+            if pointer.as_ref().same_client_as(focus.as_ref()) {
+                pointer.relative_motion(
+                    (utime >> 32) as u32,
+                    utime as u32,
+                    delta.0,
+                    delta.1,
+                    delta_unaccel.0,
+                    delta_unaccel.1,
+                );
+            }
+        }
+    }
+}
+
+pub fn init_relative_pointer_manager(
+    display: &mut Display,
+    log: ::slog::Logger,
+) -> Global<ZwpRelativePointerManagerV1> {
+    display.create_global::<ZwpRelativePointerManagerV1, _>(
+        1,
+        Filter::new(move |(manager, _version): (Main<ZwpRelativePointerManagerV1>, u32), _, _| {
+            let log = log.clone();
+            manager.quick_assign(move |_, request, mut ddata| match request {
+                zwp_relative_pointer_manager_v1::Request::GetRelativePointer { id, pointer } => {
+                    trace!(log, "New relative pointer");
+                    id.quick_assign(|_, request, _| match request {
+                        zwp_relative_pointer_v1::Request::Destroy => {}
+                        _ => unreachable!(),
+                    });
+                    let state = ddata.get::<AnvilState>().unwrap();
+                    let seat = state.seat_of_pointer(&pointer);
+                    state.seats[seat].relative_pointers.borrow_mut().pointers.push((*id).clone());
+                }
+                zwp_relative_pointer_manager_v1::Request::Destroy => {}
+                _ => unreachable!(),
+            });
+        }),
+    )
+}
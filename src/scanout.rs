@@ -0,0 +1,356 @@
+use std::{cell::RefCell, convert::TryFrom, error::Error};
+
+use smithay::{
+    backend::renderer::gles2::Gles2Texture,
+    reexports::{
+        drm::control::{crtc, framebuffer, Device as ControlDevice, PageFlipFlags},
+        gbm::{BufferObject, BufferObjectFlags, Device as GbmDevice, Format, Modifier},
+        wayland_server::protocol::{wl_buffer::WlBuffer, wl_output},
+    },
+    utils::Rectangle,
+    wayland::{compositor::TraversalAction, shm::with_buffer_contents},
+};
+
+use crate::{
+    dmabuf::{buffer_dmabuf, DmabufBuffer},
+    drawing::surface_buffer,
+    shell::{MyCompositorToken, MyWindowMap, SurfaceData},
+};
+
+/// What decides whether the topmost window of an output can be shown without compositing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    /// The bounding box of the window, with its subsurfaces and popups
+    bbox: Rectangle,
+    /// The number of surfaces of the window with contents
+    mapped: usize,
+    buffer_transform: wl_output::Transform,
+    /// Whether the viewport of the surface crops its buffer
+    cropped: bool,
+    /// The size of the buffer, in pixels
+    buffer_size: Option<(i32, i32)>,
+}
+
+impl Candidate {
+    /// Whether the window covers the output exactly with a single surface, showing a buffer of
+    /// the size of the mode without a transform or a crop.
+    fn eligible(&self, geometry: &Rectangle, mode_size: (u32, u32)) -> bool {
+        self.bbox == *geometry
+            && self.mapped == 1
+            && self.buffer_transform == wl_output::Transform::Normal
+            && !self.cropped
+            && self.buffer_size == Some((mode_size.0 as i32, mode_size.1 as i32))
+    }
+}
+
+/// Returns the buffer of the window covering an output, if it could be shown without compositing.
+///
+/// The window has to be the topmost one on the output, cover it exactly with a single surface and
+/// show a hardware buffer of the size of the mode, without a transform or a crop.
+pub fn fullscreen_buffer(
+    window_map: &MyWindowMap,
+    geometry: &Rectangle,
+    mode_size: (u32, u32),
+    token: MyCompositorToken,
+) -> Option<WlBuffer> {
+    let mut top = None;
+    window_map.with_windows_from_bottom_to_top(|toplevel, _, bbox| {
+        if geometry.overlaps(bbox) {
+            top = Some((toplevel.get_surface().cloned(), *bbox));
+        }
+    });
+    let (surface, bbox) = match top {
+        Some((Some(surface), bbox)) => (surface, bbox),
+        _ => return None,
+    };
+
+    let mut mapped = 0;
+    token.with_surface_tree_downward(
+        &surface,
+        (),
+        |_, attributes, _, _| {
+            let data = attributes.user_data.get::<RefCell<SurfaceData>>();
+            if data.and_then(|data| data.borrow().size()).is_some() {
+                mapped += 1;
+            }
+            TraversalAction::DoChildren(())
+        },
+        |_, _, _, _| {},
+        |_, _, _, _| true,
+    );
+
+    let buffer = token.with_surface_data(&surface, |attributes| {
+        let data = attributes.user_data.get::<RefCell<SurfaceData>>()?.borrow();
+        let candidate = Candidate {
+            bbox,
+            mapped,
+            buffer_transform: attributes.buffer_transform,
+            cropped: data.current_state.viewport.source.is_some(),
+            buffer_size: data.current_state.buffer_dimensions,
+        };
+        if !candidate.eligible(geometry, mode_size) {
+            return None;
+        }
+        surface_buffer::<Gles2Texture>(&data)
+    })?;
+    // shm buffers can only be read by the cpu
+    if with_buffer_contents(&buffer, |_, _| ()).is_ok() {
+        return None;
+    }
+    Some(buffer)
+}
+
+/// The flag of addfb2 saying the buffer has explicit modifiers, from drm_mode.h.
+const DRM_MODE_FB_MODIFIERS: u32 = 1 << 1;
+
+/// A client buffer on the primary plane, it has to live until the crtc shows another one.
+struct ScanoutBuffer {
+    fb: framebuffer::Handle,
+    _bo: BufferObject<()>,
+    _buffer: WlBuffer,
+}
+
+/// Shows client buffers on the primary plane of a crtc, instead of compositing them.
+///
+/// The client buffers are released like composited ones, when the client attaches the next buffer.
+///
+/// The flips go past the rendering surface of the crtc, it has to be set up again before it
+/// composites the next frame.
+pub struct DirectScanout<A: ControlDevice + 'static> {
+    gbm: GbmDevice<A>,
+    crtc: crtc::Handle,
+    /// The client buffer the crtc shows
+    current: Option<ScanoutBuffer>,
+    /// The client buffer the crtc flips to with the next vblank
+    pending: Option<ScanoutBuffer>,
+    /// Whether the crtc flips with the next vblank, to a client buffer or a composited frame
+    flip_pending: bool,
+    /// Whether the rendering surface of the crtc has to be set up again
+    reset: bool,
+}
+
+impl<A: ControlDevice + 'static> DirectScanout<A> {
+    pub fn new(gbm: GbmDevice<A>, crtc: crtc::Handle) -> Self {
+        DirectScanout {
+            gbm,
+            crtc,
+            current: None,
+            pending: None,
+            flip_pending: false,
+            reset: false,
+        }
+    }
+
+    /// Whether the crtc shows or flips to a client buffer.
+    pub fn is_active(&self) -> bool {
+        self.current.is_some() || self.pending.is_some()
+    }
+
+    /// Asks for the rendering surface of the crtc to be set up again, to composite.
+    pub fn request_reset(&mut self) {
+        self.reset = true;
+    }
+
+    /// Whether the rendering surface of the crtc has to be set up again, once.
+    pub fn take_reset(&mut self) -> bool {
+        std::mem::replace(&mut self.reset, false)
+    }
+
+    /// Flips the crtc to a client buffer, returns `Ok(false)` if the primary plane can not show it.
+    pub fn flip(&mut self, buffer: &WlBuffer, mode_size: (u32, u32)) -> Result<bool, Box<dyn Error>> {
+        let bo = match buffer_dmabuf(buffer) {
+            Some(dmabuf) => self.import_dmabuf(dmabuf)?,
+            None => self
+                .gbm
+                .import_buffer_object_from_wayland::<()>(buffer, BufferObjectFlags::SCANOUT)?,
+        };
+        if (bo.width()?, bo.height()?) != mode_size {
+            return Ok(false);
+        }
+        if !matches!(bo.format()?, Format::Xrgb8888 | Format::Argb8888) {
+            return Ok(false);
+        }
+
+        let fb = match self.add_framebuffer(&bo)? {
+            Some(fb) => fb,
+            // the device does not know the layout of the buffer
+            None => return Ok(false),
+        };
+        if let Err(err) = self
+            .gbm
+            .page_flip(self.crtc, fb, &[PageFlipFlags::PageFlipEvent], None)
+        {
+            let _ = self.gbm.destroy_framebuffer(fb);
+            return Err(err.into());
+        }
+        self.pending = Some(ScanoutBuffer {
+            fb,
+            _bo: bo,
+            _buffer: buffer.clone(),
+        });
+        self.flip_pending = true;
+        Ok(true)
+    }
+
+    /// Adds a framebuffer with the format and modifier of a buffer object.
+    ///
+    /// Returns `Ok(None)` if the device can not take the modifier, devices without addfb2 only
+    /// take buffers without an explicit layout or with the linear one.
+    fn add_framebuffer(&self, bo: &BufferObject<()>) -> Result<Option<framebuffer::Handle>, Box<dyn Error>> {
+        let modifier = bo.modifier()?;
+        let planes = bo.plane_count()? as usize;
+        let implicit = modifier == Modifier::Invalid;
+        let mut modifiers = [None; 4];
+        if !implicit {
+            for plane_modifier in modifiers.iter_mut().take(planes) {
+                *plane_modifier = Some(modifier);
+            }
+        }
+        let flags = if implicit { 0 } else { DRM_MODE_FB_MODIFIERS };
+        match self.gbm.add_planar_framebuffer(bo, &modifiers, flags) {
+            Ok(fb) => Ok(Some(fb)),
+            Err(_) if (implicit || modifier == Modifier::Linear) && planes == 1 => {
+                let depth = if bo.format()? == Format::Argb8888 { 32 } else { 24 };
+                Ok(Some(self.gbm.add_framebuffer(bo, depth, 32)?))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Imports a buffer of linux-dmabuf, gbm only imports wl_drm buffers from their resources.
+    fn import_dmabuf(&self, dmabuf: &DmabufBuffer) -> Result<BufferObject<()>, Box<dyn Error>> {
+        let (mut fds, mut strides, mut offsets) = ([-1; 4], [0; 4], [0; 4]);
+        for (idx, &(fd, offset, stride)) in dmabuf.planes.iter().enumerate() {
+            fds[idx] = fd;
+            offsets[idx] = offset as i32;
+            strides[idx] = stride as i32;
+        }
+        let bo = self.gbm.import_buffer_object_from_dma_buf_with_modifiers(
+            dmabuf.planes.len() as u32,
+            fds,
+            dmabuf.width as u32,
+            dmabuf.height as u32,
+            Format::try_from(dmabuf.format)?,
+            BufferObjectFlags::SCANOUT,
+            strides,
+            offsets,
+            Modifier::from(dmabuf.modifier),
+        )?;
+        Ok(bo)
+    }
+
+    /// Notes that a composited frame was queued, the client buffer goes away with its vblank.
+    pub fn composited(&mut self) {
+        self.flip_pending = true;
+    }
+
+    /// Frees the client buffer the crtc stopped showing, with the vblank of a flip.
+    pub fn frame_submitted(&mut self) {
+        if !self.flip_pending {
+            return;
+        }
+        self.flip_pending = false;
+        let previous = std::mem::replace(&mut self.current, self.pending.take());
+        if let Some(previous) = previous {
+            let _ = self.gbm.destroy_framebuffer(previous.fb);
+        }
+    }
+}
+
+impl<A: ControlDevice + 'static> Drop for DirectScanout<A> {
+    fn drop(&mut self) {
+        for buffer in self.current.take().into_iter().chain(self.pending.take()) {
+            let _ = self.gbm.destroy_framebuffer(buffer.fb);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: Rectangle = Rectangle {
+        x: 1920,
+        y: 0,
+        width: 1280,
+        height: 720,
+    };
+
+    fn fullscreen() -> Candidate {
+        Candidate {
+            bbox: OUTPUT,
+            mapped: 1,
+            buffer_transform: wl_output::Transform::Normal,
+            cropped: false,
+            buffer_size: Some((2560, 1440)),
+        }
+    }
+
+    #[test]
+    fn a_fullscreen_window_is_eligible() {
+        assert!(fullscreen().eligible(&OUTPUT, (2560, 1440)));
+    }
+
+    #[test]
+    fn the_window_has_to_cover_the_output_exactly() {
+        let larger = Candidate {
+            bbox: Rectangle {
+                x: OUTPUT.x - 10,
+                width: OUTPUT.width + 20,
+                ..OUTPUT
+            },
+            ..fullscreen()
+        };
+        assert!(!larger.eligible(&OUTPUT, (2560, 1440)));
+        let smaller = Candidate {
+            bbox: Rectangle {
+                height: OUTPUT.height - 1,
+                ..OUTPUT
+            },
+            ..fullscreen()
+        };
+        assert!(!smaller.eligible(&OUTPUT, (2560, 1440)));
+    }
+
+    #[test]
+    fn subsurfaces_need_compositing() {
+        let candidate = Candidate {
+            mapped: 2,
+            ..fullscreen()
+        };
+        assert!(!candidate.eligible(&OUTPUT, (2560, 1440)));
+    }
+
+    #[test]
+    fn transformed_buffers_need_compositing() {
+        let candidate = Candidate {
+            buffer_transform: wl_output::Transform::_90,
+            buffer_size: Some((1440, 2560)),
+            ..fullscreen()
+        };
+        assert!(!candidate.eligible(&OUTPUT, (2560, 1440)));
+    }
+
+    #[test]
+    fn cropped_buffers_need_compositing() {
+        let candidate = Candidate {
+            cropped: true,
+            ..fullscreen()
+        };
+        assert!(!candidate.eligible(&OUTPUT, (2560, 1440)));
+    }
+
+    #[test]
+    fn the_buffer_has_to_have_the_size_of_the_mode() {
+        let candidate = Candidate {
+            buffer_size: Some((1280, 720)),
+            ..fullscreen()
+        };
+        assert!(!candidate.eligible(&OUTPUT, (2560, 1440)));
+        let unmapped = Candidate {
+            buffer_size: None,
+            ..fullscreen()
+        };
+        assert!(!unmapped.eligible(&OUTPUT, (2560, 1440)));
+    }
+}
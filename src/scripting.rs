@@ -0,0 +1,311 @@
+//! Scripts hooking into the compositor, written in Rhai.
+//!
+//! The script is `waybox.rhai` next to the configuration file, or the file the `script` key of
+//! the configuration names. Its statements run when it is loaded, and it can define the hooks
+//!
+//! - `on_window_open(window)` and `on_focus_change(window)`, with a window like the ipc `windows`
+//!   query describes it, or `()` when no window has the focus anymore,
+//! - `on_output_added(output)`, with an output like the ipc `outputs` query describes it,
+//! - `on_key(key, modifiers)`, with the keysym name and the modifier names (`Ctrl`, `Alt`,
+//!   `Shift`, `Logo`), for every key press before the key bindings, it handled the key if it
+//!   returns `true`.
+//!
+//! `register_action(name, function)` makes a function the action of the key bindings with that
+//! `action` instead of a `command`, the built-in actions `next_window` and `previous_window` can
+//! not be replaced.
+//!
+//! The scripts query the compositor with `windows()`, `focused()` (the id of the focused window
+//! or `()`), `workspace()`, `workspaces()` (their number) and `outputs()`, and control it with
+//! `focus(id)`, `close(id)`, `move_window(id, x, y)`, `resize_window(id, width, height)`,
+//! `switch_workspace(number)` and `exec(command)`. These are the ipc commands, they apply after
+//! the script returned. `print` and `debug` go to the log.
+//!
+//! A script failing or running for too long is stopped and logged, the compositor goes on.
+
+use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
+
+use rhai::{serde::to_dynamic, Array, CallFnOptions, Dynamic, Engine, FnPtr, FuncArgs, Scope, AST, INT};
+use serde_json::{json, Value};
+use slog::Logger;
+use smithay::wayland::seat::{Keysym, ModifiersState};
+
+use crate::{
+    ipc::{EventKind, Snapshot},
+    state::AnvilState,
+};
+
+/// A script is stopped after this many operations, so that a loop can not hang the compositor.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// The hooks called for changes of the windows and outputs.
+const EVENT_HOOKS: [&str; 3] = ["on_window_open", "on_focus_change", "on_output_added"];
+
+/// What the functions of the script share with the compositor.
+#[derive(Default)]
+struct Context {
+    /// The state the queries answer from, taken before a hook or action is called
+    snapshot: Option<Snapshot>,
+    /// The ipc commands the script asked for, applied after it returned
+    commands: Vec<Value>,
+    /// The actions key bindings can run, by name
+    actions: HashMap<String, FnPtr>,
+}
+
+impl Context {
+    fn command(&mut self, command: Value) {
+        self.commands.push(command);
+    }
+}
+
+/// A loaded script.
+pub struct Scripts {
+    engine: Engine,
+    ast: AST,
+    /// The variables of the script
+    scope: RefCell<Scope<'static>>,
+    context: Rc<RefCell<Context>>,
+    /// The names of the functions the script defines
+    functions: Vec<String>,
+    log: Logger,
+}
+
+impl Scripts {
+    /// Compiles and runs a script, a broken one is logged and there are no scripts then.
+    pub fn load(path: &Path, log: &Logger) -> Option<Scripts> {
+        let log = log.new(o!("script" => path.display().to_string()));
+        let context = Rc::new(RefCell::new(Context::default()));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let print_log = log.clone();
+        engine.on_print(move |text| info!(print_log, "{}", text));
+        let debug_log = log.clone();
+        engine.on_debug(move |text, _, position| debug!(debug_log, "{}", text; "position" => position.to_string()));
+        register_api(&mut engine, &context);
+
+        let ast = match engine.compile_file(path.into()) {
+            Ok(ast) => ast,
+            Err(err) => {
+                error!(log, "Failed to load the script: {}", err);
+                return None;
+            }
+        };
+        let mut scope = Scope::new();
+        if let Err(err) = engine.run_ast_with_scope(&mut scope, &ast) {
+            error!(log, "The script failed: {}", err);
+            return None;
+        }
+        let functions = ast.iter_functions().map(|function| function.name.to_string()).collect();
+        info!(log, "Loaded the script");
+
+        Some(Scripts {
+            engine,
+            ast,
+            scope: RefCell::new(scope),
+            context,
+            functions,
+            log,
+        })
+    }
+
+    fn has_hook(&self, hook: &str) -> bool {
+        self.functions.iter().any(|function| function == hook)
+    }
+
+    /// Whether the script wants to hear about the windows and outputs changing.
+    pub fn wants_events(&self) -> bool {
+        EVENT_HOOKS.iter().any(|hook| self.has_hook(hook))
+    }
+
+    /// Calls a hook the script defines, `None` if it failed.
+    fn call(&self, snapshot: Snapshot, hook: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        self.context.borrow_mut().snapshot = Some(snapshot);
+        // the statements of the script ran when it was loaded
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope.borrow_mut(),
+            &self.ast,
+            hook,
+            args,
+        );
+        self.context.borrow_mut().snapshot = None;
+        result
+            .map_err(|err| error!(self.log, "The script failed in {}: {}", hook, err))
+            .ok()
+    }
+
+    /// Calls the event hooks for the events leading to `current`.
+    pub fn publish(&self, current: &Snapshot, events: &[(EventKind, Value)]) {
+        for (_, event) in events {
+            let (hook, subject) = match (event["event"].as_str(), event["change"].as_str()) {
+                (Some("window"), Some("opened")) => ("on_window_open", &event["window"]),
+                (Some("window"), Some("focused")) => ("on_focus_change", &event["window"]),
+                (Some("output"), Some("added")) => ("on_output_added", &event["output"]),
+                _ => continue,
+            };
+            if self.has_hook(hook) {
+                let subject = to_dynamic(subject).unwrap_or_default();
+                self.call(current.clone(), hook, (subject,));
+            }
+        }
+    }
+
+    /// Whether the script wants the `on_key` hook called, the snapshot is only taken then.
+    pub fn wants_keys(&self) -> bool {
+        self.has_hook("on_key")
+    }
+
+    /// Calls the `on_key` hook, returns whether the script handled the key.
+    pub fn key(&self, snapshot: Snapshot, modifiers: ModifiersState, keysym: Keysym) -> bool {
+        let key = ::xkbcommon::xkb::keysym_get_name(keysym);
+        let modifiers = [
+            (modifiers.ctrl, "Ctrl"),
+            (modifiers.alt, "Alt"),
+            (modifiers.shift, "Shift"),
+            (modifiers.logo, "Logo"),
+        ]
+        .iter()
+        .filter(|(pressed, _)| *pressed)
+        .map(|(_, name)| Dynamic::from(*name))
+        .collect::<Array>();
+        self.call(snapshot, "on_key", (key, modifiers))
+            .and_then(|handled| handled.as_bool().ok())
+            .unwrap_or(false)
+    }
+
+    /// Runs an action the script registered.
+    pub fn action(&self, snapshot: Snapshot, name: &str) {
+        let action = self.context.borrow().actions.get(name).cloned();
+        let action = match action {
+            Some(action) => action,
+            None => {
+                warn!(self.log, "The script has no action {}", name);
+                return;
+            }
+        };
+        self.context.borrow_mut().snapshot = Some(snapshot);
+        let result = action.call::<Dynamic>(&self.engine, &self.ast, ());
+        self.context.borrow_mut().snapshot = None;
+        if let Err(err) = result {
+            error!(self.log, "The script failed in action {}: {}", name, err);
+        }
+    }
+
+    /// The commands the script asked for since the last call.
+    pub fn take_commands(&self) -> Vec<Value> {
+        std::mem::take(&mut self.context.borrow_mut().commands)
+    }
+}
+
+/// Registers the functions the scripts use to query and control the compositor.
+fn register_api(engine: &mut Engine, context: &Rc<RefCell<Context>>) {
+    let ctx = context.clone();
+    engine.register_fn("windows", move || -> Dynamic {
+        match ctx.borrow().snapshot {
+            Some(ref snapshot) => to_dynamic(&snapshot.tree.windows).unwrap_or_default(),
+            None => Dynamic::from(Array::new()),
+        }
+    });
+    let ctx = context.clone();
+    engine.register_fn("focused", move || -> Dynamic {
+        match ctx.borrow().snapshot.as_ref().and_then(|snapshot| snapshot.tree.focused) {
+            Some(id) => Dynamic::from(id as INT),
+            None => Dynamic::UNIT,
+        }
+    });
+    let ctx = context.clone();
+    engine.register_fn("workspace", move || -> INT {
+        ctx.borrow()
+            .snapshot
+            .as_ref()
+            .map_or(1, |snapshot| snapshot.tree.active_workspace as INT)
+    });
+    let ctx = context.clone();
+    engine.register_fn("workspaces", move || -> INT {
+        ctx.borrow()
+            .snapshot
+            .as_ref()
+            .map_or(1, |snapshot| snapshot.tree.workspaces as INT)
+    });
+    let ctx = context.clone();
+    engine.register_fn("outputs", move || -> Dynamic {
+        match ctx.borrow().snapshot {
+            Some(ref snapshot) => to_dynamic(&snapshot.outputs).unwrap_or_default(),
+            None => Dynamic::from(Array::new()),
+        }
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("focus", move |id: INT| {
+        ctx.borrow_mut().command(json!({ "type": "focus", "id": id }));
+    });
+    let ctx = context.clone();
+    engine.register_fn("close", move |id: INT| {
+        ctx.borrow_mut().command(json!({ "type": "close", "id": id }));
+    });
+    let ctx = context.clone();
+    engine.register_fn("move_window", move |id: INT, x: INT, y: INT| {
+        ctx.borrow_mut().command(json!({ "type": "move", "id": id, "x": x, "y": y }));
+    });
+    let ctx = context.clone();
+    engine.register_fn("resize_window", move |id: INT, width: INT, height: INT| {
+        ctx.borrow_mut()
+            .command(json!({ "type": "resize", "id": id, "width": width, "height": height }));
+    });
+    let ctx = context.clone();
+    engine.register_fn("switch_workspace", move |number: INT| {
+        ctx.borrow_mut().command(json!({ "type": "workspace", "number": number }));
+    });
+    let ctx = context.clone();
+    engine.register_fn("exec", move |command: &str| {
+        ctx.borrow_mut().command(json!({ "type": "exec", "command": command }));
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("register_action", move |name: &str, action: FnPtr| {
+        ctx.borrow_mut().actions.insert(name.to_string(), action);
+    });
+}
+
+impl AnvilState {
+    /// Loads the script of the configuration, if it has one.
+    pub fn load_scripts(&mut self) {
+        self.scripts = self
+            .config
+            .script
+            .as_ref()
+            .and_then(|path| Scripts::load(path, &self.log));
+        self.run_script_commands();
+    }
+
+    /// Gives the script a key press, returns whether it handled the key.
+    pub fn script_key(&self, modifiers: ModifiersState, keysym: Keysym) -> bool {
+        match self.scripts {
+            Some(ref scripts) if scripts.wants_keys() => scripts.key(self.ipc_snapshot(), modifiers, keysym),
+            _ => false,
+        }
+    }
+
+    /// Runs an action the script registered, for a key binding.
+    pub fn run_script_action(&mut self, name: &str) {
+        match self.scripts {
+            Some(ref scripts) => scripts.action(self.ipc_snapshot(), name),
+            None => warn!(self.log, "There is no script for the action {}", name),
+        }
+        self.run_script_commands();
+    }
+
+    /// Applies what the script asked for.
+    pub fn run_script_commands(&mut self) {
+        let commands = match self.scripts {
+            Some(ref scripts) => scripts.take_commands(),
+            None => return,
+        };
+        for command in commands {
+            if let Err(err) = self.handle_command(&command) {
+                warn!(self.log, "A script command failed: {}", err; "command" => command.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use smithay::{
+    reexports::wayland_server::{
+        protocol::{wl_pointer::WlPointer, wl_surface::WlSurface},
+        Display,
+    },
+    wayland::{
+        compositor::CompositorToken,
+        data_device::set_data_device_focus,
+        seat::{CursorImageStatus, KeyboardHandle, PointerHandle, Seat},
+        Serial,
+    },
+};
+
+use crate::{
+    cursor::CursorShape, custom::config::Configuration, input_method::InputMethodState, keymap::SeatKeymap,
+    pointer_constraints::ConstraintKind, relative_pointer::RelativePointers, shell::Roles,
+    shortcuts_inhibit::ShortcutsInhibitors,
+};
+
+/// The cursor of a seat, everything a renderer needs to draw it.
+#[derive(Clone)]
+pub struct SeatCursor {
+    pub location: Rc<RefCell<(f64, f64)>>,
+    pub status: Arc<Mutex<CursorImageStatus>>,
+    /// The shape set by the grab of the compositor
+    pub shape: Rc<RefCell<CursorShape>>,
+    /// The shape the client under the pointer asked for by name
+    pub requested_shape: Rc<Cell<CursorShape>>,
+    /// Whether a client inhibits the compositor shortcuts of the seat
+    pub shortcuts_inhibited: Rc<Cell<bool>>,
+    /// The icon of the drag and drop the seat is doing
+    pub dnd_icon: Arc<Mutex<Option<WlSurface>>>,
+}
+
+impl SeatCursor {
+    /// The shape to show while the status is `CursorImageStatus::Default`, the shape of a grab
+    /// wins over the one of the client.
+    pub fn current_shape(&self) -> CursorShape {
+        match *self.shape.borrow() {
+            CursorShape::Default => self.requested_shape.get(),
+            shape => shape,
+        }
+    }
+}
+
+/// A wayland seat with its own pointer, keyboard, focus and cursor.
+#[derive(Clone)]
+pub struct MySeat {
+    pub name: String,
+    pub seat: Seat,
+    pub pointer: PointerHandle,
+    pub keyboard: KeyboardHandle,
+    pub cursor: SeatCursor,
+    pub shortcuts_inhibitors: Rc<RefCell<ShortcutsInhibitors>>,
+    pub input_method: Rc<RefCell<InputMethodState>>,
+    /// The surface with the keyboard focus
+    pub keyboard_focus: Rc<RefCell<Option<WlSurface>>>,
+    /// The keyboard focus as last noted in the focus history
+    pub noted_focus: Rc<RefCell<Option<WlSurface>>>,
+    /// The surface under the pointer and its origin, as last sent to the clients
+    pub pointer_focus: Rc<RefCell<Option<(WlSurface, (f64, f64))>>>,
+    /// The serial of the last pointer enter, sent as the pointer focus changed
+    pub pointer_enter_serial: Rc<Cell<Option<Serial>>>,
+    /// The pointer constraint active on the seat
+    pub pointer_constraint: Rc<RefCell<Option<ConstraintKind>>>,
+    /// The relative pointers of the clients, they get the motion of this seat
+    pub relative_pointers: Rc<RefCell<RelativePointers>>,
+    /// When a key of this seat last went to a client, for the focus stealing prevention
+    pub last_typed: Rc<Cell<Option<Instant>>>,
+    /// The keycodes of the presses the script handled, their releases are not forwarded either
+    pub script_keys: Rc<RefCell<Vec<u32>>>,
+}
+
+impl MySeat {
+    pub fn new(
+        display: &mut Display,
+        name: String,
+        token: CompositorToken<Roles>,
+        config: &Configuration,
+        log: slog::Logger,
+    ) -> MySeat {
+        let (mut seat, _) = Seat::new(display, name.clone(), token, log);
+
+        let cursor = SeatCursor {
+            location: Rc::new(RefCell::new((0.0, 0.0))),
+            status: Arc::new(Mutex::new(CursorImageStatus::Default)),
+            shape: Rc::new(RefCell::new(CursorShape::Default)),
+            requested_shape: Rc::new(Cell::new(CursorShape::Default)),
+            shortcuts_inhibited: Rc::new(Cell::new(false)),
+            dnd_icon: Arc::new(Mutex::new(None)),
+        };
+
+        // the shell grabs only know about the seat, they find the cursor shape to set here
+        let shape = cursor.shape.clone();
+        seat.user_data().insert_if_missing(move || shape);
+        // same for the protocol globals, which get the seat from the client
+        let shortcuts_inhibitors = Rc::new(RefCell::new(ShortcutsInhibitors::new(
+            cursor.shortcuts_inhibited.clone(),
+        )));
+        let inhibitors = shortcuts_inhibitors.clone();
+        seat.user_data().insert_if_missing(move || inhibitors);
+        let input_method = Rc::new(RefCell::new(InputMethodState::default()));
+        let stored_input_method = input_method.clone();
+        seat.user_data().insert_if_missing(move || stored_input_method);
+        let keymap = SeatKeymap::new(&config.keyboard);
+        seat.user_data().insert_if_missing(move || keymap);
+
+        let cursor_status = cursor.status.clone();
+        let requested_shape = cursor.requested_shape.clone();
+        let pointer = seat.add_pointer(token, move |new_status| {
+            // TODO: hide winit system cursor when relevant
+            // a cursor surface or a new focus replaces the shape a client asked for
+            requested_shape.set(CursorShape::Default);
+            *cursor_status.lock().unwrap() = new_status
+        });
+
+        let inhibitors = shortcuts_inhibitors.clone();
+        let focus_input_method = input_method.clone();
+        let keyboard_focus = Rc::new(RefCell::new(None));
+        let current_focus = keyboard_focus.clone();
+        let keyboard = seat
+            .add_keyboard(config.keyboard.get_seat_xkbconfig(), 200, 25, move |seat, focus| {
+                set_data_device_focus(seat, focus.and_then(|s| s.as_ref().client()));
+                inhibitors.borrow_mut().set_focus(focus);
+                focus_input_method.borrow_mut().set_focus(focus);
+                *current_focus.borrow_mut() = focus.cloned();
+            })
+            .expect("Failed to initialize the keyboard");
+
+        MySeat {
+            name,
+            seat,
+            pointer,
+            keyboard,
+            cursor,
+            shortcuts_inhibitors,
+            input_method,
+            keyboard_focus,
+            noted_focus: Rc::new(RefCell::new(None)),
+            pointer_focus: Rc::new(RefCell::new(None)),
+            pointer_enter_serial: Rc::new(Cell::new(None)),
+            pointer_constraint: Rc::new(RefCell::new(None)),
+            relative_pointers: Rc::new(RefCell::new(RelativePointers::default())),
+            last_typed: Rc::new(Cell::new(None)),
+            script_keys: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn pointer_location(&self) -> (f64, f64) {
+        *self.cursor.location.borrow()
+    }
+
+    pub fn set_pointer_location(&self, location: (f64, f64)) {
+        *self.cursor.location.borrow_mut() = location;
+    }
+
+    /// Moves the pointer of the seat, `under` is the surface under it with its location.
+    pub fn pointer_motion(
+        &self,
+        location: (f64, f64),
+        under: Option<(WlSurface, (f64, f64))>,
+        serial: Serial,
+        time: u32,
+    ) {
+        let entered = match (&*self.pointer_focus.borrow(), &under) {
+            (Some((old, _)), Some((new, _))) => old != new,
+            (_, new) => new.is_some(),
+        };
+        if entered {
+            self.pointer_enter_serial.set(Some(serial));
+        }
+        *self.pointer_focus.borrow_mut() = under.clone();
+        self.pointer.motion(location, under, serial, time);
+    }
+
+    /// Whether a request of a client with the serial of a pointer enter comes from the pointer
+    /// of this seat.
+    ///
+    /// A `wl_pointer` does not know its seat, but the serials are unique to the compositor: only
+    /// the seat that sent the enter, with its pointer still on a surface of the client, matches.
+    pub fn entered_pointer(&self, pointer: &WlPointer, serial: Serial) -> bool {
+        self.pointer_enter_serial.get() == Some(serial)
+            && self.pointer_focus.borrow().as_ref().map_or(false, |(focus, _)| {
+                pointer.as_ref().same_client_as(focus.as_ref())
+            })
+    }
+
+    /// Shows the named shape a client asked for.
+    pub fn request_cursor_shape(&self, shape: CursorShape) {
+        *self.cursor.status.lock().unwrap() = CursorImageStatus::Default;
+        self.cursor.requested_shape.set(shape);
+    }
+
+    /// Forgets a key the script handled, returns whether the released key was one of them.
+    pub fn release_script_key(&self, keycode: u32) -> bool {
+        let mut script_keys = self.script_keys.borrow_mut();
+        match script_keys.iter().position(|&key| key == keycode) {
+            Some(index) => {
+                script_keys.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns the cursor shape shared with the given seat.
+pub fn seat_cursor_shape(seat: &Seat) -> Rc<RefCell<CursorShape>> {
+    seat.user_data()
+        .get::<Rc<RefCell<CursorShape>>>()
+        .expect("Seat without cursor shape")
+        .clone()
+}
+
+/// Returns the keyboard shortcut inhibitors of the given seat.
+pub fn seat_shortcuts_inhibitors(seat: &Seat) -> Rc<RefCell<ShortcutsInhibitors>> {
+    seat.user_data()
+        .get::<Rc<RefCell<ShortcutsInhibitors>>>()
+        .expect("Seat without shortcuts inhibitors")
+        .clone()
+}
+
+/// Returns the input method state of the given seat.
+pub fn seat_input_method(seat: &Seat) -> Rc<RefCell<InputMethodState>> {
+    seat.user_data()
+        .get::<Rc<RefCell<InputMethodState>>>()
+        .expect("Seat without input method state")
+        .clone()
+}
+
+/// Returns the keymap of the given seat.
+pub fn seat_keymap(seat: &Seat) -> SeatKeymap {
+    seat.user_data()
+        .get::<SeatKeymap>()
+        .expect("Seat without keymap")
+        .clone()
+}
@@ -9,16 +9,18 @@ use smithay::backend::egl::display::EGLBufferReader;
 use smithay::{
     backend::renderer::buffer_dimensions,
     reexports::{
-        wayland_protocols::xdg_shell::server::xdg_toplevel,
+        wayland_protocols::{viewporter::server::wp_viewport::WpViewport, xdg_shell::server::xdg_toplevel},
         wayland_server::{
-            protocol::{wl_buffer, wl_callback, wl_pointer::ButtonState, wl_shell_surface, wl_surface},
+            protocol::{
+                wl_buffer, wl_callback, wl_output, wl_pointer::ButtonState, wl_shell_surface, wl_surface,
+            },
             Display,
         },
     },
     utils::Rectangle,
     wayland::{
         compositor::{
-            compositor_init, roles::Role, BufferAssignment, CompositorToken, RegionAttributes,
+            compositor_init, roles::Role, BufferAssignment, CompositorToken, Damage, RegionAttributes,
             SubsurfaceRole, SurfaceEvent, TraversalAction,
         },
         data_device::DnDIconRole,
@@ -32,11 +34,19 @@ use smithay::{
                 XdgSurfacePendingState, XdgSurfaceRole,
             },
         },
-        Serial,
+        Serial, SERIAL_COUNTER as SCOUNTER,
     },
 };
 
-use crate::window_map::{Kind as SurfaceKind, WindowMap};
+use crate::{
+    cursor::CursorShape,
+    damage::bounding_box,
+    input_method::InputPopupRole,
+    seat::seat_cursor_shape,
+    snap::SnapState,
+    viewporter::{surface_size, ViewportState},
+    window_map::{Kind as SurfaceKind, WindowMap},
+};
 
 #[cfg(feature = "xwayland")]
 use crate::xwayland::X11SurfaceRole;
@@ -48,6 +58,7 @@ define_roles!(Roles =>
     [ ShellSurface, ShellSurfaceRole]
     [ DnDIcon, DnDIconRole ]
     [ CursorImage, CursorImageRole ]
+    [ InputPopup, InputPopupRole ]
 );
 #[cfg(feature = "xwayland")]
 define_roles!(Roles =>
@@ -56,6 +67,7 @@ define_roles!(Roles =>
     [ X11Surface, X11SurfaceRole ]
     [ DnDIcon, DnDIconRole ]
     [ CursorImage, CursorImageRole ]
+    [ InputPopup, InputPopupRole ]
 );
 
 pub type MyWindowMap = WindowMap<Roles>;
@@ -64,9 +76,14 @@ pub type MyCompositorToken = CompositorToken<Roles>;
 
 struct MoveSurfaceGrab {
     start_data: GrabStartData,
+    ctoken: MyCompositorToken,
     window_map: Rc<RefCell<MyWindowMap>>,
+    snap: Rc<RefCell<SnapState>>,
     toplevel: SurfaceKind<Roles>,
     initial_window_location: (i32, i32),
+    /// The pointer location of the last motion
+    location: (f64, f64),
+    cursor_shape: Rc<RefCell<CursorShape>>,
 }
 
 impl PointerGrab for MoveSurfaceGrab {
@@ -82,10 +99,19 @@ impl PointerGrab for MoveSurfaceGrab {
         let dy = location.1 - self.start_data.location.1;
         let new_window_x = (self.initial_window_location.0 as f64 + dx) as i32;
         let new_window_y = (self.initial_window_location.1 as f64 + dy) as i32;
+        self.location = location;
+
+        let mut snap = self.snap.borrow_mut();
+        let new_window_location = snap.snap(
+            &self.window_map.borrow(),
+            &self.toplevel,
+            (new_window_x, new_window_y),
+        );
+        snap.preview = snap.zone(location).map(|(area, _)| area);
 
         self.window_map
             .borrow_mut()
-            .set_location(&self.toplevel, (new_window_x, new_window_y));
+            .set_location(&self.toplevel, new_window_location);
     }
 
     fn button(
@@ -100,6 +126,18 @@ impl PointerGrab for MoveSurfaceGrab {
         if handle.current_pressed().is_empty() {
             // No more buttons are pressed, release the grab.
             handle.unset_grab(serial, time);
+            *self.cursor_shape.borrow_mut() = CursorShape::Default;
+
+            let mut snap = self.snap.borrow_mut();
+            snap.preview = None;
+            if let Some(zone) = snap.zone(self.location) {
+                snap.tile(
+                    &mut self.window_map.borrow_mut(),
+                    &self.toplevel,
+                    zone,
+                    self.ctoken,
+                );
+            }
         }
     }
 
@@ -113,7 +151,7 @@ impl PointerGrab for MoveSurfaceGrab {
 }
 
 bitflags::bitflags! {
-    struct ResizeEdge: u32 {
+    pub struct ResizeEdge: u32 {
         const NONE = 0;
         const TOP = 1;
         const BOTTOM = 2;
@@ -154,8 +192,44 @@ impl From<ResizeEdge> for xdg_toplevel::ResizeEdge {
     }
 }
 
-struct ResizeSurfaceGrab {
-    start_data: GrabStartData,
+impl ResizeEdge {
+    /// The cursor to show while resizing along these edges.
+    fn cursor_shape(self) -> CursorShape {
+        match self {
+            ResizeEdge::TOP => CursorShape::ResizeTop,
+            ResizeEdge::BOTTOM => CursorShape::ResizeBottom,
+            ResizeEdge::LEFT => CursorShape::ResizeLeft,
+            ResizeEdge::RIGHT => CursorShape::ResizeRight,
+            ResizeEdge::TOP_LEFT => CursorShape::ResizeTopLeft,
+            ResizeEdge::TOP_RIGHT => CursorShape::ResizeTopRight,
+            ResizeEdge::BOTTOM_LEFT => CursorShape::ResizeBottomLeft,
+            ResizeEdge::BOTTOM_RIGHT => CursorShape::ResizeBottomRight,
+            _ => CursorShape::Default,
+        }
+    }
+
+    /// Parses an edge name like `left` or `bottom_right`, as used by the key bindings.
+    pub fn from_name(name: &str) -> Option<ResizeEdge> {
+        match name {
+            "top" => Some(ResizeEdge::TOP),
+            "bottom" => Some(ResizeEdge::BOTTOM),
+            "left" => Some(ResizeEdge::LEFT),
+            "right" => Some(ResizeEdge::RIGHT),
+            "top_left" => Some(ResizeEdge::TOP_LEFT),
+            "top_right" => Some(ResizeEdge::TOP_RIGHT),
+            "bottom_left" => Some(ResizeEdge::BOTTOM_LEFT),
+            "bottom_right" => Some(ResizeEdge::BOTTOM_RIGHT),
+            _ => None,
+        }
+    }
+}
+
+/// A resize of a toplevel along some edges, driven by the pointer or the keyboard.
+///
+/// The configures sent while it goes on carry the resizing state. Once finished, the surface
+/// goes through `ResizeState` until it committed the final size, so that a window resized
+/// along its top or left edge keeps its opposite edges in place.
+pub struct Resize {
     ctoken: MyCompositorToken,
     toplevel: SurfaceKind<Roles>,
     edges: ResizeEdge,
@@ -163,17 +237,60 @@ struct ResizeSurfaceGrab {
     last_window_size: (i32, i32),
 }
 
-impl PointerGrab for ResizeSurfaceGrab {
-    fn motion(
-        &mut self,
-        _handle: &mut PointerInnerHandle<'_>,
-        location: (f64, f64),
-        _focus: Option<(wl_surface::WlSurface, (f64, f64))>,
-        serial: Serial,
-        _time: u32,
-    ) {
-        let mut dx = location.0 - self.start_data.location.0;
-        let mut dy = location.1 - self.start_data.location.1;
+impl Resize {
+    /// Starts resizing a mapped toplevel, `None` if it is not mapped.
+    pub fn start(
+        ctoken: MyCompositorToken,
+        toplevel: SurfaceKind<Roles>,
+        edges: ResizeEdge,
+        window_map: &MyWindowMap,
+    ) -> Option<Resize> {
+        let initial_window_location = window_map.location(&toplevel)?;
+        let geometry = window_map.geometry(&toplevel)?;
+        let initial_window_size = (geometry.width, geometry.height);
+
+        let resize_data = ctoken.with_surface_data(toplevel.get_surface()?, move |attrs| {
+            let mut data = attrs
+                .user_data
+                .get::<RefCell<SurfaceData>>()
+                .unwrap()
+                .borrow_mut();
+            // A resize along the same edges whose size the client did not commit yet goes on
+            // from the size it asked for, keeping the location it started from.
+            let resize_data = match data.resize_state {
+                ResizeState::WaitingForFinalAck(resize_data, _)
+                | ResizeState::WaitingForCommit(resize_data)
+                    if resize_data.edges == edges =>
+                {
+                    resize_data
+                }
+                _ => ResizeData {
+                    edges,
+                    initial_window_location,
+                    initial_window_size,
+                    requested_size: initial_window_size,
+                },
+            };
+            data.resize_state = ResizeState::Resizing(resize_data);
+            resize_data
+        });
+
+        Some(Resize {
+            ctoken,
+            toplevel,
+            edges,
+            initial_window_size: resize_data.requested_size,
+            last_window_size: resize_data.requested_size,
+        })
+    }
+
+    /// Asks for the initial size changed by moving the pointer by `(dx, dy)` from where the
+    /// resize started, within the size limits of the surface.
+    pub fn update(&mut self, (mut dx, mut dy): (f64, f64), serial: Serial) {
+        let surface = match self.toplevel.get_surface() {
+            Some(surface) => surface,
+            None => return,
+        };
 
         let mut new_window_width = self.initial_window_size.0;
         let mut new_window_height = self.initial_window_size.1;
@@ -197,12 +314,10 @@ impl PointerGrab for ResizeSurfaceGrab {
             new_window_height = (self.initial_window_size.1 as f64 + dy) as i32;
         }
 
-        let (min_size, max_size) =
-            self.ctoken
-                .with_surface_data(self.toplevel.get_surface().unwrap(), |attrs| {
-                    let data = attrs.user_data.get::<RefCell<SurfaceData>>().unwrap().borrow();
-                    (data.min_size, data.max_size)
-                });
+        let (min_size, max_size) = self.ctoken.with_surface_data(surface, |attrs| {
+            let data = attrs.user_data.get::<RefCell<SurfaceData>>().unwrap().borrow();
+            (data.min_size, data.max_size)
+        });
 
         let min_width = min_size.0.max(1);
         let min_height = min_size.1.max(1);
@@ -239,6 +354,69 @@ impl PointerGrab for ResizeSurfaceGrab {
         }
     }
 
+    /// Finishes the resize with the last size asked for.
+    pub fn finish(&self, serial: Serial) {
+        let surface = match self.toplevel.get_surface() {
+            Some(surface) => surface,
+            None => return,
+        };
+
+        if let SurfaceKind::Xdg(xdg) = &self.toplevel {
+            // Send the final configure without the resizing state.
+            xdg.send_configure(ToplevelConfigure {
+                size: Some(self.last_window_size),
+                states: vec![],
+                serial,
+            });
+
+            self.ctoken.with_surface_data(surface, |attrs| {
+                let mut data = attrs
+                    .user_data
+                    .get::<RefCell<SurfaceData>>()
+                    .unwrap()
+                    .borrow_mut();
+                // a pointer resize may have taken over a keyboard resize, or the other way
+                if let ResizeState::Resizing(mut resize_data) = data.resize_state {
+                    resize_data.requested_size = self.last_window_size;
+                    data.resize_state = ResizeState::WaitingForFinalAck(resize_data, serial);
+                }
+            });
+        } else {
+            self.ctoken.with_surface_data(surface, |attrs| {
+                let mut data = attrs
+                    .user_data
+                    .get::<RefCell<SurfaceData>>()
+                    .unwrap()
+                    .borrow_mut();
+                if let ResizeState::Resizing(mut resize_data) = data.resize_state {
+                    resize_data.requested_size = self.last_window_size;
+                    data.resize_state = ResizeState::WaitingForCommit(resize_data);
+                }
+            });
+        }
+    }
+}
+
+struct ResizeSurfaceGrab {
+    start_data: GrabStartData,
+    resize: Resize,
+    cursor_shape: Rc<RefCell<CursorShape>>,
+}
+
+impl PointerGrab for ResizeSurfaceGrab {
+    fn motion(
+        &mut self,
+        _handle: &mut PointerInnerHandle<'_>,
+        location: (f64, f64),
+        _focus: Option<(wl_surface::WlSurface, (f64, f64))>,
+        serial: Serial,
+        _time: u32,
+    ) {
+        let dx = location.0 - self.start_data.location.0;
+        let dy = location.1 - self.start_data.location.1;
+        self.resize.update((dx, dy), serial);
+    }
+
     fn button(
         &mut self,
         handle: &mut PointerInnerHandle<'_>,
@@ -251,43 +429,8 @@ impl PointerGrab for ResizeSurfaceGrab {
         if handle.current_pressed().is_empty() {
             // No more buttons are pressed, release the grab.
             handle.unset_grab(serial, time);
-
-            if let SurfaceKind::Xdg(xdg) = &self.toplevel {
-                // Send the final configure without the resizing state.
-                xdg.send_configure(ToplevelConfigure {
-                    size: Some(self.last_window_size),
-                    states: vec![],
-                    serial,
-                });
-
-                self.ctoken
-                    .with_surface_data(self.toplevel.get_surface().unwrap(), |attrs| {
-                        let mut data = attrs
-                            .user_data
-                            .get::<RefCell<SurfaceData>>()
-                            .unwrap()
-                            .borrow_mut();
-                        if let ResizeState::Resizing(resize_data) = data.resize_state {
-                            data.resize_state = ResizeState::WaitingForFinalAck(resize_data, serial);
-                        } else {
-                            panic!("invalid resize state: {:?}", data.resize_state);
-                        }
-                    });
-            } else {
-                self.ctoken
-                    .with_surface_data(self.toplevel.get_surface().unwrap(), |attrs| {
-                        let mut data = attrs
-                            .user_data
-                            .get::<RefCell<SurfaceData>>()
-                            .unwrap()
-                            .borrow_mut();
-                        if let ResizeState::Resizing(resize_data) = data.resize_state {
-                            data.resize_state = ResizeState::WaitingForCommit(resize_data);
-                        } else {
-                            panic!("invalid resize state: {:?}", data.resize_state);
-                        }
-                    });
-            }
+            *self.cursor_shape.borrow_mut() = CursorShape::Default;
+            self.resize.finish(serial);
         }
     }
 
@@ -306,6 +449,7 @@ pub struct ShellHandles {
     pub xdg_state: Arc<Mutex<XdgShellState<Roles>>>,
     pub wl_state: Arc<Mutex<WlShellState<Roles>>>,
     pub window_map: Rc<RefCell<MyWindowMap>>,
+    pub snap: Rc<RefCell<SnapState>>,
 }
 
 pub fn init_shell(
@@ -341,8 +485,11 @@ pub fn init_shell(
     let window_map = Rc::new(RefCell::new(WindowMap::new(compositor_token)));
     *almost_window_map.borrow_mut() = Some(window_map.clone());
 
+    let snap = Rc::new(RefCell::new(SnapState::default()));
+
     // init the xdg_shell
     let xdg_window_map = window_map.clone();
+    let xdg_snap = snap.clone();
     let (xdg_shell_state, _, _) = xdg_shell_init(
         display,
         compositor_token,
@@ -399,12 +546,26 @@ pub fn init_shell(
 
                 let toplevel = SurfaceKind::Xdg(surface);
                 let initial_window_location = xdg_window_map.borrow().location(&toplevel).unwrap();
+                let initial_window_location = xdg_snap.borrow_mut().untile(
+                    &xdg_window_map.borrow(),
+                    &toplevel,
+                    initial_window_location,
+                    start_data.location,
+                    compositor_token,
+                );
 
+                let cursor_shape = seat_cursor_shape(&seat);
+                *cursor_shape.borrow_mut() = CursorShape::Move;
+                let location = start_data.location;
                 let grab = MoveSurfaceGrab {
                     start_data,
+                    ctoken: compositor_token,
                     window_map: xdg_window_map.clone(),
+                    snap: xdg_snap.clone(),
                     toplevel,
                     initial_window_location,
+                    location,
+                    cursor_shape,
                 };
 
                 pointer.set_grab(grab, serial);
@@ -440,30 +601,19 @@ pub fn init_shell(
                 }
 
                 let toplevel = SurfaceKind::Xdg(surface.clone());
-                let initial_window_location = xdg_window_map.borrow().location(&toplevel).unwrap();
-                let geometry = xdg_window_map.borrow().geometry(&toplevel).unwrap();
-                let initial_window_size = (geometry.width, geometry.height);
-
-                compositor_token.with_surface_data(surface.get_surface().unwrap(), move |attrs| {
-                    attrs
-                        .user_data
-                        .get::<RefCell<SurfaceData>>()
-                        .unwrap()
-                        .borrow_mut()
-                        .resize_state = ResizeState::Resizing(ResizeData {
-                        edges: edges.into(),
-                        initial_window_location,
-                        initial_window_size,
-                    });
-                });
+                let edges: ResizeEdge = edges.into();
+                let resize = match Resize::start(compositor_token, toplevel, edges, &xdg_window_map.borrow())
+                {
+                    Some(resize) => resize,
+                    None => return,
+                };
 
+                let cursor_shape = seat_cursor_shape(&seat);
+                *cursor_shape.borrow_mut() = edges.cursor_shape();
                 let grab = ResizeSurfaceGrab {
                     start_data,
-                    ctoken: compositor_token,
-                    toplevel,
-                    edges: edges.into(),
-                    initial_window_size,
-                    last_window_size: initial_window_size,
+                    resize,
+                    cursor_shape,
                 };
 
                 pointer.set_grab(grab, serial);
@@ -502,6 +652,12 @@ pub fn init_shell(
                     }
                 }
             }
+            XdgRequest::Minimize { surface } => {
+                // it is shown again when it gets the focus, e.g. by the window cycling
+                xdg_window_map
+                    .borrow_mut()
+                    .set_minimized(&SurfaceKind::Xdg(surface), true);
+            }
             _ => (),
         },
         log.clone(),
@@ -509,6 +665,7 @@ pub fn init_shell(
 
     // init the wl_shell
     let shell_window_map = window_map.clone();
+    let shell_snap = snap.clone();
     let (wl_shell_state, _) = wl_shell_init(
         display,
         compositor_token,
@@ -559,12 +716,26 @@ pub fn init_shell(
 
                     let toplevel = SurfaceKind::Wl(surface);
                     let initial_window_location = shell_window_map.borrow().location(&toplevel).unwrap();
+                    let initial_window_location = shell_snap.borrow_mut().untile(
+                        &shell_window_map.borrow(),
+                        &toplevel,
+                        initial_window_location,
+                        start_data.location,
+                        compositor_token,
+                    );
 
+                    let cursor_shape = seat_cursor_shape(&seat);
+                    *cursor_shape.borrow_mut() = CursorShape::Move;
+                    let location = start_data.location;
                     let grab = MoveSurfaceGrab {
                         start_data,
+                        ctoken: compositor_token,
                         window_map: shell_window_map.clone(),
+                        snap: shell_snap.clone(),
                         toplevel,
                         initial_window_location,
+                        location,
+                        cursor_shape,
                     };
 
                     pointer.set_grab(grab, serial);
@@ -600,30 +771,19 @@ pub fn init_shell(
                     }
 
                     let toplevel = SurfaceKind::Wl(surface.clone());
-                    let initial_window_location = shell_window_map.borrow().location(&toplevel).unwrap();
-                    let geometry = shell_window_map.borrow().geometry(&toplevel).unwrap();
-                    let initial_window_size = (geometry.width, geometry.height);
-
-                    compositor_token.with_surface_data(surface.get_surface().unwrap(), move |attrs| {
-                        attrs
-                            .user_data
-                            .get::<RefCell<SurfaceData>>()
-                            .unwrap()
-                            .borrow_mut()
-                            .resize_state = ResizeState::Resizing(ResizeData {
-                            edges: edges.into(),
-                            initial_window_location,
-                            initial_window_size,
-                        });
-                    });
-
+                    let edges: ResizeEdge = edges.into();
+                    let resize =
+                        match Resize::start(compositor_token, toplevel, edges, &shell_window_map.borrow()) {
+                            Some(resize) => resize,
+                            None => return,
+                        };
+
+                    let cursor_shape = seat_cursor_shape(&seat);
+                    *cursor_shape.borrow_mut() = edges.cursor_shape();
                     let grab = ResizeSurfaceGrab {
                         start_data,
-                        ctoken: compositor_token,
-                        toplevel,
-                        edges: edges.into(),
-                        initial_window_size,
-                        last_window_size: initial_window_size,
+                        resize,
+                        cursor_shape,
                     };
 
                     pointer.set_grab(grab, serial);
@@ -639,6 +799,7 @@ pub fn init_shell(
         xdg_state: xdg_shell_state,
         wl_state: wl_shell_state,
         window_map,
+        snap,
     }
 }
 
@@ -651,6 +812,8 @@ pub struct ResizeData {
     initial_window_location: (i32, i32),
     /// The initial window size (geometry width and height).
     initial_window_size: (i32, i32),
+    /// The last size asked for, which the surface may not have committed yet.
+    requested_size: (i32, i32),
 }
 
 /// State of the resize operation.
@@ -676,11 +839,18 @@ impl Default for ResizeState {
 pub struct CommitedState {
     pub buffer: Option<wl_buffer::WlBuffer>,
     pub input_region: Option<RegionAttributes>,
+    /// The size of the buffer, in pixels
+    pub buffer_dimensions: Option<(i32, i32)>,
+    /// The size of the surface, in surface coordinates
     pub dimensions: Option<(i32, i32)>,
+    pub viewport: ViewportState,
     pub frame_callbacks: Vec<wl_callback::WlCallback>,
     pub sub_location: (i32, i32),
 }
 
+/// The number of damage rectangles a surface keeps before merging them.
+const MAX_SURFACE_DAMAGE: usize = 16;
+
 #[derive(Default)]
 pub struct SurfaceData {
     pub texture: Option<Box<dyn std::any::Any + 'static>>,
@@ -696,6 +866,12 @@ pub struct SurfaceData {
     pub max_size: (i32, i32),
     pub current_state: CommitedState,
     pub cached_state: Option<CommitedState>,
+    /// The viewport of the surface, if the client created one
+    pub viewport: Option<WpViewport>,
+    /// The viewport state applied with the next commit
+    pub pending_viewport: ViewportState,
+    /// The damage of the commits the renderers did not collect yet, in surface coordinates
+    pub damage: Vec<Rectangle>,
 }
 
 impl SurfaceData {
@@ -741,6 +917,17 @@ impl SurfaceData {
 }
 
 impl SurfaceData {
+    /// Adds damage for the renderers to collect.
+    pub fn add_damage(&mut self, damage: Rectangle) {
+        // the damage of surfaces that are not drawn is never collected, merge it instead of growing
+        if self.damage.len() >= MAX_SURFACE_DAMAGE {
+            let merged = self.damage.drain(..).fold(damage, bounding_box);
+            self.damage.push(merged);
+        } else {
+            self.damage.push(damage);
+        }
+    }
+
     /// Returns the size of the surface.
     pub fn size(&self) -> Option<(i32, i32)> {
         self.current_state.dimensions
@@ -784,6 +971,94 @@ impl SurfaceData {
     }
 }
 
+/// Returns the app id of an xdg toplevel surface, if the client set one.
+pub fn app_id(surface: &wl_surface::WlSurface, token: CompositorToken<Roles>) -> Option<String> {
+    token
+        .with_role_data(surface, |role: &mut XdgSurfaceRole| match role.pending_state {
+            XdgSurfacePendingState::Toplevel(ref state) if !state.app_id.is_empty() => Some(state.app_id.clone()),
+            _ => None,
+        })
+        .ok()
+        .flatten()
+}
+
+/// Asks the client of a toplevel for a new size, within the limits the client set.
+///
+/// Returns `false` for X11 windows, the compositor does not configure them yet.
+pub fn request_size(toplevel: &SurfaceKind<Roles>, size: (i32, i32), token: MyCompositorToken) -> bool {
+    configure_size(toplevel, size, vec![], token)
+}
+
+/// Asks the client of a toplevel to show it maximized at a size, like `request_size`.
+///
+/// Only xdg toplevels are told that they are maximized, wl_shell surfaces just get the size.
+pub fn request_maximized(toplevel: &SurfaceKind<Roles>, size: (i32, i32), token: MyCompositorToken) -> bool {
+    configure_size(toplevel, size, vec![xdg_toplevel::State::Maximized], token)
+}
+
+fn configure_size(
+    toplevel: &SurfaceKind<Roles>,
+    size: (i32, i32),
+    states: Vec<xdg_toplevel::State>,
+    token: MyCompositorToken,
+) -> bool {
+    let surface = match toplevel.get_surface() {
+        Some(surface) => surface,
+        None => return false,
+    };
+    let (min_size, max_size) = token.with_surface_data(surface, |attrs| {
+        attrs
+            .user_data
+            .get::<RefCell<SurfaceData>>()
+            .map(|data| {
+                let data = data.borrow();
+                (data.min_size, data.max_size)
+            })
+            .unwrap_or_default()
+    });
+    // a maximum of 0 means there is none
+    let limit = |size: i32, min: i32, max: i32| {
+        let size = size.max(min.max(1));
+        if max == 0 {
+            size
+        } else {
+            size.min(max)
+        }
+    };
+    let size = (
+        limit(size.0, min_size.0, max_size.0),
+        limit(size.1, min_size.1, max_size.1),
+    );
+
+    match toplevel {
+        SurfaceKind::Xdg(xdg) => {
+            xdg.send_configure(ToplevelConfigure {
+                size: Some(size),
+                states,
+                serial: SCOUNTER.next_serial(),
+            });
+            true
+        }
+        SurfaceKind::Wl(wl) => {
+            wl.send_configure((size.0 as u32, size.1 as u32), wl_shell_surface::Resize::None);
+            true
+        }
+        #[cfg(feature = "xwayland")]
+        SurfaceKind::X11(_) => false,
+    }
+}
+
+/// Returns the title of an xdg toplevel surface, if the client set one.
+pub fn title(surface: &wl_surface::WlSurface, token: CompositorToken<Roles>) -> Option<String> {
+    token
+        .with_role_data(surface, |role: &mut XdgSurfaceRole| match role.pending_state {
+            XdgSurfacePendingState::Toplevel(ref state) if !state.title.is_empty() => Some(state.title.clone()),
+            _ => None,
+        })
+        .ok()
+        .flatten()
+}
+
 fn surface_commit(
     surface: &wl_surface::WlSurface,
     token: CompositorToken<Roles>,
@@ -841,22 +1116,72 @@ fn surface_commit(
                 // new contents
                 #[cfg(feature = "egl")]
                 {
-                    next_state.dimensions = buffer_dimensions(&buffer, egl_reader);
+                    next_state.buffer_dimensions = buffer_dimensions(&buffer, egl_reader);
                 }
                 #[cfg(not(feature = "egl"))]
                 {
-                    next_state.dimensions = buffer_dimensions(&buffer, None);
+                    next_state.buffer_dimensions = buffer_dimensions(&buffer, None);
                 }
                 next_state.buffer = Some(buffer);
             }
             Some(BufferAssignment::Removed) => {
                 // remove the contents
                 next_state.buffer = None;
-                next_state.dimensions = None;
+                next_state.buffer_dimensions = None;
             }
             None => {}
         }
 
+        // the buffer scale and the viewport turn the buffer size into the surface size
+        next_state.viewport = data.pending_viewport;
+        next_state.dimensions = next_state.buffer_dimensions.map(|size| {
+            surface_size(
+                size,
+                attributes.buffer_scale,
+                attributes.buffer_transform,
+                &next_state.viewport,
+                data.viewport.as_ref(),
+            )
+        });
+
+        // the renderers repaint the damaged parts, and the whole surface if it changed its place or size
+        let (width, height) = next_state.dimensions.unwrap_or((0, 0));
+        let resized = next_state.dimensions != data.current_state.dimensions
+            || next_state.sub_location != data.current_state.sub_location;
+        if resized {
+            let (old_width, old_height) = data.current_state.dimensions.unwrap_or((0, 0));
+            data.add_damage(Rectangle {
+                x: data.current_state.sub_location.0 - next_state.sub_location.0,
+                y: data.current_state.sub_location.1 - next_state.sub_location.1,
+                width: old_width,
+                height: old_height,
+            });
+        }
+        // buffer damage only maps to the surface through a plain scale
+        let transformed = next_state.viewport != ViewportState::default()
+            || attributes.buffer_transform != wl_output::Transform::Normal;
+        if resized || transformed {
+            data.add_damage(Rectangle {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            });
+        } else {
+            let scale = attributes.buffer_scale.max(1);
+            for damage in &attributes.damage {
+                data.add_damage(match *damage {
+                    Damage::Surface(rect) => rect,
+                    Damage::Buffer(rect) => Rectangle {
+                        x: rect.x / scale,
+                        y: rect.y / scale,
+                        width: (rect.width + scale - 1) / scale + 1,
+                        height: (rect.height + scale - 1) / scale + 1,
+                    },
+                });
+            }
+        }
+
         // Append the current frame callbacks to the next state
         next_state
             .frame_callbacks
@@ -935,6 +1260,7 @@ fn surface_commit(
                         edges,
                         initial_window_location,
                         initial_window_size,
+                        ..
                     } = resize_data;
 
                     if edges.intersects(ResizeEdge::TOP_LEFT) {
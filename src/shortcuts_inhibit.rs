@@ -0,0 +1,188 @@
+use std::{cell::Cell, rc::Rc};
+
+use smithay::{
+    reexports::{
+        wayland_protocols::unstable::keyboard_shortcuts_inhibit::v1::server::{
+            zwp_keyboard_shortcuts_inhibit_manager_v1::{self, ZwpKeyboardShortcutsInhibitManagerV1},
+            zwp_keyboard_shortcuts_inhibitor_v1::{self, ZwpKeyboardShortcutsInhibitorV1},
+        },
+        wayland_server::{protocol::wl_surface::WlSurface, Display, Filter, Global, Main},
+    },
+    wayland::{compositor::CompositorToken, seat::Seat},
+};
+
+use crate::{
+    custom::config::ShortcutsInhibit,
+    seat::seat_shortcuts_inhibitors,
+    shell::{app_id, Roles},
+    state::AnvilState,
+};
+
+struct Inhibitor {
+    inhibitor: ZwpKeyboardShortcutsInhibitorV1,
+    surface: WlSurface,
+    /// Whether the configuration lets the client inhibit the shortcuts at all.
+    allowed: bool,
+    active: bool,
+}
+
+/// The shortcut inhibitors of a single seat.
+///
+/// An inhibitor is active while its surface has the keyboard focus of the seat, unless the user
+/// suspended it with the escape binding.
+pub struct ShortcutsInhibitors {
+    inhibitors: Vec<Inhibitor>,
+    focus: Option<WlSurface>,
+    suspended: bool,
+    /// Shared with the renderers, to draw an indicator while the shortcuts are inhibited.
+    indicator: Rc<Cell<bool>>,
+}
+
+impl ShortcutsInhibitors {
+    pub fn new(indicator: Rc<Cell<bool>>) -> ShortcutsInhibitors {
+        ShortcutsInhibitors {
+            inhibitors: Vec::new(),
+            focus: None,
+            suspended: false,
+            indicator,
+        }
+    }
+
+    /// Whether the compositor shortcuts are currently inhibited.
+    pub fn is_active(&self) -> bool {
+        self.inhibitors.iter().any(|inhibitor| inhibitor.active)
+    }
+
+    /// Whether the focused surface has an inhibitor, active or suspended.
+    pub fn has_focused_inhibitor(&self) -> bool {
+        self.inhibitors
+            .iter()
+            .any(|inhibitor| inhibitor.allowed && self.is_focused(&inhibitor.surface))
+    }
+
+    /// Called when the keyboard focus of the seat changes, a new focus resumes suspended inhibitors.
+    pub fn set_focus(&mut self, focus: Option<&WlSurface>) {
+        self.focus = focus.cloned();
+        self.suspended = false;
+        self.update();
+    }
+
+    /// Checks the inhibitors against the allow list of a new configuration.
+    pub fn update_allowed(&mut self, config: &ShortcutsInhibit, token: CompositorToken<Roles>) {
+        for inhibitor in &mut self.inhibitors {
+            if inhibitor.surface.as_ref().is_alive() {
+                inhibitor.allowed = config.allows(app_id(&inhibitor.surface, token).as_deref());
+            }
+        }
+        self.update();
+    }
+
+    /// Suspends the inhibitor of the focused surface, or resumes it if it already is suspended.
+    pub fn toggle_suspended(&mut self) {
+        self.suspended = !self.suspended;
+        self.update();
+    }
+
+    fn is_focused(&self, surface: &WlSurface) -> bool {
+        self.focus
+            .as_ref()
+            .map(|focus| focus.as_ref().equals(surface.as_ref()))
+            .unwrap_or(false)
+    }
+
+    fn has_inhibitor(&self, surface: &WlSurface) -> bool {
+        self.inhibitors
+            .iter()
+            .any(|inhibitor| inhibitor.surface.as_ref().equals(surface.as_ref()))
+    }
+
+    fn remove(&mut self, inhibitor: &ZwpKeyboardShortcutsInhibitorV1) {
+        self.inhibitors
+            .retain(|other| !other.inhibitor.as_ref().equals(inhibitor.as_ref()));
+        self.update();
+    }
+
+    /// Sends `active`/`inactive` to every inhibitor whose state changed.
+    fn update(&mut self) {
+        self.inhibitors
+            .retain(|inhibitor| inhibitor.inhibitor.as_ref().is_alive() && inhibitor.surface.as_ref().is_alive());
+        let suspended = self.suspended;
+        let focus = self.focus.clone();
+        for inhibitor in &mut self.inhibitors {
+            let focused = focus
+                .as_ref()
+                .map(|focus| focus.as_ref().equals(inhibitor.surface.as_ref()))
+                .unwrap_or(false);
+            let active = inhibitor.allowed && !suspended && focused;
+            if active != inhibitor.active {
+                inhibitor.active = active;
+                if active {
+                    inhibitor.inhibitor.active();
+                } else {
+                    inhibitor.inhibitor.inactive();
+                }
+            }
+        }
+        self.indicator.set(self.is_active());
+    }
+}
+
+pub fn init_keyboard_shortcuts_inhibit(
+    display: &mut Display,
+    token: CompositorToken<Roles>,
+    log: ::slog::Logger,
+) -> Global<ZwpKeyboardShortcutsInhibitManagerV1> {
+    display.create_global::<ZwpKeyboardShortcutsInhibitManagerV1, _>(
+        1,
+        Filter::new(
+            move |(manager, _version): (Main<ZwpKeyboardShortcutsInhibitManagerV1>, u32), _, _| {
+                let log = log.clone();
+                manager.quick_assign(move |manager, request, mut ddata| match request {
+                    zwp_keyboard_shortcuts_inhibit_manager_v1::Request::InhibitShortcuts { id, surface, seat } => {
+                        let inhibitors = match Seat::from_resource(&seat) {
+                            Some(seat) => seat_shortcuts_inhibitors(&seat),
+                            None => return,
+                        };
+                        if inhibitors.borrow().has_inhibitor(&surface) {
+                            manager.as_ref().post_error(
+                                zwp_keyboard_shortcuts_inhibit_manager_v1::Error::AlreadyInhibited.to_raw(),
+                                "The surface already inhibits the shortcuts of this seat.".into(),
+                            );
+                            return;
+                        }
+
+                        // the allow list of the current configuration, it is checked again on reload
+                        let config = &ddata.get::<AnvilState>().unwrap().config.shortcuts_inhibit;
+                        let app_id = app_id(&surface, token);
+                        let allowed = config.allows(app_id.as_deref());
+                        info!(log, "New keyboard shortcuts inhibitor";
+                            "app_id" => app_id.unwrap_or_default(),
+                            "allowed" => allowed
+                        );
+
+                        let destroyed_inhibitors = Rc::downgrade(&inhibitors);
+                        id.quick_assign(move |inhibitor, request, _| match request {
+                            zwp_keyboard_shortcuts_inhibitor_v1::Request::Destroy => {
+                                if let Some(inhibitors) = destroyed_inhibitors.upgrade() {
+                                    inhibitors.borrow_mut().remove(&*inhibitor);
+                                }
+                            }
+                            _ => unreachable!(),
+                        });
+
+                        let mut inhibitors = inhibitors.borrow_mut();
+                        inhibitors.inhibitors.push(Inhibitor {
+                            inhibitor: (*id).clone(),
+                            surface,
+                            allowed,
+                            active: false,
+                        });
+                        inhibitors.update();
+                    }
+                    zwp_keyboard_shortcuts_inhibit_manager_v1::Request::Destroy => {}
+                    _ => unreachable!(),
+                });
+            },
+        ),
+    )
+}
@@ -0,0 +1,392 @@
+//! Snapping windows while they are moved with the pointer.
+//!
+//! A moved window snaps its edges to the edges of the outputs, of their usable areas and of the
+//! other windows, once it gets closer to them than the threshold of each. With the snap zones,
+//! moving the pointer to the left or right edge of an output tiles the window to that half of the
+//! usable area and to the top edge maximizes it. A translucent preview shows where the window
+//! goes while the pointer is in a zone, and a tiled window gets its size back once it is moved.
+//!
+//! The thresholds are the `output_edges`, `usable_area` and `window_edges` of the `snapping`
+//! section of `config.json`, a threshold of 0 turns the snapping to those edges off.
+
+#[cfg(feature = "snap_preview")]
+use image::{ImageBuffer, Rgba};
+#[cfg(feature = "snap_preview")]
+use smithay::backend::renderer::{Frame, Renderer, Texture, Transform};
+use smithay::utils::Rectangle;
+
+#[cfg(feature = "snap_preview")]
+use crate::drawing::OutputView;
+use crate::{
+    custom::config::Snapping,
+    shell::{request_maximized, request_size, MyCompositorToken, MyWindowMap, Roles},
+    state::AnvilState,
+    window_map::Kind,
+};
+
+/// The color of the snap zone preview, premultiplied
+#[cfg(feature = "snap_preview")]
+const PREVIEW_COLOR: [u8; 4] = [0x1e, 0x30, 0x48, 0x60];
+
+/// An output as the snapping sees it.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapOutput {
+    pub geometry: Rectangle,
+    /// The part of the output the windows are placed in
+    pub usable_area: Rectangle,
+}
+
+/// What the moves snap to, shared by the state and the move grabs of the shell.
+#[derive(Default)]
+pub struct SnapState {
+    pub options: Snapping,
+    pub outputs: Vec<SnapOutput>,
+    /// The area the window of a move is tiled to if it ends now
+    pub preview: Option<Rectangle>,
+    /// The tiled windows, with their size from before the tiling
+    tiled: Vec<(Kind<Roles>, (i32, i32))>,
+}
+
+impl SnapState {
+    /// Where a window moved to `location` goes with its edges snapped.
+    pub fn snap(&self, window_map: &MyWindowMap, toplevel: &Kind<Roles>, (x, y): (i32, i32)) -> (i32, i32) {
+        let options = self.options;
+        let window = match window_rect(window_map, toplevel, (x, y)) {
+            Some(window) => window,
+            None => return (x, y),
+        };
+
+        // the edges by axis, with their thresholds
+        let (mut xs, mut ys) = (Vec::new(), Vec::new());
+        let mut add = |rect: Rectangle, threshold: i32| {
+            if threshold <= 0 {
+                return;
+            }
+            // only the edges the window is next to
+            if spans_overlap((rect.y, rect.height), (window.y, window.height), threshold) {
+                xs.push((rect.x, threshold));
+                xs.push((rect.x + rect.width, threshold));
+            }
+            if spans_overlap((rect.x, rect.width), (window.x, window.width), threshold) {
+                ys.push((rect.y, threshold));
+                ys.push((rect.y + rect.height, threshold));
+            }
+        };
+        for output in &self.outputs {
+            add(output.geometry, options.output_edges);
+            add(output.usable_area, options.usable_area);
+        }
+        window_map.with_windows_from_bottom_to_top(|other, location, _| {
+            if other.equals(toplevel) {
+                return;
+            }
+            if let Some(rect) = window_rect(window_map, other, location) {
+                add(rect, options.window_edges);
+            }
+        });
+
+        (
+            x + snap_offset((window.x, window.width), &xs),
+            y + snap_offset((window.y, window.height), &ys),
+        )
+    }
+
+    /// The snap zone under the pointer, the area a window is tiled to and whether that
+    /// maximizes it.
+    pub fn zone(&self, (x, y): (f64, f64)) -> Option<(Rectangle, bool)> {
+        if !self.options.zones {
+            return None;
+        }
+        let output = self
+            .outputs
+            .iter()
+            .find(|output| output.geometry.contains((x as i32, y as i32)))?;
+        // the pointer reaches the edges of the output, the windows are tiled into the usable area
+        let (geometry, area) = (output.geometry, output.usable_area);
+        let size = self.options.zone_size as f64;
+        if y < geometry.y as f64 + size {
+            Some((area, true))
+        } else if x < geometry.x as f64 + size {
+            let left = Rectangle {
+                width: area.width / 2,
+                ..area
+            };
+            Some((left, false))
+        } else if x >= (geometry.x + geometry.width) as f64 - size {
+            let right = Rectangle {
+                x: area.x + area.width / 2,
+                width: area.width - area.width / 2,
+                ..area
+            };
+            Some((right, false))
+        } else {
+            None
+        }
+    }
+
+    /// Tiles a window to the area of a snap zone, keeping its size to give it back later.
+    pub fn tile(
+        &mut self,
+        window_map: &mut MyWindowMap,
+        toplevel: &Kind<Roles>,
+        (area, maximize): (Rectangle, bool),
+        ctoken: MyCompositorToken,
+    ) {
+        let size = window_map
+            .geometry(toplevel)
+            .map(|geometry| (geometry.width, geometry.height));
+        let requested = if maximize {
+            request_maximized(toplevel, (area.width, area.height), ctoken)
+        } else {
+            request_size(toplevel, (area.width, area.height), ctoken)
+        };
+        if !requested {
+            return;
+        }
+        // a window tiled again keeps the size from before the first tiling
+        if let Some(size) = size {
+            if !self.tiled.iter().any(|(tiled, _)| tiled.equals(toplevel)) {
+                self.tiled.push((toplevel.clone(), size));
+            }
+        }
+        // the client side decorations stay out of the area
+        let (dx, dy) = window_map.geometry_offset(toplevel).unwrap_or((0, 0));
+        window_map.set_location(toplevel, (area.x - dx, area.y - dy));
+    }
+
+    /// Gives a tiled window its size back as a move of it starts at `pointer`.
+    ///
+    /// Returns where the move starts the window from, the pointer keeps its place relative to
+    /// the width of the window.
+    pub fn untile(
+        &mut self,
+        window_map: &MyWindowMap,
+        toplevel: &Kind<Roles>,
+        location: (i32, i32),
+        pointer: (f64, f64),
+        ctoken: MyCompositorToken,
+    ) -> (i32, i32) {
+        let idx = match self.tiled.iter().position(|(tiled, _)| tiled.equals(toplevel)) {
+            Some(idx) => idx,
+            None => return location,
+        };
+        let (_, size) = self.tiled.remove(idx);
+        let window = match window_rect(window_map, toplevel, location) {
+            Some(window) => window,
+            None => return location,
+        };
+        request_size(toplevel, size, ctoken);
+        let grabbed = (pointer.0 - window.x as f64) / window.width.max(1) as f64;
+        let x = (pointer.0 - grabbed * size.0 as f64) as i32;
+        // back from the window to its surface
+        (x - (window.x - location.0), location.1)
+    }
+}
+
+/// The rectangle a window at `location` covers, without its client side decorations.
+fn window_rect(window_map: &MyWindowMap, toplevel: &Kind<Roles>, location: (i32, i32)) -> Option<Rectangle> {
+    let geometry = window_map.geometry(toplevel)?;
+    let (dx, dy) = window_map.geometry_offset(toplevel)?;
+    Some(Rectangle {
+        x: location.0 + dx,
+        y: location.1 + dy,
+        width: geometry.width,
+        height: geometry.height,
+    })
+}
+
+/// Whether the spans, as start and size, overlap once one of them is grown by `margin`.
+fn spans_overlap((a, a_size): (i32, i32), (b, b_size): (i32, i32), margin: i32) -> bool {
+    a - margin < b + b_size && b < a + a_size + margin
+}
+
+/// The offset bringing the start or the end of the span onto the closest edge within its
+/// threshold, 0 if there is none.
+fn snap_offset((start, size): (i32, i32), edges: &[(i32, i32)]) -> i32 {
+    let mut best: Option<i32> = None;
+    for &(edge, threshold) in edges {
+        for &offset in &[edge - start, edge - (start + size)] {
+            if offset.abs() <= threshold && best.map_or(true, |best| offset.abs() < best.abs()) {
+                best = Some(offset);
+            }
+        }
+    }
+    best.unwrap_or(0)
+}
+
+/// The texture of the snap zone preview of a renderer.
+#[cfg(feature = "snap_preview")]
+pub struct SnapPreview<T> {
+    /// The single pixel texture stretched over the zone
+    texture: Option<T>,
+}
+
+#[cfg(feature = "snap_preview")]
+impl<T> Default for SnapPreview<T> {
+    fn default() -> Self {
+        SnapPreview { texture: None }
+    }
+}
+
+#[cfg(feature = "snap_preview")]
+impl<T: Texture> SnapPreview<T> {
+    /// Draws the preview of the zone, if it is on the output.
+    pub fn draw<R, F, E>(
+        &mut self,
+        renderer: &mut R,
+        frame: &mut F,
+        zone: Rectangle,
+        output: &OutputView,
+    ) -> Result<(), E>
+    where
+        R: Renderer<Error = E, TextureId = T, Frame = F>,
+        F: Frame<Error = E, TextureId = T>,
+    {
+        if !output.geometry.overlaps(&zone) {
+            return Ok(());
+        }
+        if self.texture.is_none() {
+            self.texture =
+                Some(renderer.import_bitmap(&ImageBuffer::from_pixel(1, 1, Rgba(PREVIEW_COLOR)))?);
+        }
+        match self.texture {
+            Some(ref texture) => output.render_texture(
+                frame,
+                texture,
+                (
+                    zone.x as f64,
+                    zone.y as f64,
+                    zone.width as f64,
+                    zone.height as f64,
+                ),
+                Transform::Normal,
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AnvilState {
+    /// Hands the snapping options and the outputs to the move grabs.
+    pub fn update_snapping(&self) {
+        let mut snap = self.snap.borrow_mut();
+        snap.options = self.config.snapping;
+        snap.outputs = self
+            .output_geometries()
+            .into_iter()
+            .map(|(_, geometry)| SnapOutput {
+                geometry,
+                usable_area: self.usable_area(geometry),
+            })
+            .collect();
+        snap.tiled.retain(|(toplevel, _)| toplevel.alive());
+    }
+
+    /// The part of an output the windows are placed in, without the exclusive zones reserved by
+    /// the layer surfaces on it.
+    fn usable_area(&self, geometry: Rectangle) -> Rectangle {
+        // there is no layer shell reserving exclusive zones yet, all of an output is usable
+        geometry
+    }
+
+    /// The preview of the snap zone while a move is in one.
+    #[cfg(feature = "snap_preview")]
+    pub fn snap_preview(&self) -> Option<Rectangle> {
+        self.snap.borrow().preview
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> Rectangle {
+        Rectangle { x, y, width, height }
+    }
+
+    /// Snapping with a single 1920x1080 output at the origin.
+    fn snap_state() -> SnapState {
+        SnapState {
+            outputs: vec![SnapOutput {
+                geometry: rect(0, 0, 1920, 1080),
+                usable_area: rect(0, 0, 1920, 1080),
+            }],
+            ..SnapState::default()
+        }
+    }
+
+    #[test]
+    fn spans_overlap_within_margin() {
+        assert!(spans_overlap((0, 100), (50, 100), 0));
+        assert!(spans_overlap((50, 100), (0, 100), 0));
+        // touching spans only overlap with a margin
+        assert!(!spans_overlap((0, 100), (100, 100), 0));
+        assert!(spans_overlap((0, 100), (100, 100), 1));
+        assert!(!spans_overlap((0, 100), (120, 100), 16));
+        assert!(spans_overlap((0, 100), (115, 100), 16));
+        assert!(spans_overlap((120, 100), (0, 100), 21));
+    }
+
+    #[test]
+    fn snap_offset_to_closest_edge() {
+        // no edge within the threshold
+        assert_eq!(snap_offset((100, 50), &[(0, 16), (200, 16)]), 0);
+        // the start onto an edge
+        assert_eq!(snap_offset((10, 50), &[(0, 16)]), -10);
+        // the end onto an edge
+        assert_eq!(snap_offset((100, 50), &[(160, 16)]), 10);
+        // the closest of several edges
+        assert_eq!(snap_offset((10, 50), &[(0, 16), (65, 16)]), 5);
+        // each edge has its own threshold
+        assert_eq!(snap_offset((10, 50), &[(0, 8), (70, 16)]), 10);
+        assert_eq!(snap_offset((10, 50), &[(0, 0)]), 0);
+    }
+
+    #[test]
+    fn zone_at_output_edges() {
+        let snap = snap_state();
+        assert_eq!(snap.zone((500.0, 0.0)), Some((rect(0, 0, 1920, 1080), true)));
+        assert_eq!(snap.zone((0.0, 500.0)), Some((rect(0, 0, 960, 1080), false)));
+        assert_eq!(snap.zone((1919.0, 500.0)), Some((rect(960, 0, 960, 1080), false)));
+        assert_eq!(snap.zone((500.0, 500.0)), None);
+        // outside of the outputs
+        assert_eq!(snap.zone((-10.0, 500.0)), None);
+    }
+
+    #[test]
+    fn zone_disabled() {
+        let mut snap = snap_state();
+        snap.options.zones = false;
+        assert_eq!(snap.zone((0.0, 500.0)), None);
+    }
+
+    #[test]
+    fn zone_halves_odd_width() {
+        let snap = SnapState {
+            outputs: vec![SnapOutput {
+                geometry: rect(1920, 0, 1281, 1024),
+                usable_area: rect(1920, 0, 1281, 1024),
+            }],
+            ..SnapState::default()
+        };
+        assert_eq!(snap.zone((1920.0, 10.0)), Some((rect(1920, 0, 640, 1024), false)));
+        assert_eq!(snap.zone((3200.0, 10.0)), Some((rect(2560, 0, 641, 1024), false)));
+    }
+
+    #[test]
+    fn zone_tiles_usable_area() {
+        // a panel reserves the top 32 pixels
+        let snap = SnapState {
+            outputs: vec![SnapOutput {
+                geometry: rect(0, 0, 1920, 1080),
+                usable_area: rect(0, 32, 1920, 1048),
+            }],
+            ..SnapState::default()
+        };
+        // the pointer still reaches the zones at the edges of the output
+        assert_eq!(snap.zone((500.0, 0.0)), Some((rect(0, 32, 1920, 1048), true)));
+        assert_eq!(snap.zone((0.0, 10.0)), Some((rect(0, 32, 960, 1048), false)));
+        assert_eq!(snap.zone((1919.0, 500.0)), Some((rect(960, 32, 960, 1048), false)));
+        assert_eq!(snap.zone((500.0, 500.0)), None);
+    }
+}
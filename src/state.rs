@@ -1,26 +1,34 @@
-use crate::custom::config::Configuration;
+use crate::custom::config::{Configuration, Hook};
 use std::{
     cell::RefCell,
     rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        Arc,
     },
+    time::{Duration, Instant},
 };
 
+use serde_json::Value;
 use smithay::{
     reexports::{
         calloop::{
             generic::{Fd, Generic},
+            timer::{Timeout, Timer, TimerHandle},
             Interest, LoopHandle, Mode, Source,
         },
-        wayland_server::{protocol::wl_surface::WlSurface, Display},
+        wayland_server::{
+            protocol::{wl_pointer::WlPointer, wl_surface::WlSurface},
+            Display,
+        },
     },
+    utils::Rectangle,
     wayland::{
         compositor::CompositorToken,
-        data_device::{default_action_chooser, init_data_device, set_data_device_focus, DataDeviceEvent},
-        seat::{CursorImageStatus, KeyboardHandle, PointerHandle, Seat},
+        data_device::{default_action_chooser, init_data_device, DataDeviceEvent},
+        seat::CursorImageStatus,
         shm::init_shm_global,
+        SERIAL_COUNTER as SCOUNTER,
     },
 };
 
@@ -31,7 +39,29 @@ use smithay::backend::session::{auto::AutoSession, Session};
 #[cfg(feature = "xwayland")]
 use smithay::xwayland::XWayland;
 
-use crate::shell::init_shell;
+use crate::{
+    cursor_shape::init_cursor_shape_manager,
+    damage::{cursor_surface_location, take_surface_damage},
+    drawing::OutputView,
+    fractional_scale::{init_fractional_scale_manager, FractionalScales},
+    ipc::{EventKind, IpcServer, Snapshot},
+    input_method::{init_input_method_manager, init_text_input_manager, InputMethodState},
+    keyboard_move::KeyboardMove,
+    pointer_constraints::{init_pointer_constraints, PointerConstraints},
+    relative_pointer::init_relative_pointer_manager,
+    seat::{MySeat, SeatCursor},
+    shell::{init_shell, MyWindowMap, Roles},
+    shortcuts_inhibit::init_keyboard_shortcuts_inhibit,
+    snap::SnapState,
+    viewporter::init_viewporter,
+    virtual_keyboard::init_virtual_keyboard_manager,
+    window_cycle::WindowCycle,
+    window_map::{Kind, WindowId},
+};
+#[cfg(feature = "scripting")]
+use crate::scripting::Scripts;
+#[cfg(feature = "switcher")]
+use crate::switcher::{SwitcherFiles, SwitcherView};
 #[cfg(feature = "udev")]
 use crate::udev::MyOutput;
 #[cfg(feature = "xwayland")]
@@ -44,19 +74,44 @@ pub struct AnvilState {
     pub handle: LoopHandle<AnvilState>,
     pub ctoken: CompositorToken<crate::shell::Roles>,
     pub window_map: Rc<RefCell<crate::window_map::WindowMap<crate::shell::Roles>>>,
-    pub dnd_icon: Arc<Mutex<Option<WlSurface>>>,
     pub config: Configuration,
     pub log: slog::Logger,
     // input-related fields
-    pub pointer: PointerHandle,
-    pub keyboard: KeyboardHandle,
-    pub pointer_location: Rc<RefCell<(f64, f64)>>,
-    pub cursor_status: Arc<Mutex<CursorImageStatus>>,
+    /// All the seats, the first one is the default seat
+    pub seats: Vec<MySeat>,
+    pub pointer_constraints: Rc<RefCell<PointerConstraints>>,
+    pub fractional_scales: Rc<RefCell<FractionalScales>>,
+    /// The output of the winit backend, the window shows it
+    pub winit_output: Option<OutputView>,
     #[cfg(feature = "udev")]
     pub output_map: Option<Rc<RefCell<Vec<MyOutput>>>>,
     pub seat_name: String,
     #[cfg(feature = "udev")]
     pub session: Option<AutoSession>,
+    /// The ipc socket, if it could be created
+    pub ipc: Option<IpcServer>,
+    /// What the events were last derived from, only kept while someone is interested in them
+    pub published: Option<Snapshot>,
+    /// When the last input event came in, for the idle hooks
+    pub last_input: Instant,
+    pub idle: bool,
+    /// The timeout raising the window focused by the pointer once the auto-raise delay passed
+    pub auto_raise: Option<Timeout>,
+    pub auto_raise_timer: TimerHandle<WindowId>,
+    /// The window cycling, while the modifiers of its key binding are held
+    pub window_cycle: Option<WindowCycle>,
+    /// The keyboard move or resize mode, while it goes on
+    pub keyboard_move: Option<KeyboardMove>,
+    /// What the pointer moves of windows snap to, shared with their grabs
+    pub snap: Rc<RefCell<SnapState>>,
+    /// What the window switcher shows while the window cycling goes on
+    #[cfg(feature = "switcher")]
+    pub switcher: Rc<RefCell<Option<SwitcherView>>>,
+    #[cfg(feature = "switcher")]
+    pub switcher_files: SwitcherFiles,
+    /// The script of the configuration, if it has one that loaded
+    #[cfg(feature = "scripting")]
+    pub scripts: Option<Scripts>,
     // things we must keep alive
     _wayland_event_source: Source<Generic<Fd>>,
     #[cfg(feature = "xwayland")]
@@ -97,6 +152,12 @@ impl AnvilState {
             )
             .expect("Failed to init the wayland event source.");
 
+        let timer = Timer::new().expect("Failed to create the auto-raise timer.");
+        let auto_raise_timer = timer.handle();
+        handle
+            .insert_source(timer, |id, _, state: &mut AnvilState| state.auto_raise_window(id))
+            .expect("Failed to init the auto-raise event source.");
+
         // Init the basic compositor globals
 
         init_shm_global(&mut display.borrow_mut(), vec![], log.clone());
@@ -115,19 +176,63 @@ impl AnvilState {
         info!(log, "Listening on wayland socket"; "name" => socket_name.clone());
         ::std::env::set_var("WAYLAND_DISPLAY", &socket_name);
 
-        // init data device
+        // init input
+        #[cfg(feature = "udev")]
+        let seat_name = if let Some(ref session) = session {
+            session.seat()
+        } else {
+            "anvil".into()
+        };
+        #[cfg(not(feature = "udev"))]
+        let seat_name: String = "anvil".into();
 
-        let dnd_icon = Arc::new(Mutex::new(None));
+        // the default seat gets every device that is not assigned to another seat
+        let mut seat_names = vec![seat_name.clone()];
+        seat_names.extend(
+            config
+                .seats
+                .iter()
+                .map(|seat| seat.name.clone())
+                .filter(|name| *name != seat_name),
+        );
+        let seats: Vec<MySeat> = seat_names
+            .into_iter()
+            .map(|name| {
+                info!(log, "Creating seat"; "name" => name.clone());
+                MySeat::new(
+                    &mut display.borrow_mut(),
+                    name,
+                    shell_handles.token,
+                    &config,
+                    log.clone(),
+                )
+            })
+            .collect();
+
+        // init data device
 
-        let dnd_icon2 = dnd_icon.clone();
+        // a drag and drop grabs the pointer of the seat that started it, its icon follows the
+        // cursor of that seat
+        let dnd_seats = seats
+            .iter()
+            .map(|seat| (seat.pointer.clone(), seat.cursor.dnd_icon.clone()))
+            .collect::<Vec<_>>();
+        let mut dnd_seat = None;
         init_data_device(
             &mut display.borrow_mut(),
             move |event| match event {
                 DataDeviceEvent::DnDStarted { icon, .. } => {
-                    *dnd_icon2.lock().unwrap() = icon;
+                    let seat = dnd_seats
+                        .iter()
+                        .position(|(pointer, _)| pointer.is_grabbed())
+                        .unwrap_or(0);
+                    *dnd_seats[seat].1.lock().unwrap() = icon;
+                    dnd_seat = Some(seat);
                 }
                 DataDeviceEvent::DnDDropped => {
-                    *dnd_icon2.lock().unwrap() = None;
+                    if let Some(seat) = dnd_seat.take() {
+                        *dnd_seats[seat].1.lock().unwrap() = None;
+                    }
                 }
                 _ => {}
             },
@@ -136,36 +241,17 @@ impl AnvilState {
             log.clone(),
         );
 
-        // init input
-        #[cfg(feature = "udev")]
-        let seat_name = if let Some(ref session) = session {
-            session.seat()
-        } else {
-            "anvil".into()
-        };
-        #[cfg(not(feature = "udev"))]
-        let seat_name: String = "anvil".into();
+        let (pointer_constraints, _) = init_pointer_constraints(&mut display.borrow_mut(), log.clone());
+        init_relative_pointer_manager(&mut display.borrow_mut(), log.clone());
+        init_cursor_shape_manager(&mut display.borrow_mut(), log.clone());
+        init_keyboard_shortcuts_inhibit(&mut display.borrow_mut(), shell_handles.token, log.clone());
+        init_virtual_keyboard_manager(&mut display.borrow_mut(), log.clone());
+        init_text_input_manager(&mut display.borrow_mut(), log.clone());
+        init_input_method_manager(&mut display.borrow_mut(), shell_handles.token, log.clone());
+        init_viewporter(&mut display.borrow_mut(), shell_handles.token, log.clone());
+        let (fractional_scales, _) = init_fractional_scale_manager(&mut display.borrow_mut(), log.clone());
 
-        let (mut seat, _) = Seat::new(
-            &mut display.borrow_mut(),
-            seat_name.clone(),
-            shell_handles.token,
-            log.clone(),
-        );
-
-        let cursor_status = Arc::new(Mutex::new(CursorImageStatus::Default));
-
-        let cursor_status2 = cursor_status.clone();
-        let pointer = seat.add_pointer(shell_handles.token, move |new_status| {
-            // TODO: hide winit system cursor when relevant
-            *cursor_status2.lock().unwrap() = new_status
-        });
-
-        let keyboard = seat
-            .add_keyboard(config.keyboard.get_seat_xkbconfig(), 200, 25, |seat, focus| {
-                set_data_device_focus(seat, focus.and_then(|s| s.as_ref().client()))
-            })
-            .expect("Failed to initialize the keyboard");
+        let ipc = IpcServer::new(&handle, &socket_name, log.clone());
 
         #[cfg(feature = "xwayland")]
         let _xwayland = {
@@ -178,28 +264,400 @@ impl AnvilState {
             XWayland::init(xwm, handle.clone(), display.clone(), &mut (), log.clone()).unwrap()
         };
 
-        AnvilState {
+        #[allow(unused_mut)]
+        let mut state = AnvilState {
             running: Arc::new(AtomicBool::new(true)),
             display,
             handle,
             ctoken: shell_handles.token,
             window_map: shell_handles.window_map,
-            dnd_icon,
             config,
             log,
             socket_name,
-            pointer,
-            keyboard,
-            cursor_status,
-            pointer_location: Rc::new(RefCell::new((0.0, 0.0))),
+            seats,
+            pointer_constraints,
+            fractional_scales,
+            winit_output: None,
             #[cfg(feature = "udev")]
             output_map,
             seat_name,
             #[cfg(feature = "udev")]
             session,
+            ipc,
+            published: None,
+            last_input: Instant::now(),
+            idle: false,
+            auto_raise: None,
+            auto_raise_timer,
+            window_cycle: None,
+            keyboard_move: None,
+            snap: shell_handles.snap,
+            #[cfg(feature = "switcher")]
+            switcher: Rc::new(RefCell::new(None)),
+            #[cfg(feature = "switcher")]
+            switcher_files: SwitcherFiles::default(),
+            #[cfg(feature = "scripting")]
+            scripts: None,
             _wayland_event_source,
             #[cfg(feature = "xwayland")]
             _xwayland,
+        };
+        #[cfg(feature = "switcher")]
+        state.switcher_files.configure(&state.config.switcher, &state.log);
+        #[cfg(feature = "scripting")]
+        state.load_scripts();
+        state
+    }
+
+    /// Returns the index of the seat an input device is assigned to.
+    ///
+    /// Devices that are not listed in the configuration belong to the default seat.
+    pub fn seat_for_device(&self, device: Option<&str>) -> usize {
+        device
+            .and_then(|device| self.config.seat_for_device(device))
+            .and_then(|name| self.seats.iter().position(|seat| seat.name == name))
+            .unwrap_or(0)
+    }
+
+    /// Returns the index of the seat a `wl_pointer` of a client most likely belongs to.
+    ///
+    /// The pointer does not know its seat, it is taken to be the seat whose pointer is on a
+    /// surface of the client, as clients ask for pointer features while they have the pointer.
+    pub fn seat_of_pointer(&self, pointer: &WlPointer) -> usize {
+        self.seats
+            .iter()
+            .position(|seat| {
+                seat.pointer_focus
+                    .borrow()
+                    .as_ref()
+                    .map_or(false, |(focus, _)| pointer.as_ref().same_client_as(focus.as_ref()))
+            })
+            .unwrap_or(0)
+    }
+
+    /// The input method states of all seats, for the renderers to draw the popups.
+    pub fn input_methods(&self) -> Vec<Rc<RefCell<InputMethodState>>> {
+        self.seats.iter().map(|seat| seat.input_method.clone()).collect()
+    }
+
+    /// The cursors of all seats, for the renderers.
+    pub fn seat_cursors(&self) -> Vec<SeatCursor> {
+        self.seats.iter().map(|seat| seat.cursor.clone()).collect()
+    }
+
+    /// Takes the damage of the windows and of every surface drawn over them since the last call,
+    /// in global coordinates.
+    pub fn take_damage(&self) -> Vec<Rectangle> {
+        let mut damage = self.window_map.borrow_mut().take_damage();
+        let window_map = self.window_map.borrow();
+        // the windows that committed, for the previews of the switcher
+        let mut committed = Vec::new();
+        window_map.with_windows_from_bottom_to_top(|toplevel, location, _| {
+            if let Some(surface) = toplevel.get_surface() {
+                let before = damage.len();
+                take_surface_damage(surface, location, self.ctoken, &mut damage);
+                if damage.len() > before {
+                    committed.extend(window_map.id(toplevel));
+                }
+            }
+        });
+        // the hidden windows are damaged as a whole when they are shown again
+        window_map.with_hidden_windows(|toplevel, location| {
+            if let Some(surface) = toplevel.get_surface() {
+                let mut hidden_damage = Vec::new();
+                take_surface_damage(surface, location, self.ctoken, &mut hidden_damage);
+                if !hidden_damage.is_empty() {
+                    committed.extend(window_map.id(toplevel));
+                }
+            }
+        });
+        for input_method in self.input_methods() {
+            for (surface, location) in input_method.borrow().popups(&window_map) {
+                take_surface_damage(&surface, location, self.ctoken, &mut damage);
+            }
+        }
+        for seat in &self.seats {
+            let (x, y) = *seat.cursor.location.borrow();
+            let location = (x as i32, y as i32);
+            if let Some(ref surface) = *seat.cursor.dnd_icon.lock().unwrap() {
+                take_surface_damage(surface, location, self.ctoken, &mut damage);
+            }
+            if let CursorImageStatus::Image(ref surface) = *seat.cursor.status.lock().unwrap() {
+                let location = cursor_surface_location(surface, location, self.ctoken);
+                take_surface_damage(surface, location, self.ctoken, &mut damage);
+            }
         }
+        // the previews of the switcher show the windows live
+        #[cfg(feature = "switcher")]
+        damage.extend(self.switcher_damage(&committed));
+        #[cfg(not(feature = "switcher"))]
+        let _ = committed;
+        damage
+    }
+
+    /// The window with the keyboard focus of the default seat.
+    pub fn focused_window(&self) -> Option<Kind<Roles>> {
+        let focus = self.seats[0].keyboard_focus.borrow().clone()?;
+        self.window_of(&self.window_map.borrow(), &focus)
+    }
+
+    /// Finds the window of a surface, a subsurface belongs to the window of its parent.
+    pub fn window_of(&self, window_map: &MyWindowMap, surface: &WlSurface) -> Option<Kind<Roles>> {
+        let mut root = surface.clone();
+        while let Some(parent) = self.ctoken.get_parent(&root) {
+            root = parent;
+        }
+        window_map.find(&root)
+    }
+
+    /// Raises a window and gives it the keyboard focus of the default seat, showing its workspace.
+    pub fn focus_window(&mut self, toplevel: &Kind<Roles>) {
+        self.focus_window_on(0, toplevel);
+    }
+
+    /// Raises a window and gives it the keyboard focus of a seat, showing its workspace and
+    /// restoring it if it is minimized.
+    pub fn focus_window_on(&mut self, seat: usize, toplevel: &Kind<Roles>) {
+        let workspace = self.window_map.borrow().workspace(toplevel);
+        if let Some(workspace) = workspace {
+            self.switch_workspace(workspace);
+        }
+        {
+            let mut window_map = self.window_map.borrow_mut();
+            window_map.set_minimized(toplevel, false);
+            window_map.bring_to_top(toplevel);
+        }
+        self.seats[seat]
+            .keyboard
+            .set_focus(toplevel.get_surface(), SCOUNTER.next_serial());
+    }
+
+    /// Keeps the focus history up to date with the focus of the seats, and moves the focus of the
+    /// seats away from minimized windows.
+    pub fn update_focus_history(&mut self) {
+        for seat in &self.seats {
+            // only a new focus goes to the front, the focus of a seat left alone stays behind
+            let focus = seat.keyboard_focus.borrow().clone();
+            let noted = match (&focus, &*seat.noted_focus.borrow()) {
+                (Some(focus), Some(noted)) => focus.as_ref().equals(noted.as_ref()),
+                (focus, noted) => focus.is_none() && noted.is_none(),
+            };
+            if noted {
+                continue;
+            }
+            match focus {
+                Some(focus) => {
+                    let mut window_map = self.window_map.borrow_mut();
+                    // a surface that is not a window yet is noted once it is one
+                    if let Some(toplevel) = self.window_of(&window_map, &focus) {
+                        window_map.note_focus(&toplevel);
+                        *seat.noted_focus.borrow_mut() = Some(focus);
+                    }
+                }
+                None => *seat.noted_focus.borrow_mut() = None,
+            }
+        }
+        let top = self.window_map.borrow().top_window();
+        for seat in &self.seats {
+            let minimized = {
+                let window_map = self.window_map.borrow();
+                let focus = seat.keyboard_focus.borrow();
+                focus
+                    .as_ref()
+                    .and_then(|focus| self.window_of(&window_map, focus))
+                    .map_or(false, |toplevel| window_map.is_minimized(&toplevel))
+            };
+            if minimized {
+                seat.keyboard.set_focus(
+                    top.as_ref().and_then(|toplevel| toplevel.get_surface()),
+                    SCOUNTER.next_serial(),
+                );
+            }
+        }
+    }
+
+    /// Shows the windows of another workspace.
+    ///
+    /// The seats focusing a window that gets hidden focus the topmost window of the workspace instead.
+    pub fn switch_workspace(&mut self, workspace: usize) {
+        let mut window_map = self.window_map.borrow_mut();
+        if window_map.active_workspace() == workspace {
+            return;
+        }
+        info!(self.log, "Switching to workspace {}", workspace + 1);
+        window_map.set_active_workspace(workspace);
+        let top = window_map.top_window();
+        for seat in &self.seats {
+            let visible = seat
+                .keyboard_focus
+                .borrow()
+                .as_ref()
+                .and_then(|focus| self.window_of(&window_map, focus))
+                .and_then(|toplevel| window_map.workspace(&toplevel))
+                == Some(workspace);
+            if !visible {
+                seat.keyboard.set_focus(
+                    top.as_ref().and_then(|toplevel| toplevel.get_surface()),
+                    SCOUNTER.next_serial(),
+                );
+            }
+        }
+    }
+
+    /// Reads the configuration file again.
+    ///
+    /// The key bindings, the workspaces, the focus model, the shortcuts inhibitor allow list and
+    /// the script apply right away, the other settings at the next start.
+    pub fn reload_config(&mut self) -> Result<(), String> {
+        let config = self.config.reload()?;
+        info!(self.log, "Reloaded the configuration");
+        let last = config.workspaces - 1;
+        self.config = config;
+        for seat in &self.seats {
+            seat.shortcuts_inhibitors
+                .borrow_mut()
+                .update_allowed(&self.config.shortcuts_inhibit, self.ctoken);
+        }
+        #[cfg(feature = "switcher")]
+        self.switcher_files.configure(&self.config.switcher, &self.log);
+        #[cfg(feature = "scripting")]
+        self.load_scripts();
+
+        // the windows of workspaces that are gone end up on the last one
+        if self.window_map.borrow().active_workspace() > last {
+            self.switch_workspace(last);
+        }
+        let mut window_map = self.window_map.borrow_mut();
+        let mut orphans = Vec::new();
+        window_map.with_all_windows(|_, toplevel, _, workspace| {
+            if workspace > last {
+                orphans.push(toplevel.clone());
+            }
+        });
+        for toplevel in orphans {
+            window_map.set_workspace(&toplevel, last);
+        }
+        Ok(())
+    }
+
+    /// Tells the subscribed ipc clients, the script and the hooks what changed since the last
+    /// call.
+    pub fn publish_events(&mut self) {
+        #[allow(unused_mut)]
+        let mut interested = self.ipc.as_ref().map_or(false, IpcServer::subscribed)
+            || self.config.hooks.wants_events();
+        #[cfg(feature = "scripting")]
+        {
+            interested |= self.scripts.as_ref().map_or(false, Scripts::wants_events);
+        }
+        if !interested {
+            self.published = None;
+            return;
+        }
+
+        let current = self.ipc_snapshot();
+        let events = match self.published.take() {
+            Some(previous) => previous.changes(&current),
+            None => Vec::new(),
+        };
+        if let Some(ref mut ipc) = self.ipc {
+            ipc.publish(&events, &self.handle);
+        }
+        #[cfg(feature = "scripting")]
+        {
+            if let Some(ref scripts) = self.scripts {
+                scripts.publish(&current, &events);
+            }
+            self.run_script_commands();
+        }
+        self.run_event_hooks(&events);
+        self.published = Some(current);
+    }
+
+    /// Runs the commands hooked to the windows and workspaces changing.
+    fn run_event_hooks(&self, events: &[(EventKind, Value)]) {
+        let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+        for (_, event) in events {
+            let window = &event["window"];
+            let window_vars = || {
+                vec![
+                    ("WAYBOX_WINDOW_ID", window["id"].to_string()),
+                    ("WAYBOX_APP_ID", text(&window["app_id"])),
+                    ("WAYBOX_TITLE", text(&window["title"])),
+                    ("WAYBOX_WORKSPACE", window["workspace"].to_string()),
+                ]
+            };
+            let (hook, vars) = match (event["event"].as_str(), event["change"].as_str()) {
+                (Some("window"), Some("opened")) => (Hook::WindowOpened, window_vars()),
+                (Some("window"), Some("closed")) => (Hook::WindowClosed, window_vars()),
+                (Some("workspace"), Some("switched")) => (
+                    Hook::WorkspaceSwitched,
+                    vec![
+                        ("WAYBOX_WORKSPACE", event["workspace"].to_string()),
+                        ("WAYBOX_PREVIOUS_WORKSPACE", event["previous"].to_string()),
+                    ],
+                ),
+                _ => continue,
+            };
+            self.config.hooks.run(hook, &vars, &self.log);
+        }
+    }
+
+    /// Notes an input event, the session is not idle anymore.
+    pub fn input_activity(&mut self) {
+        self.last_input = Instant::now();
+        if self.idle {
+            self.idle = false;
+            info!(self.log, "Resumed from idle");
+            self.config.hooks.run(Hook::Resumed, &[], &self.log);
+        }
+    }
+
+    /// Runs the idle hook once there was no input for the configured time.
+    pub fn check_idle(&mut self) {
+        let timeout = match self.config.hooks.idle_timeout {
+            Some(timeout) if !self.idle => Duration::from_secs(timeout),
+            _ => return,
+        };
+        if self.last_input.elapsed() >= timeout {
+            self.idle = true;
+            info!(self.log, "Idle");
+            self.config.hooks.run(Hook::Idle, &[], &self.log);
+        }
+    }
+
+    /// The outputs of the backend, with their place in the global space.
+    pub fn output_views(&self) -> Vec<OutputView> {
+        #[allow(unused_mut)]
+        let mut views = self.winit_output.iter().cloned().collect::<Vec<_>>();
+        #[cfg(feature = "udev")]
+        {
+            if let Some(ref output_map) = self.output_map {
+                views.extend(output_map.borrow().iter().map(|output| output.view()));
+            }
+        }
+        views
+    }
+
+    /// Sends every surface the scale of the output its window is on.
+    pub fn update_fractional_scales(&self) {
+        let window_map = self.window_map.borrow();
+        let outputs = self.output_views();
+        let ctoken = self.ctoken;
+        self.fractional_scales.borrow_mut().update(|surface| {
+            // subsurfaces are shown with their parent
+            let mut root = surface.clone();
+            while let Some(parent) = ctoken.get_parent(&root) {
+                root = parent;
+            }
+            let location = window_map
+                .find(&root)
+                .and_then(|toplevel| window_map.location(&toplevel));
+            location
+                .and_then(|location| outputs.iter().find(|output| output.geometry.contains(location)))
+                .or_else(|| outputs.first())
+                .map(|output| output.scale)
+                .unwrap_or(1.0)
+        });
     }
 }
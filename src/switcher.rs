@@ -0,0 +1,682 @@
+//! The window switcher, shown while the window cycling goes on.
+//!
+//! It is a row of live previews of the cycled windows, centered on the output the cycling started
+//! on, each with the icon and title of its window below it and the selected one highlighted.
+//! Pointing at a preview selects it, clicking it also ends the cycling.
+//!
+//! The previews are the surface trees of the windows drawn scaled down into offscreen textures,
+//! again only once their windows committed, and the frames draw the textures. The icons are the
+//! PNG icons named by the desktop entries of the apps, or named after their app ids. The font and
+//! the icon files are looked up as the configuration is loaded and the cycling starts, the frames
+//! only read them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use fontdue::{Font, FontSettings};
+use image::{imageops, ImageBuffer, Rgba, RgbaImage};
+use slog::Logger;
+use smithay::{
+    backend::{
+        egl::display::EGLBufferReader,
+        renderer::{Frame, ImportDma, Renderer, Texture, Transform},
+        SwapBuffersError,
+    },
+    utils::Rectangle,
+};
+
+use crate::{
+    custom::config::Switcher,
+    drawing::{draw_surface_tree_scaled, OutputView},
+    gl::{Offscreen, TextureProgram},
+    shell::{MyCompositorToken, MyWindowMap},
+    state::AnvilState,
+    window_map::WindowId,
+};
+
+/// The space around and between the previews, in logical pixels
+const PADDING: i32 = 16;
+/// The space between a preview and its label
+const LABEL_GAP: i32 = 8;
+const ICON_SIZE: i32 = 24;
+/// The size of the titles, in logical pixels
+const TITLE_SIZE: f64 = 14.0;
+/// The sizes of the icon theme directories, the preferred ones first
+const ICON_SIZES: [&str; 6] = ["48x48", "64x64", "32x32", "128x128", "256x256", "24x24"];
+
+/// The colors, with premultiplied alpha
+const PANEL_COLOR: [u8; 4] = [0x10, 0x10, 0x10, 0xc0];
+const HIGHLIGHT_COLOR: [u8; 4] = [0x50, 0x70, 0xa0, 0xc0];
+const TITLE_COLOR: [u8; 3] = [0xff, 0xff, 0xff];
+
+/// A window in the switcher.
+#[derive(Debug, Clone)]
+pub struct SwitcherEntry {
+    pub id: WindowId,
+    pub title: String,
+    /// The icon file of the app id, or of the class of X11 windows
+    pub icon: Option<PathBuf>,
+    /// Counts the commits of the window while it is shown, its preview is drawn again when it
+    /// changes
+    pub commits: usize,
+}
+
+/// What the switcher shows, shared with the renderers.
+#[derive(Debug, Clone)]
+pub struct SwitcherView {
+    /// The output it is shown on, in global coordinates
+    pub output: Rectangle,
+    pub entries: Vec<SwitcherEntry>,
+    pub selected: usize,
+    /// The configured largest side of a preview
+    pub thumbnail_size: i32,
+    /// The font file of the titles
+    pub font: Option<PathBuf>,
+    /// Counts the changes of the switcher, the renderers repaint it when it changes
+    pub revision: usize,
+}
+
+impl SwitcherView {
+    /// The side of the square a preview fits into, smaller than configured if the row would not
+    /// fit on the output otherwise.
+    fn preview_size(&self) -> i32 {
+        let count = self.entries.len().max(1) as i32;
+        let fitting = (self.output.width - PADDING * (count + 1)) / count;
+        self.thumbnail_size.min(fitting).max(1)
+    }
+
+    /// The panel behind the previews, in global coordinates.
+    pub fn bounds(&self) -> Rectangle {
+        let size = self.preview_size();
+        let count = self.entries.len() as i32;
+        let width = count * size + (count + 1) * PADDING;
+        let height = size + LABEL_GAP + ICON_SIZE + 2 * PADDING;
+        Rectangle {
+            x: self.output.x + (self.output.width - width) / 2,
+            y: self.output.y + (self.output.height - height) / 2,
+            width,
+            height,
+        }
+    }
+
+    /// The places of the entries, a preview with its label below it.
+    pub fn cells(&self) -> Vec<Rectangle> {
+        let bounds = self.bounds();
+        let size = self.preview_size();
+        (0..self.entries.len() as i32)
+            .map(|idx| Rectangle {
+                x: bounds.x + PADDING + idx * (size + PADDING),
+                y: bounds.y + PADDING,
+                width: size,
+                height: size + LABEL_GAP + ICON_SIZE,
+            })
+            .collect()
+    }
+
+    /// The panel and its revision, an overlay of the outputs.
+    pub fn overlay(&self) -> (Rectangle, usize) {
+        (self.bounds(), self.revision)
+    }
+
+    /// The entry at a point of the global space.
+    pub fn entry_at(&self, (x, y): (f64, f64)) -> Option<usize> {
+        self.cells()
+            .iter()
+            .position(|cell| cell.contains((x as i32, y as i32)))
+    }
+}
+
+/// The preview of a window, drawn for a place in the frame.
+struct Preview {
+    texture: Offscreen,
+    /// The pixels of the frame it was drawn for, as gl counts them
+    place: Rectangle,
+    /// The commits of the window it shows
+    commits: usize,
+}
+
+/// The textures of the switcher for a renderer.
+///
+/// The titles are rasterized for the scale of the output showing them, the ones that are no
+/// longer shown are dropped after every frame, as are the previews.
+pub struct SwitcherTextures<T> {
+    /// The single pixel textures the panel and the highlight are stretched from
+    panel: Option<T>,
+    highlight: Option<T>,
+    /// The font by path, `None` if it could not be loaded
+    font: Option<(Option<PathBuf>, Option<Font>)>,
+    /// The titles by text and height in physical pixels, `None` if nothing of them fits
+    titles: HashMap<(String, u32), Option<T>>,
+    /// The icons by file and size in physical pixels, `None` if it could not be loaded
+    icons: HashMap<(PathBuf, u32), Option<T>>,
+    /// The program drawing the previews, `None` if it could not be compiled
+    program: Option<Option<TextureProgram>>,
+    /// The previews by window
+    previews: HashMap<WindowId, Preview>,
+    log: Logger,
+}
+
+impl<T: Texture + 'static> SwitcherTextures<T> {
+    pub fn new(log: Logger) -> Self {
+        SwitcherTextures {
+            panel: None,
+            highlight: None,
+            font: None,
+            titles: HashMap::new(),
+            icons: HashMap::new(),
+            program: None,
+            previews: HashMap::new(),
+            log,
+        }
+    }
+
+    /// Draws the switcher, if it is on the output.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw<R, F, E>(
+        &mut self,
+        renderer: &mut R,
+        frame: &mut F,
+        egl_buffer_reader: Option<&EGLBufferReader>,
+        window_map: &MyWindowMap,
+        view: &SwitcherView,
+        output: &OutputView,
+        compositor_token: MyCompositorToken,
+    ) -> Result<(), SwapBuffersError>
+    where
+        R: Renderer<Error = E, TextureId = T, Frame = F> + ImportDma,
+        F: Frame<Error = E, TextureId = T>,
+        E: std::error::Error + Into<SwapBuffersError>,
+    {
+        let bounds = view.bounds();
+        if !output.geometry.overlaps(&bounds) {
+            return Ok(());
+        }
+        self.load_font(view.font.as_deref());
+        if self.panel.is_none() {
+            self.panel = Some(import_pixel(renderer, PANEL_COLOR).map_err(Into::<SwapBuffersError>::into)?);
+        }
+        if self.highlight.is_none() {
+            self.highlight = Some(import_pixel(renderer, HIGHLIGHT_COLOR).map_err(Into::<SwapBuffersError>::into)?);
+        }
+        if self.program.is_none() {
+            let program = TextureProgram::new();
+            if program.is_none() {
+                warn!(self.log, "Failed to compile the program drawing the previews of the switcher");
+            }
+            self.program = Some(program);
+        }
+
+        let rect = |rect: Rectangle| (rect.x as f64, rect.y as f64, rect.width as f64, rect.height as f64);
+        if let Some(ref panel) = self.panel {
+            output
+                .render_texture(frame, panel, rect(bounds), Transform::Normal)
+                .map_err(Into::<SwapBuffersError>::into)?;
+        }
+
+        let size = view.preview_size();
+        let mut shown_titles = HashSet::new();
+        let mut shown_previews = HashSet::new();
+        for (idx, (entry, cell)) in view.entries.iter().zip(view.cells()).enumerate() {
+            if idx == view.selected {
+                if let Some(ref highlight) = self.highlight {
+                    let around = Rectangle {
+                        x: cell.x - PADDING / 2,
+                        y: cell.y - PADDING / 2,
+                        width: cell.width + PADDING,
+                        height: cell.height + PADDING,
+                    };
+                    output
+                        .render_texture(frame, highlight, rect(around), Transform::Normal)
+                        .map_err(Into::<SwapBuffersError>::into)?;
+                }
+            }
+
+            // the window scaled down to fit the square, centered in it
+            let toplevel = window_map.find_by_id(entry.id);
+            let surface = toplevel.as_ref().and_then(|toplevel| toplevel.get_surface());
+            let geometry = toplevel.as_ref().and_then(|toplevel| window_map.geometry(toplevel));
+            if let (Some(surface), Some(geometry)) = (surface, geometry) {
+                let (width, height) = (geometry.width.max(1) as f64, geometry.height.max(1) as f64);
+                let scale = (size as f64 / width).min(size as f64 / height).min(1.0);
+                let x = cell.x as f64 + (size as f64 - width * scale) / 2.0;
+                let y = cell.y as f64 + (size as f64 - height * scale) / 2.0;
+                let log = &self.log;
+                let mut draw = || {
+                    draw_surface_tree_scaled(
+                        renderer,
+                        frame,
+                        surface,
+                        egl_buffer_reader,
+                        (x, y),
+                        scale,
+                        output,
+                        compositor_token,
+                        log,
+                    )
+                };
+                let square = (cell.x as f64, cell.y as f64, size as f64, size as f64);
+                let place = output.framebuffer_rect(square);
+                match self.program {
+                    Some(Some(ref program)) if place.width > 0 && place.height > 0 => {
+                        shown_previews.insert(entry.id);
+                        let current = self.previews.get(&entry.id).map_or(false, |preview| {
+                            preview.place == place && preview.commits == entry.commits
+                        });
+                        if !current {
+                            // the window committed since, or the preview was drawn for another place
+                            let pixels = (place.width, place.height);
+                            let texture = match self.previews.remove(&entry.id) {
+                                Some(stale) if stale.texture.size == pixels => stale.texture,
+                                stale => {
+                                    if let Some(stale) = stale {
+                                        stale.texture.destroy();
+                                    }
+                                    Offscreen::new(pixels)
+                                }
+                            };
+                            let drawn = texture.draw_into(place, draw);
+                            let commits = entry.commits;
+                            self.previews.insert(entry.id, Preview { texture, place, commits });
+                            drawn?;
+                        }
+                        self.previews[&entry.id].texture.draw(program, place);
+                    }
+                    // without the program the preview is drawn into the frame
+                    _ => draw()?,
+                }
+            }
+
+            // the icon and the title below
+            let label_y = cell.y + size + LABEL_GAP;
+            let mut title_x = cell.x;
+            if let Some(ref path) = entry.icon {
+                let icon_size = (ICON_SIZE as f64 * output.scale).round() as u32;
+                let key = (path.clone(), icon_size);
+                if !self.icons.contains_key(&key) {
+                    let icon = match load_icon(path, icon_size) {
+                        Some(image) => Some(renderer.import_bitmap(&image).map_err(Into::<SwapBuffersError>::into)?),
+                        None => None,
+                    };
+                    self.icons.insert(key.clone(), icon);
+                }
+                if let Some(Some(icon)) = self.icons.get(&key) {
+                    let place = (cell.x as f64, label_y as f64, ICON_SIZE as f64, ICON_SIZE as f64);
+                    output
+                        .render_texture(frame, icon, place, Transform::Normal)
+                        .map_err(Into::<SwapBuffersError>::into)?;
+                    title_x += ICON_SIZE + LABEL_GAP / 2;
+                }
+            }
+
+            let height = (TITLE_SIZE * output.scale).round() as u32;
+            let max_width = ((cell.x + cell.width - title_x) as f64 * output.scale).max(0.0) as u32;
+            let key = (entry.title.clone(), height);
+            if !self.titles.contains_key(&key) {
+                let image = self
+                    .font
+                    .as_ref()
+                    .and_then(|(_, font)| font.as_ref())
+                    .and_then(|font| render_text(font, &entry.title, height, max_width));
+                let title = match image {
+                    Some(image) => Some(renderer.import_bitmap(&image).map_err(Into::<SwapBuffersError>::into)?),
+                    None => None,
+                };
+                self.titles.insert(key.clone(), title);
+            }
+            if let Some(Some(title)) = self.titles.get(&key) {
+                let (width, height) = (
+                    title.width() as f64 / output.scale,
+                    title.height() as f64 / output.scale,
+                );
+                let y = label_y as f64 + (ICON_SIZE as f64 - height) / 2.0;
+                output
+                    .render_texture(frame, title, (title_x as f64, y, width, height), Transform::Normal)
+                    .map_err(Into::<SwapBuffersError>::into)?;
+            }
+            shown_titles.insert(key);
+        }
+
+        // the titles of windows that changed them or went away
+        let outdated = self
+            .titles
+            .keys()
+            .filter(|key| !shown_titles.contains(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in outdated {
+            if let Some(Some(texture)) = self.titles.remove(&key) {
+                renderer.destroy_texture(texture).map_err(Into::<SwapBuffersError>::into)?;
+            }
+        }
+        let outdated = self
+            .previews
+            .keys()
+            .filter(|id| !shown_previews.contains(*id))
+            .copied()
+            .collect::<Vec<_>>();
+        for id in outdated {
+            if let Some(preview) = self.previews.remove(&id) {
+                preview.texture.destroy();
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the font of the titles, unless it is loaded already.
+    fn load_font(&mut self, path: Option<&Path>) {
+        if matches!(self.font, Some((ref loaded, _)) if loaded.as_deref() == path) {
+            return;
+        }
+        let font = match path {
+            Some(file) => match fs::read(&file)
+                .map_err(|err| err.to_string())
+                .and_then(|data| Font::from_bytes(data, FontSettings::default()).map_err(String::from))
+            {
+                Ok(font) => Some(font),
+                Err(err) => {
+                    warn!(self.log, "Failed to load the font of the switcher: {}", err; "path" => file.display().to_string());
+                    None
+                }
+            },
+            None => None,
+        };
+        // the titles were rasterized with the old font
+        self.titles.clear();
+        self.font = Some((path.map(PathBuf::from), font));
+    }
+}
+
+/// Uploads a texture of a single pixel, to be stretched over a rectangle.
+fn import_pixel<R, F, E, T>(renderer: &mut R, color: [u8; 4]) -> Result<T, E>
+where
+    R: Renderer<Error = E, TextureId = T, Frame = F>,
+    F: Frame<Error = E, TextureId = T>,
+{
+    renderer.import_bitmap(&ImageBuffer::from_pixel(1, 1, Rgba(color)))
+}
+
+/// The sans-serif font of fontconfig.
+fn default_font() -> Option<PathBuf> {
+    let output = Command::new("fc-match")
+        .args(&["--format=%{file}", "sans-serif"])
+        .output()
+        .ok()?;
+    let path = String::from_utf8(output.stdout).ok()?;
+    Some(PathBuf::from(path)).filter(|path| path.is_file())
+}
+
+/// Rasterizes a line of text with a height of `height` pixels, cut off after `max_width` pixels.
+fn render_text(font: &Font, text: &str, height: u32, max_width: u32) -> Option<RgbaImage> {
+    let px = height as f32;
+    let line = font.horizontal_line_metrics(px)?;
+    let baseline = line.ascent.ceil() as i32;
+    let height = (line.ascent - line.descent).ceil().max(1.0) as u32;
+
+    let mut glyphs = Vec::new();
+    let mut advance = 0.0f32;
+    for character in text.chars() {
+        let (metrics, bitmap) = font.rasterize(character, px);
+        if (advance + metrics.advance_width).ceil() as u32 > max_width {
+            break;
+        }
+        glyphs.push((advance.round() as i32, metrics, bitmap));
+        advance += metrics.advance_width;
+    }
+    let width = advance.ceil() as u32;
+    if width == 0 {
+        return None;
+    }
+
+    let mut image = RgbaImage::new(width, height);
+    for (x, metrics, bitmap) in glyphs {
+        let left = x + metrics.xmin;
+        let top = baseline - metrics.height as i32 - metrics.ymin;
+        for row in 0..metrics.height {
+            for column in 0..metrics.width {
+                let (x, y) = (left + column as i32, top + row as i32);
+                if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+                    continue;
+                }
+                let coverage = bitmap[row * metrics.width + column];
+                let pixel = image.get_pixel_mut(x as u32, y as u32);
+                // glyphs may overlap a little, the coverage adds up
+                let alpha = pixel[3].saturating_add(coverage);
+                let premultiply = |channel: u8| (channel as u32 * alpha as u32 / 255) as u8;
+                *pixel = Rgba([
+                    premultiply(TITLE_COLOR[0]),
+                    premultiply(TITLE_COLOR[1]),
+                    premultiply(TITLE_COLOR[2]),
+                    alpha,
+                ]);
+            }
+        }
+    }
+    Some(image)
+}
+
+/// The directories with the shared data, like the icons and desktop entries, the user's first.
+fn data_dirs() -> Vec<PathBuf> {
+    let home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    let dirs = env::var("XDG_DATA_DIRS").unwrap_or_else(|_| String::from("/usr/local/share:/usr/share"));
+    home.into_iter()
+        .chain(dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .collect()
+}
+
+/// The icon named by the desktop entry of an app.
+fn desktop_entry_icon(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Icon="))
+        .map(|icon| icon.trim().to_string())
+}
+
+/// Finds the PNG icon of an app, in the icon theme, hicolor or the pixmaps.
+fn find_icon(app_id: &str, theme: Option<&str>) -> Option<PathBuf> {
+    let dirs = data_dirs();
+    let name = dirs
+        .iter()
+        .find_map(|dir| desktop_entry_icon(&dir.join("applications").join(format!("{}.desktop", app_id))))
+        .unwrap_or_else(|| app_id.to_string());
+    if Path::new(&name).is_absolute() {
+        return Some(PathBuf::from(name)).filter(|path| path.is_file());
+    }
+
+    let file = format!("{}.png", name);
+    let themes = theme.into_iter().chain(Some("hicolor"));
+    for theme in themes {
+        for dir in &dirs {
+            for size in ICON_SIZES.iter() {
+                let path = dir.join("icons").join(theme).join(size).join("apps").join(&file);
+                if path.is_file() {
+                    return Some(path);
+                }
+            }
+        }
+    }
+    dirs.iter()
+        .map(|dir| dir.join("pixmaps").join(&file))
+        .find(|path| path.is_file())
+}
+
+/// Loads an icon, scaled to `size` pixels and with premultiplied alpha.
+fn load_icon(path: &Path, size: u32) -> Option<RgbaImage> {
+    let image = image::open(path).ok()?.to_rgba8();
+    let mut icon = imageops::resize(&image, size, size, imageops::FilterType::Triangle);
+    for pixel in icon.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        for channel in pixel.0.iter_mut().take(3) {
+            *channel = (*channel as u32 * alpha / 255) as u8;
+        }
+    }
+    Some(icon)
+}
+
+/// The font and icon files of the switcher, looked up once instead of in the frames.
+#[derive(Debug, Default)]
+pub struct SwitcherFiles {
+    /// The configured font and the file it was resolved to
+    font: Option<(Option<String>, Option<PathBuf>)>,
+    /// The icon theme the icons were looked up in
+    icon_theme: Option<String>,
+    /// The icon files by app id, `None` if an app has none
+    icons: HashMap<String, Option<PathBuf>>,
+}
+
+impl SwitcherFiles {
+    /// Resolves the font of the configuration, and forgets the icons of another theme.
+    pub fn configure(&mut self, config: &Switcher, log: &Logger) {
+        if !matches!(self.font, Some((ref font, _)) if *font == config.font) {
+            let file = config.font.as_deref().map(PathBuf::from).or_else(default_font);
+            if file.is_none() {
+                warn!(log, "There is no font for the titles of the switcher");
+            }
+            self.font = Some((config.font.clone(), file));
+        }
+        if self.icon_theme != config.icon_theme {
+            self.icon_theme = config.icon_theme.clone();
+            self.icons.clear();
+        }
+    }
+
+    fn font(&self) -> Option<PathBuf> {
+        self.font.as_ref().and_then(|(_, file)| file.clone())
+    }
+
+    fn icon(&mut self, app_id: &str) -> Option<PathBuf> {
+        let theme = self.icon_theme.as_deref();
+        self.icons
+            .entry(app_id.to_string())
+            .or_insert_with(|| find_icon(app_id, theme))
+            .clone()
+    }
+}
+
+impl AnvilState {
+    /// Shows the window cycling in the switcher, or hides it once the cycling ended.
+    pub fn update_switcher(&mut self) {
+        let cycle = match self.window_cycle {
+            Some(ref cycle) if self.config.switcher.enabled => cycle,
+            _ => {
+                *self.switcher.borrow_mut() = None;
+                return;
+            }
+        };
+        let tree = self.window_tree();
+        let outputs = self.output_geometries();
+        // the output of the focused window, or the first one if the pointer was on none
+        let output = cycle.output.or_else(|| {
+            let focused = tree
+                .focused
+                .and_then(|id| tree.get(id))
+                .and_then(|window| window.output.as_ref());
+            outputs
+                .iter()
+                .find(|(name, _)| Some(name) == focused)
+                .or_else(|| outputs.first())
+                .map(|(_, geometry)| *geometry)
+        });
+        let output = match output {
+            Some(output) => output,
+            None => {
+                *self.switcher.borrow_mut() = None;
+                return;
+            }
+        };
+
+        let files = &mut self.switcher_files;
+        let previous = self.switcher.borrow_mut().take();
+        let commits = |id| {
+            previous
+                .iter()
+                .flat_map(|view| &view.entries)
+                .find(|entry| entry.id == id)
+                .map_or(0, |entry| entry.commits)
+        };
+        let entries = cycle
+            .windows
+            .iter()
+            .filter_map(|&id| tree.get(id))
+            .map(|window| SwitcherEntry {
+                id: window.id,
+                title: window.title.clone().unwrap_or_default(),
+                icon: window
+                    .app_id
+                    .as_ref()
+                    .or_else(|| window.class.as_ref())
+                    .and_then(|app_id| files.icon(app_id)),
+                commits: commits(window.id),
+            })
+            .collect::<Vec<_>>();
+        // windows that went away are left out
+        let selected = cycle
+            .selected()
+            .and_then(|id| entries.iter().position(|entry| entry.id == id))
+            .unwrap_or(0);
+        *self.switcher.borrow_mut() = Some(SwitcherView {
+            output,
+            entries,
+            selected,
+            thumbnail_size: self.config.switcher.thumbnail_size as i32,
+            font: files.font(),
+            revision: previous.map_or(0, |view| view.revision + 1),
+        });
+    }
+
+    /// The previews of the windows among `committed` that the switcher shows, their windows
+    /// committed since the last frames.
+    pub fn switcher_damage(&self, committed: &[WindowId]) -> Vec<Rectangle> {
+        let mut switcher = self.switcher.borrow_mut();
+        let view = match *switcher {
+            Some(ref mut view) => view,
+            None => return Vec::new(),
+        };
+        let cells = view.cells();
+        view.entries
+            .iter_mut()
+            .zip(cells)
+            .filter(|(entry, _)| committed.contains(&entry.id))
+            .map(|(entry, cell)| {
+                entry.commits += 1;
+                cell
+            })
+            .collect()
+    }
+
+    /// Selects the window under the pointer in the switcher, returns whether there is one.
+    pub fn point_switcher(&mut self, location: (f64, f64)) -> bool {
+        let id = self
+            .switcher
+            .borrow()
+            .as_ref()
+            .and_then(|view| view.entry_at(location).map(|idx| view.entries[idx].id));
+        let (cycle, id) = match (self.window_cycle.as_mut(), id) {
+            (Some(cycle), Some(id)) => (cycle, id),
+            _ => return false,
+        };
+        if let Some(selected) = cycle.windows.iter().position(|&window| window == id) {
+            if cycle.selected != selected {
+                cycle.selected = selected;
+                self.update_switcher();
+            }
+        }
+        true
+    }
+
+    /// Ends the cycling with the window clicked in the switcher, returns whether one was.
+    pub fn click_switcher(&mut self, location: (f64, f64)) -> bool {
+        if self.point_switcher(location) {
+            self.finish_window_cycle();
+            true
+        } else {
+            false
+        }
+    }
+}
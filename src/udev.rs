@@ -1,11 +1,14 @@
 use std::{
     cell::RefCell,
-    collections::hash_map::{Entry, HashMap},
+    collections::{
+        hash_map::{Entry, HashMap},
+        HashSet,
+    },
     io::Error as IoError,
     os::unix::io::{AsRawFd, RawFd},
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
-    sync::{atomic::Ordering, Arc, Mutex},
+    sync::atomic::Ordering,
     time::Duration,
 };
 
@@ -18,10 +21,11 @@ use smithay::{
     backend::{
         drm::{device_bind, DeviceHandler, DrmDevice, DrmError, DrmRenderSurface},
         egl::{EGLContext, EGLDisplay},
+        input::InputEvent,
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
             gles2::{Gles2Renderer, Gles2Texture},
-            Frame, Renderer, Transform,
+            Frame, Renderer, Texture, Transform,
         },
         session::{auto::AutoSession, Session, Signal as SessionSignal},
         udev::{UdevBackend, UdevEvent},
@@ -36,15 +40,18 @@ use smithay::{
         drm::{
             self,
             control::{
-                connector::{Info as ConnectorInfo, State as ConnectorState},
+                connector::{self, Info as ConnectorInfo, State as ConnectorState},
                 crtc,
                 encoder::Info as EncoderInfo,
-                Device as ControlDevice,
+                Device as ControlDevice, Mode as DrmMode,
             },
         },
         gbm::{BufferObject as GbmBuffer, Device as GbmDevice},
-        input::Libinput,
-        nix::{fcntl::OFlag, sys::stat::dev_t},
+        input::{event::EventTrait, Libinput},
+        nix::{
+            fcntl::{open, OFlag},
+            sys::stat::{dev_t, Mode},
+        },
         wayland_server::{
             protocol::{wl_output, wl_surface},
             Display, Global,
@@ -55,13 +62,35 @@ use smithay::{
     wayland::{
         compositor::CompositorToken,
         output::{Mode, Output, PhysicalProperties},
-        seat::CursorImageStatus,
+        seat::{CursorImageRole, CursorImageStatus},
     },
 };
 
-use crate::{custom::config::Configuration, drawing::*};
+use crate::{
+    background::Backgrounds,
+    cursor::{CursorShape, CursorTheme},
+    cursor_plane::{CursorImage, CursorKey, DrmCursorPlane, HardwareCursor},
+    custom::config::{
+        Background, Configuration, Hook, Hooks, OutputConfig, OutputMode, OutputTransform, Outputs,
+    },
+    damage::{surface_overlays, OutputDamage},
+    drawing::*,
+    edid::Edid,
+    input_method::InputMethodState,
+    output_management::{init_output_management, Head, HeadMode, OutputManagement, OutputRequest},
+    scanout::{fullscreen_buffer, DirectScanout},
+    seat::SeatCursor,
+    shell::SurfaceData,
+    viewporter::ViewportState,
+};
+#[cfg(feature = "egl")]
+use crate::dmabuf::{can_import, init_linux_dmabuf, protocol::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1, renderer_formats};
 use crate::shell::{MyWindowMap, Roles};
 use crate::state::AnvilState;
+#[cfg(feature = "snap_preview")]
+use crate::snap::{SnapPreview, SnapState};
+#[cfg(feature = "switcher")]
+use crate::switcher::{SwitcherTextures, SwitcherView};
 
 #[derive(Clone)]
 pub struct SessionFd(RawFd);
@@ -70,6 +99,8 @@ impl AsRawFd for SessionFd {
         self.0
     }
 }
+impl drm::Device for SessionFd {}
+impl ControlDevice for SessionFd {}
 
 pub fn run_udev(
     display: Rc<RefCell<Display>>,
@@ -114,28 +145,20 @@ pub fn run_udev(
     /*
      * Initialize the udev backend
      */
-    let bytes = include_bytes!("../resources/cursor2.rgba");
     let udev_backend = UdevBackend::new(state.seat_name.clone(), log.clone()).map_err(|_| ())?;
 
-    let mut udev_handler = UdevHandlerImpl {
-        compositor_token: state.ctoken,
+    let udev_handler = Rc::new(RefCell::new(UdevHandlerImpl::new(
+        &state,
+        display.clone(),
+        output_map,
         #[cfg(feature = "egl")]
         egl_buffer_reader,
-        session: state.session.clone().unwrap(),
-        backends: HashMap::new(),
-        output_map,
-        display: display.clone(),
         #[cfg(feature = "egl")]
-        primary_gpu: primary_gpu(&state.seat_name).unwrap_or_default(),
-        window_map: state.window_map.clone(),
-        pointer_location: state.pointer_location.clone(),
-        pointer_image: ImageBuffer::from_raw(64, 64, bytes.to_vec()).unwrap(),
-        cursor_status: state.cursor_status.clone(),
-        dnd_icon: state.dnd_icon.clone(),
-        loop_handle: event_loop.handle(),
-        signaler: session_signal.clone(),
-        logger: log.clone(),
-    };
+        primary_gpu(&state.seat_name).unwrap_or_default(),
+        session_signal.clone(),
+        event_loop.handle(),
+        log.clone(),
+    )));
 
     /*
      * Initialize a fake output (we render one screen to every device in this example)
@@ -157,7 +180,8 @@ pub fn run_udev(
     let libinput_event_source = event_loop
         .handle()
         .insert_source(libinput_backend, move |event, _, anvil_state| {
-            anvil_state.process_input_event(event)
+            let device = event_device_name(&event);
+            anvil_state.process_input_event(event, device.as_deref())
         })
         .unwrap();
     let session_event_source = event_loop
@@ -165,15 +189,19 @@ pub fn run_udev(
         .insert_source(notifier, |(), &mut (), _anvil_state| {})
         .unwrap();
     for (dev, path) in udev_backend.device_list() {
-        udev_handler.device_added(dev, path.into())
+        udev_handler.borrow_mut().device_added(dev, path.into())
     }
 
+    let udev_event_handler = udev_handler.clone();
     let udev_event_source = event_loop
         .handle()
-        .insert_source(udev_backend, move |event, _, _state| match event {
-            UdevEvent::Added { device_id, path } => udev_handler.device_added(device_id, path),
-            UdevEvent::Changed { device_id } => udev_handler.device_changed(device_id),
-            UdevEvent::Removed { device_id } => udev_handler.device_removed(device_id),
+        .insert_source(udev_backend, move |event, _, _state| {
+            let mut udev_handler = udev_event_handler.borrow_mut();
+            match event {
+                UdevEvent::Added { device_id, path } => udev_handler.device_added(device_id, path),
+                UdevEvent::Changed { device_id } => udev_handler.device_changed(device_id),
+                UdevEvent::Removed { device_id } => udev_handler.device_removed(device_id),
+            }
         })
         .map_err(|e| -> IoError { e.into() })
         .unwrap();
@@ -182,17 +210,7 @@ pub fn run_udev(
      * And run our loop
      */
 
-    while state.running.load(Ordering::SeqCst) {
-        if event_loop
-            .dispatch(Some(Duration::from_millis(16)), &mut state)
-            .is_err()
-        {
-            state.running.store(false, Ordering::SeqCst);
-        } else {
-            display.borrow_mut().flush_clients(&mut state);
-            state.window_map.borrow_mut().refresh();
-        }
-    }
+    run_loop(&mut state, event_loop, &display, &udev_handler);
 
     // Cleanup stuff
     state.window_map.borrow_mut().clear();
@@ -204,60 +222,284 @@ pub fn run_udev(
     Ok(())
 }
 
+/// Dispatches the event loop until the compositor stops, repainting the outputs in between.
+pub fn run_loop(
+    state: &mut AnvilState,
+    event_loop: &mut EventLoop<AnvilState>,
+    display: &Rc<RefCell<Display>>,
+    udev_handler: &RefCell<UdevHandlerImpl<AnvilState>>,
+) {
+    while state.running.load(Ordering::SeqCst) {
+        if event_loop
+            .dispatch(Some(Duration::from_millis(16)), state)
+            .is_err()
+        {
+            state.running.store(false, Ordering::SeqCst);
+        } else {
+            // output configurations are applied outside of the wayland dispatching,
+            // because reconfiguring the outputs creates and destroys globals
+            udev_handler.borrow_mut().process_output_requests();
+            udev_handler.borrow_mut().run_hooks(&state.config.hooks);
+            udev_handler.borrow_mut().render_damaged(&state.take_damage());
+            state.update_fractional_scales();
+            display.borrow_mut().flush_clients(state);
+            state.window_map.borrow_mut().refresh();
+            state.focus_new_windows();
+            state.update_snapping();
+            state.update_focus_history();
+            state.publish_events();
+            state.check_idle();
+        }
+    }
+}
+
 pub struct MyOutput {
     pub device_id: dev_t,
     pub crtc: crtc::Handle,
+    pub connector: connector::Handle,
+    /// The connector name, e.g. "HDMI-A-1"
+    pub name: String,
+    pub mode: DrmMode,
+    /// The size of the current mode, in physical pixels
     pub size: (u32, u32),
-    _wl: Output,
+    /// The refresh rate of the current mode, in Hz
+    pub refresh: u32,
+    /// The position in the global space, in logical pixels
+    pub location: (i32, i32),
+    pub scale: f64,
+    pub transform: wl_output::Transform,
+    /// The names the output can be configured by
+    pub identifiers: Vec<String>,
+    /// Whether the output is turned on, nothing is rendered on outputs turned off
+    pub powered: bool,
+    pub damage: OutputDamage,
+    output: Output,
     global: Option<Global<wl_output::WlOutput>>,
 }
 
 impl MyOutput {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         display: &mut Display,
         device_id: dev_t,
         crtc: crtc::Handle,
         conn: ConnectorInfo,
+        mode: DrmMode,
+        edid: Option<Edid>,
+        location: (i32, i32),
+        config: &OutputConfig,
         logger: ::slog::Logger,
     ) -> MyOutput {
+        let identifiers = output_identifiers(&connector_name(&conn), edid.as_ref());
+        let edid = edid.unwrap_or_default();
         let (output, global) = Output::new(
             display,
-            format!("{:?}", conn.interface()),
+            connector_name(&conn),
             PhysicalProperties {
                 width: conn.size().unwrap_or((0, 0)).0 as i32,
                 height: conn.size().unwrap_or((0, 0)).1 as i32,
                 subpixel: wl_output::Subpixel::Unknown,
-                make: "Smithay".into(),
-                model: "Generic DRM".into(),
+                make: if edid.make.is_empty() { "Smithay".into() } else { edid.make },
+                model: if edid.model.is_empty() { "Generic DRM".into() } else { edid.model },
             },
             logger,
         );
 
-        let mode = conn.modes()[0];
         let (w, h) = mode.size();
+        let scale = config.scale.unwrap_or(1.0);
+        let transform = output_transform(config.transform);
         output.change_current_state(
             Some(Mode {
                 width: w as i32,
                 height: h as i32,
                 refresh: (mode.vrefresh() * 1000) as i32,
             }),
-            None,
-            None,
+            Some(transform),
+            // wl_output only knows integer scales, clients render at the next bigger one
+            Some(scale.ceil() as i32),
         );
+        let preferred = conn.modes()[0];
         output.set_preferred(Mode {
-            width: w as i32,
-            height: h as i32,
-            refresh: (mode.vrefresh() * 1000) as i32,
+            width: preferred.size().0 as i32,
+            height: preferred.size().1 as i32,
+            refresh: (preferred.vrefresh() * 1000) as i32,
         });
 
         MyOutput {
             device_id,
             crtc,
+            connector: conn.handle(),
+            name: connector_name(&conn),
+            mode,
             size: (w as u32, h as u32),
-            _wl: output,
+            refresh: mode.vrefresh(),
+            location,
+            scale,
+            transform,
+            identifiers,
+            powered: true,
+            damage: OutputDamage::default(),
+            output,
             global: Some(global),
         }
     }
+
+    pub fn owns(&self, output: &wl_output::WlOutput) -> bool {
+        self.output.owns(output)
+    }
+
+    /// The area of the output in the global space, in logical pixels.
+    pub fn geometry(&self) -> Rectangle {
+        let (width, height) = match self.transform {
+            wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270 => (self.size.1, self.size.0),
+            _ => self.size,
+        };
+        Rectangle {
+            x: self.location.0,
+            y: self.location.1,
+            width: (width as f64 / self.scale).round() as i32,
+            height: (height as f64 / self.scale).round() as i32,
+        }
+    }
+
+    pub fn view(&self) -> OutputView {
+        OutputView {
+            geometry: self.geometry(),
+            scale: self.scale,
+            transform: renderer_transform(self.transform),
+            bottom_up: false,
+        }
+    }
+}
+
+/// The kernel name of a connector, e.g. "HDMI-A-1".
+fn connector_name(conn: &ConnectorInfo) -> String {
+    use drm::control::connector::Interface;
+    let interface = match conn.interface() {
+        Interface::VGA => "VGA",
+        Interface::DVII => "DVI-I",
+        Interface::DVID => "DVI-D",
+        Interface::DVIA => "DVI-A",
+        Interface::Composite => "Composite",
+        Interface::SVideo => "SVIDEO",
+        Interface::LVDS => "LVDS",
+        Interface::Component => "Component",
+        Interface::NinePinDIN => "DIN",
+        Interface::DisplayPort => "DP",
+        Interface::HDMIA => "HDMI-A",
+        Interface::HDMIB => "HDMI-B",
+        Interface::TV => "TV",
+        Interface::EmbeddedDisplayPort => "eDP",
+        Interface::Virtual => "Virtual",
+        Interface::DSI => "DSI",
+        Interface::DPI => "DPI",
+        _ => "Unknown",
+    };
+    format!("{}-{}", interface, conn.interface_id())
+}
+
+/// Reads the EDID property of a connector.
+fn connector_edid(device: &DrmDevice<SessionFd>, conn: &ConnectorInfo) -> Option<Edid> {
+    let properties = device.get_properties(conn.handle()).ok()?;
+    let (handles, values) = properties.as_props_and_values();
+    handles.iter().zip(values.iter()).find_map(|(handle, value)| {
+        let info = device.get_property(*handle).ok()?;
+        if info.name().to_str() != Ok("EDID") {
+            return None;
+        }
+        let blob = device.get_property_blob(*value).ok()?;
+        Edid::parse(&blob)
+    })
+}
+
+/// The mode of the connector matching the configured one, the preferred mode otherwise.
+fn select_mode(conn: &ConnectorInfo, config: &OutputConfig, logger: &::slog::Logger) -> DrmMode {
+    if let Some(wanted) = config.mode {
+        match conn.modes().iter().find(|mode| mode_matches(mode, &wanted)) {
+            Some(mode) => return *mode,
+            None => warn!(logger, "Configured mode not supported by {}: {:?}", connector_name(conn), wanted),
+        }
+    }
+    conn.modes()[0]
+}
+
+fn mode_matches(mode: &DrmMode, wanted: &OutputMode) -> bool {
+    let (w, h) = mode.size();
+    w as u32 == wanted.width
+        && h as u32 == wanted.height
+        && wanted.refresh.map(|refresh| refresh == mode.vrefresh()).unwrap_or(true)
+}
+
+/// Sets the rendering of a crtc showing `mode` on a connector up.
+#[allow(clippy::too_many_arguments)]
+fn create_render_surface(
+    device: &mut DrmDevice<SessionFd>,
+    gbm: &GbmDevice<SessionFd>,
+    egl: &EGLDisplay,
+    context: &EGLContext,
+    crtc: crtc::Handle,
+    mode: DrmMode,
+    connector: connector::Handle,
+    signaler: &Signaler<SessionSignal>,
+    logger: &::slog::Logger,
+) -> Option<RenderSurface> {
+    let context = match EGLContext::new_shared(egl, context, logger.clone()) {
+        Ok(context) => context,
+        Err(err) => {
+            warn!(logger, "Failed to create EGLContext: {}", err);
+            return None;
+        }
+    };
+    let renderer = match unsafe { Gles2Renderer::new(context, logger.clone()) } {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            warn!(logger, "Failed to create Gles2 Renderer: {}", err);
+            return None;
+        }
+    };
+    let mut surface = match device.create_surface(crtc, mode, &[connector]) {
+        Ok(surface) => surface,
+        Err(err) => {
+            warn!(logger, "Failed to create drm surface: {}", err);
+            return None;
+        }
+    };
+    surface.link(signaler.clone());
+    match DrmRenderSurface::new(surface, gbm.clone(), renderer, logger.clone()) {
+        Ok(renderer) => Some(renderer),
+        Err(err) => {
+            warn!(logger, "Failed to create rendering surface: {}", err);
+            None
+        }
+    }
+}
+
+/// The names an output can be configured by: its connector, make, model and serial.
+fn output_identifiers(name: &str, edid: Option<&Edid>) -> Vec<String> {
+    let mut identifiers = vec![name.to_string()];
+    if let Some(edid) = edid {
+        identifiers.push(edid.make.clone());
+        identifiers.push(edid.model.clone());
+        identifiers.push(edid.serial.clone());
+        identifiers.push(format!("{} {}", edid.make, edid.model));
+    }
+    identifiers
+}
+
+fn output_transform(transform: OutputTransform) -> wl_output::Transform {
+    match transform {
+        OutputTransform::Normal => wl_output::Transform::Normal,
+        OutputTransform::Rotate90 => wl_output::Transform::_90,
+        OutputTransform::Rotate180 => wl_output::Transform::_180,
+        OutputTransform::Rotate270 => wl_output::Transform::_270,
+        OutputTransform::Flipped => wl_output::Transform::Flipped,
+        OutputTransform::Flipped90 => wl_output::Transform::Flipped90,
+        OutputTransform::Flipped180 => wl_output::Transform::Flipped180,
+        OutputTransform::Flipped270 => wl_output::Transform::Flipped270,
+    }
 }
 
 impl Drop for MyOutput {
@@ -270,6 +512,7 @@ pub type RenderSurface = DrmRenderSurface<SessionFd, GbmDevice<SessionFd>, Gles2
 
 struct BackendData {
     _restart_token: SignalToken,
+    renderer: Rc<DrmRenderer>,
     surfaces: Rc<RefCell<HashMap<crtc::Handle, Rc<RefCell<RenderSurface>>>>>,
     context: EGLContext,
     egl: EGLDisplay,
@@ -277,27 +520,112 @@ struct BackendData {
     event_source: Source<Generic<DrmDevice<SessionFd>>>,
 }
 
-struct UdevHandlerImpl<Data: 'static> {
+/// How often the clients get their frame callbacks while no output repaints.
+const IDLE_FRAME_INTERVAL: Duration = Duration::from_millis(1000 /*a seconds*/ / 60 /*refresh rate*/);
+
+pub struct UdevHandlerImpl<Data: 'static> {
     compositor_token: CompositorToken<Roles>,
     #[cfg(feature = "egl")]
     egl_buffer_reader: Rc<RefCell<Option<EGLBufferReader>>>,
-    session: AutoSession,
+    /// The session devices are opened with, devices are opened directly without one
+    session: Option<AutoSession>,
     backends: HashMap<dev_t, BackendData>,
     display: Rc<RefCell<Display>>,
     #[cfg(feature = "egl")]
     primary_gpu: Option<PathBuf>,
+    /// The linux-dmabuf global, for the primary gpu
+    #[cfg(feature = "egl")]
+    dmabuf_global: Option<Global<ZwpLinuxDmabufV1>>,
     window_map: Rc<RefCell<MyWindowMap>>,
     output_map: Rc<RefCell<Vec<MyOutput>>>,
-    pointer_location: Rc<RefCell<(f64, f64)>>,
-    pointer_image: ImageBuffer<Rgba<u8>, Vec<u8>>,
-    cursor_status: Arc<Mutex<CursorImageStatus>>,
-    dnd_icon: Arc<Mutex<Option<wl_surface::WlSurface>>>,
+    outputs_config: Outputs,
+    background: Background,
+    /// The identifiers of the connected monitors, the connector name first
+    connected: Vec<Vec<String>>,
+    /// The hooks of the monitors that came and went, run with the current configuration
+    output_hooks: Vec<(Hook, Vec<(&'static str, String)>)>,
+    /// The hooks of the session being paused and activated again, queued by the session signals
+    session_hooks: Rc<RefCell<Vec<Hook>>>,
+    _session_token: SignalToken,
+    output_management: Rc<RefCell<OutputManagement>>,
+    cursors: Vec<SeatCursor>,
+    input_methods: Vec<Rc<RefCell<InputMethodState>>>,
+    cursor_theme: Rc<CursorTheme>,
+    #[cfg(feature = "switcher")]
+    switcher: Rc<RefCell<Option<SwitcherView>>>,
+    #[cfg(feature = "snap_preview")]
+    snap: Rc<RefCell<SnapState>>,
     loop_handle: LoopHandle<Data>,
     signaler: Signaler<SessionSignal>,
+    start_time: std::time::Instant,
+    /// When the clients last got their frame callbacks while no output was repainting
+    idle_frames: Duration,
     logger: ::slog::Logger,
 }
 
 impl<Data: 'static> UdevHandlerImpl<Data> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        state: &AnvilState,
+        display: Rc<RefCell<Display>>,
+        output_map: Rc<RefCell<Vec<MyOutput>>>,
+        #[cfg(feature = "egl")] egl_buffer_reader: Rc<RefCell<Option<EGLBufferReader>>>,
+        #[cfg(feature = "egl")] primary_gpu: Option<PathBuf>,
+        signaler: Signaler<SessionSignal>,
+        loop_handle: LoopHandle<Data>,
+        log: Logger,
+    ) -> Self {
+        let cursor_theme = Rc::new(CursorTheme::load(
+            &state.config.cursor.theme(),
+            state.config.cursor.size(),
+            &log,
+        ));
+        let (output_management, _, _) =
+            init_output_management(&mut display.borrow_mut(), output_map.clone(), log.clone());
+        // the session is paused when switching to another vt or when logind locks it
+        let session_hooks = Rc::new(RefCell::new(Vec::new()));
+        let queued_hooks = session_hooks.clone();
+        let session_token = signaler.register(move |signal| match signal {
+            SessionSignal::PauseSession => queued_hooks.borrow_mut().push(Hook::SessionLocked),
+            SessionSignal::ActivateSession => queued_hooks.borrow_mut().push(Hook::SessionUnlocked),
+            _ => {}
+        });
+
+        UdevHandlerImpl {
+            compositor_token: state.ctoken,
+            #[cfg(feature = "egl")]
+            egl_buffer_reader,
+            session: state.session.clone(),
+            backends: HashMap::new(),
+            output_map,
+            display,
+            #[cfg(feature = "egl")]
+            primary_gpu,
+            #[cfg(feature = "egl")]
+            dmabuf_global: None,
+            window_map: state.window_map.clone(),
+            outputs_config: state.config.outputs.clone(),
+            background: state.config.background.clone(),
+            connected: Vec::new(),
+            output_hooks: Vec::new(),
+            session_hooks,
+            _session_token: session_token,
+            output_management,
+            cursors: state.seat_cursors(),
+            input_methods: state.input_methods(),
+            cursor_theme,
+            #[cfg(feature = "switcher")]
+            switcher: state.switcher.clone(),
+            #[cfg(feature = "snap_preview")]
+            snap: state.snap.clone(),
+            loop_handle,
+            signaler,
+            start_time: std::time::Instant::now(),
+            idle_frames: Duration::default(),
+            logger: log,
+        }
+    }
+
     pub fn scan_connectors(
         device: &mut DrmDevice<SessionFd>,
         gbm: &GbmDevice<SessionFd>,
@@ -305,6 +633,7 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
         context: &EGLContext,
         display: &mut Display,
         output_map: &mut Vec<MyOutput>,
+        outputs_config: &Outputs,
         signaler: &Signaler<SessionSignal>,
         logger: &::slog::Logger,
     ) -> HashMap<crtc::Handle, Rc<RefCell<RenderSurface>>> {
@@ -324,6 +653,16 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
 
         // very naive way of finding good crtc/encoder/connector combinations. This problem is np-complete
         for connector_info in connector_infos {
+            let name = connector_name(&connector_info);
+            let edid = connector_edid(device, &connector_info);
+            let identifiers = output_identifiers(&name, edid.as_ref());
+            let config = outputs_config.get(&identifiers).cloned().unwrap_or_default();
+            if !config.enabled {
+                info!(logger, "Output {} is disabled", name);
+                continue;
+            }
+            let mode = select_mode(&connector_info, &config, logger);
+
             let encoder_infos = connector_info
                 .encoders()
                 .iter()
@@ -340,46 +679,40 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                             connector_info.interface_id(),
                             crtc,
                         );
-                        let context = match EGLContext::new_shared(egl, context, logger.clone()) {
-                            Ok(context) => context,
-                            Err(err) => {
-                                warn!(logger, "Failed to create EGLContext: {}", err);
-                                continue;
-                            }
-                        };
-                        let renderer = match unsafe { Gles2Renderer::new(context, logger.clone()) } {
-                            Ok(renderer) => renderer,
-                            Err(err) => {
-                                warn!(logger, "Failed to create Gles2 Renderer: {}", err);
-                                continue;
-                            }
-                        };
-                        let mut surface = match device.create_surface(
+                        let renderer = match create_render_surface(
+                            device,
+                            gbm,
+                            egl,
+                            context,
                             crtc,
-                            connector_info.modes()[0],
-                            &[connector_info.handle()],
+                            mode,
+                            connector_info.handle(),
+                            signaler,
+                            logger,
                         ) {
-                            Ok(surface) => surface,
-                            Err(err) => {
-                                warn!(logger, "Failed to create drm surface: {}", err);
-                                continue;
-                            }
+                            Some(renderer) => renderer,
+                            None => continue,
                         };
-                        surface.link(signaler.clone());
-                        let renderer =
-                            match DrmRenderSurface::new(surface, gbm.clone(), renderer, logger.clone()) {
-                                Ok(renderer) => renderer,
-                                Err(err) => {
-                                    warn!(logger, "Failed to create rendering surface: {}", err);
-                                    continue;
-                                }
-                            };
 
+                        // unpositioned outputs go right of all others
+                        let location = config.position.map(|pos| (pos.x, pos.y)).unwrap_or_else(|| {
+                            let x = output_map
+                                .iter()
+                                .map(|output| output.geometry().x + output.geometry().width)
+                                .max()
+                                .unwrap_or(0);
+                            (x, 0)
+                        });
+                        info!(logger, "Output {} at {:?}", name, location; "mode" => format!("{:?}", mode.size()));
                         output_map.push(MyOutput::new(
                             display,
                             device.device_id(),
                             crtc,
                             connector_info,
+                            mode,
+                            edid,
+                            location,
+                            &config,
                             logger.clone(),
                         ));
 
@@ -395,15 +728,22 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
 }
 
 impl<Data: 'static> UdevHandlerImpl<Data> {
-    fn device_added(&mut self, device_id: dev_t, path: PathBuf) {
+    /// Opens a device through the session, or directly if there is none.
+    fn open_device(&mut self, path: &Path) -> Option<RawFd> {
+        let flags = OFlag::O_RDWR | OFlag::O_CLOEXEC | OFlag::O_NOCTTY | OFlag::O_NONBLOCK;
+        let result = match self.session {
+            Some(ref mut session) => session.open(path, flags).map_err(|err| err.to_string()),
+            None => open(path, flags, Mode::empty()).map_err(|err| err.to_string()),
+        };
+        result
+            .map_err(|err| warn!(self.logger, "Failed to open device {:?}: {}", path, err))
+            .ok()
+    }
+
+    pub fn device_added(&mut self, device_id: dev_t, path: PathBuf) {
         // Try to open the device
         if let Some((mut device, gbm)) = self
-            .session
-            .open(
-                &path,
-                OFlag::O_RDWR | OFlag::O_CLOEXEC | OFlag::O_NOCTTY | OFlag::O_NONBLOCK,
-            )
-            .ok()
+            .open_device(&path)
             .and_then(|fd| {
                 match {
                     let fd = SessionFd(fd);
@@ -474,20 +814,41 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                 &context,
                 &mut *self.display.borrow_mut(),
                 &mut *self.output_map.borrow_mut(),
+                &self.outputs_config,
                 &self.signaler,
                 &self.logger,
             )));
 
-            // we leak this texture (we would need to call `destroy_texture` on Drop of DrmRenderer),
+            // we leak these textures (we would need to call `destroy_texture` on Drop of DrmRenderer),
             // but only on shutdown anyway, because we do not support hot-pluggin, so it does not really matter.
-            let pointer_image = {
+            let (pointer_textures, inhibit_indicator) = {
                 let context = EGLContext::new_shared(&egl, &context, self.logger.clone()).unwrap();
                 let mut renderer = unsafe { Gles2Renderer::new(context, self.logger.clone()).unwrap() };
-                renderer
-                    .import_bitmap(&self.pointer_image)
-                    .expect("Failed to load pointer")
+                (
+                    import_cursor_theme(&mut renderer, &self.cursor_theme),
+                    import_inhibit_indicator(&mut renderer),
+                )
             };
 
+            // clients allocate their dmabufs on the primary gpu
+            #[cfg(feature = "egl")]
+            {
+                if is_primary {
+                    let context = EGLContext::new_shared(&egl, &context, self.logger.clone()).unwrap();
+                    let mut renderer = unsafe { Gles2Renderer::new(context, self.logger.clone()).unwrap() };
+                    if let Some(global) = self.dmabuf_global.take() {
+                        global.destroy();
+                    }
+                    self.dmabuf_global = Some(init_linux_dmabuf(
+                        &mut *self.display.borrow_mut(),
+                        renderer_formats(&renderer),
+                        Some(device_id),
+                        move |dmabuf| can_import(&mut renderer, dmabuf),
+                        self.logger.clone(),
+                    ));
+                }
+            }
+
             // Set the handler.
             // Note: if you replicate this (very simple) structure, it is rather easy
             // to introduce reference cycles with Rc. Be sure about your drop order
@@ -503,12 +864,25 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                 backends: backends.clone(),
                 window_map: self.window_map.clone(),
                 output_map: self.output_map.clone(),
-                pointer_location: self.pointer_location.clone(),
-                pointer_image,
-                cursor_status: self.cursor_status.clone(),
-                dnd_icon: self.dnd_icon.clone(),
+                cursors: self.cursors.clone(),
+                input_methods: self.input_methods.clone(),
+                pointer_textures,
+                inhibit_indicator,
+                cursor_theme: self.cursor_theme.clone(),
+                backgrounds: RefCell::new(Backgrounds::new(self.background.clone(), self.logger.clone())),
+                gbm: gbm.clone(),
+                hardware_cursors: RefCell::new(HashMap::new()),
+                scanouts: RefCell::new(HashMap::new()),
+                #[cfg(feature = "switcher")]
+                switcher: self.switcher.clone(),
+                #[cfg(feature = "switcher")]
+                switcher_textures: RefCell::new(SwitcherTextures::new(self.logger.clone())),
+                #[cfg(feature = "snap_preview")]
+                snap: self.snap.clone(),
+                #[cfg(feature = "snap_preview")]
+                snap_preview: RefCell::new(SnapPreview::default()),
                 logger: self.logger.clone(),
-                start_time: std::time::Instant::now(),
+                start_time: self.start_time,
             });
             let mut listener = DrmRendererSessionListener {
                 renderer: renderer.clone(),
@@ -518,6 +892,7 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                 SessionSignal::ActivateSession | SessionSignal::ActivateDevice { .. } => listener.activate(),
                 _ => {}
             });
+            let renderer_handle = renderer.clone();
             device.set_handler(DrmHandlerImpl {
                 renderer,
                 loop_handle: self.loop_handle.clone(),
@@ -540,6 +915,7 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                 dev_id,
                 BackendData {
                     _restart_token: restart_token,
+                    renderer: renderer_handle,
                     event_source,
                     surfaces: backends,
                     egl,
@@ -548,6 +924,8 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                 },
             );
         }
+        self.update_heads();
+        self.update_connected();
     }
 
     fn device_changed(&mut self, device: dev_t) {
@@ -558,10 +936,14 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
             let mut display = self.display.borrow_mut();
             let mut output_map = self.output_map.borrow_mut();
             let signaler = self.signaler.clone();
+            let outputs_config = &self.outputs_config;
             output_map.retain(|output| output.device_id != device);
             self.loop_handle
                 .with_source(&backend_data.event_source, |source| {
                     let mut backends = backend_data.surfaces.borrow_mut();
+                    // release the crtcs first, the connectors may get them reassigned
+                    backends.clear();
+                    backend_data.renderer.scanouts.borrow_mut().clear();
                     *backends = UdevHandlerImpl::<Data>::scan_connectors(
                         &mut source.file,
                         &backend_data.gbm,
@@ -569,6 +951,7 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                         &backend_data.context,
                         &mut *display,
                         &mut *output_map,
+                        outputs_config,
                         &signaler,
                         &logger,
                     );
@@ -579,10 +962,18 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
                         schedule_initial_render(renderer.clone(), &loop_handle, logger);
                     }
                 });
+            let names = output_map
+                .iter()
+                .filter(|output| output.device_id == device)
+                .map(|output| output.name.clone())
+                .collect::<Vec<_>>();
+            backend_data.renderer.backgrounds.borrow_mut().retain_outputs(&names);
         }
+        self.update_heads();
+        self.update_connected();
     }
 
-    fn device_removed(&mut self, device: dev_t) {
+    pub fn device_removed(&mut self, device: dev_t) {
         // drop the backends on this side
         if let Some(backend_data) = self.backends.remove(&device) {
             // drop surfaces
@@ -600,11 +991,409 @@ impl<Data: 'static> UdevHandlerImpl<Data> {
             {
                 if _device.dev_path().and_then(|path| path.canonicalize().ok()) == self.primary_gpu {
                     *self.egl_buffer_reader.borrow_mut() = None;
+                    if let Some(global) = self.dmabuf_global.take() {
+                        global.destroy();
+                    }
                 }
             }
             debug!(self.logger, "Dropping device");
         }
+        self.update_heads();
+        self.update_connected();
+    }
+
+    /// Queues the hooks for the monitors that were plugged in or out with a device change.
+    ///
+    /// Outputs turned off by the configuration stay connected.
+    fn update_connected(&mut self) {
+        let mut current = Vec::new();
+        for backend_data in self.backends.values() {
+            self.loop_handle.with_source(&backend_data.event_source, |source| {
+                current.extend(connected_identifiers(&source.file));
+            });
+        }
+        let vars = |identifiers: &[String]| {
+            // the connector name, then the make, model and serial if there is an edid
+            ["WAYBOX_OUTPUT", "WAYBOX_OUTPUT_MAKE", "WAYBOX_OUTPUT_MODEL", "WAYBOX_OUTPUT_SERIAL"]
+                .iter()
+                .zip(identifiers)
+                .map(|(name, value)| (*name, value.clone()))
+                .collect::<Vec<_>>()
+        };
+        for identifiers in &self.connected {
+            if !current.iter().any(|other| other[0] == identifiers[0]) {
+                info!(self.logger, "Output disconnected"; "name" => identifiers[0].clone());
+                self.output_hooks.push((Hook::OutputDisconnected, vars(identifiers)));
+            }
+        }
+        for identifiers in &current {
+            if !self.connected.iter().any(|other| other[0] == identifiers[0]) {
+                info!(self.logger, "Output connected"; "name" => identifiers[0].clone());
+                self.output_hooks.push((Hook::OutputConnected, vars(identifiers)));
+            }
+        }
+        self.connected = current;
+    }
+
+    /// Runs the queued hooks of the monitors that came and went and of the session.
+    pub fn run_hooks(&mut self, hooks: &Hooks) {
+        for (hook, vars) in self.output_hooks.drain(..) {
+            hooks.run(hook, &vars, &self.logger);
+        }
+        for hook in self.session_hooks.borrow_mut().drain(..) {
+            hooks.run(hook, &[], &self.logger);
+        }
+    }
+
+    /// Publishes the connected monitors of all devices to the output management clients.
+    fn update_heads(&mut self) {
+        let output_map = self.output_map.borrow();
+        let mut heads = Vec::new();
+        for (device_id, backend_data) in self.backends.iter() {
+            self.loop_handle.with_source(&backend_data.event_source, |source| {
+                heads.extend(device_heads(&source.file, *device_id, &output_map));
+            });
+        }
+        self.output_management.borrow_mut().set_heads(heads);
+    }
+
+    /// Adds the damage to the outputs, and repaints the ones that are not waiting for a vblank.
+    fn render_damaged(&mut self, damage: &[Rectangle]) {
+        self.reset_scanout_surfaces();
+        for output in self.output_map.borrow_mut().iter_mut() {
+            let geometry = output.geometry();
+            output.damage.add(&geometry, damage);
+        }
+        for backend_data in self.backends.values() {
+            backend_data.renderer.clone().render_damaged(&self.loop_handle);
+        }
+
+        // the clients still get their frame callbacks while no output repaints
+        let idle = self
+            .output_map
+            .borrow()
+            .iter()
+            .all(|output| !output.damage.frame_pending);
+        let time = self.start_time.elapsed();
+        if idle && time >= self.idle_frames + IDLE_FRAME_INTERVAL {
+            self.idle_frames = time;
+            let time = time.as_millis() as u32;
+            self.window_map.borrow().send_frames(time);
+            for input_method in &self.input_methods {
+                input_method.borrow().send_frames(time, self.compositor_token);
+            }
+        }
+    }
+
+    /// Sets the rendering surfaces of the crtcs leaving the direct scanout up again.
+    ///
+    /// The client buffers are flipped to past the surfaces, so they do not know what their crtcs
+    /// show anymore.
+    fn reset_scanout_surfaces(&mut self) {
+        for backend_data in self.backends.values() {
+            let renderer = &backend_data.renderer;
+            let crtcs = renderer
+                .scanouts
+                .borrow_mut()
+                .iter_mut()
+                .filter_map(|(crtc, scanout)| if scanout.take_reset() { Some(*crtc) } else { None })
+                .collect::<Vec<_>>();
+            for crtc in crtcs {
+                let setup = self
+                    .output_map
+                    .borrow()
+                    .iter()
+                    .find(|output| output.device_id == renderer.device_id && output.crtc == crtc)
+                    .map(|output| (output.mode, output.connector));
+                let (mode, connector) = match setup {
+                    Some(setup) => setup,
+                    None => continue,
+                };
+                // the crtc keeps showing the client buffer until the new surface replaced it
+                backend_data.surfaces.borrow_mut().remove(&crtc);
+                renderer.hardware_cursors.borrow_mut().remove(&crtc);
+                let mut surface = None;
+                let (signaler, logger) = (&self.signaler, &self.logger);
+                self.loop_handle.with_source(&backend_data.event_source, |source| {
+                    surface = create_render_surface(
+                        &mut source.file,
+                        &backend_data.gbm,
+                        &backend_data.egl,
+                        &backend_data.context,
+                        crtc,
+                        mode,
+                        connector,
+                        signaler,
+                        logger,
+                    );
+                });
+                let surface = match surface {
+                    Some(surface) => Rc::new(RefCell::new(surface)),
+                    None => {
+                        warn!(self.logger, "Failed to set the rendering of {:?} up again", crtc);
+                        continue;
+                    }
+                };
+                backend_data.surfaces.borrow_mut().insert(crtc, surface.clone());
+                if let Some(scanout) = renderer.scanouts.borrow_mut().get_mut(&crtc) {
+                    scanout.composited();
+                }
+                // the vblank of the initial frame renders the output again
+                if let Some(output) = self
+                    .output_map
+                    .borrow_mut()
+                    .iter_mut()
+                    .find(|output| output.device_id == renderer.device_id && output.crtc == crtc)
+                {
+                    // the new surface draws into new buffers
+                    output.damage.reset_buffers();
+                    output.damage.frame_pending = true;
+                }
+                schedule_initial_render(surface, &self.loop_handle, self.logger.clone());
+            }
+        }
     }
+
+    /// Carries out the requests of the output management clients.
+    fn process_output_requests(&mut self) {
+        let requests = self.output_management.borrow_mut().take_requests();
+        for request in requests {
+            match request {
+                OutputRequest::Apply { configuration, heads } => {
+                    if let Err(err) = self.check_heads(&heads) {
+                        warn!(self.logger, "Rejected an output configuration: {}", err);
+                        configuration.failed();
+                        continue;
+                    }
+                    let previous = self.outputs_config.clone();
+                    for (name, config) in &heads {
+                        self.outputs_config.set(name, config.clone());
+                    }
+                    self.rescan_devices();
+
+                    // an enabled head without output failed to set its mode up after all
+                    let applied = {
+                        let output_map = self.output_map.borrow();
+                        heads
+                            .iter()
+                            .filter(|(_, config)| config.enabled)
+                            .all(|(name, _)| output_map.iter().any(|output| &output.name == name))
+                    };
+                    if applied {
+                        configuration.succeeded();
+                    } else {
+                        warn!(self.logger, "Failed to apply an output configuration, restoring the previous one");
+                        self.outputs_config = previous;
+                        self.rescan_devices();
+                        configuration.failed();
+                    }
+                }
+                OutputRequest::Power { name, on } => self.set_output_power(&name, on),
+            }
+        }
+    }
+
+    /// Checks that every enabled head of a configuration is connected, supports its mode and
+    /// gets a crtc, before anything changes.
+    fn check_heads(&self, heads: &[(String, OutputConfig)]) -> Result<(), String> {
+        let mut pending = heads
+            .iter()
+            .filter(|(_, config)| config.enabled)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        for backend_data in self.backends.values() {
+            let mut result = Ok(());
+            self.loop_handle.with_source(&backend_data.event_source, |source| {
+                result = check_device_heads(&source.file, heads, &mut pending);
+            });
+            result?;
+        }
+        match pending.first() {
+            Some(name) => Err(format!("{} is not connected", name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the outputs of all devices up again, with the current configuration.
+    fn rescan_devices(&mut self) {
+        let devices = self.backends.keys().cloned().collect::<Vec<_>>();
+        for device in devices {
+            self.device_changed(device);
+        }
+    }
+
+    fn set_output_power(&mut self, name: &str, on: bool) {
+        let (device_id, crtc) = match self
+            .output_map
+            .borrow_mut()
+            .iter_mut()
+            .find(|output| output.name == name)
+        {
+            Some(output) => {
+                output.powered = on;
+                if on {
+                    // the initial frame is scheduled below and paints one of the buffers, the
+                    // next one repaints everything
+                    output.damage.reset_buffers();
+                    output.damage.frame_pending = true;
+                }
+                (output.device_id, output.crtc)
+            }
+            None => return,
+        };
+        info!(self.logger, "Turning output {} {}", name, if on { "on" } else { "off" });
+
+        if let Some(backend_data) = self.backends.get(&device_id) {
+            let logger = &self.logger;
+            self.loop_handle.with_source(&backend_data.event_source, |source| {
+                if let Err(err) = set_dpms(&source.file, name, on) {
+                    warn!(logger, "Failed to set the DPMS state of {}: {}", name, err);
+                }
+            });
+            // the rendering stopped while the output was off
+            if on {
+                if let Some(surface) = backend_data.surfaces.borrow().get(&crtc) {
+                    schedule_initial_render(surface.clone(), &self.loop_handle, self.logger.clone());
+                }
+            }
+        }
+        self.output_management.borrow().power_changed(name, on);
+    }
+}
+
+/// Checks the enabled heads of a configuration connected to a device, like `scan_connectors`
+/// assigns the crtcs, and takes them out of `pending`.
+fn check_device_heads(
+    device: &DrmDevice<SessionFd>,
+    heads: &[(String, OutputConfig)],
+    pending: &mut Vec<&str>,
+) -> Result<(), String> {
+    let res_handles = device.resource_handles().map_err(|err| err.to_string())?;
+    let mut crtcs = HashSet::new();
+    let connectors = res_handles
+        .connectors()
+        .iter()
+        .flat_map(|conn| device.get_connector(*conn))
+        .filter(|conn| conn.state() == ConnectorState::Connected);
+    for conn in connectors {
+        let name = connector_name(&conn);
+        let config = match heads.iter().find(|(head, config)| config.enabled && *head == name) {
+            Some((_, config)) => config,
+            None => continue,
+        };
+        match config.mode {
+            Some(wanted) if !conn.modes().iter().any(|mode| mode_matches(mode, &wanted)) => {
+                return Err(format!("{} does not support the mode {:?}", name, wanted));
+            }
+            None if conn.modes().is_empty() => return Err(format!("{} has no modes", name)),
+            _ => {}
+        }
+        let crtc = conn
+            .encoders()
+            .iter()
+            .filter_map(|encoder| *encoder)
+            .flat_map(|encoder| device.get_encoder(encoder))
+            .flat_map(|encoder| res_handles.filter_crtcs(encoder.possible_crtcs()))
+            .find(|crtc| !crtcs.contains(crtc));
+        match crtc {
+            Some(crtc) => crtcs.insert(crtc),
+            None => return Err(format!("No crtc left for {}", name)),
+        };
+        pending.retain(|head| *head != name);
+    }
+    Ok(())
+}
+
+/// The connected monitors of a device, as heads of the output management.
+/// The identifiers of the connected monitors of a device.
+fn connected_identifiers(device: &DrmDevice<SessionFd>) -> Vec<Vec<String>> {
+    let res_handles = match device.resource_handles() {
+        Ok(res_handles) => res_handles,
+        Err(_) => return Vec::new(),
+    };
+    res_handles
+        .connectors()
+        .iter()
+        .flat_map(|conn| device.get_connector(*conn))
+        .filter(|conn| conn.state() == ConnectorState::Connected)
+        .map(|conn| output_identifiers(&connector_name(&conn), connector_edid(device, &conn).as_ref()))
+        .collect()
+}
+
+fn device_heads(device: &DrmDevice<SessionFd>, device_id: dev_t, output_map: &[MyOutput]) -> Vec<Head> {
+    let res_handles = match device.resource_handles() {
+        Ok(res_handles) => res_handles,
+        Err(_) => return Vec::new(),
+    };
+    res_handles
+        .connectors()
+        .iter()
+        .flat_map(|conn| device.get_connector(*conn))
+        .filter(|conn| conn.state() == ConnectorState::Connected)
+        .map(|conn| {
+            let name = connector_name(&conn);
+            let edid = connector_edid(device, &conn).unwrap_or_default();
+            let output = output_map
+                .iter()
+                .find(|output| output.device_id == device_id && output.name == name);
+            let modes = conn
+                .modes()
+                .iter()
+                .enumerate()
+                .map(|(idx, mode)| HeadMode {
+                    width: mode.size().0 as i32,
+                    height: mode.size().1 as i32,
+                    refresh: mode.vrefresh() as i32 * 1000,
+                    preferred: idx == 0,
+                })
+                .collect::<Vec<_>>();
+            let current_mode = output.and_then(|output| {
+                modes.iter().position(|mode| {
+                    (mode.width as u32, mode.height as u32) == output.size
+                        && mode.refresh as u32 == output.refresh * 1000
+                })
+            });
+            let (width, height) = conn.size().unwrap_or((0, 0));
+            Head {
+                description: format!("{} {} ({})", edid.make, edid.model, name).trim().to_string(),
+                name,
+                make: edid.make,
+                model: edid.model,
+                serial: edid.serial,
+                physical_size: (width as i32, height as i32),
+                modes,
+                current_mode,
+                enabled: output.is_some(),
+                position: output.map(|output| output.location).unwrap_or_default(),
+                transform: output
+                    .map(|output| output.transform)
+                    .unwrap_or(wl_output::Transform::Normal),
+                scale: output.map(|output| output.scale).unwrap_or(1.0),
+            }
+        })
+        .collect()
+}
+
+const DPMS_ON: u64 = 0;
+const DPMS_OFF: u64 = 3;
+
+/// Sets the DPMS property of the connector of an output.
+fn set_dpms(device: &DrmDevice<SessionFd>, name: &str, on: bool) -> Result<(), drm::SystemError> {
+    let res_handles = device.resource_handles()?;
+    for conn in res_handles.connectors() {
+        let info = device.get_connector(*conn)?;
+        if connector_name(&info) != name {
+            continue;
+        }
+        let properties = device.get_properties(*conn)?;
+        let (handles, _) = properties.as_props_and_values();
+        for handle in handles {
+            if device.get_property(*handle)?.name().to_str() == Ok("DPMS") {
+                return device.set_property(*conn, *handle, if on { DPMS_ON } else { DPMS_OFF });
+            }
+        }
+    }
+    Ok(())
 }
 
 pub struct DrmHandlerImpl<Data: 'static> {
@@ -631,6 +1420,8 @@ impl<Data: 'static> DrmRendererSessionListener<Data> {
     fn activate(&mut self) {
         // we want to be called, after all session handling is done (TODO this is not so nice)
         let renderer = self.renderer.clone();
+        // the cursor planes are set up again, another session may have changed them
+        renderer.hardware_cursors.borrow_mut().clear();
         let handle = self.loop_handle.clone();
         self.loop_handle
             .insert_idle(move |_| renderer.render_all(Some(&handle)));
@@ -645,20 +1436,229 @@ pub struct DrmRenderer {
     backends: Rc<RefCell<HashMap<crtc::Handle, Rc<RefCell<RenderSurface>>>>>,
     window_map: Rc<RefCell<MyWindowMap>>,
     output_map: Rc<RefCell<Vec<MyOutput>>>,
-    pointer_location: Rc<RefCell<(f64, f64)>>,
-    pointer_image: Gles2Texture,
-    cursor_status: Arc<Mutex<CursorImageStatus>>,
-    dnd_icon: Arc<Mutex<Option<wl_surface::WlSurface>>>,
+    cursors: Vec<SeatCursor>,
+    input_methods: Vec<Rc<RefCell<InputMethodState>>>,
+    pointer_textures: HashMap<CursorShape, Vec<Gles2Texture>>,
+    inhibit_indicator: Gles2Texture,
+    cursor_theme: Rc<CursorTheme>,
+    backgrounds: RefCell<Backgrounds<Gles2Texture>>,
+    gbm: GbmDevice<SessionFd>,
+    /// The cursor planes by crtc, set up once the crtc renders
+    hardware_cursors: RefCell<HashMap<crtc::Handle, HardwareCursor<DrmCursorPlane<SessionFd>>>>,
+    /// The direct scanout of fullscreen windows by crtc
+    scanouts: RefCell<HashMap<crtc::Handle, DirectScanout<SessionFd>>>,
+    #[cfg(feature = "switcher")]
+    switcher: Rc<RefCell<Option<SwitcherView>>>,
+    #[cfg(feature = "switcher")]
+    switcher_textures: RefCell<SwitcherTextures<Gles2Texture>>,
+    #[cfg(feature = "snap_preview")]
+    snap: Rc<RefCell<SnapState>>,
+    #[cfg(feature = "snap_preview")]
+    snap_preview: RefCell<SnapPreview<Gles2Texture>>,
     logger: ::slog::Logger,
     start_time: std::time::Instant,
 }
 
 impl DrmRenderer {
+    /// Returns the current frame of the themed cursor of the given shape and its hotspot.
+    fn pointer_image(&self, shape: CursorShape) -> (&Gles2Texture, (i32, i32)) {
+        let cursor = self.cursor_theme.get(shape);
+        let idx = cursor.frame_idx(self.start_time.elapsed().as_millis() as u32);
+        (&self.pointer_textures[&shape][idx], cursor.frames()[idx].hotspot)
+    }
+
     fn render_all<Data: 'static>(self: Rc<Self>, evt_handle: Option<&LoopHandle<Data>>) {
         for crtc in self.backends.borrow().keys() {
             self.clone().render(*crtc, None, evt_handle);
         }
     }
+
+    /// Renders the outputs with damage that are not waiting for a vblank, the others render with
+    /// their next vblank.
+    fn render_damaged<Data: 'static>(self: Rc<Self>, evt_handle: &LoopHandle<Data>) {
+        // the outputs show their wallpaper once it is composed
+        let composed = self.backgrounds.borrow_mut().receive();
+        if !composed.is_empty() {
+            for output in self.output_map.borrow_mut().iter_mut() {
+                if output.device_id == self.device_id && composed.contains(&output.name) {
+                    output.damage.damage_all();
+                }
+            }
+        }
+        let input_popups = self.input_popups();
+        let crtcs = self.backends.borrow().keys().cloned().collect::<Vec<_>>();
+        for crtc in crtcs {
+            // moving the cursor plane needs no new frame
+            let cursor_on_plane = self.update_hardware_cursor(crtc);
+            self.update_overlays(crtc, &input_popups, cursor_on_plane);
+            let idle = self
+                .output_map
+                .borrow()
+                .iter()
+                .find(|output| output.device_id == self.device_id && output.crtc == crtc)
+                .map(|output| output.powered && !output.damage.frame_pending && output.damage.is_damaged())
+                .unwrap_or(false);
+            if idle {
+                self.clone().render(crtc, None, Some(evt_handle));
+            }
+        }
+    }
+
+    fn input_popups(&self) -> Vec<(wl_surface::WlSurface, (i32, i32))> {
+        self.input_methods
+            .iter()
+            .flat_map(|input_method| input_method.borrow().popups(&*self.window_map.borrow()))
+            .collect()
+    }
+
+    /// Shows the cursor of the default seat on the cursor plane of `crtc`, returns whether the
+    /// frames of the crtc leave it out.
+    ///
+    /// Outputs that are scaled or transformed, and cursors the plane can not show, get the cursor
+    /// drawn into their frames.
+    fn update_hardware_cursor(&self, crtc: crtc::Handle) -> bool {
+        let (geometry, scale, transform) = match self
+            .output_map
+            .borrow()
+            .iter()
+            .find(|output| output.device_id == self.device_id && output.crtc == crtc)
+        {
+            Some(output) => (output.geometry(), output.scale, output.transform),
+            None => return false,
+        };
+        let mut hardware_cursors = self.hardware_cursors.borrow_mut();
+        let hardware_cursor = hardware_cursors
+            .entry(crtc)
+            .or_insert_with(|| HardwareCursor::new(DrmCursorPlane::new(self.gbm.clone(), crtc)));
+        let cursor = match self.cursors.first() {
+            Some(cursor) => cursor,
+            None => {
+                hardware_cursor.hide(&self.logger);
+                return false;
+            }
+        };
+
+        let (ptr_x, ptr_y) = *cursor.location.borrow();
+        let (ptr_x, ptr_y) = (ptr_x.trunc() as i32, ptr_y.trunc() as i32);
+        hardware_cursor.set_output(scale, transform, &self.logger);
+        if !geometry.contains((ptr_x, ptr_y)) {
+            hardware_cursor.hide(&self.logger);
+            return false;
+        }
+        let position = (ptr_x - geometry.x, ptr_y - geometry.y);
+
+        match *cursor.status.lock().unwrap() {
+            CursorImageStatus::Default => {
+                let shape = cursor.current_shape();
+                let theme_cursor = self.cursor_theme.get(shape);
+                let idx = theme_cursor.frame_idx(self.start_time.elapsed().as_millis() as u32);
+                hardware_cursor.show(
+                    CursorKey::Theme(shape, idx),
+                    position,
+                    || Some(CursorImage::from_frame(&theme_cursor.frames()[idx])),
+                    &self.logger,
+                )
+            }
+            CursorImageStatus::Image(ref surface) if surface.as_ref().is_alive() => {
+                let hotspot = self
+                    .compositor_token
+                    .with_role_data::<CursorImageRole, _, _>(surface, |data| data.hotspot)
+                    .unwrap_or((0, 0));
+                // once drawn into a frame the buffer is released, the cursor stays there until
+                // the client attaches a new one
+                let buffer = self.compositor_token.with_surface_data(surface, |attributes| {
+                    let data = attributes.user_data.get::<RefCell<SurfaceData>>()?.borrow();
+                    if attributes.buffer_scale != 1
+                        || attributes.buffer_transform != wl_output::Transform::Normal
+                        || data.current_state.viewport != ViewportState::default()
+                    {
+                        return None;
+                    }
+                    data.current_state.buffer.clone()
+                });
+                match buffer {
+                    Some(buffer) => hardware_cursor.show(
+                        CursorKey::Surface(surface.as_ref().id(), buffer.as_ref().id()),
+                        position,
+                        || CursorImage::from_buffer(&buffer, hotspot),
+                        &self.logger,
+                    ),
+                    None => {
+                        hardware_cursor.hide(&self.logger);
+                        false
+                    }
+                }
+            }
+            // dead surfaces are reset to the default cursor by the next frame
+            CursorImageStatus::Image(_) => {
+                hardware_cursor.hide(&self.logger);
+                false
+            }
+            CursorImageStatus::Hidden => {
+                hardware_cursor.hide(&self.logger);
+                true
+            }
+        }
+    }
+
+    /// Damages the cursors and surfaces over the windows that changed on the output of `crtc`.
+    fn update_overlays(
+        &self,
+        crtc: crtc::Handle,
+        input_popups: &[(wl_surface::WlSurface, (i32, i32))],
+        cursor_on_plane: bool,
+    ) {
+        let mut overlays = surface_overlays(
+            &self.cursors,
+            input_popups,
+            cursor_on_plane,
+            self.compositor_token,
+        );
+        // the switcher also keeps fullscreen windows from being scanned out
+        #[cfg(feature = "switcher")]
+        overlays.extend(self.switcher.borrow().as_ref().map(SwitcherView::overlay));
+        #[cfg(feature = "snap_preview")]
+        overlays.extend(self.snap.borrow().preview.map(|zone| (zone, 0)));
+        let time = self.start_time.elapsed().as_millis() as u32;
+        for (idx, cursor) in self.cursors.iter().enumerate() {
+            let (ptr_x, ptr_y) = *cursor.location.borrow();
+            let (ptr_x, ptr_y) = (ptr_x.trunc() as i32, ptr_y.trunc() as i32);
+            // the cursor plane shows the cursor without new frames
+            let on_plane = idx == 0 && cursor_on_plane;
+            let default = matches!(*cursor.status.lock().unwrap(), CursorImageStatus::Default);
+            if default && !on_plane {
+                // animated cursors damage their place with every new image
+                let theme_cursor = self.cursor_theme.get(cursor.current_shape());
+                let idx = theme_cursor.frame_idx(time);
+                let image = &theme_cursor.frames()[idx];
+                let rect = Rectangle {
+                    x: ptr_x - image.hotspot.0,
+                    y: ptr_y - image.hotspot.1,
+                    width: image.width as i32,
+                    height: image.height as i32,
+                };
+                overlays.push((rect, idx));
+            }
+            if cursor.shortcuts_inhibited.get() {
+                let rect = Rectangle {
+                    x: ptr_x + INHIBIT_INDICATOR_OFFSET,
+                    y: ptr_y + INHIBIT_INDICATOR_OFFSET,
+                    width: INHIBIT_INDICATOR_SIZE as i32,
+                    height: INHIBIT_INDICATOR_SIZE as i32,
+                };
+                overlays.push((rect, 0));
+            }
+        }
+
+        let mut output_map = self.output_map.borrow_mut();
+        if let Some(output) = output_map
+            .iter_mut()
+            .find(|output| output.device_id == self.device_id && output.crtc == crtc)
+        {
+            let geometry = output.geometry();
+            output.damage.set_overlays(&geometry, overlays);
+        }
+    }
     fn render<Data: 'static>(
         self: Rc<Self>,
         crtc: crtc::Handle,
@@ -666,8 +1666,21 @@ impl DrmRenderer {
         evt_handle: Option<&LoopHandle<Data>>,
     ) {
         if let Some(surface) = self.backends.borrow().get(&crtc) {
+            let pointer_images = self
+                .cursors
+                .iter()
+                .map(|cursor| self.pointer_image(cursor.current_shape()))
+                .collect::<Vec<_>>();
+            let input_popups = self.input_popups();
+            let cursor_on_plane = self.update_hardware_cursor(crtc);
+            self.update_overlays(crtc, &input_popups, cursor_on_plane);
+            let mut scanouts = self.scanouts.borrow_mut();
+            let scanout = scanouts
+                .entry(crtc)
+                .or_insert_with(|| DirectScanout::new(self.gbm.clone(), crtc));
             let result = DrmRenderer::render_surface(
                 &mut *surface.borrow_mut(),
+                scanout,
                 #[cfg(feature = "egl")]
                 self.egl_buffer_reader.as_ref(),
                 self.device_id,
@@ -675,10 +1688,20 @@ impl DrmRenderer {
                 &mut *self.window_map.borrow_mut(),
                 &mut *self.output_map.borrow_mut(),
                 &self.compositor_token,
-                &*self.pointer_location.borrow(),
-                &self.pointer_image,
-                &*self.dnd_icon.lock().unwrap(),
-                &mut *self.cursor_status.lock().unwrap(),
+                &input_popups,
+                &self.cursors,
+                cursor_on_plane,
+                &pointer_images,
+                &self.inhibit_indicator,
+                &mut *self.backgrounds.borrow_mut(),
+                #[cfg(feature = "switcher")]
+                self.switcher.borrow().as_ref(),
+                #[cfg(feature = "switcher")]
+                &mut *self.switcher_textures.borrow_mut(),
+                #[cfg(feature = "snap_preview")]
+                self.snap.borrow().preview,
+                #[cfg(feature = "snap_preview")]
+                &mut *self.snap_preview.borrow_mut(),
                 &self.logger,
             );
             if let Err(err) = result {
@@ -730,9 +1753,11 @@ impl DrmRenderer {
             } else {
                 // TODO: only send drawn windows the frames callback
                 // Send frame events so that client start drawing their next frame
-                self.window_map
-                    .borrow()
-                    .send_frames(self.start_time.elapsed().as_millis() as u32);
+                let time = self.start_time.elapsed().as_millis() as u32;
+                self.window_map.borrow().send_frames(time);
+                for input_method in &self.input_methods {
+                    input_method.borrow().send_frames(time, self.compositor_token);
+                }
             }
         }
     }
@@ -740,64 +1765,143 @@ impl DrmRenderer {
     #[allow(clippy::too_many_arguments)]
     fn render_surface(
         surface: &mut RenderSurface,
+        scanout: &mut DirectScanout<SessionFd>,
         #[cfg(feature = "egl")] egl_buffer_reader: Option<&EGLBufferReader>,
         device_id: dev_t,
         crtc: crtc::Handle,
         window_map: &mut MyWindowMap,
         output_map: &mut Vec<MyOutput>,
         compositor_token: &CompositorToken<Roles>,
-        pointer_location: &(f64, f64),
-        pointer_image: &Gles2Texture,
-        dnd_icon: &Option<wl_surface::WlSurface>,
-        cursor_status: &mut CursorImageStatus,
+        input_popups: &[(wl_surface::WlSurface, (i32, i32))],
+        cursors: &[SeatCursor],
+        cursor_on_plane: bool,
+        pointer_images: &[(&Gles2Texture, (i32, i32))],
+        inhibit_indicator: &Gles2Texture,
+        backgrounds: &mut Backgrounds<Gles2Texture>,
+        #[cfg(feature = "switcher")] switcher: Option<&SwitcherView>,
+        #[cfg(feature = "switcher")] switcher_textures: &mut SwitcherTextures<Gles2Texture>,
+        #[cfg(feature = "snap_preview")] snap_zone: Option<Rectangle>,
+        #[cfg(feature = "snap_preview")] snap_preview: &mut SnapPreview<Gles2Texture>,
         logger: &slog::Logger,
     ) -> Result<(), SwapBuffersError> {
         #[cfg(not(feature = "egl"))]
         let egl_buffer_reader = None;
 
         surface.frame_submitted()?;
+        scanout.frame_submitted();
+
+        let mut output = output_map
+            .iter_mut()
+            .find(|output| output.device_id == device_id && output.crtc == crtc);
+        if let Some(ref mut output) = output {
+            output.damage.frame_pending = false;
+            // outputs turned off stop rendering until they are turned on again, and outputs
+            // without damage until something changes on them
+            if !output.powered || !output.damage.is_damaged() {
+                return Ok(());
+            }
+
+            // a fullscreen window is shown as it is, as long as nothing is drawn over it
+            let geometry = output.geometry();
+            if output.transform == wl_output::Transform::Normal && !output.damage.has_overlays(&geometry) {
+                if let Some(buffer) = fullscreen_buffer(window_map, &geometry, output.size, *compositor_token) {
+                    match scanout.flip(&buffer, output.size) {
+                        Ok(true) => {
+                            output.damage.reset();
+                            output.damage.frame_pending = true;
+                            return Ok(());
+                        }
+                        Ok(false) => {}
+                        Err(err) => debug!(logger, "Direct scanout failed, compositing instead: {}", err),
+                    }
+                }
+            }
+        }
 
         // get output coordinates
-        let (x, y) = output_map
-            .iter()
-            .take_while(|output| output.device_id != device_id || output.crtc != crtc)
-            .fold((0u32, 0u32), |pos, output| (pos.0 + output.size.0, pos.1));
-        let (width, height) = output_map
-            .iter()
-            .find(|output| output.device_id == device_id && output.crtc == crtc)
-            .map(|output| output.size)
-            .unwrap_or((0, 0)); // in this case the output will be removed.
+        let view = output.as_ref().map(|output| output.view()).unwrap_or(OutputView {
+            // in this case the output will be removed.
+            geometry: Rectangle::default(),
+            scale: 1.0,
+            transform: Transform::Normal,
+            bottom_up: false,
+        });
+
+        let (name, identifiers) = output
+            .as_ref()
+            .map(|output| (output.name.clone(), output.identifiers.clone()))
+            .unwrap_or_default();
+
+        // the client buffers were flipped to past the surface, it is set up again before it composites
+        if scanout.is_active() {
+            scanout.request_reset();
+            return Ok(());
+        }
 
         // and draw in sync with our monitor
-        surface
+        let result = surface
             .render(|renderer, frame| {
-                frame.clear([0.8, 0.8, 0.9, 1.0])?;
-                // draw the surfaces
-                draw_windows(
-                    renderer,
-                    frame,
-                    egl_buffer_reader,
-                    window_map,
-                    Some(Rectangle {
-                        x: x as i32,
-                        y: y as i32,
-                        width: width as i32,
-                        height: height as i32,
-                    }),
-                    *compositor_token,
-                    logger,
-                )?;
-
-                // get pointer coordinates
-                let (ptr_x, ptr_y) = *pointer_location;
-                let ptr_x = ptr_x.trunc().abs() as i32 - x as i32;
-                let ptr_y = ptr_y.trunc().abs() as i32 - y as i32;
-
-                // set cursor
-                if ptr_x >= 0 && ptr_x < width as i32 && ptr_y >= 0 && ptr_y < height as i32 {
-                    // draw the dnd icon if applicable
+                // only the damage of the frames since the buffer was last painted is repainted
+                let region = match output {
+                    Some(ref mut output) => output.damage.repaint(&view.geometry),
+                    None => vec![view.geometry],
+                };
+                let drawn: Result<(), SwapBuffersError> = region.into_iter().try_for_each(|clip| {
+                    view.clip(Some(clip));
+                    backgrounds.draw(renderer, frame, &name, &identifiers, &view)?;
+                    // draw the surfaces
+                    draw_windows(
+                        renderer,
+                        frame,
+                        egl_buffer_reader,
+                        window_map,
+                        &view,
+                        *compositor_token,
+                        logger,
+                    )?;
+                    #[cfg(feature = "snap_preview")]
+                    {
+                        if let Some(zone) = snap_zone {
+                            snap_preview.draw(renderer, frame, zone, &view)?;
+                        }
+                    }
+                    #[cfg(feature = "switcher")]
+                    {
+                        if let Some(switcher) = switcher {
+                            switcher_textures.draw(
+                                renderer,
+                                frame,
+                                egl_buffer_reader,
+                                window_map,
+                                switcher,
+                                &view,
+                                *compositor_token,
+                            )?;
+                        }
+                    }
+                    draw_input_popups(
+                        renderer,
+                        frame,
+                        egl_buffer_reader,
+                        input_popups,
+                        &view,
+                        *compositor_token,
+                        logger,
+                    )?;
+
+                    for (idx, (cursor, &(pointer_image, (hotspot_x, hotspot_y)))) in
+                        cursors.iter().zip(pointer_images).enumerate()
                     {
-                        if let Some(ref wl_surface) = dnd_icon.as_ref() {
+                        // get pointer coordinates
+                        let (ptr_x, ptr_y) = *cursor.location.borrow();
+                        let (ptr_x, ptr_y) = (ptr_x.trunc() as i32, ptr_y.trunc() as i32);
+
+                        // set cursor
+                        if !view.geometry.contains((ptr_x, ptr_y)) {
+                            continue;
+                        }
+                        // draw the dnd icon if applicable
+                        if let Some(ref wl_surface) = *cursor.dnd_icon.lock().unwrap() {
                             if wl_surface.as_ref().is_alive() {
                                 draw_dnd_icon(
                                     renderer,
@@ -805,47 +1909,128 @@ impl DrmRenderer {
                                     wl_surface,
                                     egl_buffer_reader,
                                     (ptr_x, ptr_y),
+                                    &view,
                                     *compositor_token,
                                     logger,
                                 )?;
                             }
                         }
-                    }
-                    // draw the cursor as relevant
-                    {
-                        // reset the cursor if the surface is no longer alive
-                        let mut reset = false;
-                        if let CursorImageStatus::Image(ref surface) = *cursor_status {
-                            reset = !surface.as_ref().is_alive();
-                        }
-                        if reset {
-                            *cursor_status = CursorImageStatus::Default;
-                        }
+                        // draw the cursor as relevant
+                        {
+                            let mut cursor_status = cursor.status.lock().unwrap();
+                            // reset the cursor if the surface is no longer alive
+                            let mut reset = false;
+                            if let CursorImageStatus::Image(ref surface) = *cursor_status {
+                                reset = !surface.as_ref().is_alive();
+                            }
+                            if reset {
+                                *cursor_status = CursorImageStatus::Default;
+                            }
 
-                        if let CursorImageStatus::Image(ref wl_surface) = *cursor_status {
-                            draw_cursor(
-                                renderer,
+                            match *cursor_status {
+                                // the cursor plane shows it over the frame
+                                _ if idx == 0 && cursor_on_plane => {}
+                                CursorImageStatus::Image(ref wl_surface) => {
+                                    draw_cursor(
+                                        renderer,
+                                        frame,
+                                        wl_surface,
+                                        egl_buffer_reader,
+                                        (ptr_x, ptr_y),
+                                        &view,
+                                        *compositor_token,
+                                        logger,
+                                    )?;
+                                }
+                                CursorImageStatus::Default => {
+                                    // the theme cursors are sized in logical pixels, like client cursors
+                                    view.render_texture(
+                                        frame,
+                                        pointer_image,
+                                        (
+                                            (ptr_x - hotspot_x) as f64,
+                                            (ptr_y - hotspot_y) as f64,
+                                            pointer_image.width() as f64,
+                                            pointer_image.height() as f64,
+                                        ),
+                                        Transform::Normal,
+                                    )?;
+                                }
+                                CursorImageStatus::Hidden => {}
+                            }
+                        }
+                        // mark the cursor while the focused client receives all the keys
+                        if cursor.shortcuts_inhibited.get() {
+                            view.render_texture(
                                 frame,
-                                wl_surface,
-                                egl_buffer_reader,
-                                (ptr_x, ptr_y),
-                                *compositor_token,
-                                logger,
+                                inhibit_indicator,
+                                (
+                                    (ptr_x + INHIBIT_INDICATOR_OFFSET) as f64,
+                                    (ptr_y + INHIBIT_INDICATOR_OFFSET) as f64,
+                                    INHIBIT_INDICATOR_SIZE as f64,
+                                    INHIBIT_INDICATOR_SIZE as f64,
+                                ),
+                                Transform::Normal,
                             )?;
-                        } else {
-                            frame.render_texture_at(pointer_image, (ptr_x, ptr_y), Transform::Normal, 1.0)?;
                         }
                     }
-                }
 
-                Ok(())
+                    Ok(())
+                });
+                view.clip(None);
+                drawn
             })
             .map_err(Into::<SwapBuffersError>::into)
             .and_then(|x| x)
-            .map_err(Into::<SwapBuffersError>::into)
+            .map_err(Into::<SwapBuffersError>::into);
+
+        if let (Ok(()), Some(output)) = (&result, output) {
+            output.damage.reset();
+            output.damage.frame_pending = true;
+            scanout.composited();
+        }
+        result
     }
 }
 
+const INHIBIT_INDICATOR_SIZE: u32 = 8;
+const INHIBIT_INDICATOR_OFFSET: i32 = 16;
+
+/// Uploads the badge drawn next to the cursor of a seat whose shortcuts are inhibited.
+fn import_inhibit_indicator(renderer: &mut Gles2Renderer) -> Gles2Texture {
+    let image = ImageBuffer::from_pixel(
+        INHIBIT_INDICATOR_SIZE,
+        INHIBIT_INDICATOR_SIZE,
+        Rgba([0xffu8, 0x80, 0x00, 0xff]),
+    );
+    renderer
+        .import_bitmap(&image)
+        .expect("Failed to load the shortcuts inhibitor indicator")
+}
+
+/// Uploads every frame of every cursor shape of the theme.
+fn import_cursor_theme(
+    renderer: &mut Gles2Renderer,
+    theme: &CursorTheme,
+) -> HashMap<CursorShape, Vec<Gles2Texture>> {
+    CursorShape::ALL
+        .iter()
+        .map(|shape| {
+            let textures = theme
+                .get(*shape)
+                .frames()
+                .iter()
+                .map(|frame| {
+                    let image: ImageBuffer<Rgba<u8>, Vec<u8>> =
+                        ImageBuffer::from_raw(frame.width, frame.height, frame.pixels.clone()).unwrap();
+                    renderer.import_bitmap(&image).expect("Failed to load pointer")
+                })
+                .collect();
+            (*shape, textures)
+        })
+        .collect()
+}
+
 fn schedule_initial_render<Data: 'static>(
     renderer: Rc<RefCell<RenderSurface>>,
     evt_handle: &LoopHandle<Data>,
@@ -876,3 +2061,16 @@ fn schedule_initial_render<Data: 'static>(
         }
     }
 }
+
+/// Returns the name of the libinput device an event originates from.
+pub fn event_device_name(event: &InputEvent<LibinputInputBackend>) -> Option<String> {
+    let device = match event {
+        InputEvent::Keyboard { event } => event.device(),
+        InputEvent::PointerMotion { event } => event.device(),
+        InputEvent::PointerMotionAbsolute { event } => event.device(),
+        InputEvent::PointerButton { event } => event.device(),
+        InputEvent::PointerAxis { event } => event.device(),
+        _ => return None,
+    };
+    Some(device.name().to_owned())
+}
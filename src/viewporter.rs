@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+
+use smithay::{
+    reexports::{
+        wayland_protocols::viewporter::server::{
+            wp_viewport::{self, WpViewport},
+            wp_viewporter::{self, WpViewporter},
+        },
+        wayland_server::{
+            protocol::{wl_output, wl_surface::WlSurface},
+            Display, Filter, Global, Main,
+        },
+    },
+    wayland::compositor::CompositorToken,
+};
+
+use crate::shell::{Roles, SurfaceData};
+
+/// The viewport of a surface, as set by the client.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct ViewportState {
+    /// The part of the buffer to show as x, y, width and height, in buffer coordinates divided by
+    /// the buffer scale
+    pub source: Option<(f64, f64, f64, f64)>,
+    /// The size of the surface
+    pub destination: Option<(i32, i32)>,
+}
+
+/// Returns the size of a surface in surface coordinates, from the size of its buffer in pixels.
+///
+/// The buffer transform is applied first, so a rotated buffer gives a surface of swapped size.
+///
+/// Posts an error on the viewport if its source rectangle does not fit the buffer.
+pub fn surface_size(
+    buffer_size: (i32, i32),
+    buffer_scale: i32,
+    buffer_transform: wl_output::Transform,
+    state: &ViewportState,
+    viewport: Option<&WpViewport>,
+) -> (i32, i32) {
+    let buffer_scale = buffer_scale.max(1);
+    let buffer_size = match buffer_transform {
+        wl_output::Transform::_90
+        | wl_output::Transform::_270
+        | wl_output::Transform::Flipped90
+        | wl_output::Transform::Flipped270 => (buffer_size.1, buffer_size.0),
+        _ => buffer_size,
+    };
+    let (width, height) = (buffer_size.0 / buffer_scale, buffer_size.1 / buffer_scale);
+
+    if let (Some((x, y, source_width, source_height)), Some(viewport)) = (state.source, viewport) {
+        if x + source_width > width as f64 || y + source_height > height as f64 {
+            viewport.as_ref().post_error(
+                wp_viewport::Error::OutOfBuffer.to_raw(),
+                "The source rectangle extends outside of the buffer.".into(),
+            );
+        }
+    }
+
+    match (state.destination, state.source) {
+        (Some(destination), _) => destination,
+        (None, Some((_, _, source_width, source_height))) => {
+            if source_width.fract() != 0.0 || source_height.fract() != 0.0 {
+                if let Some(viewport) = viewport {
+                    viewport.as_ref().post_error(
+                        wp_viewport::Error::BadSize.to_raw(),
+                        "The source size must be integer without a destination size.".into(),
+                    );
+                }
+            }
+            (source_width as i32, source_height as i32)
+        }
+        (None, None) => (width, height),
+    }
+}
+
+fn with_surface_data<F, T>(surface: &WlSurface, token: CompositorToken<Roles>, f: F) -> T
+where
+    F: FnOnce(&mut SurfaceData) -> T,
+{
+    token.with_surface_data(surface, |attributes| {
+        attributes
+            .user_data
+            .insert_if_missing(|| RefCell::new(SurfaceData::default()));
+        let mut data = attributes
+            .user_data
+            .get::<RefCell<SurfaceData>>()
+            .unwrap()
+            .borrow_mut();
+        f(&mut data)
+    })
+}
+
+pub fn init_viewporter(
+    display: &mut Display,
+    token: CompositorToken<Roles>,
+    log: ::slog::Logger,
+) -> Global<WpViewporter> {
+    display.create_global::<WpViewporter, _>(
+        1,
+        Filter::new(move |(viewporter, _version): (Main<WpViewporter>, u32), _, _| {
+            let log = log.clone();
+            viewporter.quick_assign(move |viewporter, request, _| match request {
+                wp_viewporter::Request::GetViewport { id, surface } => {
+                    let exists = with_surface_data(&surface, token, |data| {
+                        data.viewport
+                            .as_ref()
+                            .map(|viewport| viewport.as_ref().is_alive())
+                            .unwrap_or(false)
+                    });
+                    if exists {
+                        viewporter.as_ref().post_error(
+                            wp_viewporter::Error::ViewportExists.to_raw(),
+                            "The surface already has a viewport.".into(),
+                        );
+                        return;
+                    }
+                    trace!(log, "New viewport");
+
+                    with_surface_data(&surface, token, |data| data.viewport = Some((*id).clone()));
+                    id.quick_assign(move |viewport, request, _| {
+                        if !surface.as_ref().is_alive() {
+                            if let wp_viewport::Request::Destroy = request {
+                                return;
+                            }
+                            viewport.as_ref().post_error(
+                                wp_viewport::Error::NoSurface.to_raw(),
+                                "The surface of the viewport was destroyed.".into(),
+                            );
+                            return;
+                        }
+                        match request {
+                            wp_viewport::Request::SetSource { x, y, width, height } => {
+                                let source = if x == -1.0 && y == -1.0 && width == -1.0 && height == -1.0 {
+                                    None
+                                } else if x < 0.0 || y < 0.0 || width <= 0.0 || height <= 0.0 {
+                                    bad_value(&viewport, "Invalid source rectangle.");
+                                    return;
+                                } else {
+                                    Some((x, y, width, height))
+                                };
+                                with_surface_data(&surface, token, |data| data.pending_viewport.source = source);
+                            }
+                            wp_viewport::Request::SetDestination { width, height } => {
+                                let destination = if width == -1 && height == -1 {
+                                    None
+                                } else if width <= 0 || height <= 0 {
+                                    bad_value(&viewport, "Invalid destination size.");
+                                    return;
+                                } else {
+                                    Some((width, height))
+                                };
+                                with_surface_data(&surface, token, |data| {
+                                    data.pending_viewport.destination = destination
+                                });
+                            }
+                            wp_viewport::Request::Destroy => {
+                                // the surface goes back to its buffer size with the next commit
+                                with_surface_data(&surface, token, |data| {
+                                    data.viewport = None;
+                                    data.pending_viewport = ViewportState::default();
+                                });
+                            }
+                            _ => unreachable!(),
+                        }
+                    });
+                }
+                wp_viewporter::Request::Destroy => {}
+                _ => unreachable!(),
+            });
+        }),
+    )
+}
+
+fn bad_value(viewport: &WpViewport, message: &str) {
+    viewport
+        .as_ref()
+        .post_error(wp_viewport::Error::BadValue.to_raw(), message.into());
+}
@@ -0,0 +1,207 @@
+use std::os::unix::io::RawFd;
+
+use smithay::{
+    backend::input::KeyState,
+    reexports::{
+        nix::unistd::close,
+        wayland_protocols::misc::zwp_virtual_keyboard_v1::server::{
+            zwp_virtual_keyboard_manager_v1::{self, ZwpVirtualKeyboardManagerV1},
+            zwp_virtual_keyboard_v1::{self, ZwpVirtualKeyboardV1},
+        },
+        wayland_server::{Display, Filter, Global, Main},
+    },
+    wayland::{
+        seat::{keysyms, KeyboardHandle, Seat},
+        SERIAL_COUNTER as SCOUNTER,
+    },
+};
+use xkbcommon::xkb;
+
+use crate::{keymap::SeatKeymap, seat::seat_keymap};
+
+/// The modifiers a virtual keyboard can set, with the key we press on the seat keyboard for them.
+const MODIFIERS: [(&str, u32); 4] = [
+    (xkb::MOD_NAME_SHIFT, keysyms::KEY_Shift_L),
+    (xkb::MOD_NAME_CTRL, keysyms::KEY_Control_L),
+    (xkb::MOD_NAME_ALT, keysyms::KEY_Alt_L),
+    (xkb::MOD_NAME_LOGO, keysyms::KEY_Super_L),
+];
+
+/// A virtual keyboard feeding keys into the keyboard of a seat.
+///
+/// Its keymap can differ from the one of the seat, so keys are translated to keysyms with the
+/// virtual keymap and pressed on the seat keyboard with the keycode producing the same keysym.
+/// Keys without such a keycode are dropped.
+struct VirtualKeyboard {
+    keyboard: KeyboardHandle,
+    seat_keymap: SeatKeymap,
+    state: Option<xkb::State>,
+    /// The pressed keys, as (virtual keycode, seat keycode)
+    pressed: Vec<(u32, u32)>,
+    /// The modifiers (indices into `MODIFIERS`) currently pressed on the seat keyboard
+    modifiers: [bool; 4],
+}
+
+impl VirtualKeyboard {
+    fn set_keymap(&mut self, format: u32, fd: RawFd, size: u32, log: &::slog::Logger) {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_fd(
+            &context,
+            fd,
+            size as usize,
+            format,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        );
+        let _ = close(fd);
+        match keymap {
+            Some(keymap) => self.state = Some(xkb::State::new(&keymap)),
+            None => warn!(log, "Failed to compile the keymap of a virtual keyboard"),
+        }
+    }
+
+    fn key(&mut self, time: u32, key: u32, state: KeyState, log: &::slog::Logger) {
+        let seat_key = match state {
+            KeyState::Pressed => {
+                let keysym = self
+                    .state
+                    .as_ref()
+                    .map(|xkb_state| xkb_state.key_get_one_sym(key + 8))
+                    .unwrap_or(keysyms::KEY_NoSymbol);
+                // the raw keycode means something else in the seat keymap, a key it has no
+                // keysym for is dropped
+                let seat_key = match self.seat_keymap.keycode_for(keysym) {
+                    Some(seat_key) if keysym != keysyms::KEY_NoSymbol => seat_key,
+                    _ => {
+                        debug!(log, "Dropping a virtual key the seat keymap can not produce";
+                            "keycode" => key,
+                            "keysym" => xkb::keysym_get_name(keysym)
+                        );
+                        return;
+                    }
+                };
+                self.pressed.push((key, seat_key));
+                seat_key
+            }
+            KeyState::Released => match self.pressed.iter().position(|&(pressed, _)| pressed == key) {
+                Some(idx) => self.pressed.remove(idx).1,
+                None => return,
+            },
+        };
+        self.input(seat_key, state, time);
+    }
+
+    fn modifiers(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        let xkb_state = match self.state {
+            Some(ref mut xkb_state) => xkb_state,
+            None => return,
+        };
+        xkb_state.update_mask(depressed, latched, locked, 0, 0, group);
+
+        let mut changes = Vec::new();
+        for (idx, &(name, keysym)) in MODIFIERS.iter().enumerate() {
+            let active = xkb_state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE);
+            if active != self.modifiers[idx] {
+                self.modifiers[idx] = active;
+                changes.push((keysym, active));
+            }
+        }
+        for (keysym, active) in changes {
+            if let Some(seat_key) = self.seat_keymap.keycode_for(keysym) {
+                let state = if active {
+                    KeyState::Pressed
+                } else {
+                    KeyState::Released
+                };
+                self.input(seat_key, state, 0);
+            }
+        }
+    }
+
+    fn input(&self, keycode: u32, state: KeyState, time: u32) {
+        // virtual keyboards bypass the compositor shortcuts, they always reach the focused client
+        self.keyboard
+            .input(keycode, state, SCOUNTER.next_serial(), time, |_, _| true);
+    }
+}
+
+fn key_state(state: u32) -> KeyState {
+    if state == 0 {
+        KeyState::Released
+    } else {
+        KeyState::Pressed
+    }
+}
+
+pub fn init_virtual_keyboard_manager(
+    display: &mut Display,
+    log: ::slog::Logger,
+) -> Global<ZwpVirtualKeyboardManagerV1> {
+    display.create_global::<ZwpVirtualKeyboardManagerV1, _>(
+        1,
+        Filter::new(move |(manager, _version): (Main<ZwpVirtualKeyboardManagerV1>, u32), _, _| {
+            let log = log.clone();
+            manager.quick_assign(move |_, request, _| match request {
+                zwp_virtual_keyboard_manager_v1::Request::CreateVirtualKeyboard { seat, id } => {
+                    let seat = match Seat::from_resource(&seat) {
+                        Some(seat) => seat,
+                        None => return,
+                    };
+                    let keyboard = match seat.get_keyboard() {
+                        Some(keyboard) => keyboard,
+                        None => return,
+                    };
+                    debug!(log, "New virtual keyboard");
+
+                    let mut virtual_keyboard = VirtualKeyboard {
+                        keyboard,
+                        seat_keymap: seat_keymap(&seat),
+                        state: None,
+                        pressed: Vec::new(),
+                        modifiers: [false; 4],
+                    };
+                    let log = log.clone();
+                    id.quick_assign(move |virtual_keyboard_resource, request, _| match request {
+                        zwp_virtual_keyboard_v1::Request::Keymap { format, fd, size } => {
+                            virtual_keyboard.set_keymap(format, fd, size, &log)
+                        }
+                        zwp_virtual_keyboard_v1::Request::Key { time, key, state } => {
+                            if virtual_keyboard.state.is_none() {
+                                no_keymap(&virtual_keyboard_resource);
+                                return;
+                            }
+                            virtual_keyboard.key(time, key, key_state(state), &log)
+                        }
+                        zwp_virtual_keyboard_v1::Request::Modifiers {
+                            mods_depressed,
+                            mods_latched,
+                            mods_locked,
+                            group,
+                        } => {
+                            if virtual_keyboard.state.is_none() {
+                                no_keymap(&virtual_keyboard_resource);
+                                return;
+                            }
+                            virtual_keyboard.modifiers(mods_depressed, mods_latched, mods_locked, group)
+                        }
+                        zwp_virtual_keyboard_v1::Request::Destroy => {
+                            // release everything, so no key stays stuck on the seat keyboard
+                            for (key, _) in virtual_keyboard.pressed.clone() {
+                                virtual_keyboard.key(0, key, KeyState::Released, &log);
+                            }
+                            virtual_keyboard.modifiers(0, 0, 0, 0);
+                        }
+                        _ => unreachable!(),
+                    });
+                }
+                _ => unreachable!(),
+            });
+        }),
+    )
+}
+
+fn no_keymap(virtual_keyboard: &ZwpVirtualKeyboardV1) {
+    virtual_keyboard.as_ref().post_error(
+        zwp_virtual_keyboard_v1::Error::NoKeymap.to_raw(),
+        "No keymap was set before sending keys.".into(),
+    );
+}
@@ -0,0 +1,171 @@
+//! Cycling through the windows by how recently they had the focus, like alt-tab elsewhere.
+//!
+//! The `next_window` and `previous_window` actions select the windows one after the other while
+//! the modifiers of their key binding are held, the selected window is raised and focused when
+//! they are released. Escape cancels the cycling.
+
+use smithay::{
+    utils::Rectangle,
+    wayland::seat::{keysyms as xkb, Keysym, ModifiersState},
+};
+
+use crate::{
+    custom::config::{CycleScope, KeyAction},
+    state::AnvilState,
+    window_map::WindowId,
+};
+
+/// A window cycling going on.
+pub struct WindowCycle {
+    /// The windows to go through, by how recently they had the focus
+    pub windows: Vec<WindowId>,
+    /// The index of the selected window
+    pub selected: usize,
+    /// The modifiers held when the cycling started
+    modifiers: ModifiersState,
+    /// The index of the seat whose keyboard started it, the only one whose keys it gets
+    pub seat: usize,
+    /// The output with the pointer of that seat when it started
+    pub output: Option<Rectangle>,
+}
+
+impl WindowCycle {
+    /// Whether one of the modifiers the cycling started with is still held.
+    fn held(&self, modifiers: ModifiersState) -> bool {
+        (self.modifiers.ctrl && modifiers.ctrl)
+            || (self.modifiers.alt && modifiers.alt)
+            || (self.modifiers.logo && modifiers.logo)
+    }
+
+    /// What a key does while the cycling goes on, `binding` is what the key bindings make of it.
+    pub fn key_action(&self, modifiers: ModifiersState, keysym: Keysym, pressed: bool, binding: KeyAction) -> KeyAction {
+        if !self.held(modifiers) {
+            KeyAction::FinishWindowCycle
+        } else if pressed && keysym == xkb::KEY_Escape {
+            KeyAction::CancelWindowCycle
+        } else {
+            match binding {
+                KeyAction::NextWindow | KeyAction::PreviousWindow => binding,
+                // the clients get no keys while the cycling goes on
+                _ => KeyAction::None,
+            }
+        }
+    }
+
+    /// The selected window.
+    pub fn selected(&self) -> Option<WindowId> {
+        self.windows.get(self.selected).copied()
+    }
+}
+
+impl AnvilState {
+    /// The windows the cycling goes through, by how recently they had the focus.
+    ///
+    /// `output` is the output with the pointer, for the output scope.
+    fn cycle_candidates(&self, output: Option<Rectangle>) -> Vec<WindowId> {
+        let options = self.config.window_cycling;
+        let window_map = self.window_map.borrow();
+        let workspace = window_map.active_workspace();
+        let output = output.filter(|_| options.scope == CycleScope::Output);
+
+        window_map
+            .focus_order()
+            .into_iter()
+            .filter(|&id| {
+                let toplevel = match window_map.find_by_id(id) {
+                    Some(toplevel) if toplevel.alive() => toplevel,
+                    _ => return false,
+                };
+                if options.skip_minimized && window_map.is_minimized(&toplevel) {
+                    return false;
+                }
+                if options.scope != CycleScope::All && window_map.workspace(&toplevel) != Some(workspace) {
+                    return false;
+                }
+                match (output, window_map.location(&toplevel), window_map.geometry(&toplevel)) {
+                    (Some(output), Some(location), Some(geometry)) => output.contains((
+                        location.0 + geometry.width / 2,
+                        location.1 + geometry.height / 2,
+                    )),
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+
+    /// Selects the next or previous window, starting the cycling with the held modifiers if it
+    /// is not going on yet.
+    pub fn cycle_windows(&mut self, seat: usize, modifiers: ModifiersState, forward: bool) {
+        if let Some(ref mut cycle) = self.window_cycle {
+            // the other seats cannot start their own cycling meanwhile
+            if cycle.seat != seat {
+                return;
+            }
+            let len = cycle.windows.len();
+            cycle.selected = if forward {
+                (cycle.selected + 1) % len
+            } else {
+                (cycle.selected + len - 1) % len
+            };
+            #[cfg(feature = "switcher")]
+            self.update_switcher();
+            return;
+        }
+
+        let (x, y) = self.seats[seat].pointer_location();
+        let output = self
+            .output_geometries()
+            .into_iter()
+            .map(|(_, geometry)| geometry)
+            .find(|geometry| geometry.contains((x as i32, y as i32)));
+        let windows = self.cycle_candidates(output);
+        if windows.is_empty() {
+            return;
+        }
+        let focused = self.seats[seat]
+            .keyboard_focus
+            .borrow()
+            .as_ref()
+            .and_then(|focus| self.window_of(&self.window_map.borrow(), focus))
+            .and_then(|toplevel| self.window_map.borrow().id(&toplevel));
+        // the focused window is usually the first one, going forward starts after it
+        let selected = match (forward, windows.first().copied() == focused) {
+            (true, true) => 1 % windows.len(),
+            (true, false) => 0,
+            (false, _) => windows.len() - 1,
+        };
+        self.window_cycle = Some(WindowCycle {
+            windows,
+            selected,
+            modifiers,
+            seat,
+            output,
+        });
+        #[cfg(feature = "switcher")]
+        self.update_switcher();
+    }
+
+    /// Raises and focuses the selected window, ending the cycling.
+    pub fn finish_window_cycle(&mut self) {
+        let cycle = match self.window_cycle.take() {
+            Some(cycle) => cycle,
+            None => return,
+        };
+        #[cfg(feature = "switcher")]
+        self.update_switcher();
+        let toplevel = cycle
+            .selected()
+            .and_then(|id| self.window_map.borrow().find_by_id(id));
+        // the window may have gone away while cycling
+        if let Some(toplevel) = toplevel {
+            self.focus_window_on(cycle.seat, &toplevel);
+        }
+    }
+
+    /// Ends the cycling, the focus stays where it was.
+    pub fn cancel_window_cycle(&mut self) {
+        self.window_cycle = None;
+        #[cfg(feature = "switcher")]
+        self.update_switcher();
+    }
+}
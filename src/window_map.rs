@@ -56,6 +56,21 @@ where
         }
     }
 
+    /// Asks the client to close the window, returns `false` if it can not be asked to.
+    pub fn close(&self) -> bool {
+        match *self {
+            Kind::Xdg(ref t) => {
+                t.send_close();
+                true
+            }
+            // wl_shell has no way to ask for it
+            Kind::Wl(_) => false,
+            // the window manager does not speak WM_DELETE_WINDOW yet
+            #[cfg(feature = "xwayland")]
+            Kind::X11(_) => false,
+        }
+    }
+
     /// Do this handle and the other one actually refer to the same toplevel surface?
     pub fn equals(&self, other: &Self) -> bool {
         match (self, other) {
@@ -68,7 +83,11 @@ where
     }
 }
 
+/// Identifies a window for its whole lifetime, ids are not reused.
+pub type WindowId = u64;
+
 struct Window<R> {
+    id: WindowId,
     location: (i32, i32),
     /// A bounding box over this window and its children.
     ///
@@ -76,6 +95,10 @@ struct Window<R> {
     /// geometry if that's not set explicitly.
     bbox: Rectangle,
     toplevel: Kind<R>,
+    /// The workspace the window is on, it is only shown while that workspace is active
+    workspace: usize,
+    /// Minimized windows are not shown until they get the focus again
+    minimized: bool,
 }
 
 impl<R> Window<R>
@@ -126,6 +149,16 @@ where
         found.into_inner()
     }
 
+    /// Updates the bounding box, and damages the old and new one if it changed.
+    fn update(&mut self, ctoken: CompositorToken<R>, damage: &mut Vec<Rectangle>) {
+        let bbox = self.bbox;
+        self.self_update(ctoken);
+        if self.bbox != bbox {
+            damage.push(bbox);
+            damage.push(self.bbox);
+        }
+    }
+
     fn self_update(&mut self, ctoken: CompositorToken<R>) {
         let (base_x, base_y) = self.location;
         let (mut min_x, mut min_y, mut max_x, mut max_y) = (base_x, base_y, base_x, base_y);
@@ -167,6 +200,18 @@ where
         };
     }
 
+    /// Whether the toplevel surface has a buffer.
+    fn mapped(&self, ctoken: CompositorToken<R>) -> bool {
+        self.toplevel.get_surface().map_or(false, |surface| {
+            ctoken.with_surface_data(surface, |attributes| {
+                attributes
+                    .user_data
+                    .get::<RefCell<SurfaceData>>()
+                    .map_or(false, |data| data.borrow().size().is_some())
+            })
+        })
+    }
+
     /// Returns the geometry of this window.
     pub fn geometry(&self, ctoken: CompositorToken<R>) -> Rectangle {
         // It's the set geometry with the full bounding box as the fallback.
@@ -182,6 +227,22 @@ where
             .unwrap_or(self.bbox)
     }
 
+    /// Where the set geometry starts in the surface, the client side decorations around it are
+    /// not part of the window.
+    pub fn geometry_offset(&self, ctoken: CompositorToken<R>) -> (i32, i32) {
+        self.toplevel
+            .get_surface()
+            .and_then(|surface| {
+                ctoken.with_surface_data(surface, |attributes| {
+                    attributes
+                        .user_data
+                        .get::<RefCell<SurfaceData>>()
+                        .and_then(|data| data.borrow().geometry)
+                })
+            })
+            .map_or((0, 0), |geometry| (geometry.x, geometry.y))
+    }
+
     /// Sends the frame callback to all the subsurfaces in this
     /// window that requested it
     pub fn send_frame(&self, time: u32, ctoken: CompositorToken<R>) {
@@ -206,6 +267,16 @@ where
 pub struct WindowMap<R> {
     ctoken: CompositorToken<R>,
     windows: Vec<Window<R>>,
+    /// The places windows appeared at, left or moved from, or got restacked at
+    damage: Vec<Rectangle>,
+    /// The workspace whose windows are shown, new windows open on it
+    active_workspace: usize,
+    /// The id of the next window
+    next_id: WindowId,
+    /// The windows that had the keyboard focus, the most recent first
+    focus_history: Vec<WindowId>,
+    /// The windows added and not taken by `take_mapped_windows` yet
+    new_windows: Vec<WindowId>,
 }
 
 impl<R> WindowMap<R>
@@ -216,21 +287,60 @@ where
         WindowMap {
             ctoken,
             windows: Vec::new(),
+            damage: Vec::new(),
+            active_workspace: 0,
+            next_id: 1,
+            focus_history: Vec::new(),
+            new_windows: Vec::new(),
         }
     }
 
-    pub fn insert(&mut self, toplevel: Kind<R>, location: (i32, i32)) {
+    pub fn insert(&mut self, toplevel: Kind<R>, location: (i32, i32)) -> WindowId {
+        let id = self.next_id;
+        self.next_id += 1;
         let mut window = Window {
+            id,
             location,
             bbox: Rectangle::default(),
             toplevel,
+            workspace: self.active_workspace,
+            minimized: false,
         };
         window.self_update(self.ctoken);
+        self.damage.push(window.bbox);
         self.windows.insert(0, window);
+        self.new_windows.push(id);
+        id
+    }
+
+    /// Takes the ids of the new windows that got their first buffer, the oldest first.
+    ///
+    /// xdg toplevels are added as they are created, before the client showed anything.
+    pub fn take_mapped_windows(&mut self) -> Vec<WindowId> {
+        let (windows, ctoken) = (&self.windows, self.ctoken);
+        let mut mapped = Vec::new();
+        self.new_windows
+            .retain(|&id| match windows.iter().find(|w| w.id == id) {
+                Some(w) if w.mapped(ctoken) => {
+                    mapped.push(id);
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            });
+        mapped
+    }
+
+    /// The windows shown on the active workspace, from top to bottom.
+    fn visible(&self) -> impl DoubleEndedIterator<Item = &Window<R>> {
+        let workspace = self.active_workspace;
+        self.windows
+            .iter()
+            .filter(move |w| w.workspace == workspace && !w.minimized)
     }
 
     pub fn get_surface_under(&self, point: (f64, f64)) -> Option<(wl_surface::WlSurface, (f64, f64))> {
-        for w in &self.windows {
+        for w in self.visible() {
             if let Some(surface) = w.matching(point, self.ctoken) {
                 return Some(surface);
             }
@@ -244,6 +354,9 @@ where
     ) -> Option<(wl_surface::WlSurface, (f64, f64))> {
         let mut found = None;
         for (i, w) in self.windows.iter().enumerate() {
+            if w.workspace != self.active_workspace || w.minimized {
+                continue;
+            }
             if let Some(surface) = w.matching(point, self.ctoken) {
                 found = Some((i, surface));
                 break;
@@ -251,6 +364,9 @@ where
         }
         if let Some((i, surface)) = found {
             let winner = self.windows.remove(i);
+            if i != 0 {
+                self.damage.push(winner.bbox);
+            }
             self.windows.insert(0, winner);
             Some(surface)
         } else {
@@ -262,27 +378,120 @@ where
     where
         Func: FnMut(&Kind<R>, (i32, i32), &Rectangle),
     {
-        for w in self.windows.iter().rev() {
+        for w in self.visible().rev() {
             f(&w.toplevel, w.location, &w.bbox)
         }
     }
 
+    /// Calls `f` for the windows that are not shown, on the other workspaces or minimized.
+    pub fn with_hidden_windows<Func>(&self, mut f: Func)
+    where
+        Func: FnMut(&Kind<R>, (i32, i32)),
+    {
+        for w in self
+            .windows
+            .iter()
+            .filter(|w| w.workspace != self.active_workspace || w.minimized)
+        {
+            f(&w.toplevel, w.location)
+        }
+    }
+
+    /// Calls `f` for every window of every workspace from top to bottom, with its id and workspace.
+    pub fn with_all_windows<Func>(&self, mut f: Func)
+    where
+        Func: FnMut(WindowId, &Kind<R>, (i32, i32), usize),
+    {
+        for w in &self.windows {
+            f(w.id, &w.toplevel, w.location, w.workspace)
+        }
+    }
+
+    /// The topmost window on the active workspace.
+    pub fn top_window(&self) -> Option<Kind<R>> {
+        self.visible().next().map(|w| w.toplevel.clone())
+    }
+
+    /// Raises the toplevel above all other windows, if it exists.
+    pub fn bring_to_top(&mut self, toplevel: &Kind<R>) {
+        if let Some(i) = self.windows.iter().position(|w| w.toplevel.equals(toplevel)) {
+            let winner = self.windows.remove(i);
+            if i != 0 {
+                self.damage.push(winner.bbox);
+            }
+            self.windows.insert(0, winner);
+        }
+    }
+
+    pub fn active_workspace(&self) -> usize {
+        self.active_workspace
+    }
+
+    /// Shows the windows of another workspace instead of the active one.
+    pub fn set_active_workspace(&mut self, workspace: usize) {
+        if workspace == self.active_workspace {
+            return;
+        }
+        let previous = self.active_workspace;
+        self.damage.extend(
+            self.windows
+                .iter()
+                .filter(|w| w.workspace == previous || w.workspace == workspace)
+                .map(|w| w.bbox),
+        );
+        self.active_workspace = workspace;
+    }
+
+    /// Returns the workspace of the toplevel, if it exists.
+    pub fn workspace(&self, toplevel: &Kind<R>) -> Option<usize> {
+        self.windows
+            .iter()
+            .find(|w| w.toplevel.equals(toplevel))
+            .map(|w| w.workspace)
+    }
+
+    /// Moves the toplevel to another workspace, if it exists.
+    pub fn set_workspace(&mut self, toplevel: &Kind<R>, workspace: usize) {
+        if let Some(w) = self.windows.iter_mut().find(|w| w.toplevel.equals(toplevel)) {
+            if w.workspace != workspace {
+                w.workspace = workspace;
+                self.damage.push(w.bbox);
+            }
+        }
+    }
+
     pub fn refresh(&mut self) {
-        self.windows.retain(|w| w.toplevel.alive());
+        let damage = &mut self.damage;
+        let focus_history = &mut self.focus_history;
+        self.windows.retain(|w| {
+            let alive = w.toplevel.alive();
+            if !alive {
+                damage.push(w.bbox);
+                focus_history.retain(|&id| id != w.id);
+            }
+            alive
+        });
         for w in &mut self.windows {
-            w.self_update(self.ctoken);
+            w.update(self.ctoken, &mut self.damage);
         }
     }
 
     /// Refreshes the state of the toplevel, if it exists.
     pub fn refresh_toplevel(&mut self, toplevel: &Kind<R>) {
         if let Some(w) = self.windows.iter_mut().find(|w| w.toplevel.equals(toplevel)) {
-            w.self_update(self.ctoken);
+            w.update(self.ctoken, &mut self.damage);
         }
     }
 
     pub fn clear(&mut self) {
-        self.windows.clear();
+        self.damage.extend(self.windows.drain(..).map(|w| w.bbox));
+        self.focus_history.clear();
+        self.new_windows.clear();
+    }
+
+    /// Takes the damage of the window changes since the last call, in global coordinates.
+    pub fn take_damage(&mut self) -> Vec<Rectangle> {
+        std::mem::take(&mut self.damage)
     }
 
     /// Finds the toplevel corresponding to the given `WlSurface`.
@@ -300,6 +509,63 @@ where
         })
     }
 
+    /// Returns the id of the toplevel, if it exists.
+    pub fn id(&self, toplevel: &Kind<R>) -> Option<WindowId> {
+        self.windows
+            .iter()
+            .find(|w| w.toplevel.equals(toplevel))
+            .map(|w| w.id)
+    }
+
+    /// Finds the toplevel with the given id.
+    pub fn find_by_id(&self, id: WindowId) -> Option<Kind<R>> {
+        self.windows
+            .iter()
+            .find(|w| w.id == id)
+            .map(|w| w.toplevel.clone())
+    }
+
+    /// Returns whether the toplevel is minimized, `false` if it does not exist.
+    pub fn is_minimized(&self, toplevel: &Kind<R>) -> bool {
+        self.windows
+            .iter()
+            .find(|w| w.toplevel.equals(toplevel))
+            .map_or(false, |w| w.minimized)
+    }
+
+    /// Hides or shows the toplevel, if it exists.
+    pub fn set_minimized(&mut self, toplevel: &Kind<R>, minimized: bool) {
+        if let Some(w) = self.windows.iter_mut().find(|w| w.toplevel.equals(toplevel)) {
+            if w.minimized != minimized {
+                w.minimized = minimized;
+                self.damage.push(w.bbox);
+            }
+        }
+    }
+
+    /// Notes that the toplevel got the keyboard focus, it goes to the front of the focus history.
+    pub fn note_focus(&mut self, toplevel: &Kind<R>) {
+        if let Some(id) = self.id(toplevel) {
+            if self.focus_history.first() != Some(&id) {
+                self.focus_history.retain(|&other| other != id);
+                self.focus_history.insert(0, id);
+            }
+        }
+    }
+
+    /// The ids of all windows by how recently they had the keyboard focus, the ones that never
+    /// had it follow from top to bottom.
+    pub fn focus_order(&self) -> Vec<WindowId> {
+        let mut order = self.focus_history.clone();
+        order.extend(
+            self.windows
+                .iter()
+                .map(|w| w.id)
+                .filter(|id| !self.focus_history.contains(id)),
+        );
+        order
+    }
+
     /// Returns the location of the toplevel, if it exists.
     pub fn location(&self, toplevel: &Kind<R>) -> Option<(i32, i32)> {
         self.windows
@@ -312,7 +578,7 @@ where
     pub fn set_location(&mut self, toplevel: &Kind<R>, location: (i32, i32)) {
         if let Some(w) = self.windows.iter_mut().find(|w| w.toplevel.equals(toplevel)) {
             w.location = location;
-            w.self_update(self.ctoken);
+            w.update(self.ctoken, &mut self.damage);
         }
     }
 
@@ -324,8 +590,17 @@ where
             .map(|w| w.geometry(self.ctoken))
     }
 
+    /// Returns where the geometry of the toplevel starts in its surface, if it exists.
+    pub fn geometry_offset(&self, toplevel: &Kind<R>) -> Option<(i32, i32)> {
+        self.windows
+            .iter()
+            .find(|w| w.toplevel.equals(toplevel))
+            .map(|w| w.geometry_offset(self.ctoken))
+    }
+
     pub fn send_frames(&self, time: u32) {
-        for window in &self.windows {
+        // hidden windows get no frames, like on an output that is turned off
+        for window in self.visible() {
             window.send_frame(time, self.ctoken);
         }
     }
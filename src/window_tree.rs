@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+
+#[cfg(feature = "egl")]
+use smithay::reexports::wayland_server::Client;
+use smithay::utils::Rectangle;
+
+use crate::{
+    shell::{app_id, title, ResizeState, Roles, SurfaceData},
+    state::AnvilState,
+    window_map::{Kind, WindowId},
+};
+
+/// A rectangle in the global space, in logical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl From<Rectangle> for Geometry {
+    fn from(rect: Rectangle) -> Self {
+        Geometry {
+            x: rect.x,
+            y: rect.y,
+            width: rect.width,
+            height: rect.height,
+        }
+    }
+}
+
+/// What is known about a window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WindowInfo {
+    pub id: WindowId,
+    /// The shell of the window: `xdg`, `wl_shell` or `x11`
+    pub kind: &'static str,
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    /// The process of the client, which is Xwayland for X11 windows
+    pub pid: Option<i32>,
+    /// The WM_CLASS class of X11 windows
+    pub class: Option<String>,
+    /// Counting from 1
+    pub workspace: usize,
+    /// The output showing the center of the window
+    pub output: Option<String>,
+    pub geometry: Geometry,
+    /// Whether the window has the keyboard focus of the default seat
+    pub focused: bool,
+    /// Whether the window is shown, on the active workspace and not minimized
+    pub visible: bool,
+    pub minimized: bool,
+    /// Whether the window is being resized interactively
+    pub resizing: bool,
+}
+
+/// A snapshot of all windows, for the ipc clients, debugging and the window rules.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WindowTree {
+    /// Counting from 1
+    pub active_workspace: usize,
+    pub workspaces: usize,
+    pub focused: Option<WindowId>,
+    /// The windows of every workspace, from top to bottom
+    pub windows: Vec<WindowInfo>,
+}
+
+impl WindowTree {
+    pub fn get(&self, id: WindowId) -> Option<&WindowInfo> {
+        self.windows.iter().find(|window| window.id == id)
+    }
+}
+
+/// The process id of a client, it is only known with the system libwayland.
+#[cfg(feature = "egl")]
+fn client_pid(client: &Client) -> Option<i32> {
+    use wayland_sys::{pid_t, server::*};
+    let mut pid: pid_t = 0;
+    unsafe {
+        wayland_sys::ffi_dispatch!(
+            WAYLAND_SERVER_HANDLE,
+            wl_client_get_credentials,
+            client.c_ptr(),
+            &mut pid,
+            std::ptr::null_mut(),
+            std::ptr::null_mut()
+        );
+    }
+    Some(pid).filter(|&pid| pid > 0)
+}
+
+fn window_kind(toplevel: &Kind<Roles>) -> &'static str {
+    match toplevel {
+        Kind::Xdg(_) => "xdg",
+        Kind::Wl(_) => "wl_shell",
+        #[cfg(feature = "xwayland")]
+        Kind::X11(_) => "x11",
+    }
+}
+
+impl AnvilState {
+    /// The names and geometries of the outputs.
+    pub fn output_geometries(&self) -> Vec<(String, Rectangle)> {
+        #[allow(unused_mut)]
+        let mut outputs = self
+            .winit_output
+            .iter()
+            .map(|view| (String::from("winit"), view.geometry))
+            .collect::<Vec<_>>();
+        #[cfg(feature = "udev")]
+        {
+            if let Some(ref output_map) = self.output_map {
+                outputs.extend(
+                    output_map
+                        .borrow()
+                        .iter()
+                        .map(|output| (output.name.clone(), output.geometry())),
+                );
+            }
+        }
+        outputs
+    }
+
+    /// Takes a snapshot of all windows.
+    pub fn window_tree(&self) -> WindowTree {
+        let window_map = self.window_map.borrow();
+        let focus = self.focused_window();
+        let focused = focus.as_ref().and_then(|focus| window_map.id(focus));
+        let outputs = self.output_geometries();
+        let active_workspace = window_map.active_workspace();
+
+        let mut windows = Vec::new();
+        window_map.with_all_windows(|id, toplevel, location, workspace| {
+            let surface = match toplevel.get_surface() {
+                Some(surface) => surface,
+                // it is gone with the next refresh
+                None => return,
+            };
+            let size = window_map.geometry(toplevel).unwrap_or_default();
+            let geometry = Rectangle {
+                x: location.0,
+                y: location.1,
+                width: size.width,
+                height: size.height,
+            };
+            let center = (geometry.x + geometry.width / 2, geometry.y + geometry.height / 2);
+            let output = outputs
+                .iter()
+                .find(|(_, output)| output.contains(center))
+                .map(|(name, _)| name.clone());
+            let minimized = window_map.is_minimized(toplevel);
+            let resizing = self.ctoken.with_surface_data(surface, |attributes| {
+                attributes
+                    .user_data
+                    .get::<RefCell<SurfaceData>>()
+                    .map_or(false, |data| data.borrow().resize_state != ResizeState::NotResizing)
+            });
+
+            #[allow(unused_mut)]
+            let (mut title, mut class) = (title(surface, self.ctoken), None);
+            #[cfg(feature = "xwayland")]
+            {
+                if let Kind::X11(ref x11) = toplevel {
+                    title = x11.title();
+                    class = x11.class();
+                }
+            }
+            #[cfg(feature = "egl")]
+            let pid = surface.as_ref().client().and_then(|client| client_pid(&client));
+            #[cfg(not(feature = "egl"))]
+            let pid = None;
+
+            windows.push(WindowInfo {
+                id,
+                kind: window_kind(toplevel),
+                app_id: app_id(surface, self.ctoken),
+                title,
+                pid,
+                class,
+                workspace: workspace + 1,
+                output,
+                geometry: geometry.into(),
+                focused: focused == Some(id),
+                visible: workspace == active_workspace && !minimized,
+                minimized,
+                resizing,
+            });
+        });
+
+        WindowTree {
+            active_workspace: active_workspace + 1,
+            workspaces: self.config.workspaces,
+            focused,
+            windows,
+        }
+    }
+}
@@ -1,11 +1,17 @@
 use std::{cell::RefCell, rc::Rc, sync::atomic::Ordering, time::Duration};
 
 use smithay::{
-    backend::{input::InputBackend, renderer::Frame, winit, SwapBuffersError},
+    backend::{
+        input::InputBackend,
+        renderer::{Frame, Transform},
+        winit, SwapBuffersError,
+    },
     reexports::{
         calloop::EventLoop,
         wayland_server::{protocol::wl_output, Display},
+        winit::window::CursorIcon,
     },
+    utils::Rectangle,
     wayland::{
         output::{Mode, Output, PhysicalProperties},
         seat::CursorImageStatus,
@@ -14,8 +20,19 @@ use smithay::{
 
 use slog::Logger;
 
-use crate::{custom::config::Configuration, drawing::*};
+use crate::{
+    cursor::CursorShape,
+    custom::config::Configuration,
+    background::Backgrounds,
+    damage::{surface_overlays, OutputDamage},
+    dmabuf::{can_import, init_linux_dmabuf, renderer_formats},
+    drawing::*,
+};
 use crate::state::AnvilState;
+#[cfg(feature = "snap_preview")]
+use crate::snap::SnapPreview;
+#[cfg(feature = "switcher")]
+use crate::switcher::{SwitcherTextures, SwitcherView};
 
 pub fn run_winit(
     display: Rc<RefCell<Display>>,
@@ -38,6 +55,17 @@ pub fn run_winit(
         info!(log, "EGL hardware-acceleration enabled");
     };
 
+    let dmabuf_formats = renderer_formats(renderer.borrow_mut().renderer());
+    let dmabuf_renderer = renderer.clone();
+    let _dmabuf_global = init_linux_dmabuf(
+        &mut display.borrow_mut(),
+        dmabuf_formats,
+        // the device the host compositor renders with is not known
+        None,
+        move |dmabuf| can_import(dmabuf_renderer.borrow_mut().renderer(), dmabuf),
+        log.clone(),
+    );
+
     let (w, h): (u32, u32) = renderer.borrow().window_size().physical_size.into();
 
     /*
@@ -68,6 +96,14 @@ pub fn run_winit(
         log.clone(),
     );
 
+    // the window can be configured like a connector named "Winit"
+    let identifiers = ["Winit".to_string()];
+    let scale = state
+        .config
+        .outputs
+        .get(&identifiers)
+        .and_then(|config| config.scale)
+        .unwrap_or(1.0);
     output.change_current_state(
         Some(Mode {
             width: w as i32,
@@ -75,7 +111,7 @@ pub fn run_winit(
             refresh: 60_000,
         }),
         None,
-        None,
+        Some(scale.ceil() as i32),
     );
     output.set_preferred(Mode {
         width: w as i32,
@@ -83,15 +119,35 @@ pub fn run_winit(
         refresh: 60_000,
     });
 
+    let view = OutputView {
+        geometry: Rectangle {
+            x: 0,
+            y: 0,
+            width: (w as f64 / scale).round() as i32,
+            height: (h as f64 / scale).round() as i32,
+        },
+        scale,
+        transform: Transform::Normal,
+        // the window of the host is drawn into from the bottom up
+        bottom_up: true,
+    };
+    state.winit_output = Some(view);
+    let mut damage = OutputDamage::default();
+    let mut backgrounds = Backgrounds::new(state.config.background.clone(), log.clone());
+    #[cfg(feature = "switcher")]
+    let mut switcher = SwitcherTextures::new(log.clone());
+    #[cfg(feature = "snap_preview")]
+    let mut snap_preview = SnapPreview::default();
+
     let start_time = std::time::Instant::now();
-    let mut cursor_visible = true;
+    let mut shortcuts_inhibited = false;
 
     info!(log, "Initialization completed, starting the main loop.");
     state.config.bar.spawn();
 
     while state.running.load(Ordering::SeqCst) {
         if input
-            .dispatch_new_events(|event, _| state.process_input_event(event))
+            .dispatch_new_events(|event, _| state.process_input_event(event, None))
             .is_err()
         {
             state.running.store(false, Ordering::SeqCst);
@@ -101,75 +157,156 @@ pub fn run_winit(
         // drawing logic
         {
             let mut renderer = renderer.borrow_mut();
+            // the winit backend only has a single pointer and keyboard, they belong to the default seat
+            let cursor = state.seats[0].cursor.clone();
+            let input_popups = state.seats[0].input_method.borrow().popups(&*state.window_map.borrow());
 
-            let result = renderer
-                .render(|renderer, frame| {
-                    frame.clear([0.8, 0.8, 0.9, 1.0])?;
-
-                    // draw the windows
-                    draw_windows(
-                        renderer,
-                        frame,
-                        reader.as_ref(),
-                        &*state.window_map.borrow(),
-                        None,
-                        state.ctoken,
-                        &log,
-                    )?;
-
-                    let (x, y) = *state.pointer_location.borrow();
-                    // draw the dnd icon if any
-                    {
-                        let guard = state.dnd_icon.lock().unwrap();
-                        if let Some(ref surface) = *guard {
-                            if surface.as_ref().is_alive() {
-                                draw_dnd_icon(
-                                    renderer,
-                                    frame,
-                                    surface,
-                                    reader.as_ref(),
-                                    (x as i32, y as i32),
-                                    state.ctoken,
-                                    &log,
-                                )?;
+            // the window is only redrawn when something changed on it
+            damage.add(&view.geometry, &state.take_damage());
+            #[allow(unused_mut)]
+            let mut overlays = surface_overlays(
+                std::slice::from_ref(&cursor),
+                &input_popups,
+                false,
+                state.ctoken,
+            );
+            #[cfg(feature = "switcher")]
+            overlays.extend(state.switcher.borrow().as_ref().map(SwitcherView::overlay));
+            #[cfg(feature = "snap_preview")]
+            overlays.extend(state.snap_preview().map(|zone| (zone, 0)));
+            damage.set_overlays(&view.geometry, overlays);
+            // the wallpaper shows once it is composed
+            if !backgrounds.receive().is_empty() {
+                damage.damage_all();
+            }
+            let damaged = damage.is_damaged();
+            let result = if damaged {
+                renderer
+                    .render(|renderer, frame| {
+                        // only the damage of the frames since the buffer was last shown is repainted
+                        let region = damage.repaint(&view.geometry);
+                        let drawn: Result<(), SwapBuffersError> = region.into_iter().try_for_each(|clip| {
+                            view.clip(Some(clip));
+                            backgrounds.draw(renderer, frame, "Winit", &identifiers, &view)?;
+
+                            // draw the windows
+                            draw_windows(
+                                renderer,
+                                frame,
+                                reader.as_ref(),
+                                &*state.window_map.borrow(),
+                                &view,
+                                state.ctoken,
+                                &log,
+                            )?;
+                            #[cfg(feature = "snap_preview")]
+                            {
+                                if let Some(zone) = state.snap_preview() {
+                                    snap_preview.draw(renderer, frame, zone, &view)?;
+                                }
                             }
-                        }
-                    }
-                    // draw the cursor as relevant
-                    {
-                        let mut guard = state.cursor_status.lock().unwrap();
-                        // reset the cursor if the surface is no longer alive
-                        let mut reset = false;
-                        if let CursorImageStatus::Image(ref surface) = *guard {
-                            reset = !surface.as_ref().is_alive();
-                        }
-                        if reset {
-                            *guard = CursorImageStatus::Default;
-                        }
-
-                        // draw as relevant
-                        if let CursorImageStatus::Image(ref surface) = *guard {
-                            cursor_visible = false;
-                            draw_cursor(
+                            #[cfg(feature = "switcher")]
+                            {
+                                if let Some(ref switcher_view) = *state.switcher.borrow() {
+                                    switcher.draw(
+                                        renderer,
+                                        frame,
+                                        reader.as_ref(),
+                                        &*state.window_map.borrow(),
+                                        switcher_view,
+                                        &view,
+                                        state.ctoken,
+                                    )?;
+                                }
+                            }
+                            draw_input_popups(
                                 renderer,
                                 frame,
-                                surface,
                                 reader.as_ref(),
-                                (x as i32, y as i32),
+                                &input_popups,
+                                &view,
                                 state.ctoken,
                                 &log,
                             )?;
-                        } else {
-                            cursor_visible = true;
-                        }
-                    }
 
-                    Ok(())
-                })
-                .map_err(Into::<SwapBuffersError>::into)
-                .and_then(|x| x.into());
+                            let (x, y) = *cursor.location.borrow();
+                            // draw the dnd icon if any
+                            {
+                                let guard = cursor.dnd_icon.lock().unwrap();
+                                if let Some(ref surface) = *guard {
+                                    if surface.as_ref().is_alive() {
+                                        draw_dnd_icon(
+                                            renderer,
+                                            frame,
+                                            surface,
+                                            reader.as_ref(),
+                                            (x as i32, y as i32),
+                                            &view,
+                                            state.ctoken,
+                                            &log,
+                                        )?;
+                                    }
+                                }
+                            }
+                            // draw the cursor as relevant
+                            {
+                                let mut guard = cursor.status.lock().unwrap();
+                                // reset the cursor if the surface is no longer alive
+                                let mut reset = false;
+                                if let CursorImageStatus::Image(ref surface) = *guard {
+                                    reset = !surface.as_ref().is_alive();
+                                }
+                                if reset {
+                                    *guard = CursorImageStatus::Default;
+                                }
+
+                                // draw as relevant
+                                match *guard {
+                                    CursorImageStatus::Image(ref surface) => {
+                                        draw_cursor(
+                                            renderer,
+                                            frame,
+                                            surface,
+                                            reader.as_ref(),
+                                            (x as i32, y as i32),
+                                            &view,
+                                            state.ctoken,
+                                            &log,
+                                        )?;
+                                    }
+                                    CursorImageStatus::Default | CursorImageStatus::Hidden => {}
+                                }
+                            }
+
+                            Ok(())
+                        });
+                        view.clip(None);
+                        drawn
+                    })
+                    .map_err(Into::<SwapBuffersError>::into)
+                    .and_then(|x| x.into())
+            } else {
+                Ok(())
+            };
+            if damaged && result.is_ok() {
+                damage.reset();
+            }
 
+            let cursor_visible = matches!(*cursor.status.lock().unwrap(), CursorImageStatus::Default);
             renderer.window().set_cursor_visible(cursor_visible);
+            // the host draws the default cursor for us, so only forward the shape
+            renderer
+                .window()
+                .set_cursor_icon(cursor_icon(cursor.current_shape()));
+            // for the same reason there is no cursor to mark, so the window title shows the inhibitor
+            if cursor.shortcuts_inhibited.get() != shortcuts_inhibited {
+                shortcuts_inhibited = cursor.shortcuts_inhibited.get();
+                renderer.window().set_title(if shortcuts_inhibited {
+                    "Smithay (shortcuts inhibited)"
+                } else {
+                    "Smithay"
+                });
+            }
 
             if let Err(SwapBuffersError::ContextLost(err)) = result {
                 error!(log, "Critical Rendering Error: {}", err);
@@ -178,10 +315,10 @@ pub fn run_winit(
         }
 
         // Send frame events so that client start drawing their next frame
-        state
-            .window_map
-            .borrow()
-            .send_frames(start_time.elapsed().as_millis() as u32);
+        let time = start_time.elapsed().as_millis() as u32;
+        state.window_map.borrow().send_frames(time);
+        state.seats[0].input_method.borrow().send_frames(time, state.ctoken);
+        state.update_fractional_scales();
         display.borrow_mut().flush_clients(&mut state);
 
         if event_loop
@@ -192,6 +329,11 @@ pub fn run_winit(
         } else {
             display.borrow_mut().flush_clients(&mut state);
             state.window_map.borrow_mut().refresh();
+            state.focus_new_windows();
+            state.update_snapping();
+            state.update_focus_history();
+            state.publish_events();
+            state.check_idle();
         }
     }
 
@@ -200,3 +342,42 @@ pub fn run_winit(
 
     Ok(())
 }
+
+fn cursor_icon(shape: CursorShape) -> CursorIcon {
+    match shape {
+        CursorShape::Default => CursorIcon::Default,
+        CursorShape::Move => CursorIcon::Move,
+        CursorShape::ResizeTop => CursorIcon::NResize,
+        CursorShape::ResizeBottom => CursorIcon::SResize,
+        CursorShape::ResizeLeft => CursorIcon::WResize,
+        CursorShape::ResizeRight => CursorIcon::EResize,
+        CursorShape::ResizeTopLeft => CursorIcon::NwResize,
+        CursorShape::ResizeTopRight => CursorIcon::NeResize,
+        CursorShape::ResizeBottomLeft => CursorIcon::SwResize,
+        CursorShape::ResizeBottomRight => CursorIcon::SeResize,
+        CursorShape::ContextMenu => CursorIcon::ContextMenu,
+        CursorShape::Help => CursorIcon::Help,
+        CursorShape::Pointer => CursorIcon::Hand,
+        CursorShape::Progress => CursorIcon::Progress,
+        CursorShape::Wait => CursorIcon::Wait,
+        CursorShape::Cell => CursorIcon::Cell,
+        CursorShape::Crosshair => CursorIcon::Crosshair,
+        CursorShape::Text => CursorIcon::Text,
+        CursorShape::VerticalText => CursorIcon::VerticalText,
+        CursorShape::Alias => CursorIcon::Alias,
+        CursorShape::Copy => CursorIcon::Copy,
+        CursorShape::NoDrop => CursorIcon::NoDrop,
+        CursorShape::NotAllowed => CursorIcon::NotAllowed,
+        CursorShape::Grab => CursorIcon::Grab,
+        CursorShape::Grabbing => CursorIcon::Grabbing,
+        CursorShape::ResizeLeftRight => CursorIcon::EwResize,
+        CursorShape::ResizeTopBottom => CursorIcon::NsResize,
+        CursorShape::ResizeTopRightBottomLeft => CursorIcon::NeswResize,
+        CursorShape::ResizeTopLeftBottomRight => CursorIcon::NwseResize,
+        CursorShape::ColumnResize => CursorIcon::ColResize,
+        CursorShape::RowResize => CursorIcon::RowResize,
+        CursorShape::AllScroll => CursorIcon::AllScroll,
+        CursorShape::ZoomIn => CursorIcon::ZoomIn,
+        CursorShape::ZoomOut => CursorIcon::ZoomOut,
+    }
+}
@@ -15,8 +15,8 @@ use x11rb::{
     protocol::{
         composite::{ConnectionExt as _, Redirect},
         xproto::{
-            ChangeWindowAttributesAux, ConfigWindow, ConfigureWindowAux, ConnectionExt as _, EventMask,
-            Window, WindowClass,
+            Atom, AtomEnum, ChangeWindowAttributesAux, ConfigWindow, ConfigureWindowAux, ConnectionExt as _,
+            EventMask, Window, WindowClass,
         },
         Event,
     },
@@ -82,6 +82,8 @@ x11rb::atom_manager! {
     Atoms: AtomsCookie {
         WM_S0,
         WL_SURFACE_ID,
+        _NET_WM_NAME,
+        UTF8_STRING,
     }
 }
 
@@ -91,6 +93,8 @@ struct X11State {
     atoms: Atoms,
     log: slog::Logger,
     unpaired_surfaces: HashMap<u32, (Window, (i32, i32))>,
+    /// The paired windows, to update their properties
+    windows: HashMap<Window, X11Surface>,
     token: CompositorToken<Roles>,
     window_map: Rc<RefCell<MyWindowMap>>,
 }
@@ -144,6 +148,7 @@ impl X11State {
             conn: Rc::clone(&conn),
             atoms,
             unpaired_surfaces: Default::default(),
+            windows: Default::default(),
             token,
             window_map,
             log,
@@ -222,6 +227,17 @@ impl X11State {
                     }
                 }
             }
+            Event::PropertyNotify(n) => {
+                let atom = n.atom;
+                if atom == AtomEnum::WM_CLASS.into()
+                    || atom == AtomEnum::WM_NAME.into()
+                    || atom == self.atoms._NET_WM_NAME
+                {
+                    if let Some(x11surface) = self.windows.get(&n.window) {
+                        *x11surface.properties.borrow_mut() = self.properties(n.window);
+                    }
+                }
+            }
             _ => {}
         }
         Ok(())
@@ -236,13 +252,60 @@ impl X11State {
             return;
         }
 
-        let x11surface = X11Surface { surface };
+        // the title and the class may change while the window is mapped
+        let event_mask = ChangeWindowAttributesAux::default().event_mask(EventMask::PropertyChange);
+        if let Err(err) = self.conn.change_window_attributes(window, &event_mask) {
+            warn!(self.log, "Failed to watch the properties of {:x?}", window; "err" => format!("{:?}", err));
+        }
+
+        let x11surface = X11Surface {
+            surface,
+            properties: Rc::new(RefCell::new(self.properties(window))),
+        };
+        // the window manager does not hear about destroyed windows, their surfaces tell
+        self.windows.retain(|_, other| other.alive());
+        self.windows.insert(window, x11surface.clone());
         self.window_map
             .borrow_mut()
             .insert(Kind::X11(x11surface), location);
     }
 }
 
+impl X11State {
+    /// Reads the class and the title of a window.
+    fn properties(&self, window: Window) -> X11Properties {
+        // WM_CLASS holds the instance and the class name
+        let class = self
+            .property_strings(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())
+            .and_then(|strings| strings.into_iter().nth(1));
+        let title = self
+            .property_strings(window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING)
+            .or_else(|| self.property_strings(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into()))
+            .and_then(|strings| strings.into_iter().next());
+        X11Properties { class, title }
+    }
+
+    /// Reads a property made of nul separated strings.
+    fn property_strings(&self, window: Window, property: Atom, type_: Atom) -> Option<Vec<String>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, type_, 0, 1024)
+            .ok()?
+            .reply()
+            .ok()?;
+        if reply.value.is_empty() {
+            return None;
+        }
+        Some(
+            reply
+                .value
+                .split(|&byte| byte == 0)
+                .map(|string| String::from_utf8_lossy(string).into_owned())
+                .collect(),
+        )
+    }
+}
+
 // Called when a WlSurface commits.
 pub fn commit_hook(surface: &WlSurface) {
     // Is this the Xwayland client?
@@ -260,9 +323,19 @@ pub fn commit_hook(surface: &WlSurface) {
 
 pub struct X11SurfaceRole;
 
+/// The properties of an X11 window, kept up to date by the window manager.
+#[derive(Debug, Default)]
+struct X11Properties {
+    /// The class of WM_CLASS
+    class: Option<String>,
+    title: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct X11Surface {
     surface: WlSurface,
+    /// Shared by the clones of the window map and the window manager
+    properties: Rc<RefCell<X11Properties>>,
 }
 
 impl X11Surface {
@@ -281,4 +354,12 @@ impl X11Surface {
             None
         }
     }
+
+    pub fn class(&self) -> Option<String> {
+        self.properties.borrow().class.clone()
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.properties.borrow().title.clone()
+    }
 }